@@ -98,6 +98,11 @@ impl CloneToOwned for ByteBuf<'_> {
     type Target = ByteBufOwned;
 
     fn clone_to_owned(&self, within_buffer: Option<&Bytes>) -> Self::Target {
+        // Defaulted fields (e.g. missing "pieces" in v2 torrents) don't point into any buffer.
+        if self.0.is_empty() {
+            return ByteBufOwned(Bytes::new());
+        }
+
         // Try zero-copy from the provided buffer.
         if let Some(within_buffer) = within_buffer {
             let haystack = within_buffer.as_ptr() as usize;
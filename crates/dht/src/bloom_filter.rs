@@ -0,0 +1,106 @@
+// BEP 33: bloom filters of peer IPs, used to estimate swarm size with DHT scrapes.
+
+use std::net::IpAddr;
+
+const BYTES: usize = 256;
+const BITS: usize = BYTES * 8;
+const HASHES: f64 = 2.;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScrapeBloomFilter([u8; BYTES]);
+
+impl Default for ScrapeBloomFilter {
+    fn default() -> Self {
+        Self([0u8; BYTES])
+    }
+}
+
+impl std::fmt::Debug for ScrapeBloomFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ScrapeBloomFilter(~{:.0})", self.estimate())
+    }
+}
+
+impl ScrapeBloomFilter {
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        Some(Self(b.try_into().ok()?))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn insert(&mut self, ip: IpAddr) {
+        let hash = match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, &ip.octets())
+            }
+            IpAddr::V6(ip) => {
+                ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, &ip.octets())
+            }
+        };
+        let hash = hash.as_ref();
+        for idx in [
+            u16::from_le_bytes([hash[0], hash[1]]),
+            u16::from_le_bytes([hash[2], hash[3]]),
+        ] {
+            let idx = usize::from(idx) % BITS;
+            self.0[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+
+    pub fn union(&mut self, other: &Self) {
+        for (b, o) in self.0.iter_mut().zip(other.0.iter()) {
+            *b |= o;
+        }
+    }
+
+    /// Estimated number of IPs in the filter.
+    pub fn estimate(&self) -> f64 {
+        let zeros = self.0.iter().map(|b| b.count_zeros()).sum::<u32>();
+        // BEP 33 caps this at m - 1. The lower bound keeps a full filter from giving infinity.
+        let zeros = f64::from(zeros).clamp(1., (BITS - 1) as f64);
+        let bits = BITS as f64;
+        (zeros / bits).ln() / (HASHES * (1. - 1. / bits).ln())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::ScrapeBloomFilter;
+
+    #[test]
+    fn test_bep33_vector() {
+        let mut bf = ScrapeBloomFilter::default();
+        for i in 0..=255 {
+            bf.insert(IpAddr::V4(Ipv4Addr::new(192, 0, 2, i)));
+        }
+        for i in 0..=0x3e7 {
+            bf.insert(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i)));
+        }
+        assert!(
+            (bf.estimate() - 1224.9308).abs() < 0.001,
+            "{}",
+            bf.estimate()
+        );
+    }
+
+    #[test]
+    fn test_union() {
+        let mut a = ScrapeBloomFilter::default();
+        assert!(a.estimate() < 1.);
+        a.insert(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        let mut b = ScrapeBloomFilter::default();
+        b.insert(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        b.insert(IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8)));
+        a.union(&b);
+        assert_eq!(a, b);
+        assert_eq!(
+            ScrapeBloomFilter::from_bytes(b.as_bytes()).unwrap(),
+            b.clone()
+        );
+        assert!((a.estimate() - 2.).abs() < 0.1);
+    }
+}
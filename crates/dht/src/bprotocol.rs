@@ -4,7 +4,7 @@ use std::{
     net::{SocketAddr, SocketAddrV4, SocketAddrV6},
 };
 
-use bencode::{ByteBuf, ByteBufOwned};
+use bencode::{ByteBuf, ByteBufOwned, WithRawBytes, raw_value::RawValue};
 use buffers::ByteBufT;
 use bytes::Bytes;
 use clone_to_owned::CloneToOwned;
@@ -168,6 +168,9 @@ struct RawMessage<BufT, Args = IgnoredAny, Resp = IgnoredAny> {
     version: Option<BufT>,
     #[serde(rename = "ip", skip_serializing_if = "Option::is_none")]
     ip: Option<CompactSocketAddr>,
+    // BEP 43 read-only nodes.
+    #[serde(rename = "ro", skip_serializing_if = "Option::is_none")]
+    read_only: Option<u8>,
 }
 
 pub struct Node<A> {
@@ -328,6 +331,28 @@ pub struct FindNodeRequest {
     pub want: Option<Want>,
 }
 
+/// An arbitrary bencoded value, kept as raw bytes (BEP 44 "v").
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BencodedValue<BufT>(pub BufT);
+
+impl<BufT: AsRef<[u8]>> Serialize for BencodedValue<BufT> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        RawValue(self.0.as_ref()).serialize(serializer)
+    }
+}
+
+impl<'de, BufT: Deserialize<'de>> Deserialize<'de> for BencodedValue<BufT> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        WithRawBytes::<IgnoredAny, BufT>::deserialize(deserializer).map(|v| Self(v.raw_bytes))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Response<BufT: ByteBufT> {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -339,6 +364,27 @@ pub struct Response<BufT: ByteBufT> {
     pub nodes6: Option<CompactNodeInfo<BufT, SocketAddrV6>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<BufT>,
+    // BEP 44 get responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v: Option<BencodedValue<BufT>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k: Option<BufT>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sig: Option<BufT>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    // BEP 51 sample_infohashes responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<BufT>,
+    // BEP 33 scrape responses: bloom filters of seeders and downloaders.
+    #[serde(rename = "BFsd", skip_serializing_if = "Option::is_none")]
+    pub bf_seeds: Option<BufT>,
+    #[serde(rename = "BFpe", skip_serializing_if = "Option::is_none")]
+    pub bf_peers: Option<BufT>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -347,6 +393,16 @@ pub struct GetPeersRequest {
     pub info_hash: Id20,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub want: Option<Want>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scrape: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SampleInfohashesRequest {
+    pub id: Id20,
+    pub target: Id20,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub want: Option<Want>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -361,6 +417,36 @@ pub struct AnnouncePeer<BufT> {
     pub info_hash: Id20,
     pub port: u16,
     pub token: BufT,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetRequest {
+    pub id: Id20,
+    pub target: Id20,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub want: Option<Want>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PutRequest<BufT: ByteBufT> {
+    pub id: Id20,
+    pub token: BufT,
+    pub v: BencodedValue<BufT>,
+    // The rest is only set for mutable items.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k: Option<BufT>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sig: Option<BufT>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cas: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salt: Option<BufT>,
 }
 
 #[derive(Debug)]
@@ -369,6 +455,23 @@ pub struct Message<BufT: ByteBufT> {
     pub transaction_id: BufT,
     pub version: Option<BufT>,
     pub ip: Option<SocketAddr>,
+    pub read_only: bool,
+}
+
+impl<BufT: ByteBufT> MessageKind<BufT> {
+    /// The node ID of the querying node, if this is a query.
+    pub fn query_sender_id(&self) -> Option<Id20> {
+        match self {
+            MessageKind::Error(_) | MessageKind::Response(_) => None,
+            MessageKind::GetPeersRequest(r) => Some(r.id),
+            MessageKind::FindNodeRequest(r) => Some(r.id),
+            MessageKind::PingRequest(r) => Some(r.id),
+            MessageKind::AnnouncePeer(r) => Some(r.id),
+            MessageKind::GetRequest(r) => Some(r.id),
+            MessageKind::PutRequest(r) => Some(r.id),
+            MessageKind::SampleInfohashesRequest(r) => Some(r.id),
+        }
+    }
 }
 
 impl Message<ByteBufOwned> {
@@ -390,6 +493,9 @@ pub enum MessageKind<BufT: ByteBufT> {
     Response(Response<BufT>),
     PingRequest(PingRequest),
     AnnouncePeer(AnnouncePeer<BufT>),
+    GetRequest(GetRequest),
+    PutRequest(PutRequest<BufT>),
+    SampleInfohashesRequest(SampleInfohashesRequest),
 }
 
 impl<BufT: ByteBufT> core::fmt::Debug for MessageKind<BufT> {
@@ -401,6 +507,9 @@ impl<BufT: ByteBufT> core::fmt::Debug for MessageKind<BufT> {
             Self::Response(r) => write!(f, "{r:?}"),
             Self::PingRequest(r) => write!(f, "{r:?}"),
             Self::AnnouncePeer(r) => write!(f, "{r:?}"),
+            Self::GetRequest(r) => write!(f, "{r:?}"),
+            Self::PutRequest(r) => write!(f, "{r:?}"),
+            Self::SampleInfohashesRequest(r) => write!(f, "{r:?}"),
         }
     }
 }
@@ -410,9 +519,11 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
     transaction_id: BufT,
     version: Option<BufT>,
     ip: Option<SocketAddr>,
+    read_only: bool,
     kind: MessageKind<BufT>,
 ) -> crate::Result<()> {
     let ip = ip.map(Compact);
+    let read_only = read_only.then_some(1);
     match kind {
         MessageKind::Error(e) => {
             let msg: RawMessage<BufT, (), ()> = RawMessage {
@@ -423,6 +534,7 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
                 method_name: None,
                 version,
                 ip,
+                read_only,
                 arguments: None,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
@@ -436,6 +548,7 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
                 method_name: Some(BufT::from(b"get_peers")),
                 arguments: Some(req),
                 ip,
+                read_only,
                 version,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
@@ -449,6 +562,7 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
                 method_name: Some(BufT::from(b"find_node")),
                 arguments: Some(req),
                 ip,
+                read_only,
                 version,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
@@ -462,6 +576,7 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
                 method_name: None,
                 arguments: None,
                 ip,
+                read_only,
                 version,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
@@ -475,6 +590,7 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
                 method_name: Some(BufT::from(b"ping")),
                 arguments: Some(ping),
                 ip,
+                read_only,
                 version,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
@@ -488,6 +604,49 @@ pub fn serialize_message<'a, W: Write, BufT: ByteBufT + From<&'a [u8]>>(
                 method_name: Some(BufT::from(b"announce_peer")),
                 arguments: Some(announce),
                 ip,
+                read_only,
+                version,
+            };
+            Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
+        }
+        MessageKind::GetRequest(req) => {
+            let msg: RawMessage<BufT, _, ()> = RawMessage {
+                message_type: MessageType::Request,
+                transaction_id,
+                error: None,
+                response: None,
+                method_name: Some(BufT::from(b"get")),
+                arguments: Some(req),
+                ip,
+                read_only,
+                version,
+            };
+            Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
+        }
+        MessageKind::PutRequest(req) => {
+            let msg: RawMessage<BufT, _, ()> = RawMessage {
+                message_type: MessageType::Request,
+                transaction_id,
+                error: None,
+                response: None,
+                method_name: Some(BufT::from(b"put")),
+                arguments: Some(req),
+                ip,
+                read_only,
+                version,
+            };
+            Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
+        }
+        MessageKind::SampleInfohashesRequest(req) => {
+            let msg: RawMessage<BufT, _, ()> = RawMessage {
+                message_type: MessageType::Request,
+                transaction_id,
+                error: None,
+                response: None,
+                method_name: Some(BufT::from(b"sample_infohashes")),
+                arguments: Some(req),
+                ip,
+                read_only,
                 version,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
@@ -510,6 +669,7 @@ where
                         transaction_id: de.transaction_id,
                         version: de.version,
                         ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
                         kind: MessageKind::FindNodeRequest(de.arguments.unwrap()),
                     })
                 }
@@ -520,6 +680,7 @@ where
                         transaction_id: de.transaction_id,
                         version: de.version,
                         ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
                         kind: MessageKind::GetPeersRequest(de.arguments.unwrap()),
                     })
                 }
@@ -530,6 +691,7 @@ where
                         transaction_id: de.transaction_id,
                         version: de.version,
                         ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
                         kind: MessageKind::PingRequest(de.arguments.unwrap()),
                     })
                 }
@@ -540,9 +702,43 @@ where
                         transaction_id: de.transaction_id,
                         version: de.version,
                         ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
                         kind: MessageKind::AnnouncePeer(de.arguments.unwrap()),
                     })
                 }
+                b"get" => {
+                    let de: RawMessage<BufT, GetRequest> =
+                        bencode::from_bytes(buf).map_err(|e| e.into_anyhow())?;
+                    Ok(Message {
+                        transaction_id: de.transaction_id,
+                        version: de.version,
+                        ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
+                        kind: MessageKind::GetRequest(de.arguments.unwrap()),
+                    })
+                }
+                b"put" => {
+                    let de: RawMessage<BufT, PutRequest<BufT>> =
+                        bencode::from_bytes(buf).map_err(|e| e.into_anyhow())?;
+                    Ok(Message {
+                        transaction_id: de.transaction_id,
+                        version: de.version,
+                        ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
+                        kind: MessageKind::PutRequest(de.arguments.unwrap()),
+                    })
+                }
+                b"sample_infohashes" => {
+                    let de: RawMessage<BufT, SampleInfohashesRequest> =
+                        bencode::from_bytes(buf).map_err(|e| e.into_anyhow())?;
+                    Ok(Message {
+                        transaction_id: de.transaction_id,
+                        version: de.version,
+                        ip: de.ip.map(|c| c.0),
+                        read_only: de.read_only == Some(1),
+                        kind: MessageKind::SampleInfohashesRequest(de.arguments.unwrap()),
+                    })
+                }
                 other => anyhow::bail!("unsupported method {:?}", ByteBuf(other)),
             },
             _ => anyhow::bail!(
@@ -559,6 +755,7 @@ where
                     transaction_id: de.transaction_id,
                     version: de.version,
                     ip: de.ip.map(|c| c.0),
+                    read_only: de.read_only == Some(1),
                     kind: MessageKind::Response(de.response.unwrap()),
                 })
             }
@@ -576,6 +773,7 @@ where
                     transaction_id: de.transaction_id,
                     version: de.version,
                     ip: de.ip.map(|c| c.0),
+                    read_only: de.read_only == Some(1),
                     kind: MessageKind::Error(de.error.unwrap()),
                 })
             }
@@ -633,9 +831,11 @@ mod tests {
             transaction_id,
             version,
             ip,
+            read_only,
         } = dbg!(bprotocol::deserialize_message::<ByteBuf>(data).unwrap());
         let mut buf = Vec::new();
-        bprotocol::serialize_message(&mut buf, transaction_id, version, ip, kind).unwrap();
+        bprotocol::serialize_message(&mut buf, transaction_id, version, ip, read_only, kind)
+            .unwrap();
 
         if buf.as_slice() != data {
             write(&format!("{name}-serialized"), buf.as_slice());
@@ -656,6 +856,7 @@ mod tests {
             transaction_id,
             None,
             None,
+            false,
             bprotocol::MessageKind::Error(bprotocol::ErrorDescription {
                 code: 201,
                 description: ByteBuf(b"Some error"),
@@ -670,7 +871,7 @@ mod tests {
         } = bprotocol::deserialize_message::<ByteBuf>(&buf).unwrap();
 
         let mut buf2 = Vec::new();
-        bprotocol::serialize_message(&mut buf2, transaction_id, None, None, kind).unwrap();
+        bprotocol::serialize_message(&mut buf2, transaction_id, None, None, false, kind).unwrap();
 
         if buf.as_slice() != buf2.as_slice() {
             write("error-serialized", buf.as_slice());
@@ -720,11 +921,187 @@ mod tests {
             _ => panic!("wrong kind"),
         }
         let mut buf = Vec::new();
-        bprotocol::serialize_message(&mut buf, msg.transaction_id, msg.version, msg.ip, msg.kind)
-            .unwrap();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
         assert_eq!(ann[..], buf[..]);
     }
 
+    #[test]
+    fn test_get_put() {
+        // Examples from BEP 44.
+        let get = b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q3:get1:t2:aa1:y1:qe";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(get).unwrap();
+        assert!(matches!(&msg.kind, bprotocol::MessageKind::GetRequest(_)));
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(get[..], buf[..]);
+
+        let put = b"d1:ad3:casi3e2:id20:abcdefghij01234567891:k32:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa4:salt6:foobar3:seqi4e3:sig64:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa5:token8:aoeusnth1:vd1:ali1ei2eeee1:q3:put1:t2:aa1:y1:qe";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(put).unwrap();
+        match &msg.kind {
+            bprotocol::MessageKind::PutRequest(put) => {
+                assert_eq!(put.v.0.as_ref(), b"d1:ali1ei2eee");
+                assert_eq!(put.seq, Some(4));
+                assert_eq!(put.cas, Some(3));
+            }
+            _ => panic!("wrong kind"),
+        }
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(put[..], buf[..]);
+
+        let resp =
+            b"d1:rd2:id20:0123456789abcdefghij5:token8:aoeusnth1:v12:Hello World!e1:t2:aa1:y1:re";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(resp).unwrap();
+        match &msg.kind {
+            bprotocol::MessageKind::Response(r) => {
+                assert_eq!(r.v.as_ref().unwrap().0.as_ref(), b"12:Hello World!");
+            }
+            _ => panic!("wrong kind"),
+        }
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(resp[..], buf[..]);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let req = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping2:roi1e1:t2:aa1:y1:qe";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(req).unwrap();
+        assert!(msg.read_only);
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(req[..], buf[..]);
+    }
+
+    #[test]
+    fn test_scrape() {
+        let req = b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234566:scrapei1ee1:q9:get_peers1:t2:aa1:y1:qe";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(req).unwrap();
+        match &msg.kind {
+            bprotocol::MessageKind::GetPeersRequest(r) => assert_eq!(r.scrape, Some(1)),
+            _ => panic!("wrong kind"),
+        }
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(req[..], buf[..]);
+
+        let mut resp = b"d1:rd4:BFpe256:".to_vec();
+        resp.extend_from_slice(&[1u8; 256]);
+        resp.extend_from_slice(b"4:BFsd256:");
+        resp.extend_from_slice(&[2u8; 256]);
+        resp.extend_from_slice(b"2:id20:0123456789abcdefghije1:t2:aa1:y1:re");
+        let msg = bprotocol::deserialize_message::<ByteBuf>(&resp).unwrap();
+        match &msg.kind {
+            bprotocol::MessageKind::Response(r) => {
+                assert_eq!(r.bf_peers.as_ref().unwrap().as_ref(), &[1u8; 256][..]);
+                assert_eq!(r.bf_seeds.as_ref().unwrap().as_ref(), &[2u8; 256][..]);
+            }
+            _ => panic!("wrong kind"),
+        }
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(resp[..], buf[..]);
+    }
+
+    #[test]
+    fn test_sample_infohashes() {
+        let req = b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q17:sample_infohashes1:t2:aa1:y1:qe";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(req).unwrap();
+        assert!(matches!(
+            &msg.kind,
+            bprotocol::MessageKind::SampleInfohashesRequest(_)
+        ));
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(req[..], buf[..]);
+
+        let resp = b"d1:rd2:id20:0123456789abcdefghij8:intervali21600e3:numi3e7:samples40:aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbe1:t2:aa1:y1:re";
+        let msg = bprotocol::deserialize_message::<ByteBuf>(resp).unwrap();
+        match &msg.kind {
+            bprotocol::MessageKind::Response(r) => {
+                assert_eq!(r.interval, Some(21600));
+                assert_eq!(r.num, Some(3));
+                assert_eq!(r.samples.as_ref().unwrap().as_ref().len(), 40);
+            }
+            _ => panic!("wrong kind"),
+        }
+        let mut buf = Vec::new();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.read_only,
+            msg.kind,
+        )
+        .unwrap();
+        assert_eq!(resp[..], buf[..]);
+    }
+
     #[test]
     fn deserialize_bencode_packets_captured_from_wireshark() {
         debug_bencode("req: find_node", FIND_NODE_REQUEST);
@@ -1,10 +1,12 @@
 use std::{
     cmp::Reverse,
-    net::{Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    collections::{BinaryHeap, HashSet, VecDeque},
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    num::NonZeroUsize,
     str::FromStr,
     sync::{
         Arc,
-        atomic::{AtomicU16, Ordering},
+        atomic::{AtomicU16, AtomicU64, Ordering},
     },
     task::Poll,
     time::{Duration, Instant},
@@ -12,15 +14,29 @@ use std::{
 
 use crate::{
     Error, INACTIVITY_TIMEOUT, REQUERY_INTERVAL, RESPONSE_TIMEOUT,
+    bloom_filter::ScrapeBloomFilter,
     bprotocol::{
-        self, AnnouncePeer, CompactNodeInfo, CompactNodeInfoOwned, ErrorDescription,
-        FindNodeRequest, GetPeersRequest, Message, MessageKind, Node, PingRequest, Response, Want,
+        self, AnnouncePeer, BencodedValue, CompactNodeInfo, CompactNodeInfoOwned, ErrorDescription,
+        FindNodeRequest, GetPeersRequest, GetRequest, Message, MessageKind, Node, PingRequest,
+        PutRequest, Response, SampleInfohashesRequest, Want,
+    },
+    item_store::{
+        ItemStore, MAX_SALT_LEN, MutableItem, PublicKey, PutError, Signature, SigningKey,
+        StoredItem, immutable_target, mutable_target, validate_value,
+    },
+    node_id::{ExternalIpVotes, generate_node_id, is_valid_node_id},
+    peer_store::{PeerStore, PeerStoreStats},
+    query_limiter::{
+        DEFAULT_QUERIES_PER_SECOND_PER_PREFIX, DEFAULT_QUERIES_PER_SECOND_PER_SOURCE, Dropped,
+        QueryLimiter,
+    },
+    routing_table::{
+        InsertResult, NodeStatus, RoutingTable, RoutingTableHealth, generate_random_id,
     },
-    peer_store::PeerStore,
-    routing_table::{InsertResult, NodeStatus, RoutingTable},
 };
 use backon::{ExponentialBuilder, Retryable};
 use bencode::ByteBufOwned;
+use bytes::Bytes;
 use dashmap::DashMap;
 use futures::{
     FutureExt, Stream, StreamExt, TryFutureExt, future::BoxFuture, stream::FuturesUnordered,
@@ -35,9 +51,10 @@ use librqbit_core::{
     spawn_utils::{spawn, spawn_with_cancel},
 };
 use librqbit_dualstack_sockets::{BindDevice, UdpSocket};
-use parking_lot::RwLock;
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{Sender, UnboundedReceiver, UnboundedSender, channel, unbounded_channel};
 
 use tokio_util::sync::CancellationToken;
@@ -47,6 +64,22 @@ fn now() -> Instant {
     Instant::now()
 }
 
+// Lookups of the nodes closest to a target, for BEP 44 items and BEP 33 scrapes.
+const LOOKUP_K: usize = 8;
+const LOOKUP_PARALLELISM: usize = 4;
+const LOOKUP_MAX_REQUESTS: usize = 128;
+
+// BEP 51 sample_infohashes.
+const SAMPLE_INFOHASHES_MAX: usize = 20;
+const SAMPLE_INFOHASHES_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+const CRAWL_PARALLELISM: usize = 32;
+const CRAWL_MAX_QUEUE: usize = 10_000;
+// The crawl runs for long, so it only remembers the most recently seen nodes and info hashes.
+const CRAWL_MAX_NODES: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+const CRAWL_MAX_INFO_HASHES: NonZeroUsize = NonZeroUsize::new(500_000).unwrap();
+
+const PEER_STORE_CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 #[derive(Debug, Serialize)]
 pub struct DhtStats {
     #[serde(serialize_with = "crate::utils::serialize_id20")]
@@ -54,6 +87,174 @@ pub struct DhtStats {
     pub outstanding_requests: usize,
     pub routing_table_size: usize,
     pub routing_table_size_v6: usize,
+    pub routing_table_health: RoutingTableHealth,
+    pub routing_table_health_v6: RoutingTableHealth,
+    pub external_ip: Option<IpAddr>,
+    pub queries_received: DhtQueryStats,
+    /// Queries dropped because their source (or its network) sent too many.
+    pub queries_rate_limited: u64,
+    /// Queries dropped because their source misbehaved recently.
+    pub queries_blacklisted: u64,
+    /// Messages dropped because of the IP filter (e.g. the session blocklist).
+    pub messages_blocked: u64,
+    /// Messages we couldn't parse.
+    pub messages_malformed: u64,
+    pub peer_store: PeerStoreStats,
+}
+
+/// Number of queries received from other nodes, by type.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct DhtQueryStats {
+    pub ping: u64,
+    pub find_node: u64,
+    pub get_peers: u64,
+    pub announce_peer: u64,
+    pub get: u64,
+    pub put: u64,
+    pub sample_infohashes: u64,
+}
+
+#[derive(Default)]
+struct QueryCounters {
+    ping: AtomicU64,
+    find_node: AtomicU64,
+    get_peers: AtomicU64,
+    announce_peer: AtomicU64,
+    get: AtomicU64,
+    put: AtomicU64,
+    sample_infohashes: AtomicU64,
+    rate_limited: AtomicU64,
+    blacklisted: AtomicU64,
+    blocked: AtomicU64,
+    malformed: AtomicU64,
+}
+
+impl QueryCounters {
+    fn on_query(&self, kind: &MessageKind<ByteBufOwned>) {
+        let counter = match kind {
+            MessageKind::PingRequest(_) => &self.ping,
+            MessageKind::FindNodeRequest(_) => &self.find_node,
+            MessageKind::GetPeersRequest(_) => &self.get_peers,
+            MessageKind::AnnouncePeer(_) => &self.announce_peer,
+            MessageKind::GetRequest(_) => &self.get,
+            MessageKind::PutRequest(_) => &self.put,
+            MessageKind::SampleInfohashesRequest(_) => &self.sample_infohashes,
+            MessageKind::Error(_) | MessageKind::Response(_) => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> DhtQueryStats {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        DhtQueryStats {
+            ping: get(&self.ping),
+            find_node: get(&self.find_node),
+            get_peers: get(&self.get_peers),
+            announce_peer: get(&self.announce_peer),
+            get: get(&self.get),
+            put: get(&self.put),
+            sample_infohashes: get(&self.sample_infohashes),
+        }
+    }
+}
+
+impl DhtStats {
+    pub fn as_prometheus(&self, mut out: &mut String) {
+        use core::fmt::Write;
+
+        out.push('\n');
+
+        macro_rules! m {
+            ($type:ident, $name:ident, $value:expr) => {{
+                writeln!(
+                    &mut out,
+                    concat!("# TYPE ", stringify!($name), " ", stringify!($type))
+                )
+                .unwrap();
+                writeln!(&mut out, concat!(stringify!($name), " {}"), $value).unwrap();
+            }};
+        }
+
+        m!(
+            gauge,
+            rqbit_dht_outstanding_requests,
+            self.outstanding_requests
+        );
+
+        writeln!(&mut out, "# TYPE rqbit_dht_routing_table_nodes gauge").unwrap();
+        for (family, health) in [
+            ("v4", &self.routing_table_health),
+            ("v6", &self.routing_table_health_v6),
+        ] {
+            for (status, count) in [
+                ("good", health.good),
+                ("questionable", health.questionable),
+                ("bad", health.bad),
+                ("unknown", health.unknown),
+            ] {
+                writeln!(
+                    &mut out,
+                    "rqbit_dht_routing_table_nodes{{family=\"{family}\",status=\"{status}\"}} {count}"
+                )
+                .unwrap();
+            }
+        }
+
+        let q = &self.queries_received;
+        writeln!(&mut out, "# TYPE rqbit_dht_queries_received counter").unwrap();
+        for (kind, count) in [
+            ("ping", q.ping),
+            ("find_node", q.find_node),
+            ("get_peers", q.get_peers),
+            ("announce_peer", q.announce_peer),
+            ("get", q.get),
+            ("put", q.put),
+            ("sample_infohashes", q.sample_infohashes),
+        ] {
+            writeln!(
+                &mut out,
+                "rqbit_dht_queries_received{{type=\"{kind}\"}} {count}"
+            )
+            .unwrap();
+        }
+        writeln!(&mut out, "# TYPE rqbit_dht_messages_dropped counter").unwrap();
+        for (reason, count) in [
+            ("rate_limited", self.queries_rate_limited),
+            ("blacklisted", self.queries_blacklisted),
+            ("blocked", self.messages_blocked),
+            ("malformed", self.messages_malformed),
+        ] {
+            writeln!(
+                &mut out,
+                "rqbit_dht_messages_dropped{{reason=\"{reason}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        m!(
+            gauge,
+            rqbit_dht_stored_info_hashes,
+            self.peer_store.info_hashes
+        );
+        m!(gauge, rqbit_dht_stored_peers, self.peer_store.peers);
+        m!(
+            counter,
+            rqbit_dht_stored_peers_evicted,
+            self.peer_store.evicted
+        );
+        m!(
+            counter,
+            rqbit_dht_stored_peers_expired,
+            self.peer_store.expired
+        );
+    }
+}
+
+/// Swarm size of an info hash, estimated with a DHT scrape (BEP 33).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrapeEstimate {
+    pub seeders: u32,
+    pub leechers: u32,
 }
 
 struct OutstandingRequest {
@@ -109,8 +310,13 @@ struct RecursiveRequestCallbacksGetPeers {
     // Id20::from_str("00000fffffffffffffffffffffffffffffffffff").unwrap()
     min_distance_to_announce: Id20,
     announce_port: Option<u16>,
+    // Whether we are a seed, sent with announces for BEP 33 scrapes.
+    is_seed: Option<IsSeed>,
 }
 
+/// Tells the DHT if we are a seed of the torrent when announcing it (BEP 33).
+pub type IsSeed = Arc<dyn Fn() -> bool + Send + Sync>;
+
 impl RecursiveRequestCallbacks for RecursiveRequestCallbacksGetPeers {
     fn on_request_start(&self, _: &RecursiveRequest<Self>, _: Id20, _: SocketAddr) {}
 
@@ -145,6 +351,7 @@ impl RecursiveRequestCallbacks for RecursiveRequestCallbacksGetPeers {
                 info_hash: req.info_hash,
                 token: token.clone(),
                 port: announce_port,
+                seed: self.is_seed.as_ref().map(|is_seed| is_seed()),
             },
             addr,
         );
@@ -160,9 +367,15 @@ impl RecursiveRequestCallbacks for RecursiveRequestCallbacksGetPeers {
 struct RecursiveRequestCallbacksFindNodes {}
 impl RecursiveRequestCallbacks for RecursiveRequestCallbacksFindNodes {
     fn on_request_start(&self, req: &RecursiveRequest<Self>, target_node: Id20, addr: SocketAddr) {
+        if req.dht.is_blocked(addr.ip()) {
+            return;
+        }
         let mut rt = req.dht.get_table_for_addr(addr).write();
         match rt.add_node(target_node, addr) {
-            InsertResult::WasExisting | InsertResult::ReplacedBad(_) | InsertResult::Added => {
+            InsertResult::WasExisting
+            | InsertResult::ReplacedBad(_)
+            | InsertResult::ReplacedNonCompliant(_)
+            | InsertResult::Added => {
                 rt.mark_outgoing_request(&target_node, now());
             }
             InsertResult::Ignored => {}
@@ -204,7 +417,12 @@ pub struct RequestPeersStream {
 }
 
 impl RequestPeersStream {
-    fn new(dht: Arc<DhtState>, info_hash: Id20, announce_port: Option<u16>) -> Self {
+    fn new(
+        dht: Arc<DhtState>,
+        info_hash: Id20,
+        announce_port: Option<u16>,
+        is_seed: Option<IsSeed>,
+    ) -> Self {
         let (peer_tx, peer_rx) = unbounded_channel();
         let make = |is_v4: bool, dht: Arc<DhtState>, peer_tx: UnboundedSender<SocketAddr>| {
             let (node_tx, node_rx) = unbounded_channel();
@@ -223,6 +441,7 @@ impl RequestPeersStream {
                     )
                     .unwrap(),
                     announce_port,
+                    is_seed: is_seed.clone(),
                 },
             });
             rp.request_peers_forever(node_rx, is_v4)
@@ -258,6 +477,31 @@ impl Stream for RequestPeersStream {
     }
 }
 
+/// Info hashes discovered by crawling the DHT with BEP 51 sample_infohashes queries.
+/// Each info hash is yielded once, unless it was last seen long ago, as only the most recent ones
+/// are remembered. The crawl stops when this is dropped.
+pub struct InfoHashCrawlStream {
+    rx: UnboundedReceiver<Id20>,
+    join_handle: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for InfoHashCrawlStream {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+impl Stream for InfoHashCrawlStream {
+    type Item = Id20;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
 impl RecursiveRequest<RecursiveRequestCallbacksFindNodes> {
     async fn find_node_for_routing_table(
         dht: Arc<DhtState>,
@@ -571,7 +815,8 @@ impl<C: RecursiveRequestCallbacks> RecursiveRequest<C> {
 }
 
 pub struct DhtState {
-    id: Id20,
+    id: RwLock<Id20>,
+    external_ip_votes: Mutex<ExternalIpVotes>,
     next_transaction_id: AtomicU16,
 
     // Created requests: (transaction_id, addr) => Requests.
@@ -590,9 +835,17 @@ pub struct DhtState {
     cancellation_token: CancellationToken,
 
     pub(crate) peer_store: PeerStore,
+    item_store: ItemStore,
+    // BEP 43: don't answer queries and ask others not to add us to their routing tables.
+    read_only: bool,
+
+    query_limiter: QueryLimiter,
+    query_counters: QueryCounters,
+    ip_filter: Option<Arc<dyn IpFilter>>,
 }
 
 impl DhtState {
+    #[allow(clippy::too_many_arguments)]
     fn new_internal(
         id: Id20,
         sender: UnboundedSender<WorkerSendRequest>,
@@ -601,11 +854,15 @@ impl DhtState {
         listen_addr: SocketAddr,
         peer_store: PeerStore,
         cancellation_token: CancellationToken,
+        read_only: bool,
+        query_limiter: QueryLimiter,
+        ip_filter: Option<Arc<dyn IpFilter>>,
     ) -> Self {
         let routing_table_v4 = routing_table_v4.unwrap_or_else(|| RoutingTable::new(id, None));
         let routing_table_v6 = routing_table_v6.unwrap_or_else(|| RoutingTable::new(id, None));
         Self {
-            id,
+            id: RwLock::new(id),
+            external_ip_votes: Default::default(),
             next_transaction_id: AtomicU16::new(0),
             inflight_by_transaction_id: Default::default(),
             routing_table_v4: RwLock::new(routing_table_v4),
@@ -614,11 +871,57 @@ impl DhtState {
             listen_addr,
             rate_limiter: make_rate_limiter(),
             peer_store,
+            item_store: ItemStore::new(),
             cancellation_token,
+            read_only,
+            query_limiter,
+            query_counters: Default::default(),
+            ip_filter,
         }
     }
 
+    fn is_blocked(&self, ip: IpAddr) -> bool {
+        self.ip_filter
+            .as_ref()
+            .is_some_and(|f| f.is_blocked(ip.to_canonical()))
+    }
+
+    fn on_malformed_message(&self, addr: SocketAddr) {
+        self.query_counters
+            .malformed
+            .fetch_add(1, Ordering::Relaxed);
+        self.query_limiter.on_malformed(addr.ip());
+    }
+
+    fn id(&self) -> Id20 {
+        *self.id.read()
+    }
+
+    // BEP 42: once we know our external IP, switch to a node ID derived from it.
+    fn on_external_ip_vote(&self, voter: SocketAddr, ip: SocketAddr) {
+        let Some(external_ip) = self.external_ip_votes.lock().add_vote(voter.ip(), ip.ip()) else {
+            return;
+        };
+        if is_valid_node_id(&self.id(), external_ip) {
+            return;
+        }
+        let id = generate_node_id(external_ip);
+        info!(
+            ?external_ip,
+            "changing DHT node id to {id:?} to match our external IP"
+        );
+        *self.id.write() = id;
+        for table in [&self.routing_table_v4, &self.routing_table_v6] {
+            let mut table = table.write();
+            *table = table.with_id(id);
+        }
+        self.peer_store.set_self_id(id);
+    }
+
     async fn request(&self, request: Request, addr: SocketAddr) -> crate::Result<ResponseOrError> {
+        if self.is_blocked(addr.ip()) {
+            return Err(Error::Blocked);
+        }
         self.rate_limiter.acquire_one().await;
         let (tid, message) = self.create_request(request, addr);
         let key = (tid, addr);
@@ -668,18 +971,33 @@ impl DhtState {
                 transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
                 version: None,
                 ip: None,
+                read_only: self.read_only,
                 kind: MessageKind::GetPeersRequest(GetPeersRequest {
-                    id: self.id,
+                    id: self.id(),
                     info_hash,
                     want,
+                    scrape: None,
+                }),
+            },
+            Request::Scrape(info_hash) => Message {
+                transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
+                version: None,
+                ip: None,
+                read_only: self.read_only,
+                kind: MessageKind::GetPeersRequest(GetPeersRequest {
+                    id: self.id(),
+                    info_hash,
+                    want,
+                    scrape: Some(1),
                 }),
             },
             Request::FindNode(target) => Message {
                 transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
                 version: None,
                 ip: None,
+                read_only: self.read_only,
                 kind: MessageKind::FindNodeRequest(FindNodeRequest {
-                    id: self.id,
+                    id: self.id(),
                     target,
                     want,
                 }),
@@ -688,23 +1006,81 @@ impl DhtState {
                 transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
                 version: None,
                 ip: None,
-                kind: MessageKind::PingRequest(PingRequest { id: self.id }),
+                read_only: self.read_only,
+                kind: MessageKind::PingRequest(PingRequest { id: self.id() }),
             },
             Request::Announce {
                 info_hash,
                 token,
                 port,
+                seed,
             } => Message {
                 kind: MessageKind::AnnouncePeer(AnnouncePeer {
-                    id: self.id,
+                    id: self.id(),
                     implied_port: 0,
                     info_hash,
                     port,
                     token,
+                    seed: seed.map(u8::from),
                 }),
                 transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
                 version: None,
                 ip: None,
+                read_only: self.read_only,
+            },
+            Request::Get { target, seq } => Message {
+                transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
+                version: None,
+                ip: None,
+                read_only: self.read_only,
+                kind: MessageKind::GetRequest(GetRequest {
+                    id: self.id(),
+                    target,
+                    seq,
+                    want,
+                }),
+            },
+            Request::Put { token, item } => {
+                let put = match item {
+                    ItemToPut::Immutable(value) => PutRequest {
+                        id: self.id(),
+                        token,
+                        v: BencodedValue(value.into()),
+                        k: None,
+                        sig: None,
+                        seq: None,
+                        cas: None,
+                        salt: None,
+                    },
+                    ItemToPut::Mutable { item, cas } => PutRequest {
+                        id: self.id(),
+                        token,
+                        v: BencodedValue(item.value.into()),
+                        k: Some(ByteBufOwned::from(&item.public_key[..])),
+                        sig: Some(ByteBufOwned::from(&item.signature[..])),
+                        seq: Some(item.seq),
+                        cas,
+                        salt: (!item.salt.is_empty()).then(|| item.salt.into()),
+                    },
+                };
+                Message {
+                    transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
+                    version: None,
+                    ip: None,
+                    read_only: self.read_only,
+                    kind: MessageKind::PutRequest(put),
+                }
+            }
+            Request::SampleInfohashes(target) => Message {
+                transaction_id: ByteBufOwned::from(transaction_id_buf.as_ref()),
+                version: None,
+                ip: None,
+                read_only: self.read_only,
+                kind: MessageKind::SampleInfohashesRequest(SampleInfohashesRequest {
+                    id: self.id(),
+                    target,
+                    want,
+                }),
             },
         };
         (transaction_id, message)
@@ -744,6 +1120,16 @@ impl DhtState {
         }
     }
 
+    // Whether we are among the K closest nodes we know of to the target, i.e. other nodes
+    // would ask us to store peers for it.
+    fn is_close_to_us(&self, target: Id20, addr: SocketAddr) -> bool {
+        let our_distance = self.id().distance(&target);
+        self.get_table_for_addr(addr)
+            .read()
+            .count_closer_than(target, our_distance, LOOKUP_K)
+            < LOOKUP_K
+    }
+
     fn generate_compact_nodes<A>(
         &self,
         target: Id20,
@@ -772,6 +1158,11 @@ impl DhtState {
         msg: Message<ByteBufOwned>,
         addr: SocketAddr,
     ) -> crate::Result<()> {
+        if self.is_blocked(addr.ip()) {
+            trace!(?addr, "dropped message from blocked IP");
+            self.query_counters.blocked.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
         match &msg.kind {
             // If it's a response to a request we made, find the request task, notify it with the response,
             // and let it handle it.
@@ -791,6 +1182,10 @@ impl DhtState {
                     }
                 };
 
+                if let Some(ip) = msg.ip {
+                    self.on_external_ip_vote(addr, ip);
+                }
+
                 let response_or_error = match msg.kind {
                     MessageKind::Error(e) => ResponseOrError::Error(e),
                     MessageKind::Response(r) => ResponseOrError::Response(r),
@@ -811,6 +1206,26 @@ impl DhtState {
         };
 
         trace!("received query from {addr}: {msg:?}");
+        self.query_counters.on_query(&msg.kind);
+
+        if self.read_only {
+            return Ok(());
+        }
+        if let Err(dropped) = self.query_limiter.check(addr.ip()) {
+            trace!(?addr, ?dropped, "dropped query");
+            let counter = match dropped {
+                Dropped::RateLimited => &self.query_counters.rate_limited,
+                Dropped::Blacklisted => &self.query_counters.blacklisted,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        // BEP 43: read-only nodes don't belong in the routing table.
+        if msg.read_only
+            && let Some(id) = msg.kind.query_sender_id()
+        {
+            self.get_table_for_addr(addr).write().remove(&id);
+        }
 
         match &msg.kind {
             // Otherwise, respond to a query.
@@ -819,8 +1234,9 @@ impl DhtState {
                     transaction_id: msg.transaction_id,
                     version: None,
                     ip: Some(addr),
+                    read_only: false,
                     kind: MessageKind::Response(bprotocol::Response {
-                        id: self.id,
+                        id: self.id(),
                         ..Default::default()
                     }),
                 };
@@ -841,14 +1257,18 @@ impl DhtState {
                 self.get_table_for_addr(addr)
                     .write()
                     .mark_last_query(&ann.id, now());
-                let added = self.peer_store.store_peer(ann, addr);
+                // Tokens aren't tied to an info hash, so a node with a token could announce
+                // anything. Only store what we would be asked to store.
+                let added = self.is_close_to_us(ann.info_hash, addr)
+                    && self.peer_store.store_peer(ann, addr);
                 trace!("{addr}: added_peer={added}, announce={ann:?}");
                 let message = Message {
                     transaction_id: msg.transaction_id,
                     version: None,
                     ip: Some(addr),
+                    read_only: false,
                     kind: MessageKind::Response(bprotocol::Response {
-                        id: self.id,
+                        id: self.id(),
                         ..Default::default()
                     }),
                 };
@@ -868,6 +1288,17 @@ impl DhtState {
                     .unwrap_or(if addr.is_ipv6() { Want::V6 } else { Want::V4 });
                 let (nodes, nodes6) = self.generate_compact_nodes_both(req.info_hash, want);
                 let compact_peer_info = self.peer_store.get_for_info_hash(req.info_hash, want);
+                let (bf_seeds, bf_peers) = req
+                    .scrape
+                    .filter(|s| *s == 1)
+                    .and_then(|_| self.peer_store.scrape_filters(req.info_hash))
+                    .map(|(seeds, peers)| {
+                        (
+                            Some(ByteBufOwned::from(seeds.as_bytes())),
+                            Some(ByteBufOwned::from(peers.as_bytes())),
+                        )
+                    })
+                    .unwrap_or_default();
                 self.get_table_for_addr(addr)
                     .write()
                     .mark_last_query(&req.id, now());
@@ -875,14 +1306,19 @@ impl DhtState {
                     transaction_id: msg.transaction_id,
                     version: None,
                     ip: Some(addr),
+                    read_only: false,
                     kind: MessageKind::Response(bprotocol::Response {
-                        id: self.id,
+                        id: self.id(),
                         nodes,
                         nodes6,
                         values: Some(compact_peer_info),
-                        token: Some(ByteBufOwned::from(
-                            &self.peer_store.gen_token_for(req.id, addr)[..],
-                        )),
+                        // Don't invite announces for info hashes that should be stored elsewhere.
+                        token: self.is_close_to_us(req.info_hash, addr).then(|| {
+                            ByteBufOwned::from(&self.peer_store.gen_token_for(req.id, addr)[..])
+                        }),
+                        bf_seeds,
+                        bf_peers,
+                        ..Default::default()
                     }),
                 };
                 self.worker_sender
@@ -907,8 +1343,9 @@ impl DhtState {
                     transaction_id: msg.transaction_id,
                     version: None,
                     ip: Some(addr),
+                    read_only: false,
                     kind: MessageKind::Response(bprotocol::Response {
-                        id: self.id,
+                        id: self.id(),
                         nodes,
                         nodes6,
                         ..Default::default()
@@ -924,16 +1361,151 @@ impl DhtState {
                     .ok_or(Error::DhtDead)?;
                 Ok(())
             }
+            MessageKind::GetRequest(req) => {
+                let want = req
+                    .want
+                    .unwrap_or(if addr.is_ipv6() { Want::V6 } else { Want::V4 });
+                let (nodes, nodes6) = self.generate_compact_nodes_both(req.target, want);
+                self.get_table_for_addr(addr)
+                    .write()
+                    .mark_last_query(&req.id, now());
+                let mut response = bprotocol::Response {
+                    id: self.id(),
+                    nodes,
+                    nodes6,
+                    token: Some(ByteBufOwned::from(
+                        &self.peer_store.gen_token_for(req.id, addr)[..],
+                    )),
+                    ..Default::default()
+                };
+                match self.item_store.get(&req.target) {
+                    Some(StoredItem::Immutable(value)) => {
+                        response.v = Some(BencodedValue(value.into()));
+                    }
+                    Some(StoredItem::Mutable(item)) => {
+                        response.seq = Some(item.seq);
+                        // The requester already has this or a newer one.
+                        if req.seq.is_none_or(|seq| item.seq > seq) {
+                            response.v = Some(BencodedValue(item.value.into()));
+                            response.k = Some(ByteBufOwned::from(&item.public_key[..]));
+                            response.sig = Some(ByteBufOwned::from(&item.signature[..]));
+                        }
+                    }
+                    None => {}
+                }
+                let message = Message {
+                    transaction_id: msg.transaction_id,
+                    version: None,
+                    ip: Some(addr),
+                    read_only: false,
+                    kind: MessageKind::Response(response),
+                };
+                self.worker_sender
+                    .send(WorkerSendRequest {
+                        our_tid: None,
+                        message,
+                        addr,
+                    })
+                    .ok()
+                    .ok_or(Error::DhtDead)?;
+                Ok(())
+            }
+            MessageKind::PutRequest(req) => {
+                self.get_table_for_addr(addr)
+                    .write()
+                    .mark_last_query(&req.id, now());
+                let result = if self
+                    .peer_store
+                    .is_valid_token(req.id, addr, req.token.as_ref())
+                {
+                    self.item_store.put(req)
+                } else {
+                    Err(PutError::InvalidToken)
+                };
+                trace!("{addr}: put result={result:?}");
+                let kind = match result {
+                    Ok(_) => MessageKind::Response(bprotocol::Response {
+                        id: self.id(),
+                        ..Default::default()
+                    }),
+                    Err(e) => MessageKind::Error(ErrorDescription {
+                        code: e.code(),
+                        description: ByteBufOwned::from(e.to_string().into_bytes()),
+                    }),
+                };
+                let message = Message {
+                    transaction_id: msg.transaction_id,
+                    version: None,
+                    ip: Some(addr),
+                    read_only: false,
+                    kind,
+                };
+                self.worker_sender
+                    .send(WorkerSendRequest {
+                        our_tid: None,
+                        message,
+                        addr,
+                    })
+                    .ok()
+                    .ok_or(Error::DhtDead)?;
+                Ok(())
+            }
+            MessageKind::SampleInfohashesRequest(req) => {
+                let want = req
+                    .want
+                    .unwrap_or(if addr.is_ipv6() { Want::V6 } else { Want::V4 });
+                let (nodes, nodes6) = self.generate_compact_nodes_both(req.target, want);
+                self.get_table_for_addr(addr)
+                    .write()
+                    .mark_last_query(&req.id, now());
+                let (samples, num) = self.peer_store.sample_info_hashes(SAMPLE_INFOHASHES_MAX);
+                let message = Message {
+                    transaction_id: msg.transaction_id,
+                    version: None,
+                    ip: Some(addr),
+                    read_only: false,
+                    kind: MessageKind::Response(bprotocol::Response {
+                        id: self.id(),
+                        nodes,
+                        nodes6,
+                        interval: Some(SAMPLE_INFOHASHES_INTERVAL.as_secs() as i64),
+                        num: Some(num as i64),
+                        samples: Some(ByteBufOwned::from(
+                            samples.iter().flat_map(|h| h.0).collect::<Vec<u8>>(),
+                        )),
+                        ..Default::default()
+                    }),
+                };
+                self.worker_sender
+                    .send(WorkerSendRequest {
+                        our_tid: None,
+                        message,
+                        addr,
+                    })
+                    .ok()
+                    .ok_or(Error::DhtDead)?;
+                Ok(())
+            }
             _ => unreachable!(),
         }
     }
 
     pub fn get_stats(&self) -> DhtStats {
+        let now = Instant::now();
         DhtStats {
-            id: self.id,
+            id: self.id(),
             outstanding_requests: self.inflight_by_transaction_id.len(),
             routing_table_size: self.routing_table_v4.read().len(),
             routing_table_size_v6: self.routing_table_v6.read().len(),
+            routing_table_health: self.routing_table_v4.read().health(now),
+            routing_table_health_v6: self.routing_table_v6.read().health(now),
+            external_ip: self.external_ip_votes.lock().external_ip(),
+            queries_received: self.query_counters.snapshot(),
+            queries_rate_limited: self.query_counters.rate_limited.load(Ordering::Relaxed),
+            queries_blacklisted: self.query_counters.blacklisted.load(Ordering::Relaxed),
+            messages_blocked: self.query_counters.blocked.load(Ordering::Relaxed),
+            messages_malformed: self.query_counters.malformed.load(Ordering::Relaxed),
+            peer_store: self.peer_store.stats(),
         }
     }
 }
@@ -946,10 +1518,33 @@ enum Request {
         info_hash: Id20,
         token: ByteBufOwned,
         port: u16,
+        seed: Option<bool>,
     },
     Ping,
+    Get {
+        target: Id20,
+        seq: Option<i64>,
+    },
+    Put {
+        token: ByteBufOwned,
+        item: ItemToPut,
+    },
+    SampleInfohashes(Id20),
+    Scrape(Id20),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ItemToPut {
+    Immutable(Bytes),
+    Mutable { item: MutableItem, cas: Option<i64> },
 }
 
+struct LookupResponse {
+    addr: SocketAddr,
+    response: Response<ByteBufOwned>,
+}
+
+#[allow(clippy::large_enum_variant)]
 enum ResponseOrError {
     Response(Response<ByteBufOwned>),
     Error(ErrorDescription<ByteBufOwned>),
@@ -985,14 +1580,14 @@ impl DhtWorker {
             .collect::<Vec<_>>();
         let v4 = RecursiveRequest::find_node_for_routing_table(
             self.dht.clone(),
-            self.dht.id,
+            self.dht.id(),
             addrs.iter().copied().filter(|a| a.is_ipv4()),
         )
         .instrument(debug_span!("v4"));
 
         let v6 = RecursiveRequest::find_node_for_routing_table(
             self.dht.clone(),
-            self.dht.id,
+            self.dht.id(),
             addrs.iter().copied().filter(|a| a.is_ipv6()),
         )
         .instrument(debug_span!("v6"));
@@ -1091,6 +1686,15 @@ impl DhtWorker {
         }
     }
 
+    async fn peer_store_cleaner(&self) -> crate::Result<()> {
+        let mut interval = tokio::time::interval(PEER_STORE_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let removed = self.dht.peer_store.garbage_collect_peers();
+            trace!(removed, "cleaned up announced peers");
+        }
+    }
+
     async fn pinger(&self, is_v4: bool) -> crate::Result<()> {
         let table = if is_v4 {
             &self.dht.routing_table_v4
@@ -1168,6 +1772,7 @@ impl DhtWorker {
                     message.transaction_id,
                     message.version,
                     message.ip,
+                    message.read_only,
                     message.kind,
                 )
                 .unwrap();
@@ -1189,7 +1794,12 @@ impl DhtWorker {
                         Ok(_) => {}
                         Err(_) => return Err(Error::DhtDead),
                     },
-                    Err(e) => debug!("{}: error deserializing incoming message: {}", addr, e),
+                    Err(e) => {
+                        debug!("{}: error deserializing incoming message: {}", addr, e);
+                        if !self.dht.is_blocked(addr.ip()) {
+                            self.dht.on_malformed_message(addr);
+                        }
+                    }
                 }
             }
         };
@@ -1236,6 +1846,10 @@ impl DhtWorker {
             .bucket_refresher(false)
             .instrument(debug_span!("bucket_refresher_v6"));
 
+        let peer_store_cleaner = self
+            .peer_store_cleaner()
+            .instrument(debug_span!("peer_store_cleaner"));
+
         tokio::pin!(framer);
         tokio::pin!(bootstrap);
         tokio::pin!(response_reader);
@@ -1243,6 +1857,7 @@ impl DhtWorker {
         tokio::pin!(bucket_refresher_v4);
         tokio::pin!(pinger_v6);
         tokio::pin!(bucket_refresher_v6);
+        tokio::pin!(peer_store_cleaner);
 
         loop {
             tokio::select! {
@@ -1265,6 +1880,9 @@ impl DhtWorker {
                 err = &mut bucket_refresher_v6 => {
                     return Error::task_finished(&"bucket_refresher_v6", err);
                 },
+                err = &mut peer_store_cleaner => {
+                    return Error::task_finished(&"peer_store_cleaner", err);
+                },
                 err = &mut response_reader => {
                     return Error::task_finished(&"response_reader", err);
                 }
@@ -1273,6 +1891,11 @@ impl DhtWorker {
     }
 }
 
+/// Decides which IPs the DHT doesn't talk to, e.g. from a blocklist.
+pub trait IpFilter: Send + Sync {
+    fn is_blocked(&self, ip: IpAddr) -> bool;
+}
+
 #[derive(Default)]
 pub struct DhtConfig<'a> {
     pub peer_id: Option<Id20>,
@@ -1283,6 +1906,16 @@ pub struct DhtConfig<'a> {
     pub peer_store: Option<PeerStore>,
     pub cancellation_token: Option<CancellationToken>,
     pub bind_device: Option<&'a BindDevice>,
+    /// Read-only mode (BEP 43): query the DHT without answering queries from other nodes.
+    pub read_only: bool,
+    /// Max number of nodes in each routing table, including one loaded from persistence.
+    pub routing_table_size: Option<usize>,
+    /// How many queries per second we answer from a single IP.
+    pub queries_per_second_per_source: Option<u32>,
+    /// How many queries per second we answer from a single /24 (IPv4) or /64 (IPv6) network.
+    pub queries_per_second_per_prefix: Option<u32>,
+    /// IPs to neither answer nor query.
+    pub ip_filter: Option<Arc<dyn IpFilter>>,
 }
 
 impl DhtState {
@@ -1322,15 +1955,35 @@ impl DhtState {
 
             let token = config.cancellation_token.take().unwrap_or_default();
 
+            let routing_table_size = config.routing_table_size;
+            let make_table = |table: Option<RoutingTable>| {
+                let mut table =
+                    table.unwrap_or_else(|| RoutingTable::new(peer_id, routing_table_size));
+                if let Some(size) = routing_table_size {
+                    table.set_max_size(size);
+                }
+                table
+            };
+
             let (in_tx, in_rx) = unbounded_channel();
             let state = Arc::new(Self::new_internal(
                 peer_id,
                 in_tx,
-                config.routing_table,
-                config.routing_table_v6,
+                Some(make_table(config.routing_table)),
+                Some(make_table(config.routing_table_v6)),
                 listen_addr,
                 config.peer_store.unwrap_or_else(|| PeerStore::new(peer_id)),
                 token,
+                config.read_only,
+                QueryLimiter::new(
+                    config
+                        .queries_per_second_per_source
+                        .unwrap_or(DEFAULT_QUERIES_PER_SECOND_PER_SOURCE),
+                    config
+                        .queries_per_second_per_prefix
+                        .unwrap_or(DEFAULT_QUERIES_PER_SECOND_PER_PREFIX),
+                ),
+                config.ip_filter,
             ));
 
             spawn_with_cancel(
@@ -1355,13 +2008,410 @@ impl DhtState {
         info_hash: Id20,
         announce_port: Option<u16>,
     ) -> RequestPeersStream {
-        RequestPeersStream::new(self.clone(), info_hash, announce_port)
+        RequestPeersStream::new(self.clone(), info_hash, announce_port, None)
+    }
+
+    /// Like [`get_peers`](Self::get_peers), but the announces also say if we are a seed, so that
+    /// other nodes count us as one in BEP 33 scrapes.
+    pub fn get_peers_announcing_seed(
+        self: &Arc<Self>,
+        info_hash: Id20,
+        announce_port: u16,
+        is_seed: IsSeed,
+    ) -> RequestPeersStream {
+        RequestPeersStream::new(self.clone(), info_hash, Some(announce_port), Some(is_seed))
     }
 
     pub fn listen_addr(&self) -> SocketAddr {
         self.listen_addr
     }
 
+    /// Get an immutable item (BEP 44), returning its bencoded value.
+    pub async fn get_immutable(self: &Arc<Self>, target: Id20) -> crate::Result<Option<Bytes>> {
+        Ok(self
+            .lookup(target, Request::Get { target, seq: None })
+            .await?
+            .into_iter()
+            .filter_map(|r| r.response.v)
+            .map(|v| v.0.0)
+            .find(|v| immutable_target(v) == target))
+    }
+
+    /// Get the latest version of a mutable item (BEP 44). If `min_seq` is set, only return it
+    /// if it's newer than that.
+    pub async fn get_mutable(
+        self: &Arc<Self>,
+        public_key: PublicKey,
+        salt: Bytes,
+        min_seq: Option<i64>,
+    ) -> crate::Result<Option<MutableItem>> {
+        let target = mutable_target(&public_key, &salt);
+        Ok(self
+            .lookup(
+                target,
+                Request::Get {
+                    target,
+                    seq: min_seq,
+                },
+            )
+            .await?
+            .into_iter()
+            .filter_map(|r| {
+                let r = r.response;
+                if r.k.as_ref().map(|k| k.as_ref()) != Some(&public_key[..]) {
+                    return None;
+                }
+                let item = MutableItem {
+                    public_key,
+                    salt: salt.clone(),
+                    seq: r.seq?,
+                    value: r.v?.0.0,
+                    signature: Signature::try_from(r.sig?.as_ref()).ok()?,
+                };
+                item.verify().then_some(item)
+            })
+            .filter(|item| min_seq.is_none_or(|seq| item.seq > seq))
+            .max_by_key(|item| item.seq))
+    }
+
+    /// Store an immutable item (BEP 44) with the given bencoded value. Returns its target.
+    pub async fn put_immutable(self: &Arc<Self>, value: Bytes) -> crate::Result<Id20> {
+        validate_value(&value).map_err(Error::InvalidItem)?;
+        let target = immutable_target(&value);
+        self.put_item(target, ItemToPut::Immutable(value)).await?;
+        Ok(target)
+    }
+
+    /// Sign and store a mutable item (BEP 44) with the given bencoded value. With `cas`, nodes
+    /// only store it if their current sequence number is `cas`.
+    pub async fn put_mutable(
+        self: &Arc<Self>,
+        key: &SigningKey,
+        salt: Bytes,
+        seq: i64,
+        value: Bytes,
+        cas: Option<i64>,
+    ) -> crate::Result<MutableItem> {
+        validate_value(&value).map_err(Error::InvalidItem)?;
+        if salt.len() > MAX_SALT_LEN {
+            return Err(Error::InvalidItem(PutError::SaltTooBig));
+        }
+        let item = key.sign(salt, seq, value);
+        self.put_item(
+            item.target(),
+            ItemToPut::Mutable {
+                item: item.clone(),
+                cas,
+            },
+        )
+        .await?;
+        Ok(item)
+    }
+
+    async fn put_item(self: &Arc<Self>, target: Id20, item: ItemToPut) -> crate::Result<()> {
+        let mut futs = self
+            .lookup(target, Request::Get { target, seq: None })
+            .await?
+            .into_iter()
+            .filter_map(|r| Some((r.addr, r.response.token?)))
+            .take(LOOKUP_K)
+            .map(|(addr, token)| {
+                let item = item.clone();
+                async move { (addr, self.request(Request::Put { token, item }, addr).await) }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut successes = 0;
+        let mut errors = 0;
+        while let Some((addr, response)) = futs.next().await {
+            match response {
+                Ok(ResponseOrError::Response(_)) => successes += 1,
+                Ok(ResponseOrError::Error(e)) => {
+                    debug!(%addr, "error storing item: {e:?}");
+                    errors += 1;
+                }
+                Err(e) => {
+                    debug!(%addr, "error storing item: {e:#}");
+                    errors += 1;
+                }
+            }
+        }
+        if successes == 0 {
+            return Err(Error::NoSuccessfulPuts { errors });
+        }
+        debug!(?target, successes, errors, "stored item");
+        Ok(())
+    }
+
+    /// Estimate the swarm size of the info hash with a DHT scrape (BEP 33).
+    pub async fn scrape(self: &Arc<Self>, info_hash: Id20) -> crate::Result<ScrapeEstimate> {
+        self.scrape_swarms(&[info_hash]).await
+    }
+
+    /// Estimate the size of a swarm that is announced under several info hashes, e.g. the v1 and
+    /// the v2 ones of a hybrid torrent. Peers that are in more than one are counted once.
+    pub async fn scrape_swarms(
+        self: &Arc<Self>,
+        info_hashes: &[Id20],
+    ) -> crate::Result<ScrapeEstimate> {
+        let mut seeds = ScrapeBloomFilter::default();
+        let mut peers = ScrapeBloomFilter::default();
+        let mut responses = 0;
+        for info_hash in info_hashes.iter().copied() {
+            // Union the filters of the closest nodes that track the info hash.
+            for r in self
+                .lookup(info_hash, Request::Scrape(info_hash))
+                .await?
+                .into_iter()
+                .filter_map(|r| {
+                    let seeds = ScrapeBloomFilter::from_bytes(r.response.bf_seeds?.as_ref())?;
+                    let peers = ScrapeBloomFilter::from_bytes(r.response.bf_peers?.as_ref())?;
+                    Some((seeds, peers))
+                })
+                .take(LOOKUP_K)
+            {
+                seeds.union(&r.0);
+                peers.union(&r.1);
+                responses += 1;
+            }
+        }
+        let estimate = ScrapeEstimate {
+            seeders: seeds.estimate().round() as u32,
+            leechers: peers.estimate().round() as u32,
+        };
+        debug!(?info_hashes, responses, ?estimate, "scraped");
+        Ok(estimate)
+    }
+
+    // Find the nodes closest to the target, sending them the request. Returns their responses
+    // closest first.
+    async fn lookup(
+        self: &Arc<Self>,
+        target: Id20,
+        request: Request,
+    ) -> crate::Result<Vec<LookupResponse>> {
+        let mut seen = HashSet::new();
+        let mut queue = Vec::new();
+        for table in [&self.routing_table_v4, &self.routing_table_v6] {
+            for node in table
+                .read()
+                .sorted_by_distance_from(target, now())
+                .iter()
+                .take(LOOKUP_K)
+            {
+                if seen.insert(node.addr()) {
+                    queue.push((node.id(), node.addr()));
+                }
+            }
+        }
+
+        let mut responses: Vec<LookupResponse> = Vec::new();
+        let mut futs = FuturesUnordered::new();
+        let mut requests = 0;
+        let mut errors = 0;
+        loop {
+            // Closest last, so that pop() returns it.
+            queue.sort_by_key(|(id, _)| Reverse(id.distance(&target)));
+            while futs.len() < LOOKUP_PARALLELISM && requests < LOOKUP_MAX_REQUESTS {
+                let Some((id, addr)) = queue.pop() else {
+                    break;
+                };
+                // We already heard from K nodes closer than anything left.
+                if responses.len() >= LOOKUP_K
+                    && responses[LOOKUP_K - 1].response.id.distance(&target) < id.distance(&target)
+                {
+                    queue.clear();
+                    break;
+                }
+                requests += 1;
+                let request = request.clone();
+                futs.push(async move { (addr, self.request(request, addr).await) });
+            }
+
+            let Some((addr, response)) = futs.next().await else {
+                break;
+            };
+            let response = match response {
+                Ok(ResponseOrError::Response(r)) => r,
+                Ok(ResponseOrError::Error(e)) => {
+                    debug!(%addr, "error response: {e:?}");
+                    errors += 1;
+                    continue;
+                }
+                Err(e) => {
+                    debug!(%addr, "error: {e:#}");
+                    errors += 1;
+                    continue;
+                }
+            };
+
+            for node in response
+                .nodes
+                .iter()
+                .flat_map(|n| n.iter().map(|n| n.as_socketaddr()))
+                .chain(
+                    response
+                        .nodes6
+                        .iter()
+                        .flat_map(|n| n.iter().map(|n| n.as_socketaddr())),
+                )
+            {
+                if seen.insert(node.addr) {
+                    queue.push((node.id, node.addr));
+                }
+            }
+
+            let distance = response.id.distance(&target);
+            let pos = responses.partition_point(|r| r.response.id.distance(&target) < distance);
+            responses.insert(pos, LookupResponse { addr, response });
+        }
+
+        if responses.is_empty() {
+            return Err(Error::NoSuccessfulLookups { errors });
+        }
+        trace!(?target, requests, errors, "lookup finished");
+        Ok(responses)
+    }
+
+    /// Crawl the DHT for info hashes with BEP 51 sample_infohashes queries.
+    pub fn crawl_info_hashes(self: &Arc<Self>) -> InfoHashCrawlStream {
+        let (tx, rx) = unbounded_channel();
+        let join_handle = spawn(
+            debug_span!("dht_crawl"),
+            "dht_crawl",
+            self.clone().crawl_info_hashes_loop(tx),
+        );
+        InfoHashCrawlStream { rx, join_handle }
+    }
+
+    async fn crawl_info_hashes_loop(
+        self: Arc<Self>,
+        tx: UnboundedSender<Id20>,
+    ) -> crate::Result<()> {
+        let mut info_hashes: LruCache<Id20, ()> = LruCache::new(CRAWL_MAX_INFO_HASHES);
+        // When each node may be queried again. BEP 51 asks not to query a node again before its
+        // interval passes.
+        let mut next_query: LruCache<SocketAddr, Instant> = LruCache::new(CRAWL_MAX_NODES);
+        let mut queue = VecDeque::new();
+        // Queue "addr" if it's due. Until it responds with its interval, it's not queried again
+        // for the longest one.
+        fn try_queue(
+            queue: &mut VecDeque<SocketAddr>,
+            next_query: &mut LruCache<SocketAddr, Instant>,
+            addr: SocketAddr,
+        ) {
+            if queue.len() >= CRAWL_MAX_QUEUE || next_query.get(&addr).is_some_and(|at| *at > now())
+            {
+                return;
+            }
+            next_query.put(addr, now() + SAMPLE_INFOHASHES_INTERVAL);
+            queue.push_back(addr);
+        }
+        // Nodes that have more info hashes than they sampled, to query again once their
+        // interval passes.
+        let mut requery: BinaryHeap<Reverse<(Instant, SocketAddr)>> = BinaryHeap::new();
+        let mut futs = FuturesUnordered::new();
+
+        loop {
+            while let Some(Reverse((at, _))) = requery.peek() {
+                if *at > now() {
+                    break;
+                }
+                let Reverse((_, addr)) = requery.pop().unwrap();
+                try_queue(&mut queue, &mut next_query, addr);
+            }
+            while futs.len() < CRAWL_PARALLELISM {
+                let Some(addr) = queue.pop_front() else {
+                    break;
+                };
+                let target = generate_random_id(&Id20::default(), 160);
+                let dht = &self;
+                futs.push(async move {
+                    (
+                        addr,
+                        dht.request(Request::SampleInfohashes(target), addr).await,
+                    )
+                });
+            }
+
+            if futs.is_empty() {
+                // Nothing to do right now. Pick up whatever new nodes the routing tables have,
+                // or wait for them or for the next requery.
+                for table in [&self.routing_table_v4, &self.routing_table_v6] {
+                    for node in table.read().iter() {
+                        try_queue(&mut queue, &mut next_query, node.addr());
+                    }
+                }
+                if queue.is_empty() {
+                    let wait = requery
+                        .peek()
+                        .map(|Reverse((at, _))| at.saturating_duration_since(now()))
+                        .unwrap_or(REQUERY_INTERVAL)
+                        .min(REQUERY_INTERVAL);
+                    tokio::time::sleep(wait).await;
+                }
+                continue;
+            }
+
+            let Some((addr, response)) = futs.next().await else {
+                continue;
+            };
+            let response = match response {
+                Ok(ResponseOrError::Response(r)) => r,
+                Ok(ResponseOrError::Error(e)) => {
+                    trace!(%addr, "error response: {e:?}");
+                    continue;
+                }
+                Err(e) => {
+                    trace!(%addr, "error: {e:#}");
+                    continue;
+                }
+            };
+
+            for node in response
+                .nodes
+                .iter()
+                .flat_map(|n| n.iter().map(|n| n.as_socketaddr()))
+                .chain(
+                    response
+                        .nodes6
+                        .iter()
+                        .flat_map(|n| n.iter().map(|n| n.as_socketaddr())),
+                )
+            {
+                try_queue(&mut queue, &mut next_query, node.addr);
+            }
+
+            // Nodes without BEP 51 support just return nodes.
+            let Some(samples) = response.samples else {
+                continue;
+            };
+            let samples = samples.as_ref();
+            for info_hash in samples
+                .chunks_exact(20)
+                .filter_map(|c| Id20::from_bytes(c).ok())
+            {
+                if info_hashes.put(info_hash, ()).is_none() {
+                    tx.send(info_hash).ok().ok_or(Error::ReceiverDead)?;
+                }
+            }
+
+            // Don't query the node again before its interval. If it has more info hashes than it
+            // sampled, come back for the rest then.
+            let interval = response
+                .interval
+                .and_then(|i| u64::try_from(i).ok())
+                .map(Duration::from_secs)
+                .unwrap_or(SAMPLE_INFOHASHES_INTERVAL)
+                .min(SAMPLE_INFOHASHES_INTERVAL);
+            next_query.put(addr, now() + interval);
+            let sampled = i64::try_from(samples.len() / 20).unwrap_or(i64::MAX);
+            if response.num.is_some_and(|num| num > sampled) {
+                requery.push(Reverse((now() + interval, addr)));
+            }
+        }
+    }
+
     pub fn stats(&self) -> DhtStats {
         self.get_stats()
     }
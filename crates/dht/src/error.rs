@@ -27,6 +27,12 @@ pub enum Error {
     #[error("no successful lookups, {errors} errors")]
     NoSuccessfulLookups { errors: usize },
 
+    #[error("invalid item: {0}")]
+    InvalidItem(#[source] crate::PutError),
+
+    #[error("no node stored the item, {errors} errors")]
+    NoSuccessfulPuts { errors: usize },
+
     #[error("dht is dead")]
     DhtDead,
 
@@ -45,6 +51,9 @@ pub enum Error {
     #[error("outstanding request not found")]
     RequestNotFound,
 
+    #[error("address is blocked")]
+    Blocked,
+
     #[error(transparent)]
     BootstrapLookup(Box<LookupError>),
 
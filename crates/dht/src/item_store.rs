@@ -0,0 +1,419 @@
+// BEP 44: storing arbitrary data in the DHT.
+//
+// Immutable items are stored under the SHA-1 of their bencoded value. Mutable items are signed
+// with an ed25519 key and stored under the SHA-1 of the public key and an optional salt.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bencode::ByteBufOwned;
+use bytes::Bytes;
+use librqbit_core::hash_id::Id20;
+use parking_lot::RwLock;
+use rand::Rng;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde::de::IgnoredAny;
+use tracing::trace;
+
+use crate::bprotocol::PutRequest;
+
+pub const MAX_VALUE_LEN: usize = 1000;
+pub const MAX_SALT_LEN: usize = 64;
+
+// Items not republished for this long are forgotten.
+const ITEM_EXPIRY: Duration = Duration::from_secs(2 * 60 * 60);
+
+pub type PublicKey = [u8; 32];
+pub type Signature = [u8; 64];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MutableItem {
+    pub public_key: PublicKey,
+    pub salt: Bytes,
+    pub seq: i64,
+    /// The bencoded value.
+    pub value: Bytes,
+    pub signature: Signature,
+}
+
+impl MutableItem {
+    pub fn target(&self) -> Id20 {
+        mutable_target(&self.public_key, &self.salt)
+    }
+
+    pub fn verify(&self) -> bool {
+        UnparsedPublicKey::new(&ED25519, &self.public_key)
+            .verify(
+                &signed_payload(&self.salt, self.seq, &self.value),
+                &self.signature,
+            )
+            .is_ok()
+    }
+}
+
+/// An ed25519 key to sign mutable items with.
+pub struct SigningKey {
+    seed: [u8; 32],
+    keypair: Ed25519KeyPair,
+}
+
+impl core::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("public_key", &self.keypair.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self {
+            seed,
+            keypair: Ed25519KeyPair::from_seed_unchecked(&seed)
+                .expect("any 32 bytes are a valid ed25519 seed"),
+        }
+    }
+
+    pub fn generate() -> Self {
+        let mut seed = [0u8; 32];
+        rand::rng().fill_bytes(&mut seed);
+        Self::from_seed(seed)
+    }
+
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(self.keypair.public_key().as_ref());
+        public_key
+    }
+
+    /// Sign a bencoded value.
+    pub fn sign(&self, salt: Bytes, seq: i64, value: Bytes) -> MutableItem {
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(
+            self.keypair
+                .sign(&signed_payload(&salt, seq, &value))
+                .as_ref(),
+        );
+        MutableItem {
+            public_key: self.public_key(),
+            salt,
+            seq,
+            value,
+            signature,
+        }
+    }
+}
+
+fn sha1(parts: &[&[u8]]) -> Id20 {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY);
+    for part in parts {
+        ctx.update(part);
+    }
+    Id20::from_bytes(ctx.finish().as_ref()).unwrap()
+}
+
+pub fn immutable_target(value: &[u8]) -> Id20 {
+    sha1(&[value])
+}
+
+pub fn mutable_target(public_key: &PublicKey, salt: &[u8]) -> Id20 {
+    sha1(&[public_key, salt])
+}
+
+// What gets signed: the bencoded "salt", "seq" and "v" keys without the surrounding dict.
+fn signed_payload(salt: &[u8], seq: i64, value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(salt.len() + value.len() + 32);
+    if !salt.is_empty() {
+        buf.extend_from_slice(format!("4:salt{}:", salt.len()).as_bytes());
+        buf.extend_from_slice(salt);
+    }
+    buf.extend_from_slice(format!("3:seqi{seq}e1:v").as_bytes());
+    buf.extend_from_slice(value);
+    buf
+}
+
+pub(crate) fn validate_value(value: &[u8]) -> Result<(), PutError> {
+    if value.len() > MAX_VALUE_LEN {
+        return Err(PutError::ValueTooBig);
+    }
+    bencode::from_bytes::<IgnoredAny>(value).map_err(|_| PutError::Invalid("v isn't bencoded"))?;
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PutError {
+    #[error("invalid put: {0}")]
+    Invalid(&'static str),
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("item store is full")]
+    Full,
+    #[error("message (v field) too big")]
+    ValueTooBig,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("salt (salt field) too big")]
+    SaltTooBig,
+    #[error("the CAS hash mismatched, re-read value and try again")]
+    CasMismatch,
+    #[error("sequence number less than current")]
+    SeqTooOld,
+}
+
+impl PutError {
+    /// The KRPC error code.
+    pub fn code(&self) -> i32 {
+        match self {
+            PutError::Invalid(_) | PutError::InvalidToken => 203,
+            PutError::Full => 202,
+            PutError::ValueTooBig => 205,
+            PutError::InvalidSignature => 206,
+            PutError::SaltTooBig => 207,
+            PutError::CasMismatch => 301,
+            PutError::SeqTooOld => 302,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoredItem {
+    Immutable(Bytes),
+    Mutable(MutableItem),
+}
+
+struct Entry {
+    item: StoredItem,
+    time: Instant,
+}
+
+pub struct ItemStore {
+    max_items: usize,
+    items: RwLock<HashMap<Id20, Entry>>,
+}
+
+impl Default for ItemStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ItemStore {
+    pub fn new() -> Self {
+        Self {
+            max_items: 1000,
+            items: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, target: &Id20) -> Option<StoredItem> {
+        self.items
+            .read()
+            .get(target)
+            .filter(|e| e.time.elapsed() < ITEM_EXPIRY)
+            .map(|e| e.item.clone())
+    }
+
+    /// Validate and store the item from a put request. The token must've been checked already.
+    pub fn put(&self, put: &PutRequest<ByteBufOwned>) -> Result<Id20, PutError> {
+        let value = &put.v.0.0;
+        validate_value(value)?;
+
+        let (target, item) = match &put.k {
+            None => (
+                immutable_target(value),
+                StoredItem::Immutable(value.clone()),
+            ),
+            Some(k) => {
+                let public_key = PublicKey::try_from(k.as_ref())
+                    .map_err(|_| PutError::Invalid("k must be 32 bytes"))?;
+                let signature = put
+                    .sig
+                    .as_ref()
+                    .and_then(|s| Signature::try_from(s.as_ref()).ok())
+                    .ok_or(PutError::Invalid("sig must be 64 bytes"))?;
+                let salt = put.salt.as_ref().map(|s| s.0.clone()).unwrap_or_default();
+                if salt.len() > MAX_SALT_LEN {
+                    return Err(PutError::SaltTooBig);
+                }
+                let item = MutableItem {
+                    public_key,
+                    salt,
+                    seq: put.seq.ok_or(PutError::Invalid("seq is required"))?,
+                    value: value.clone(),
+                    signature,
+                };
+                if !item.verify() {
+                    return Err(PutError::InvalidSignature);
+                }
+                (item.target(), StoredItem::Mutable(item))
+            }
+        };
+
+        let mut items = self.items.write();
+        if let Some(existing) = items
+            .get(&target)
+            .filter(|e| e.time.elapsed() < ITEM_EXPIRY)
+            && let (StoredItem::Mutable(existing), StoredItem::Mutable(new)) =
+                (&existing.item, &item)
+        {
+            if put.cas.is_some_and(|cas| cas != existing.seq) {
+                return Err(PutError::CasMismatch);
+            }
+            if new.seq < existing.seq || (new.seq == existing.seq && new.value != existing.value) {
+                return Err(PutError::SeqTooOld);
+            }
+        }
+
+        if !items.contains_key(&target) && items.len() >= self.max_items {
+            items.retain(|_, e| e.time.elapsed() < ITEM_EXPIRY);
+            if items.len() >= self.max_items {
+                trace!("item store: out of capacity");
+                return Err(PutError::Full);
+            }
+        }
+        items.insert(
+            target,
+            Entry {
+                item,
+                time: Instant::now(),
+            },
+        );
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bencode::ByteBufOwned;
+    use bytes::Bytes;
+    use librqbit_core::hash_id::Id20;
+
+    use super::{ItemStore, MutableItem, PutError, SigningKey, StoredItem, immutable_target};
+    use crate::bprotocol::{BencodedValue, PutRequest};
+
+    fn unhex<const N: usize>(s: &str) -> [u8; N] {
+        let mut out = [0u8; N];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        out
+    }
+
+    fn put_request(item: &MutableItem, cas: Option<i64>) -> PutRequest<ByteBufOwned> {
+        PutRequest {
+            id: Id20::default(),
+            token: ByteBufOwned::from(&b"token"[..]),
+            v: BencodedValue(item.value.clone().into()),
+            k: Some(ByteBufOwned::from(&item.public_key[..])),
+            sig: Some(ByteBufOwned::from(&item.signature[..])),
+            seq: Some(item.seq),
+            cas,
+            salt: (!item.salt.is_empty()).then(|| item.salt.clone().into()),
+        }
+    }
+
+    #[test]
+    fn test_bep44_vectors() {
+        assert_eq!(
+            immutable_target(b"12:Hello World!"),
+            Id20::from_str("e5f96f6f38320f0f33959cb4d3d656452117aadb").unwrap()
+        );
+
+        let item = MutableItem {
+            public_key: unhex("77ff84905a91936367c01360803104f92432fcd904a43511876df5cdf3e7e548"),
+            salt: Bytes::new(),
+            seq: 1,
+            value: Bytes::from_static(b"12:Hello World!"),
+            signature: unhex(
+                "305ac8aeb6c9c151fa120f120ea2cfb923564e11552d06a5d856091e5e853cff1260d3f39e4999684aa92eb73ffd136e6f4f3ecbfda0ce53a1608ecd7ae21f01",
+            ),
+        };
+        assert!(item.verify());
+        assert_eq!(
+            item.target(),
+            Id20::from_str("4a533d47ec9c7d95b1ad75f576cffc641853b750").unwrap()
+        );
+
+        let salted = MutableItem {
+            salt: Bytes::from_static(b"foobar"),
+            ..item.clone()
+        };
+        assert!(!salted.verify());
+        assert_eq!(
+            salted.target(),
+            Id20::from_str("411eba73b6f087ca51a3795d9c8c938d365e32c1").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_put_immutable() {
+        let store = ItemStore::new();
+        let put = PutRequest {
+            id: Id20::default(),
+            token: ByteBufOwned::from(&b"token"[..]),
+            v: BencodedValue(ByteBufOwned::from(&b"12:Hello World!"[..])),
+            k: None,
+            sig: None,
+            seq: None,
+            cas: None,
+            salt: None,
+        };
+        let target = store.put(&put).unwrap();
+        assert_eq!(target, immutable_target(b"12:Hello World!"));
+        assert_eq!(
+            store.get(&target),
+            Some(StoredItem::Immutable(Bytes::from_static(
+                b"12:Hello World!"
+            )))
+        );
+
+        let put = PutRequest {
+            v: BencodedValue(ByteBufOwned::from(&b"12:Hello"[..])),
+            ..put
+        };
+        assert_eq!(store.put(&put), Err(PutError::Invalid("v isn't bencoded")));
+    }
+
+    #[test]
+    fn test_put_mutable() {
+        let store = ItemStore::new();
+        let key = SigningKey::generate();
+        let salt = Bytes::from_static(b"salt");
+
+        let v1 = key.sign(salt.clone(), 1, Bytes::from_static(b"2:v1"));
+        let target = store.put(&put_request(&v1, None)).unwrap();
+        assert_eq!(target, v1.target());
+        assert_eq!(store.get(&target), Some(StoredItem::Mutable(v1.clone())));
+
+        let mut forged = key.sign(salt.clone(), 2, Bytes::from_static(b"2:v2"));
+        forged.value = Bytes::from_static(b"2:v3");
+        assert_eq!(
+            store.put(&put_request(&forged, None)),
+            Err(PutError::InvalidSignature)
+        );
+
+        let v2 = key.sign(salt.clone(), 2, Bytes::from_static(b"2:v2"));
+        assert_eq!(
+            store.put(&put_request(&v2, Some(0))),
+            Err(PutError::CasMismatch)
+        );
+        store.put(&put_request(&v2, Some(1))).unwrap();
+        assert_eq!(store.get(&target), Some(StoredItem::Mutable(v2)));
+
+        assert_eq!(store.put(&put_request(&v1, None)), Err(PutError::SeqTooOld));
+
+        let too_much_salt = key.sign(Bytes::from(vec![0u8; 65]), 1, Bytes::from_static(b"0:"));
+        assert_eq!(
+            store.put(&put_request(&too_much_salt, None)),
+            Err(PutError::SaltTooBig)
+        );
+    }
+}
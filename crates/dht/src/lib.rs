@@ -1,8 +1,12 @@
+mod bloom_filter;
 mod bprotocol;
 mod dht;
 mod error;
+mod item_store;
+mod node_id;
 mod peer_store;
 mod persistence;
+mod query_limiter;
 mod routing_table;
 mod utils;
 
@@ -11,10 +15,22 @@ use std::time::Duration;
 
 pub use error::{Error, Result};
 
-pub use crate::dht::DhtStats;
-pub use crate::dht::{DhtConfig, DhtState, RequestPeersStream};
+pub use crate::dht::{
+    DhtConfig, DhtState, InfoHashCrawlStream, IpFilter, IsSeed, RequestPeersStream,
+};
+pub use crate::dht::{DhtQueryStats, DhtStats, ScrapeEstimate};
+pub use item_store::{
+    MAX_SALT_LEN, MAX_VALUE_LEN, MutableItem, PublicKey, PutError, Signature, SigningKey,
+    immutable_target, mutable_target,
+};
 pub use librqbit_core::hash_id::Id20;
+pub use node_id::{generate_node_id, is_valid_node_id};
+pub use peer_store::PeerStoreStats;
 pub use persistence::{DhtPersistenceConfig, PersistentDht, dht_listen_addr};
+pub use query_limiter::{
+    DEFAULT_QUERIES_PER_SECOND_PER_PREFIX, DEFAULT_QUERIES_PER_SECOND_PER_SOURCE,
+};
+pub use routing_table::RoutingTableHealth;
 
 pub type Dht = Arc<DhtState>;
 
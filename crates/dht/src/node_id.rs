@@ -0,0 +1,212 @@
+// BEP 42: node IDs derived from the node's external IP.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use librqbit_core::hash_id::Id20;
+use rand::Rng;
+
+const CASTAGNOLI: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+const V4_MASK: [u8; 4] = [0x03, 0x0f, 0x3f, 0xff];
+const V6_MASK: [u8; 8] = [0x01, 0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x7f, 0xff];
+
+// How many distinct nodes have to report our external IP before we believe them.
+const EXTERNAL_IP_MIN_VOTES: usize = 10;
+// Start over if there's no majority after this many.
+const EXTERNAL_IP_MAX_VOTES: usize = 50;
+
+/// Local and private addresses can't be verified, so any node ID is fine for them.
+pub fn is_exempt(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+        }
+    }
+}
+
+fn crc32c(ip: IpAddr, r: u8) -> u32 {
+    let r = (r & 0x07) << 5;
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let mut buf = ip.octets();
+            for (b, m) in buf.iter_mut().zip(V4_MASK) {
+                *b &= m;
+            }
+            buf[0] |= r;
+            CASTAGNOLI.checksum(&buf)
+        }
+        IpAddr::V6(ip) => {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&ip.octets()[..8]);
+            for (b, m) in buf.iter_mut().zip(V6_MASK) {
+                *b &= m;
+            }
+            buf[0] |= r;
+            CASTAGNOLI.checksum(&buf)
+        }
+    }
+}
+
+/// Generate a random node ID that's valid for the given external IP.
+pub fn generate_node_id(ip: IpAddr) -> Id20 {
+    let mut id = [0u8; 20];
+    rand::rng().fill_bytes(&mut id);
+    let crc = crc32c(ip, id[19]).to_be_bytes();
+    id[0] = crc[0];
+    id[1] = crc[1];
+    id[2] = (crc[2] & 0xf8) | (id[2] & 0x07);
+    Id20::new(id)
+}
+
+/// Check that the node ID was derived from the IP the node is talking to us from.
+pub fn is_valid_node_id(id: &Id20, ip: IpAddr) -> bool {
+    if is_exempt(ip) {
+        return true;
+    }
+    let crc = crc32c(ip, id.0[19]).to_be_bytes();
+    id.0[0] == crc[0] && id.0[1] == crc[1] && id.0[2] & 0xf8 == crc[2] & 0xf8
+}
+
+#[derive(Default)]
+struct VotingRound {
+    voters: HashSet<IpAddr>,
+    votes: HashMap<IpAddr, usize>,
+}
+
+/// Figures out our external IP from what the nodes we talk to see in the "ip" field.
+#[derive(Default)]
+pub struct ExternalIpVotes {
+    v4: VotingRound,
+    v6: VotingRound,
+    external_ip: Option<IpAddr>,
+}
+
+impl ExternalIpVotes {
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.external_ip
+    }
+
+    /// Record that `voter` sees us as `ip`. Returns the new external IP once most voters agree
+    /// on one that's different from the current one.
+    pub fn add_vote(&mut self, voter: IpAddr, ip: IpAddr) -> Option<IpAddr> {
+        let ip = ip.to_canonical();
+        if is_exempt(voter) || is_exempt(ip) {
+            return None;
+        }
+        // Only one IP can be used for the node ID, so stick to IPv4 once we know it.
+        if ip.is_ipv6() && self.external_ip.is_some_and(|e| e.is_ipv4()) {
+            return None;
+        }
+        let round = if ip.is_ipv4() {
+            &mut self.v4
+        } else {
+            &mut self.v6
+        };
+        if !round.voters.insert(voter) {
+            return None;
+        }
+        *round.votes.entry(ip).or_default() += 1;
+        if round.voters.len() < EXTERNAL_IP_MIN_VOTES {
+            return None;
+        }
+
+        let (winner, count) = round
+            .votes
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(ip, count)| (*ip, *count))?;
+        if count * 2 <= round.voters.len() {
+            if round.voters.len() >= EXTERNAL_IP_MAX_VOTES {
+                *round = Default::default();
+            }
+            return None;
+        }
+        // Keep voting in case the IP changes later.
+        *round = Default::default();
+        if self.external_ip == Some(winner) {
+            return None;
+        }
+        self.external_ip = Some(winner);
+        Some(winner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        str::FromStr,
+    };
+
+    use librqbit_core::hash_id::Id20;
+
+    use super::{ExternalIpVotes, generate_node_id, is_valid_node_id};
+
+    #[test]
+    fn test_bep42_vectors() {
+        for (ip, id) in [
+            ("124.31.75.21", "5fbfbff10c5d6a4ec8a88e4c6ab4c28b95eee401"),
+            ("21.75.31.124", "5a3ce9c14e7a08645677bbd1cfe7d8f956d53256"),
+            ("65.23.51.170", "a5d43220bc8f112a3d426c84764f8c2a1150e616"),
+            ("84.124.73.14", "1b0321dd1bb1fe518101ceef99462b947a01ff41"),
+            ("43.213.53.83", "e56f6cbf5b7c4be0237986d5243b87aa6d51305a"),
+        ] {
+            let ip = IpAddr::from_str(ip).unwrap();
+            let id = Id20::from_str(id).unwrap();
+            assert!(is_valid_node_id(&id, ip), "{ip} {id:?}");
+            assert!(!is_valid_node_id(&id, "1.2.3.4".parse().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_generate_node_id() {
+        for ip in ["124.31.75.21", "2001:db8::1"] {
+            let ip = IpAddr::from_str(ip).unwrap();
+            for _ in 0..100 {
+                assert!(is_valid_node_id(&generate_node_id(ip), ip));
+            }
+        }
+    }
+
+    #[test]
+    fn test_private_exempt() {
+        let id = Id20::from_str("0000000000000000000000000000000000000000").unwrap();
+        for ip in ["127.0.0.1", "192.168.1.1", "10.0.0.1", "fe80::1", "fd00::1"] {
+            assert!(is_valid_node_id(&id, ip.parse().unwrap()));
+        }
+        assert!(!is_valid_node_id(&id, "124.31.75.21".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_external_ip_votes() {
+        let mut votes = ExternalIpVotes::default();
+        let ours: IpAddr = "124.31.75.21".parse().unwrap();
+        let liar: IpAddr = "65.23.51.170".parse().unwrap();
+        let voter = |i: u8| IpAddr::V4(Ipv4Addr::new(8, 8, 8, i));
+
+        for i in 0..3 {
+            assert_eq!(votes.add_vote(voter(i), liar), None);
+        }
+        // The same voter only counts once.
+        for _ in 0..10 {
+            assert_eq!(votes.add_vote(voter(3), ours), None);
+        }
+        for i in 4..9 {
+            assert_eq!(votes.add_vote(voter(i), ours), None);
+        }
+        assert_eq!(votes.add_vote(voter(9), ours), Some(ours));
+        assert_eq!(votes.external_ip(), Some(ours));
+
+        // Private addresses don't vote.
+        assert_eq!(votes.add_vote("192.168.0.1".parse().unwrap(), liar), None);
+    }
+}
@@ -1,17 +1,40 @@
-use std::{collections::VecDeque, net::SocketAddr, str::FromStr, sync::atomic::AtomicU32};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
 
 use bencode::ByteBufOwned;
 use chrono::{DateTime, Utc};
 use librqbit_core::{compact_ip::CompactSocketAddr, hash_id::Id20};
 use parking_lot::RwLock;
-use rand::Rng;
+use rand::{Rng, seq::IteratorRandom};
 use serde::{
     Deserialize, Serialize,
     ser::{SerializeMap, SerializeStruct},
 };
 use tracing::trace;
 
-use crate::bprotocol::{AnnouncePeer, Want};
+use crate::{
+    bloom_filter::ScrapeBloomFilter,
+    bprotocol::{AnnouncePeer, Want},
+};
+
+// Peers not re-announced for this long are forgotten.
+const PEER_EXPIRY: Duration = Duration::from_secs(30 * 60);
+const MAX_PEERS_PER_INFO_HASH: u32 = 100;
+
+/// Counters of the announced peers we store.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PeerStoreStats {
+    pub info_hashes: usize,
+    pub peers: u32,
+    /// Peers dropped to make room for newer announces.
+    pub evicted: u64,
+    /// Peers dropped because they didn't re-announce in time.
+    pub expired: u64,
+}
 
 #[derive(Serialize, Deserialize)]
 struct StoredToken {
@@ -25,16 +48,20 @@ struct StoredToken {
 struct StoredPeer {
     addr: SocketAddr,
     time: DateTime<Utc>,
+    #[serde(default)]
+    seed: bool,
 }
 
 pub struct PeerStore {
-    self_id: Id20,
+    self_id: RwLock<Id20>,
     max_remembered_tokens: u32,
     max_remembered_peers: u32,
-    max_distance: Id20,
+    max_peers_per_info_hash: u32,
     tokens: RwLock<VecDeque<StoredToken>>,
     peers: dashmap::DashMap<Id20, Vec<StoredPeer>>,
     peers_len: AtomicU32,
+    evicted: AtomicU64,
+    expired: AtomicU64,
 }
 
 impl Serialize for PeerStore {
@@ -59,17 +86,13 @@ impl Serialize for PeerStore {
             }
         }
 
-        let mut s = serializer.serialize_struct("PeerStore", 7)?;
-        s.serialize_field("self_id", &self.self_id.as_string())?;
+        let mut s = serializer.serialize_struct("PeerStore", 6)?;
+        s.serialize_field("self_id", &self.self_id.read().as_string())?;
         s.serialize_field("max_remembered_tokens", &self.max_remembered_tokens)?;
         s.serialize_field("max_remembered_peers", &self.max_remembered_peers)?;
-        s.serialize_field("max_distance", &self.max_distance.as_string())?;
         s.serialize_field("tokens", &*self.tokens.read())?;
         s.serialize_field("peers", &SerializePeers { peers: &self.peers })?;
-        s.serialize_field(
-            "peers_len",
-            &self.peers_len.load(std::sync::atomic::Ordering::SeqCst),
-        )?;
+        s.serialize_field("peers_len", &self.peers_len.load(Ordering::SeqCst))?;
         s.end()
     }
 }
@@ -84,19 +107,20 @@ impl<'de> Deserialize<'de> for PeerStore {
             self_id: Id20,
             max_remembered_tokens: u32,
             max_remembered_peers: u32,
-            max_distance: Id20,
             tokens: VecDeque<StoredToken>,
             peers: dashmap::DashMap<Id20, Vec<StoredPeer>>,
         }
 
         Tmp::deserialize(deserializer).map(|tmp| Self {
-            self_id: tmp.self_id,
+            self_id: RwLock::new(tmp.self_id),
             max_remembered_tokens: tmp.max_remembered_tokens,
             max_remembered_peers: tmp.max_remembered_peers,
-            max_distance: tmp.max_distance,
+            max_peers_per_info_hash: MAX_PEERS_PER_INFO_HASH,
             tokens: RwLock::new(tmp.tokens),
             peers_len: AtomicU32::new(tmp.peers.iter().map(|e| e.value().len() as u32).sum()),
             peers: tmp.peers,
+            evicted: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
         })
     }
 }
@@ -104,16 +128,22 @@ impl<'de> Deserialize<'de> for PeerStore {
 impl PeerStore {
     pub fn new(self_id: Id20) -> Self {
         Self {
-            self_id,
+            self_id: RwLock::new(self_id),
             max_remembered_tokens: 1000,
             max_remembered_peers: 1000,
-            max_distance: Id20::from_str("00000fffffffffffffffffffffffffffffffffff").unwrap(),
+            max_peers_per_info_hash: MAX_PEERS_PER_INFO_HASH,
             tokens: RwLock::new(VecDeque::new()),
             peers: dashmap::DashMap::new(),
             peers_len: AtomicU32::new(0),
+            evicted: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
         }
     }
 
+    pub(crate) fn set_self_id(&self, self_id: Id20) {
+        *self.self_id.write() = self_id;
+    }
+
     pub fn gen_token_for(&self, node_id: Id20, addr: SocketAddr) -> [u8; 4] {
         let mut token = [0u8; 4];
         rand::rng().fill_bytes(&mut token);
@@ -129,18 +159,16 @@ impl PeerStore {
         token
     }
 
+    pub fn is_valid_token(&self, node_id: Id20, addr: SocketAddr, token: &[u8]) -> bool {
+        self.tokens
+            .read()
+            .iter()
+            .any(|t| t.token[..] == token[..] && t.addr == addr && t.node_id == node_id)
+    }
+
+    /// Store the announced peer. The caller decides if the info hash is close enough to us.
     pub fn store_peer(&self, announce: &AnnouncePeer<ByteBufOwned>, mut addr: SocketAddr) -> bool {
-        // If the info_hash in announce is too far away from us, don't store it.
-        // If the token doesn't match, don't store it.
-        // If we are out of capacity, don't store it.
-        // Otherwise, store it.
-        if announce.info_hash.distance(&self.self_id) > self.max_distance {
-            trace!("peer store: info_hash too far to store");
-            return false;
-        }
-        if !self.tokens.read().iter().any(|t| {
-            t.token[..] == announce.token.as_ref()[..] && t.addr == addr && t.node_id == announce.id
-        }) {
+        if !self.is_valid_token(announce.id, addr, announce.token.as_ref()) {
             trace!("peer store: can't find this token / addr combination");
             return false;
         }
@@ -148,42 +176,80 @@ impl PeerStore {
         if announce.implied_port == 0 {
             addr.set_port(announce.port);
         }
+        let seed = announce.seed == Some(1);
+        let now = Utc::now();
 
-        use dashmap::mapref::entry::Entry;
-        let peers_entry = self.peers.entry(announce.info_hash);
-        let peers_len = self.peers_len.load(std::sync::atomic::Ordering::SeqCst);
-        match peers_entry {
-            Entry::Occupied(mut occ) => {
-                if let Some(s) = occ.get_mut().iter_mut().find(|s| s.addr == addr) {
-                    s.time = Utc::now();
-                    return true;
-                }
-                if peers_len >= self.max_remembered_peers {
-                    trace!("peer store: out of capacity");
-                    return false;
-                }
-                occ.get_mut().push(StoredPeer {
-                    addr,
-                    time: Utc::now(),
-                });
-            }
-            Entry::Vacant(vac) => {
-                if peers_len >= self.max_remembered_peers {
-                    trace!("peer store: out of capacity");
-                    return false;
-                }
-                vac.insert(vec![StoredPeer {
-                    addr,
-                    time: Utc::now(),
-                }]);
+        // Make room first, as evicting needs to look at other info hashes.
+        let (exists, info_hash_full) = self
+            .peers
+            .get(&announce.info_hash)
+            .map(|peers| {
+                (
+                    peers.iter().any(|p| p.addr == addr),
+                    peers.len() >= self.max_peers_per_info_hash as usize,
+                )
+            })
+            .unwrap_or_default();
+        if !exists
+            && !info_hash_full
+            && self.peers_len.load(Ordering::SeqCst) >= self.max_remembered_peers
+        {
+            self.garbage_collect_peers();
+            if self.peers_len.load(Ordering::SeqCst) >= self.max_remembered_peers {
+                self.evict_least_recently_announced();
             }
         }
 
-        self.peers_len
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let new_peer = StoredPeer {
+            addr,
+            time: now,
+            seed,
+        };
+        let mut peers = self.peers.entry(announce.info_hash).or_default();
+        if let Some(s) = peers.iter_mut().find(|s| s.addr == addr) {
+            s.time = now;
+            s.seed = seed;
+            return true;
+        }
+        if peers.len() >= self.max_peers_per_info_hash as usize {
+            if let Some(oldest) = peers.iter_mut().min_by_key(|p| p.time) {
+                trace!(addr=?oldest.addr, "peer store: evicting, too many peers for info hash");
+                *oldest = new_peer;
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+            }
+            return true;
+        }
+        peers.push(new_peer);
+        self.peers_len.fetch_add(1, Ordering::SeqCst);
         true
     }
 
+    fn evict_least_recently_announced(&self) {
+        let oldest = self
+            .peers
+            .iter()
+            .filter_map(|e| {
+                e.value()
+                    .iter()
+                    .min_by_key(|p| p.time)
+                    .map(|p| (p.time, *e.key(), p.addr))
+            })
+            .min();
+        let Some((_, info_hash, addr)) = oldest else {
+            return;
+        };
+        trace!(?info_hash, ?addr, "peer store: out of capacity, evicting");
+        if let Some(mut peers) = self.peers.get_mut(&info_hash) {
+            let len = peers.len();
+            peers.retain(|p| p.addr != addr);
+            let removed = (len - peers.len()) as u32;
+            self.peers_len.fetch_sub(removed, Ordering::SeqCst);
+            self.evicted.fetch_add(removed.into(), Ordering::Relaxed);
+        }
+        self.peers
+            .remove_if(&info_hash, |_, peers| peers.is_empty());
+    }
+
     pub fn get_for_info_hash(&self, info_hash: Id20, want: Want) -> Vec<CompactSocketAddr> {
         if let Some(stored_peers) = self.peers.get(&info_hash) {
             return stored_peers
@@ -201,8 +267,146 @@ impl PeerStore {
         Vec::new()
     }
 
-    #[allow(dead_code)]
-    pub fn garbage_collect_peers(&self) {
-        todo!()
+    /// Bloom filters of the seeders and downloaders of the info hash (BEP 33).
+    pub fn scrape_filters(
+        &self,
+        info_hash: Id20,
+    ) -> Option<(ScrapeBloomFilter, ScrapeBloomFilter)> {
+        let stored_peers = self.peers.get(&info_hash)?;
+        let mut seeds = ScrapeBloomFilter::default();
+        let mut peers = ScrapeBloomFilter::default();
+        for p in stored_peers.iter() {
+            if p.seed {
+                seeds.insert(p.addr.ip());
+            } else {
+                peers.insert(p.addr.ip());
+            }
+        }
+        Some((seeds, peers))
+    }
+
+    /// A random sample of the stored info hashes, and how many there are in total (BEP 51).
+    pub fn sample_info_hashes(&self, max: usize) -> (Vec<Id20>, usize) {
+        let samples = self
+            .peers
+            .iter()
+            .map(|e| *e.key())
+            .sample(&mut rand::rng(), max);
+        (samples, self.peers.len())
+    }
+
+    /// Forget peers that haven't re-announced for a while. Returns how many were removed.
+    pub fn garbage_collect_peers(&self) -> u32 {
+        let now = Utc::now();
+        let mut removed = 0;
+        self.peers.retain(|_, peers| {
+            let len = peers.len();
+            peers.retain(|p| (now - p.time).to_std().is_ok_and(|age| age < PEER_EXPIRY));
+            removed += (len - peers.len()) as u32;
+            !peers.is_empty()
+        });
+        if removed > 0 {
+            trace!(removed, "peer store: expired peers");
+            self.peers_len.fetch_sub(removed, Ordering::SeqCst);
+            self.expired.fetch_add(removed.into(), Ordering::Relaxed);
+        }
+        removed
+    }
+
+    pub fn stats(&self) -> PeerStoreStats {
+        PeerStoreStats {
+            info_hashes: self.peers.len(),
+            peers: self.peers_len.load(Ordering::SeqCst),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use bencode::ByteBufOwned;
+    use chrono::Utc;
+    use librqbit_core::hash_id::Id20;
+
+    use super::{PEER_EXPIRY, PeerStore};
+    use crate::bprotocol::{AnnouncePeer, Want};
+
+    fn announce(store: &PeerStore, info_hash: u8, peer: u8) -> bool {
+        let id = Id20::new([peer; 20]);
+        let addr = SocketAddr::from((Ipv4Addr::new(1, 2, 3, peer), 6881));
+        let token = store.gen_token_for(id, addr);
+        store.store_peer(
+            &AnnouncePeer {
+                id,
+                implied_port: 1,
+                info_hash: Id20::new([info_hash; 20]),
+                port: 0,
+                token: ByteBufOwned::from(&token[..]),
+                seed: None,
+            },
+            addr,
+        )
+    }
+
+    fn peers(store: &PeerStore, info_hash: u8) -> usize {
+        store
+            .get_for_info_hash(Id20::new([info_hash; 20]), Want::Both)
+            .len()
+    }
+
+    #[test]
+    fn test_max_peers_per_info_hash() {
+        let mut store = PeerStore::new(Id20::default());
+        store.max_peers_per_info_hash = 2;
+        for peer in 0..3 {
+            assert!(announce(&store, 1, peer));
+        }
+        assert_eq!(peers(&store, 1), 2);
+        let stats = store.stats();
+        assert_eq!(stats.peers, 2);
+        assert_eq!(stats.evicted, 1);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_announced() {
+        let mut store = PeerStore::new(Id20::default());
+        store.max_remembered_peers = 2;
+        assert!(announce(&store, 1, 1));
+        assert!(announce(&store, 2, 2));
+        // Re-announcing makes it the most recent one.
+        assert!(announce(&store, 1, 1));
+        assert!(announce(&store, 3, 3));
+
+        assert_eq!(peers(&store, 1), 1);
+        assert_eq!(peers(&store, 2), 0);
+        assert_eq!(peers(&store, 3), 1);
+        let stats = store.stats();
+        assert_eq!(stats.info_hashes, 2);
+        assert_eq!(stats.peers, 2);
+        assert_eq!(stats.evicted, 1);
+    }
+
+    #[test]
+    fn test_garbage_collect_peers() {
+        let store = PeerStore::new(Id20::default());
+        assert!(announce(&store, 1, 1));
+        assert!(announce(&store, 2, 2));
+        store
+            .peers
+            .get_mut(&Id20::new([1; 20]))
+            .unwrap()
+            .iter_mut()
+            .for_each(|p| p.time = Utc::now() - PEER_EXPIRY * 2);
+
+        assert_eq!(store.garbage_collect_peers(), 1);
+        assert_eq!(peers(&store, 1), 0);
+        assert_eq!(peers(&store, 2), 1);
+        let stats = store.stats();
+        assert_eq!(stats.info_hashes, 1);
+        assert_eq!(stats.peers, 1);
+        assert_eq!(stats.expired, 1);
     }
 }
@@ -5,14 +5,12 @@ use futures::FutureExt;
 use futures::future::BoxFuture;
 use librqbit_core::directories::get_configuration_directory;
 use librqbit_core::spawn_utils::spawn_with_cancel;
-use librqbit_dualstack_sockets::BindDevice;
 use serde::{Deserialize, Serialize};
 use std::fs::OpenOptions;
 use std::io::{BufReader, BufWriter};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
-use tokio_util::sync::CancellationToken;
 
 use tracing::{debug_span, error, info, trace, warn};
 
@@ -100,14 +98,14 @@ impl PersistentDht {
         Ok(path)
     }
 
+    /// Start a DHT, restoring the routing table and peer store from disk if they were stored
+    /// before. The persisted state takes precedence over the same fields in `config`.
     #[inline(never)]
     pub fn create<'a>(
         persistence_config: DhtPersistenceConfig,
         port: Option<u16>,
         ipv4_only: bool,
-        bootstrap_addrs: Option<Vec<String>>,
-        cancellation_token: Option<CancellationToken>,
-        bind_device: Option<&'a BindDevice>,
+        config: DhtConfig<'a>,
     ) -> BoxFuture<'a, anyhow::Result<Dht>> {
         async move {
             let config_filename = match persistence_config.config_filename {
@@ -161,14 +159,11 @@ impl PersistentDht {
             let peer_id = routing_table.as_ref().map(|r| r.id());
 
             let dht_config = DhtConfig {
-                peer_id,
-                bootstrap_addrs,
-                routing_table,
+                peer_id: peer_id.or(config.peer_id),
+                routing_table: routing_table.or(config.routing_table),
                 listen_addr: Some(listen_addr),
-                peer_store,
-                cancellation_token,
-                bind_device,
-                ..Default::default()
+                peer_store: peer_store.or(config.peer_store),
+                ..config
             };
             let dht = DhtState::with_config(dht_config).await?;
             spawn_with_cancel::<anyhow::Error>(
@@ -0,0 +1,295 @@
+// Rate limiting of incoming DHT queries, so that a single source can't keep us busy.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tracing::debug;
+
+pub const DEFAULT_QUERIES_PER_SECOND_PER_SOURCE: u32 = 10;
+pub const DEFAULT_QUERIES_PER_SECOND_PER_PREFIX: u32 = 50;
+
+// Sources that go over their own rate limit this often get their queries ignored for a
+// while. Hitting the per-prefix limit doesn't count, as that may be the neighbours' doing
+// (e.g. behind carrier-grade NAT).
+const OFFENSES_PER_SECOND: u32 = 1;
+const OFFENSES_BURST: u32 = 20;
+// Same for sending malformed packets, but more tolerant, as some are sent by buggy clients
+// and UDP sources are easy to spoof.
+const MALFORMED_PER_SECOND: u32 = 1;
+const MALFORMED_BURST: u32 = 100;
+const BLACKLIST_DURATION: Duration = Duration::from_secs(10 * 60);
+
+// How many sources we keep track of. Once full, sources that are idle are forgotten,
+// and if none are, queries from new sources are dropped.
+const MAX_SOURCES: usize = 65536;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+fn refill(bucket: &Bucket, now: Instant, rate: f64, burst: f64) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated);
+    (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst)
+}
+
+/// A token bucket per key, refilled at `rate` tokens per second up to `burst`.
+pub(crate) struct TokenBuckets<K> {
+    buckets: HashMap<K, Bucket>,
+    rate: f64,
+    burst: f64,
+    max_keys: usize,
+}
+
+impl<K: Hash + Eq + Copy> TokenBuckets<K> {
+    pub fn new(rate: u32, burst: u32, max_keys: usize) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+            max_keys,
+        }
+    }
+
+    /// Take a token for `key`. Returns false if it's out of them.
+    pub fn try_acquire(&mut self, key: K, now: Instant) -> bool {
+        if !self.buckets.contains_key(&key) && self.buckets.len() >= self.max_keys {
+            self.evict_idle(now);
+            if self.buckets.len() >= self.max_keys {
+                return false;
+            }
+        }
+        let (rate, burst) = (self.rate, self.burst);
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let tokens = refill(bucket, now, rate, burst);
+        bucket.updated = now;
+        if tokens < 1. {
+            bucket.tokens = tokens;
+            return false;
+        }
+        bucket.tokens = tokens - 1.;
+        true
+    }
+
+    // Buckets that have refilled completely carry no state worth keeping.
+    fn evict_idle(&mut self, now: Instant) {
+        let buckets = std::mem::take(&mut self.buckets);
+        self.buckets = buckets
+            .into_iter()
+            .filter(|(_, b)| refill(b, now, self.rate, self.burst) < self.burst)
+            .collect();
+    }
+}
+
+/// Sources in the same /24 (IPv4) or /64 (IPv6) are likely the same operator.
+fn prefix(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from_bits(ip.to_bits() & !0xff)),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !(u64::MAX as u128))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dropped {
+    RateLimited,
+    Blacklisted,
+}
+
+struct Inner {
+    per_ip: TokenBuckets<IpAddr>,
+    per_prefix: TokenBuckets<IpAddr>,
+    offenses: TokenBuckets<IpAddr>,
+    malformed: TokenBuckets<IpAddr>,
+    blacklist: HashMap<IpAddr, Instant>,
+}
+
+impl Inner {
+    fn is_blacklisted(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.blacklist.get(&ip) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                self.blacklist.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn on_offense(&mut self, ip: IpAddr, now: Instant) {
+        if !self.offenses.try_acquire(ip, now) {
+            self.add_to_blacklist(ip, now);
+        }
+    }
+
+    fn add_to_blacklist(&mut self, ip: IpAddr, now: Instant) {
+        if self.blacklist.len() >= MAX_SOURCES {
+            self.blacklist.retain(|_, until| *until > now);
+            if self.blacklist.len() >= MAX_SOURCES {
+                return;
+            }
+        }
+        debug!(?ip, "blacklisting DHT node for {BLACKLIST_DURATION:?}");
+        self.blacklist.insert(ip, now + BLACKLIST_DURATION);
+    }
+}
+
+/// Limits how many queries per second we answer for each source IP and network prefix,
+/// and temporarily ignores sources that misbehave.
+pub(crate) struct QueryLimiter {
+    inner: Mutex<Inner>,
+}
+
+impl QueryLimiter {
+    pub fn new(per_source: u32, per_prefix: u32) -> Self {
+        // Allow short bursts, e.g. a node bootstrapping off us.
+        Self {
+            inner: Mutex::new(Inner {
+                per_ip: TokenBuckets::new(per_source, per_source.saturating_mul(2), MAX_SOURCES),
+                per_prefix: TokenBuckets::new(
+                    per_prefix,
+                    per_prefix.saturating_mul(2),
+                    MAX_SOURCES,
+                ),
+                offenses: TokenBuckets::new(OFFENSES_PER_SECOND, OFFENSES_BURST, MAX_SOURCES),
+                malformed: TokenBuckets::new(MALFORMED_PER_SECOND, MALFORMED_BURST, MAX_SOURCES),
+                blacklist: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Whether a query from `ip` should be answered.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Dropped> {
+        let ip = ip.to_canonical();
+        let now = Instant::now();
+        let mut inner = self.inner.lock();
+        if inner.is_blacklisted(ip, now) {
+            return Err(Dropped::Blacklisted);
+        }
+        if !inner.per_ip.try_acquire(ip, now) {
+            inner.on_offense(ip, now);
+            return Err(Dropped::RateLimited);
+        }
+        if !inner.per_prefix.try_acquire(prefix(ip), now) {
+            return Err(Dropped::RateLimited);
+        }
+        Ok(())
+    }
+
+    /// Count a malformed packet from `ip`. Sources that keep sending them get blacklisted.
+    pub fn on_malformed(&self, ip: IpAddr) {
+        let ip = ip.to_canonical();
+        let now = Instant::now();
+        let mut inner = self.inner.lock();
+        if !inner.malformed.try_acquire(ip, now) {
+            inner.add_to_blacklist(ip, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Dropped, MALFORMED_BURST, OFFENSES_BURST, QueryLimiter, TokenBuckets};
+
+    #[test]
+    fn test_token_buckets() {
+        let mut b = TokenBuckets::new(10, 20, 2);
+        let now = Instant::now();
+        for _ in 0..20 {
+            assert!(b.try_acquire(1, now));
+        }
+        assert!(!b.try_acquire(1, now));
+        // Other keys have their own budget.
+        assert!(b.try_acquire(2, now));
+
+        // Refills at the configured rate.
+        let later = now + Duration::from_millis(200);
+        assert!(b.try_acquire(1, later));
+        assert!(b.try_acquire(1, later));
+        assert!(!b.try_acquire(1, later));
+    }
+
+    #[test]
+    fn test_token_buckets_max_keys() {
+        let mut b = TokenBuckets::new(1, 1, 2);
+        let now = Instant::now();
+        assert!(b.try_acquire(1, now));
+        assert!(b.try_acquire(2, now));
+        // Both are busy, so there's no room for a new one.
+        assert!(!b.try_acquire(3, now));
+        // Once they are idle again they make room.
+        assert!(b.try_acquire(3, now + Duration::from_secs(2)));
+        assert!(b.buckets.len() <= 2);
+    }
+
+    #[test]
+    fn test_per_prefix() {
+        let limiter = QueryLimiter::new(100, 1);
+        assert_eq!(limiter.check("1.2.3.4".parse().unwrap()), Ok(()));
+        assert_eq!(limiter.check("1.2.3.5".parse().unwrap()), Ok(()));
+        // Same /24.
+        assert_eq!(
+            limiter.check("1.2.3.6".parse().unwrap()),
+            Err(Dropped::RateLimited)
+        );
+        assert_eq!(limiter.check("1.2.4.1".parse().unwrap()), Ok(()));
+
+        assert_eq!(limiter.check("2001:db8::1".parse().unwrap()), Ok(()));
+        assert_eq!(limiter.check("2001:db8::2".parse().unwrap()), Ok(()));
+        // Same /64.
+        assert_eq!(
+            limiter.check("2001:db8::3".parse().unwrap()),
+            Err(Dropped::RateLimited)
+        );
+        assert_eq!(limiter.check("2001:db8:0:1::1".parse().unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn test_blacklist() {
+        let limiter = QueryLimiter::new(1, 100);
+        let ip = "1.2.3.4".parse().unwrap();
+        // Use up the burst, then go over the limit.
+        assert_eq!(limiter.check(ip), Ok(()));
+        assert_eq!(limiter.check(ip), Ok(()));
+        for _ in 0..OFFENSES_BURST {
+            assert_eq!(limiter.check(ip), Err(Dropped::RateLimited));
+        }
+        assert_eq!(limiter.check(ip), Err(Dropped::RateLimited));
+        assert_eq!(limiter.check(ip), Err(Dropped::Blacklisted));
+        assert_eq!(limiter.check("1.2.3.5".parse().unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn test_malformed_blacklist() {
+        let limiter = QueryLimiter::new(100, 100);
+        let ip = "1.2.3.4".parse().unwrap();
+        for _ in 0..MALFORMED_BURST {
+            limiter.on_malformed(ip);
+        }
+        // A few malformed packets are tolerated.
+        assert_eq!(limiter.check(ip), Ok(()));
+        limiter.on_malformed(ip);
+        assert_eq!(limiter.check(ip), Err(Dropped::Blacklisted));
+        assert_eq!(limiter.check("1.2.3.5".parse().unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn test_prefix_limit_is_not_an_offense() {
+        let limiter = QueryLimiter::new(100, 1);
+        assert_eq!(limiter.check("1.2.3.4".parse().unwrap()), Ok(()));
+        assert_eq!(limiter.check("1.2.3.4".parse().unwrap()), Ok(()));
+        let neighbour = "1.2.3.5".parse().unwrap();
+        for _ in 0..OFFENSES_BURST * 2 {
+            assert_eq!(limiter.check(neighbour), Err(Dropped::RateLimited));
+        }
+    }
+}
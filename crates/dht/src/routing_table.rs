@@ -5,7 +5,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
 use tracing::{debug, trace};
 
-use crate::INACTIVITY_TIMEOUT;
+use crate::{INACTIVITY_TIMEOUT, node_id::is_valid_node_id};
 
 #[derive(Clone, Debug)]
 pub struct LeafBucket {
@@ -175,6 +175,7 @@ fn compute_split_start_end(
 pub enum InsertResult {
     WasExisting,
     ReplacedBad(RoutingTableNode),
+    ReplacedNonCompliant(RoutingTableNode),
     Added,
     Ignored,
 }
@@ -193,6 +194,21 @@ impl BucketTree {
         }
     }
 
+    fn remove(&mut self, id: &Id20) -> bool {
+        let idx = self.get_leaf(id);
+        let leaf = match &mut self.data[idx].data {
+            BucketTreeNodeData::Leaf(leaf) => leaf,
+            BucketTreeNodeData::LeftRight(_, _) => unreachable!(),
+        };
+        let len = leaf.nodes.len();
+        leaf.nodes.retain(|n| n.id != *id);
+        if leaf.nodes.len() == len {
+            return false;
+        }
+        self.size -= 1;
+        true
+    }
+
     fn iter_leaves(&self) -> BucketTreeIterator<'_> {
         BucketTreeIterator::new(self)
     }
@@ -201,6 +217,45 @@ impl BucketTree {
         self.iter_leaves().flat_map(|l| l.leaf.nodes.iter())
     }
 
+    // Count the nodes closer to the target than the given distance, stopping at the limit.
+    // Buckets are walked from the closest one, and those that can't hold closer nodes are
+    // skipped.
+    fn count_closer_than(&self, target: &Id20, distance: &Id20, limit: usize) -> usize {
+        let mut count = 0;
+        let mut queue = vec![0];
+        while let Some(idx) = queue.pop() {
+            let node = &self.data[idx];
+            let mut min_distance = node.start.distance(target);
+            min_distance.set_bits_range(160 - node.bits..160, false);
+            if min_distance >= *distance {
+                continue;
+            }
+            match &node.data {
+                BucketTreeNodeData::Leaf(leaf) => {
+                    count += leaf
+                        .nodes
+                        .iter()
+                        .filter(|n| n.id.distance(target) < *distance)
+                        .count();
+                    if count >= limit {
+                        return limit;
+                    }
+                }
+                BucketTreeNodeData::LeftRight(left_idx, right_idx) => {
+                    let left = &self.data[*left_idx];
+                    let (near, far) = if *target >= left.start && *target <= left.end_inclusive {
+                        (*left_idx, *right_idx)
+                    } else {
+                        (*right_idx, *left_idx)
+                    };
+                    queue.push(far);
+                    queue.push(near);
+                }
+            }
+        }
+        count
+    }
+
     fn get_leaf(&self, id: &Id20) -> usize {
         let mut idx = 0;
         loop {
@@ -293,12 +348,12 @@ impl BucketTree {
             }
 
             // if max size reached, don't bother
-            if self.size == self.max_size {
+            if self.size >= self.max_size {
                 trace!(
                     "can't add node to routing table, max size of {} reached",
                     self.max_size
                 );
-                return InsertResult::Ignored;
+                return replace_non_compliant(nodes, new_node, now);
             }
 
             if nodes.nodes.len() < 8 {
@@ -311,7 +366,7 @@ impl BucketTree {
 
             // if our id is not inside, don't bother.
             if *self_id < leaf.start || *self_id > leaf.end_inclusive {
-                return InsertResult::Ignored;
+                return replace_non_compliant(nodes, new_node, now);
             }
 
             // Split
@@ -366,6 +421,27 @@ impl BucketTree {
     }
 }
 
+// BEP 42: make room for a node with a compliant ID by dropping one without.
+fn replace_non_compliant(
+    leaf: &mut LeafBucket,
+    mut new_node: RoutingTableNode,
+    now: Instant,
+) -> InsertResult {
+    if !new_node.is_id_compliant() {
+        return InsertResult::Ignored;
+    }
+    match leaf.nodes.iter_mut().find(|n| !n.is_id_compliant()) {
+        Some(node) => {
+            std::mem::swap(node, &mut new_node);
+            leaf.nodes.sort_by_key(|n| n.id);
+            debug!("replaced non-compliant node {:?}", new_node);
+            leaf.last_refreshed = now;
+            InsertResult::ReplacedNonCompliant(new_node)
+        }
+        None => InsertResult::Ignored,
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct RoutingTableNode {
     #[serde(serialize_with = "crate::utils::serialize_id20")]
@@ -390,6 +466,7 @@ impl Serialize for RoutingTableNode {
         s.serialize_field("id", &self.id.as_string())?;
         s.serialize_field("addr", &self.addr)?;
         s.serialize_field("status", &self.status(Instant::now()))?;
+        s.serialize_field("id_compliant", &self.is_id_compliant())?;
         if let Some(l) = self.last_request {
             s.serialize_field("last_request_ago", &l.elapsed())?;
         }
@@ -404,6 +481,15 @@ impl Serialize for RoutingTableNode {
     }
 }
 
+/// How many nodes in the routing table are in each state.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RoutingTableHealth {
+    pub good: usize,
+    pub questionable: usize,
+    pub bad: usize,
+    pub unknown: usize,
+}
+
 #[derive(Serialize, Debug)]
 pub enum NodeStatus {
     Good,
@@ -419,6 +505,10 @@ impl RoutingTableNode {
     pub fn addr(&self) -> SocketAddr {
         self.addr
     }
+    /// Whether the node ID matches its IP (BEP 42).
+    pub fn is_id_compliant(&self) -> bool {
+        is_valid_node_id(&self.id, self.addr.ip())
+    }
     pub fn status(&self, now: Instant) -> NodeStatus {
         match (self.last_request, self.last_response, self.last_query) {
             // Nodes become bad when they fail to respond to multiple queries in a row.
@@ -492,6 +582,18 @@ impl RoutingTable {
     pub fn len(&self) -> usize {
         self.size
     }
+
+    /// A copy of this table rebuilt around a new ID of ours. Nodes that don't fit are dropped.
+    pub fn with_id(&self, id: Id20) -> Self {
+        let mut table = Self::new(id, Some(self.buckets.max_size));
+        for node in self.buckets.iter() {
+            table.add_node(node.id, node.addr);
+            if let Some(n) = table.buckets.get_mut(&node.id, None) {
+                *n = node.clone();
+            }
+        }
+        table
+    }
     pub fn sorted_by_distance_from(&self, id: Id20, now: Instant) -> Vec<&RoutingTableNode> {
         let mut result = Vec::with_capacity(self.size);
         for node in self.buckets.iter() {
@@ -510,6 +612,30 @@ impl RoutingTable {
         result
     }
 
+    /// How many nodes are closer to the target than the given distance, up to the limit.
+    pub fn count_closer_than(&self, target: Id20, distance: Id20, limit: usize) -> usize {
+        self.buckets.count_closer_than(&target, &distance, limit)
+    }
+
+    /// Change the max number of nodes. Shrinking it doesn't drop any nodes, only stops
+    /// adding new ones.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.buckets.max_size = max_size;
+    }
+
+    pub fn health(&self, now: Instant) -> RoutingTableHealth {
+        let mut health = RoutingTableHealth::default();
+        for node in self.buckets.iter() {
+            match node.status(now) {
+                NodeStatus::Good => health.good += 1,
+                NodeStatus::Questionable => health.questionable += 1,
+                NodeStatus::Bad => health.bad += 1,
+                NodeStatus::Unknown => health.unknown += 1,
+            }
+        }
+        health
+    }
+
     pub fn iter_buckets(&self) -> impl Iterator<Item = BucketTreeIteratorItem<'_>> + '_ {
         self.buckets.iter_leaves()
     }
@@ -523,6 +649,7 @@ impl RoutingTable {
         let replaced = match &res {
             InsertResult::WasExisting => false,
             InsertResult::ReplacedBad(..) => true,
+            InsertResult::ReplacedNonCompliant(..) => false,
             InsertResult::Added => true,
             InsertResult::Ignored => false,
         };
@@ -531,6 +658,14 @@ impl RoutingTable {
         }
         res
     }
+    pub fn remove(&mut self, id: &Id20) -> bool {
+        if !self.buckets.remove(id) {
+            return false;
+        }
+        self.size -= 1;
+        true
+    }
+
     pub fn mark_outgoing_request(&mut self, id: &Id20, now: Instant) -> bool {
         let r = match self.buckets.get_mut(id, None) {
             Some(r) => r,
@@ -582,7 +717,8 @@ mod tests {
 
     use crate::routing_table::compute_split_start_end;
 
-    use super::{RoutingTable, generate_random_id};
+    use super::{InsertResult, RoutingTable, generate_random_id};
+    use crate::node_id::generate_node_id;
 
     #[test]
     fn compute_split_start_end_root() {
@@ -687,6 +823,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_count_closer_than() {
+        let rtable = generate_table(None);
+        for _ in 0..100 {
+            let target = random_id_20();
+            let distance = random_id_20().distance(&target);
+            let expected = rtable
+                .iter()
+                .filter(|n| n.id().distance(&target) < distance)
+                .count();
+            assert_eq!(
+                rtable.count_closer_than(target, distance, usize::MAX),
+                expected
+            );
+            assert_eq!(
+                rtable.count_closer_than(target, distance, 8),
+                expected.min(8)
+            );
+        }
+    }
+
     #[test]
     fn serialize_deserialize_routing_table() {
         let table = generate_table(Some(1000));
@@ -694,6 +851,30 @@ mod tests {
         let _: RoutingTable = serde_json::from_reader(Cursor::new(v)).unwrap();
     }
 
+    #[test]
+    fn test_prefers_compliant_node_ids() {
+        let addr: SocketAddr = "124.31.75.21:6881".parse().unwrap();
+        let mut rtable = RoutingTable::new(random_id_20(), Some(8));
+        for _ in 0..8 {
+            rtable.add_node(random_id_20(), addr);
+        }
+        assert!(rtable.iter().all(|n| !n.is_id_compliant()));
+
+        let compliant = generate_node_id(addr.ip());
+        assert!(matches!(
+            rtable.add_node(compliant, addr),
+            InsertResult::ReplacedNonCompliant(_)
+        ));
+        assert_eq!(rtable.len(), 8);
+        assert!(rtable.iter().any(|n| n.id() == compliant));
+
+        // A non-compliant node doesn't replace anything.
+        assert!(matches!(
+            rtable.add_node(random_id_20(), addr),
+            InsertResult::Ignored
+        ));
+    }
+
     #[test]
     fn test_generate_random_id() {
         let start = Id20::from_str("3000000000000000000000000000000000000000").unwrap();
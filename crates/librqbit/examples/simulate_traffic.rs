@@ -187,6 +187,7 @@ impl TestHarness {
                         read_write_timeout: Some(Duration::from_secs(32)),
                         keep_alive_interval: None,
                     }),
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -275,6 +276,7 @@ impl TestHarness {
                         read_write_timeout: Some(Duration::from_secs(32)),
                         keep_alive_interval: None,
                     }),
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -2,17 +2,18 @@ use std::{collections::HashSet, marker::PhantomData, net::SocketAddr, str::FromS
 
 use anyhow::Context;
 use buffers::ByteBufOwned;
-use dht::{DhtStats, Id20};
+use dht::{DhtStats, Id20, ScrapeEstimate};
 use http::StatusCode;
 use librqbit_core::torrent_metainfo::{FileDetailsAttrs, ValidatedTorrentMetaV1Info};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::{
-    WithStatus, WithStatusError,
+    FilePriority, ScrapeStats, SeedGoals, TrackerStatus, WithStatus, WithStatusError,
     api_error::ApiError,
     session::{
-        AddTorrent, AddTorrentOptions, AddTorrentResponse, ListOnlyResponse, Session, TorrentId,
+        AddTorrent, AddTorrentOptions, AddTorrentResponse, ListOnlyResponse, QueueMove, Session,
+        TorrentId,
     },
     session_stats::snapshot::SessionStatsSnapshot,
     torrent_state::{
@@ -228,6 +229,9 @@ impl Api {
                         // These will be filled in /details and /stats endpoints
                         files: None,
                         stats: None,
+                        scrape: mgr.scrape(),
+                        dht_scrape: mgr.dht_scrape(),
+                        trackers: None,
                     };
                     if opts.with_stats {
                         r.stats = Some(mgr.stats());
@@ -243,6 +247,7 @@ impl Api {
         let handle = self.mgr_handle(idx)?;
         let info_hash = handle.shared().info_hash;
         let only_files = handle.only_files();
+        let file_priorities = handle.file_priorities();
         let output_folder = handle
             .shared()
             .options
@@ -250,14 +255,26 @@ impl Api {
             .to_string_lossy()
             .into_owned()
             .to_string();
-        make_torrent_details(
+        let mut details = make_torrent_details(
             Some(handle.id()),
             &info_hash,
             handle.metadata.load().as_ref().map(|r| &r.info),
             handle.name().as_deref(),
             only_files.as_deref(),
+            file_priorities.as_deref(),
             output_folder,
-        )
+        )?;
+        details.scrape = handle.scrape();
+        details.dht_scrape = handle.dht_scrape();
+        details.trackers = Some(self.session.tracker_statuses(&handle));
+        Ok(details)
+    }
+
+    pub fn api_torrent_trackers(&self, idx: TorrentIdOrHash) -> Result<TorrentTrackersResponse> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(TorrentTrackersResponse {
+            trackers: self.session.tracker_statuses(&handle),
+        })
     }
 
     pub fn api_session_stats(&self) -> SessionStatsSnapshot {
@@ -347,6 +364,87 @@ impl Api {
         Ok(Default::default())
     }
 
+    pub async fn api_torrent_action_update_file_priorities(
+        &self,
+        idx: TorrentIdOrHash,
+        file_priorities: &[FilePriority],
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.session
+            .update_file_priorities(&handle, file_priorities)
+            .await
+            .context("error updating file priorities")?;
+        Ok(Default::default())
+    }
+
+    pub async fn api_torrent_action_add_trackers(
+        &self,
+        idx: TorrentIdOrHash,
+        trackers: &[String],
+        tier: Option<usize>,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.session
+            .add_trackers(&handle, &parse_tracker_urls(trackers)?, tier)
+            .await
+            .with_status(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    pub async fn api_torrent_action_remove_trackers(
+        &self,
+        idx: TorrentIdOrHash,
+        trackers: &[String],
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.session
+            .remove_trackers(&handle, &parse_tracker_urls(trackers)?)
+            .await
+            .with_status(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    pub async fn api_torrent_action_replace_tracker(
+        &self,
+        idx: TorrentIdOrHash,
+        old: &str,
+        new: &str,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        let [old, new] = [old, new].map(parse_tracker_url);
+        self.session
+            .replace_tracker(&handle, &old?, new?)
+            .await
+            .with_status(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    pub async fn api_torrent_action_update_seed_goals(
+        &self,
+        idx: TorrentIdOrHash,
+        seed_goals: SeedGoals,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.session
+            .update_seed_goals(&handle, seed_goals)
+            .await
+            .context("error updating seed goals")?;
+        Ok(Default::default())
+    }
+
+    pub async fn api_torrent_action_queue_move(
+        &self,
+        idx: TorrentIdOrHash,
+        movement: QueueMove,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.session
+            .move_in_queue(&handle, movement)
+            .await
+            .context("error moving torrent in the queue")?;
+        Ok(Default::default())
+    }
+
     pub fn api_set_rust_log(&self, new_value: String) -> Result<EmptyJsonResponse> {
         let tx = self
             .rust_log_reload_tx
@@ -392,6 +490,7 @@ impl Api {
                     handle.metadata.load().as_ref().map(|r| &r.info),
                     handle.name().as_deref(),
                     handle.only_files().as_deref(),
+                    handle.file_priorities().as_deref(),
                     handle
                         .shared()
                         .options
@@ -429,6 +528,7 @@ impl Api {
                     Some(&info),
                     None,
                     only_files.as_deref(),
+                    None,
                     output_folder.to_string_lossy().into_owned().to_string(),
                 )
                 .context("error making torrent details")?,
@@ -440,6 +540,7 @@ impl Api {
                     handle.metadata.load().as_ref().map(|r| &r.info),
                     handle.name().as_deref(),
                     handle.only_files().as_deref(),
+                    handle.file_priorities().as_deref(),
                     handle
                         .shared()
                         .options
@@ -528,6 +629,8 @@ pub struct TorrentDetailsResponseFile {
     pub components: Vec<String>,
     pub length: u64,
     pub included: bool,
+    #[serde(default)]
+    pub priority: FilePriority,
     pub attributes: FileDetailsAttrs,
 }
 
@@ -549,6 +652,19 @@ pub struct TorrentDetailsResponse {
     pub files: Option<Vec<TorrentDetailsResponseFile>>,
     #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
     pub stats: Option<TorrentStats>,
+    /// Swarm counts from the latest tracker scrape.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrape: Option<ScrapeStats>,
+    /// Swarm size estimated with the latest DHT scrape (BEP 33).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dht_scrape: Option<ScrapeEstimate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trackers: Option<Vec<TrackerStatus>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TorrentTrackersResponse {
+    pub trackers: Vec<TrackerStatus>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -565,6 +681,7 @@ fn make_torrent_details(
     info: Option<&ValidatedTorrentMetaV1Info<ByteBufOwned>>,
     name: Option<&str>,
     only_files: Option<&[usize]>,
+    file_priorities: Option<&[FilePriority]>,
     output_folder: String,
 ) -> Result<TorrentDetailsResponse> {
     let files = match info {
@@ -575,11 +692,19 @@ fn make_torrent_details(
                 let name = d.filename.to_string();
                 let components = d.filename.to_vec();
                 let included = only_files.map(|o| o.contains(&idx)).unwrap_or(true);
+                let priority = if included {
+                    file_priorities
+                        .and_then(|p| p.get(idx).copied())
+                        .unwrap_or_default()
+                } else {
+                    FilePriority::Skip
+                };
                 TorrentDetailsResponseFile {
                     name,
                     components,
                     length: d.len,
                     included,
+                    priority,
                     attributes: d.attrs(),
                 }
             })
@@ -597,9 +722,22 @@ fn make_torrent_details(
         output_folder,
         total_pieces,
         stats: None,
+        scrape: None,
+        dht_scrape: None,
+        trackers: None,
     })
 }
 
+fn parse_tracker_url(url: &str) -> Result<url::Url> {
+    url::Url::parse(url)
+        .with_context(|| format!("invalid tracker URL {url:?}"))
+        .with_status(StatusCode::BAD_REQUEST)
+}
+
+fn parse_tracker_urls(trackers: &[String]) -> Result<Vec<url::Url>> {
+    trackers.iter().map(|t| parse_tracker_url(t)).collect()
+}
+
 fn torrent_file_mime_type(
     info: &ValidatedTorrentMetaV1Info<ByteBufOwned>,
     file_idx: usize,
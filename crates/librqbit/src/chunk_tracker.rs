@@ -9,6 +9,7 @@ use tracing::{debug, trace};
 use crate::{
     bitv::{BitV, BoxBitV},
     file_info::FileInfo,
+    file_priority::FilePriority,
     type_aliases::{BF, BS, FileInfos, FilePriorities},
 };
 
@@ -237,15 +238,15 @@ impl ChunkTracker {
         &'a self,
         file_priorities: &'a FilePriorities,
         file_infos: &'a FileInfos,
-    ) -> impl Iterator<Item = ValidPieceIndex> + 'a {
+    ) -> impl Iterator<Item = (FilePriority, ValidPieceIndex)> + 'a {
         file_priorities
             .iter()
-            .filter_map(|p| Some((*p, file_infos.get(*p)?)))
-            .filter(|(id, f)| self.per_file_bytes[*id] != f.len)
-            .flat_map(|(_id, f)| f.iter_piece_priorities())
-            .filter(|id| self.queue_pieces[*id])
-            .filter_map(|id| id.try_into().ok())
-            .filter_map(|id| self.lengths.validate_piece_index(id))
+            .filter_map(|(id, pri)| Some((*id, *pri, file_infos.get(*id)?)))
+            .filter(|(id, _, f)| self.per_file_bytes[*id] != f.len)
+            .flat_map(|(_, pri, f)| f.iter_piece_priorities().map(move |id| (pri, id)))
+            .filter(|(_, id)| self.queue_pieces[*id])
+            .filter_map(|(pri, id)| Some((pri, id.try_into().ok()?)))
+            .filter_map(|(pri, id)| Some((pri, self.lengths.validate_piece_index(id)?)))
     }
 
     pub(crate) fn is_piece_have(&self, id: ValidPieceIndex) -> bool {
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::io::{BufWriter, Read};
 use std::path::{Path, PathBuf};
@@ -8,17 +9,37 @@ use bencode::{WithRawBytes, bencode_serialize_to_writer};
 use buffers::ByteBufOwned;
 use bytes::Bytes;
 use librqbit_core::Id20;
+use librqbit_core::hash_id::Id32;
 use librqbit_core::magnet::Magnet;
-use librqbit_core::torrent_metainfo::{TorrentMetaV1File, TorrentMetaV1Info, TorrentMetaV1Owned};
-use sha1w::ISha1;
+use librqbit_core::merkle::{self, MERKLE_BLOCK_SIZE};
+use librqbit_core::torrent_metainfo::{
+    TorrentMetaV1File, TorrentMetaV1Info, TorrentMetaV1Owned, TorrentMetaV2File,
+    TorrentMetaV2FileTree,
+};
+use serde::{Deserialize, Serialize};
+use sha1w::{ISha1, ISha256};
 
 use crate::spawn_utils::BlockingSpawner;
 
+/// Which BitTorrent protocol versions a created torrent supports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TorrentVersion {
+    /// SHA-1 "pieces" only.
+    #[default]
+    V1,
+    /// BEP 52 "file tree" and "piece layers" only.
+    V2,
+    /// Both, with BEP 47 padding files so that v1 pieces line up with v2 files.
+    Hybrid,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct CreateTorrentOptions<'a> {
     pub name: Option<&'a str>,
     pub trackers: Vec<String>,
     pub piece_length: Option<u32>,
+    pub version: TorrentVersion,
 }
 
 fn walk_dir_find_paths(dir: &Path, out: &mut Vec<Cow<'_, Path>>) -> anyhow::Result<()> {
@@ -32,19 +53,29 @@ fn walk_dir_find_paths(dir: &Path, out: &mut Vec<Cow<'_, Path>>) -> anyhow::Resu
     Ok(())
 }
 
-fn compute_info_hash(t: &TorrentMetaV1Info<ByteBufOwned>) -> anyhow::Result<(Id20, Bytes)> {
+fn compute_info_hash(
+    t: &TorrentMetaV1Info<ByteBufOwned>,
+) -> anyhow::Result<(Id20, Option<Id32>, Bytes)> {
     let mut writer = BufWriter::new(Vec::new());
     bencode_serialize_to_writer(t, &mut writer)?;
     let bytes: Bytes = writer
         .into_inner()
         .map_err(|_| anyhow::anyhow!("into_inner errored"))?
         .into();
-    let hash = Id20::new({
-        let mut h = sha1w::Sha1::new();
+    let hash_v2 = t.has_v2().then(|| {
+        let mut h = sha1w::Sha256::new();
         h.update(&bytes);
-        h.finish()
+        Id32::new(h.finish())
     });
-    Ok((hash, bytes))
+    let hash = match hash_v2 {
+        Some(hash_v2) if !t.has_v1() => hash_v2.truncate_for_dht(),
+        _ => Id20::new({
+            let mut h = sha1w::Sha1::new();
+            h.update(&bytes);
+            h.finish()
+        }),
+    };
+    Ok((hash, hash_v2, bytes))
 }
 
 fn choose_piece_length(_input_files: &[Cow<'_, Path>]) -> u32 {
@@ -58,6 +89,7 @@ fn osstr_to_bytes(o: &OsStr) -> Vec<u8> {
 
 struct CreateTorrentRawResult {
     info: TorrentMetaV1Info<ByteBufOwned>,
+    piece_layers: Option<BTreeMap<ByteBufOwned, ByteBufOwned>>,
     output_folder: PathBuf,
 }
 
@@ -103,6 +135,23 @@ async fn create_torrent_raw<'a>(
 
     let _permit = spawner.semaphore().acquire_owned().await?;
 
+    if options.version != TorrentVersion::V1 {
+        let (info, piece_layers) = hash_v2(
+            path,
+            name,
+            single_file_mode,
+            input_files,
+            piece_length,
+            options.version == TorrentVersion::Hybrid,
+            spawner,
+        )?;
+        return Ok(CreateTorrentRawResult {
+            info,
+            piece_layers: Some(piece_layers),
+            output_folder,
+        });
+    }
+
     let mut length = 0;
     let mut remaining_piece_length = piece_length;
     let mut piece_checksum = sha1w::Sha1::new();
@@ -173,11 +222,166 @@ async fn create_torrent_raw<'a>(
             sha1: None,
             symlink_path: None,
             private: false,
+            meta_version: None,
+            file_tree: None,
         },
+        piece_layers: None,
         output_folder,
     })
 }
 
+// BEP 52 requires every file to start at a piece boundary. Hybrid torrents spell that out
+// for v1 clients with BEP 47 padding files.
+fn hash_v2(
+    path: &Path,
+    name: ByteBufOwned,
+    single_file_mode: bool,
+    input_files: Vec<Cow<'_, Path>>,
+    piece_length: u32,
+    hybrid: bool,
+    spawner: &BlockingSpawner,
+) -> anyhow::Result<(
+    TorrentMetaV1Info<ByteBufOwned>,
+    BTreeMap<ByteBufOwned, ByteBufOwned>,
+)> {
+    if !piece_length.is_power_of_two() || piece_length < MERKLE_BLOCK_SIZE {
+        anyhow::bail!(
+            "v2 torrents need a piece length that is a power of two and at least {MERKLE_BLOCK_SIZE}"
+        );
+    }
+    let blocks_per_piece = (piece_length / MERKLE_BLOCK_SIZE) as usize;
+
+    let mut files = Vec::with_capacity(input_files.len());
+    for file in input_files {
+        let torrent_path: Vec<ByteBufOwned> = if single_file_mode {
+            vec![name.clone()]
+        } else {
+            file.strip_prefix(path)
+                .context("internal error, can't strip prefix")?
+                .components()
+                .map(|c| osstr_to_bytes(c.as_os_str()).into())
+                .collect()
+        };
+        let length = std::fs::metadata(&file)
+            .with_context(|| format!("error reading metadata of {file:?}"))?
+            .len();
+        files.push((file, torrent_path, length));
+    }
+    // The file tree is a sorted dictionary, and the v1 file list must be in the same order.
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    let last_non_empty = files.iter().rposition(|(_, _, length)| *length > 0);
+
+    let mut read_buf = vec![0u8; piece_length as usize];
+    let mut v1_pieces = Vec::<u8>::new();
+    let mut v1_files = Vec::new();
+    let mut file_tree = Vec::new();
+    let mut piece_layers = BTreeMap::new();
+
+    for (idx, (file, torrent_path, length)) in files.into_iter().enumerate() {
+        let padding = match last_non_empty {
+            Some(last) if idx < last => {
+                (piece_length as u64 - length % piece_length as u64) % piece_length as u64
+            }
+            _ => 0,
+        };
+        let mut fd = std::io::BufReader::new(
+            std::fs::File::open(&file).with_context(|| format!("error opening {file:?}"))?,
+        );
+
+        let mut layer = Vec::new();
+        let mut leaves = Vec::new();
+        let mut remaining = length;
+        while remaining > 0 {
+            let piece_len: usize = remaining.min(piece_length as u64).try_into()?;
+            let buf = &mut read_buf[..piece_len];
+            spawner
+                .block_in_place(|| fd.read_exact(buf))
+                .with_context(|| format!("error reading {file:?}"))?;
+            remaining -= piece_len as u64;
+
+            leaves = buf
+                .chunks(MERKLE_BLOCK_SIZE as usize)
+                .map(merkle::hash_block)
+                .collect();
+            layer.push(merkle::root(&leaves, blocks_per_piece, Id32::default()));
+
+            if hybrid {
+                let mut piece_checksum = sha1w::Sha1::new();
+                piece_checksum.update(buf);
+                if remaining == 0 && padding > 0 {
+                    piece_checksum.update(&vec![0u8; padding.try_into()?]);
+                }
+                v1_pieces.extend_from_slice(&piece_checksum.finish());
+            }
+        }
+
+        let pieces_root = match layer.len() {
+            0 => None,
+            // Files of up to one piece don't have a piece layer, the root covers the blocks.
+            1 => Some(merkle::root(
+                &leaves,
+                leaves.len().next_power_of_two(),
+                Id32::default(),
+            )),
+            _ => {
+                let root = merkle::root_from_piece_layer(&layer, piece_length);
+                piece_layers.insert(
+                    ByteBufOwned::from(&root.0[..]),
+                    ByteBufOwned::from(layer.iter().flat_map(|h| h.0).collect::<Vec<u8>>()),
+                );
+                Some(root)
+            }
+        };
+
+        if hybrid {
+            v1_files.push(TorrentMetaV1File {
+                length,
+                path: torrent_path.clone(),
+                attr: None,
+                sha1: None,
+                symlink_path: None,
+            });
+            if padding > 0 {
+                v1_files.push(TorrentMetaV1File {
+                    length: padding,
+                    path: vec![b".pad"[..].into(), padding.to_string().into_bytes().into()],
+                    attr: Some(b"p"[..].into()),
+                    sha1: None,
+                    symlink_path: None,
+                });
+            }
+        }
+        file_tree.push(TorrentMetaV2File {
+            path: torrent_path,
+            length,
+            pieces_root: pieces_root.map(|r| ByteBufOwned::from(&r.0[..])),
+            attr: None,
+        });
+    }
+
+    let single_file_length = match (hybrid && single_file_mode, v1_files.as_slice()) {
+        (true, [f]) => Some(f.length),
+        _ => None,
+    };
+    Ok((
+        TorrentMetaV1Info {
+            name: Some(name),
+            pieces: v1_pieces.into(),
+            piece_length,
+            length: single_file_length,
+            md5sum: None,
+            files: (hybrid && single_file_length.is_none()).then_some(v1_files),
+            attr: None,
+            sha1: None,
+            symlink_path: None,
+            private: false,
+            meta_version: Some(2),
+            file_tree: Some(TorrentMetaV2FileTree(file_tree)),
+        },
+        piece_layers,
+    ))
+}
+
 #[derive(Debug)]
 pub struct CreateTorrentResult {
     pub meta: TorrentMetaV1Owned,
@@ -193,13 +397,20 @@ impl CreateTorrentResult {
         self.meta.info_hash
     }
 
+    pub fn info_hash_v2(&self) -> Option<Id32> {
+        self.meta.info_hash_v2
+    }
+
     pub fn as_magnet(&self) -> Magnet {
         let trackers = self
             .meta
             .iter_announce()
             .map(|i| std::str::from_utf8(i.as_ref()).unwrap().to_owned())
             .collect();
-        Magnet::from_id20(self.info_hash(), trackers, None)
+        // v2-only torrents don't have a real v1 info hash, only the truncated v2 one.
+        let info_hash = self.meta.info.data.has_v1().then_some(self.info_hash());
+        Magnet::new(info_hash, self.info_hash_v2(), trackers, None)
+            .expect("torrent always has at least one info hash")
     }
 
     pub fn as_bytes(&self) -> anyhow::Result<Bytes> {
@@ -220,7 +431,8 @@ pub async fn create_torrent<'a>(
         .map(|t| ByteBufOwned::from(t.as_bytes()))
         .collect();
     let res = create_torrent_raw(path, options, spawner).await?;
-    let (info_hash, bytes) = compute_info_hash(&res.info).context("error computing info hash")?;
+    let (info_hash, info_hash_v2, bytes) =
+        compute_info_hash(&res.info).context("error computing info hash")?;
     Ok(CreateTorrentResult {
         meta: TorrentMetaV1Owned {
             announce: None,
@@ -235,7 +447,11 @@ pub async fn create_torrent<'a>(
             publisher: None,
             publisher_url: None,
             creation_date: None,
+            url_list: vec![],
+            httpseeds: vec![],
+            piece_layers: res.piece_layers,
             info_hash,
+            info_hash_v2,
         },
         output_folder: res.output_folder,
     })
@@ -243,9 +459,15 @@ pub async fn create_torrent<'a>(
 
 #[cfg(test)]
 mod tests {
-    use librqbit_core::torrent_metainfo::torrent_from_bytes;
+    use librqbit_core::{
+        merkle::{self, MERKLE_BLOCK_SIZE, PieceLayers},
+        torrent_metainfo::torrent_from_bytes,
+    };
+    use sha1w::ISha1;
 
-    use crate::{create_torrent, spawn_utils::BlockingSpawner};
+    use crate::{
+        CreateTorrentOptions, TorrentVersion, create_torrent, spawn_utils::BlockingSpawner,
+    };
 
     #[tokio::test]
     async fn test_create_torrent() {
@@ -265,4 +487,123 @@ mod tests {
         let deserialized = torrent_from_bytes(&bytes).unwrap();
         assert_eq!(torrent.info_hash(), deserialized.info_hash);
     }
+
+    #[tokio::test]
+    async fn test_create_torrent_v2() {
+        use crate::tests::test_util;
+
+        let piece_length = 65536;
+        let dir = test_util::create_default_random_dir_with_torrents(
+            3,
+            1000 * 1000,
+            Some("rqbit_test_create_torrent_v2"),
+        );
+        let torrent = create_torrent(
+            dir.path(),
+            CreateTorrentOptions {
+                piece_length: Some(piece_length),
+                version: TorrentVersion::V2,
+                ..Default::default()
+            },
+            &BlockingSpawner::new(1),
+        )
+        .await
+        .unwrap();
+
+        let bytes = torrent.as_bytes().unwrap();
+        let deserialized = torrent_from_bytes(&bytes).unwrap();
+        assert_eq!(torrent.info_hash(), deserialized.info_hash);
+        assert_eq!(torrent.info_hash_v2(), deserialized.info_hash_v2);
+        assert_eq!(
+            torrent.info_hash(),
+            torrent.info_hash_v2().unwrap().truncate_for_dht()
+        );
+        assert!(!deserialized.info.data.has_v1());
+
+        let magnet = torrent.as_magnet();
+        assert_eq!(magnet.as_id20(), None);
+        assert_eq!(magnet.as_id32(), torrent.info_hash_v2());
+
+        let piece_layers = deserialized.piece_layers.clone();
+        let info = deserialized.info.data.validate().unwrap();
+        PieceLayers::from_torrent(&info, piece_layers.as_ref()).unwrap();
+
+        // The pieces root must be the merkle root of the whole file.
+        let blocks_per_piece = (piece_length / MERKLE_BLOCK_SIZE) as usize;
+        for file in info.info().file_tree.as_ref().unwrap().0.iter() {
+            let name = std::str::from_utf8(file.path[0].as_ref()).unwrap();
+            let data = std::fs::read(dir.path().join(name)).unwrap();
+            let leaves: Vec<_> = data
+                .chunks(MERKLE_BLOCK_SIZE as usize)
+                .map(merkle::hash_block)
+                .collect();
+            let pieces = leaves.len().div_ceil(blocks_per_piece);
+            let root = merkle::root(
+                &leaves,
+                pieces.next_power_of_two() * blocks_per_piece,
+                Default::default(),
+            );
+            assert_eq!(file.pieces_root.as_ref().unwrap().as_ref(), &root.0[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_torrent_hybrid() {
+        use crate::tests::test_util;
+
+        let piece_length = 65536;
+        let dir = test_util::create_default_random_dir_with_torrents(
+            3,
+            1000 * 1000,
+            Some("rqbit_test_create_torrent_hybrid"),
+        );
+        let torrent = create_torrent(
+            dir.path(),
+            CreateTorrentOptions {
+                piece_length: Some(piece_length),
+                version: TorrentVersion::Hybrid,
+                ..Default::default()
+            },
+            &BlockingSpawner::new(1),
+        )
+        .await
+        .unwrap();
+
+        let bytes = torrent.as_bytes().unwrap();
+        let deserialized = torrent_from_bytes(&bytes).unwrap();
+        assert_eq!(torrent.info_hash(), deserialized.info_hash);
+        assert_eq!(torrent.info_hash_v2(), deserialized.info_hash_v2);
+        assert!(deserialized.info.data.has_v1());
+
+        let magnet = torrent.as_magnet();
+        assert_eq!(magnet.as_id20(), Some(torrent.info_hash()));
+        assert_eq!(magnet.as_id32(), torrent.info_hash_v2());
+
+        let piece_layers = deserialized.piece_layers.clone();
+        let info = deserialized.info.data.validate().unwrap();
+        PieceLayers::from_torrent(&info, piece_layers.as_ref()).unwrap();
+
+        // v1 pieces cover the files with padding in between.
+        let mut data = Vec::new();
+        for fd in info.iter_file_details() {
+            if fd.attrs().padding {
+                data.resize(data.len() + usize::try_from(fd.len).unwrap(), 0);
+                continue;
+            }
+            data.extend(std::fs::read(dir.path().join(fd.filename.to_pathbuf())).unwrap());
+        }
+        assert_eq!(
+            data.len() % piece_length as usize,
+            1000 * 1000 % piece_length as usize
+        );
+        let expected: Vec<u8> = data
+            .chunks(piece_length as usize)
+            .flat_map(|piece| {
+                let mut h = sha1w::Sha1::new();
+                h.update(piece);
+                h.finish()
+            })
+            .collect();
+        assert_eq!(info.info().pieces.as_ref(), &expected[..]);
+    }
 }
@@ -10,7 +10,7 @@ use crate::{
     peer_connection::PeerConnectionOptions, peer_info_reader, spawn_utils::BlockingSpawner,
     stream_connect::StreamConnector,
 };
-use librqbit_core::hash_id::Id20;
+use librqbit_core::hash_id::{Id20, Id32};
 
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
@@ -18,6 +18,7 @@ pub enum ReadMetainfoResult<Rx> {
     Found {
         info: TorrentMetaV1Info<ByteBufOwned>,
         info_bytes: ByteBufOwned,
+        piece_layers: Option<peer_info_reader::RawPieceLayers>,
         rx: Rx,
         seen: HashSet<SocketAddr>,
     },
@@ -27,9 +28,11 @@ pub enum ReadMetainfoResult<Rx> {
     },
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unpin>(
     peer_id: Id20,
     info_hash: Id20,
+    info_hash_v2: Option<Id32>,
     initial_addrs: Vec<SocketAddr>,
     addrs_stream: A,
     peer_connection_options: Option<PeerConnectionOptions>,
@@ -51,6 +54,7 @@ pub async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unp
                 addr,
                 peer_id,
                 info_hash,
+                info_hash_v2,
                 peer_connection_options,
                 // This shouldn't be called anyway as we aren't reading/writing to disk, so it's
                 // ok not to use a shared one.
@@ -83,7 +87,7 @@ pub async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unp
         tokio::select! {
             done = unordered.next(), if !unordered.is_empty() => {
                 match done {
-                    Some(Ok((info, info_bytes))) => return ReadMetainfoResult::Found { info, info_bytes, seen, rx: addrs },
+                    Some(Ok((info, info_bytes, piece_layers))) => return ReadMetainfoResult::Found { info, info_bytes, piece_layers, seen, rx: addrs },
                     Some(Err(e)) => {
                         debug!("{:#}", e);
                     },
@@ -141,6 +145,7 @@ mod tests {
         match read_metainfo_from_peer_receiver(
             peer_id,
             info_hash,
+            None,
             Vec::new(),
             peer_rx,
             None,
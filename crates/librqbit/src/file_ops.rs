@@ -6,7 +6,9 @@ use std::{
 use anyhow::{Context, bail};
 use buffers::{ByteBuf, ByteBufOwned};
 use librqbit_core::{
+    hash_id::Id32,
     lengths::{ChunkInfo, ValidPieceIndex},
+    merkle::{self, MERKLE_BLOCK_SIZE, PieceLayers},
     torrent_metainfo::ValidatedTorrentMetaV1Info,
 };
 use peer_binary_protocol::{DoubleBufHelper, Piece};
@@ -50,6 +52,7 @@ pub fn update_hash_from_file<Sha1: ISha1>(
 
 pub(crate) struct FileOps<'a> {
     torrent: &'a ValidatedTorrentMetaV1Info<ByteBufOwned>,
+    piece_layers: &'a PieceLayers,
     files: &'a dyn TorrentStorage,
     file_infos: &'a FileInfos,
     phantom_data: PhantomData<Sha1>,
@@ -58,11 +61,13 @@ pub(crate) struct FileOps<'a> {
 impl<'a> FileOps<'a> {
     pub fn new(
         torrent: &'a ValidatedTorrentMetaV1Info<ByteBufOwned>,
+        piece_layers: &'a PieceLayers,
         files: &'a dyn TorrentStorage,
         file_infos: &'a FileInfos,
     ) -> Self {
         Self {
             torrent,
+            piece_layers,
             files,
             file_infos,
             phantom_data: PhantomData,
@@ -75,6 +80,10 @@ impl<'a> FileOps<'a> {
         progress: &AtomicU64,
         pause_requested: &AtomicBool,
     ) -> anyhow::Result<BF> {
+        if !self.torrent.info().has_v1() {
+            return self.initial_check_v2(progress, pause_requested);
+        }
+
         let mut have_pieces =
             BF::from_boxed_slice(vec![0u8; self.torrent.lengths().piece_bitfield_bytes()].into());
         let mut piece_files = Vec::<usize>::new();
@@ -183,11 +192,96 @@ impl<'a> FileOps<'a> {
         Ok(have_pieces)
     }
 
+    // v2-only torrents have no SHA-1 hashes, every piece is checked against its file's merkle tree.
+    fn initial_check_v2(
+        &self,
+        progress: &AtomicU64,
+        pause_requested: &AtomicBool,
+    ) -> anyhow::Result<BF> {
+        let mut have_pieces =
+            BF::from_boxed_slice(vec![0u8; self.torrent.lengths().piece_bitfield_bytes()].into());
+        for piece_info in self.torrent.lengths().iter_piece_infos() {
+            if pause_requested.load(Ordering::Relaxed) {
+                bail!("initial check paused");
+            }
+            progress.fetch_add(piece_info.len as u64, Ordering::Relaxed);
+            match self.check_piece_v2(piece_info.piece_index) {
+                Ok(true) => have_pieces.set(piece_info.piece_index.get() as usize, true),
+                Ok(false) => {}
+                Err(e) => trace!(
+                    "piece {} had errors, marking as needed: {e:#}",
+                    piece_info.piece_index
+                ),
+            }
+        }
+        Ok(have_pieces)
+    }
+
+    fn check_piece_v2(&self, piece_index: ValidPieceIndex) -> anyhow::Result<bool> {
+        let file = self
+            .torrent
+            .v2_file_for_piece(piece_index.get())
+            .context("bug: piece doesn't belong to any file")?;
+        let pieces_root = file
+            .pieces_root
+            .context("bug: non-empty file has no root")?;
+        let piece_length = self.torrent.info().piece_length;
+        let local_piece = (piece_index.get() - file.pieces.start) as usize;
+        let offset_in_file = local_piece as u64 * piece_length as u64;
+        let len_in_file = std::cmp::min(piece_length as u64, file.len - offset_in_file);
+
+        // Padding after the file is not part of the tree.
+        let mut leaves = Vec::new();
+        let mut buf = vec![0u8; MERKLE_BLOCK_SIZE as usize];
+        let mut pos = offset_in_file;
+        while pos < offset_in_file + len_in_file {
+            let block_len: usize =
+                std::cmp::min(MERKLE_BLOCK_SIZE as u64, offset_in_file + len_in_file - pos)
+                    .try_into()?;
+            self.files
+                .pread_exact(file.file_index, pos, &mut buf[..block_len])
+                .with_context(|| {
+                    format!(
+                        "error reading {block_len} bytes at {pos}, file_id: {}",
+                        file.file_index
+                    )
+                })?;
+            leaves.push(merkle::hash_block(&buf[..block_len]));
+            pos += block_len as u64;
+        }
+
+        if file.len <= piece_length as u64 {
+            // Files of at most one piece have no piece layer, the root covers the blocks directly.
+            Ok(
+                merkle::root(&leaves, leaves.len().next_power_of_two(), Id32::default())
+                    == pieces_root,
+            )
+        } else {
+            let layer = self
+                .piece_layers
+                .get(&pieces_root)
+                .context("piece layer missing for file")?;
+            let expected = layer
+                .get(local_piece)
+                .context("bug: piece layer is too short")?;
+            let blocks_per_piece = (piece_length / MERKLE_BLOCK_SIZE) as usize;
+            Ok(merkle::root(&leaves, blocks_per_piece, Id32::default()) == *expected)
+        }
+    }
+
     pub fn check_piece(&self, piece_index: ValidPieceIndex) -> anyhow::Result<bool> {
         if cfg!(feature = "_disable_disk_write_net_benchmark") {
             return Ok(true);
         }
 
+        if !self.torrent.info().has_v1() {
+            let matches = self.check_piece_v2(piece_index)?;
+            if !matches {
+                warn!("the piece={} merkle hash does not match", piece_index);
+            }
+            return Ok(matches);
+        }
+
         let mut h = Sha1::new();
         let piece_length = self.torrent.lengths().piece_length(piece_index);
         let mut absolute_offset = self.torrent.lengths().piece_offset(piece_index);
@@ -0,0 +1,108 @@
+// Per-file download priorities.
+//
+// "skip" is the same as leaving the file out of only_files. Among the other files, pieces of
+// higher priority files are downloaded first.
+
+use serde::{Deserialize, Serialize};
+
+use crate::type_aliases::{FileInfos, FilePriorities};
+
+#[derive(
+    Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FilePriority {
+    /// Don't download the file.
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl std::fmt::Display for FilePriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FilePriority::Skip => "skip",
+            FilePriority::Low => "low",
+            FilePriority::Normal => "normal",
+            FilePriority::High => "high",
+        })
+    }
+}
+
+/// The files to download pieces from, highest priority first. Files of the same priority are
+/// sorted by filename, as many torrents have random file order.
+pub(crate) fn file_download_order(
+    file_infos: &FileInfos,
+    priorities: Option<&[FilePriority]>,
+) -> FilePriorities {
+    let mut order = (0..file_infos.len())
+        .map(|id| {
+            let priority = priorities
+                .and_then(|p| p.get(id).copied())
+                .unwrap_or_default();
+            (id, priority)
+        })
+        .filter(|(_, priority)| *priority != FilePriority::Skip)
+        .collect::<Vec<_>>();
+    order.sort_by(|(l_id, l_pri), (r_id, r_pri)| {
+        r_pri.cmp(l_pri).then_with(|| {
+            file_infos[*l_id]
+                .relative_filename
+                .cmp(&file_infos[*r_id].relative_filename)
+        })
+    });
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file_info::FileInfo;
+
+    use super::{FilePriority, file_download_order};
+
+    #[test]
+    fn test_file_download_order() {
+        let file_infos = ["c", "b", "a", "d"]
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| FileInfo {
+                relative_filename: name.into(),
+                offset_in_torrent: id as u64 * 10,
+                len: 10,
+                piece_range: 0..1,
+                attrs: Default::default(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            file_download_order(&file_infos, None),
+            vec![
+                (2, FilePriority::Normal),
+                (1, FilePriority::Normal),
+                (0, FilePriority::Normal),
+                (3, FilePriority::Normal),
+            ]
+        );
+
+        use FilePriority::*;
+        assert_eq!(
+            file_download_order(&file_infos, Some(&[Low, Skip, Normal, High])),
+            vec![(3, High), (2, Normal), (0, Low)]
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        assert_eq!(
+            serde_json::from_str::<Vec<FilePriority>>(r#"["skip","low","normal","high"]"#).unwrap(),
+            vec![
+                FilePriority::Skip,
+                FilePriority::Low,
+                FilePriority::Normal,
+                FilePriority::High
+            ]
+        );
+    }
+}
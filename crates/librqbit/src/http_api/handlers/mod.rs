@@ -54,11 +54,12 @@ async fn h_api_root(parts: Parts) -> impl IntoResponse {
             "GET /torrents/{id_or_infohash}/playlist": "Generate M3U8 playlist for this torrent",
             "GET /torrents/{id_or_infohash}/stats/v1": "Torrent stats",
             "GET /torrents/{id_or_infohash}/peer_stats": "Per peer stats",
+            "GET /torrents/{id_or_infohash}/trackers": "Per tracker status",
             "GET /torrents/{id_or_infohash}/peer_stats/prometheus": "Per peer stats in prometheus format",
             "GET /torrents/{id_or_infohash}/stream/{file_idx}": "Stream a file. Accepts Range header to seek.",
             "GET /torrents/{id_or_infohash}/playlist": "Playlist for supported players",
             "POST /torrents": "Add a torrent here. magnet: or http:// or a local file.",
-            "POST /torrents/create": "Create a torrent and start seeding. Body should be a local folder",
+            "POST /torrents/create": "Create a torrent and start seeding. Body should be a local folder. Query: ?version=v1|v2|hybrid",
             "POST /torrents/resolve_magnet": "Resolve a magnet to torrent file bytes",
             "POST /torrents/{id_or_infohash}/pause": "Pause torrent",
             "POST /torrents/{id_or_infohash}/start": "Resume torrent",
@@ -66,6 +67,12 @@ async fn h_api_root(parts: Parts) -> impl IntoResponse {
             "POST /torrents/{id_or_infohash}/delete": "Forget about the torrent, remove the files",
             "POST /torrents/{id_or_infohash}/add_peers": "Add peers (newline-delimited)",
             "POST /torrents/{id_or_infohash}/update_only_files": "Change the selection of files to download. You need to POST json of the following form {\"only_files\": [0, 1, 2]}",
+            "POST /torrents/{id_or_infohash}/file_priorities": "Change the download priorities of files. You need to POST json of the following form {\"file_priorities\": [\"skip\", \"low\", \"normal\", \"high\"]}, with one entry per file",
+            "POST /torrents/{id_or_infohash}/update_seed_goals": "Change the share ratio and seeding time goals. POST json of the following form {\"max_ratio\": 2.0, \"max_ratio_action\": \"pause|forget|delete\", \"max_seeding_time\": <secs>, \"max_idle_time\": <secs>}. Unset goals fall back to the session defaults",
+            "POST /torrents/{id_or_infohash}/queue/{up|down|top|bottom}": "Move the torrent in the session queue",
+            "POST /torrents/{id_or_infohash}/trackers/add": "Add trackers. POST json of the following form {\"trackers\": [\"udp://tracker.example:1337/announce\"], \"tier\": 0}. Without a tier, each tracker is added in a new tier",
+            "POST /torrents/{id_or_infohash}/trackers/remove": "Remove trackers. POST json of the following form {\"trackers\": [\"udp://tracker.example:1337/announce\"]}",
+            "POST /torrents/{id_or_infohash}/trackers/replace": "Replace a tracker URL, keeping its position. POST json of the following form {\"old\": \"<url>\", \"new\": \"<url>\"}",
             "POST /rust_log": "Set RUST_LOG to this post launch (for debugging)",
         },
         "server": "rqbit",
@@ -90,6 +97,7 @@ pub fn make_api_router(state: ApiState) -> Router {
         .route("/torrents/{id}/stats", get(torrents::h_torrent_stats_v0))
         .route("/torrents/{id}/stats/v1", get(torrents::h_torrent_stats_v1))
         .route("/torrents/{id}/peer_stats", get(torrents::h_peer_stats))
+        .route("/torrents/{id}/trackers", get(torrents::h_torrent_trackers))
         .route(
             "/torrents/{id}/peer_stats/prometheus",
             get(torrents::h_peer_stats_prometheus),
@@ -134,6 +142,30 @@ pub fn make_api_router(state: ApiState) -> Router {
                 "/torrents/{id}/update_only_files",
                 post(torrents::h_torrent_action_update_only_files),
             )
+            .route(
+                "/torrents/{id}/file_priorities",
+                post(torrents::h_torrent_action_update_file_priorities),
+            )
+            .route(
+                "/torrents/{id}/update_seed_goals",
+                post(torrents::h_torrent_action_update_seed_goals),
+            )
+            .route(
+                "/torrents/{id}/queue/{movement}",
+                post(torrents::h_torrent_action_queue_move),
+            )
+            .route(
+                "/torrents/{id}/trackers/add",
+                post(torrents::h_torrent_action_add_trackers),
+            )
+            .route(
+                "/torrents/{id}/trackers/remove",
+                post(torrents::h_torrent_action_remove_trackers),
+            )
+            .route(
+                "/torrents/{id}/trackers/replace",
+                post(torrents::h_torrent_action_replace_tracker),
+            )
             .route("/torrents/{id}/add_peers", post(torrents::h_add_peers))
             .route("/torrents/create", post(torrents::h_create_torrent));
     }
@@ -16,7 +16,8 @@ use serde::{Deserialize, Serialize};
 
 use super::ApiState;
 use crate::{
-    AddTorrent, ApiError, CreateTorrentOptions, SUPPORTED_SCHEMES,
+    AddTorrent, ApiError, CreateTorrentOptions, FilePriority, QueueMove, SUPPORTED_SCHEMES,
+    SeedGoals, TorrentVersion,
     api::{ApiTorrentListOpts, Result, TorrentIdOrHash},
     api_error::WithStatusError,
     http_api::timeout::Timeout,
@@ -184,6 +185,13 @@ pub async fn h_torrent_stats_v1(
     state.api.api_stats_v1(idx).map(axum::Json)
 }
 
+pub async fn h_torrent_trackers(
+    State(state): State<ApiState>,
+    Path(idx): Path<TorrentIdOrHash>,
+) -> Result<impl IntoResponse> {
+    state.api.api_torrent_trackers(idx).map(axum::Json)
+}
+
 pub async fn h_peer_stats(
     State(state): State<ApiState>,
     Path(idx): Path<TorrentIdOrHash>,
@@ -253,6 +261,96 @@ pub async fn h_torrent_action_update_only_files(
         .map(axum::Json)
 }
 
+#[derive(Deserialize)]
+pub struct UpdateFilePrioritiesRequest {
+    file_priorities: Vec<FilePriority>,
+}
+
+pub async fn h_torrent_action_update_file_priorities(
+    State(state): State<ApiState>,
+    Path(idx): Path<TorrentIdOrHash>,
+    axum::Json(req): axum::Json<UpdateFilePrioritiesRequest>,
+) -> Result<impl IntoResponse> {
+    state
+        .api
+        .api_torrent_action_update_file_priorities(idx, &req.file_priorities)
+        .await
+        .map(axum::Json)
+}
+
+#[derive(Deserialize)]
+pub struct TrackersRequest {
+    trackers: Vec<String>,
+    // Only used when adding trackers.
+    #[serde(default)]
+    tier: Option<usize>,
+}
+
+pub async fn h_torrent_action_add_trackers(
+    State(state): State<ApiState>,
+    Path(idx): Path<TorrentIdOrHash>,
+    axum::Json(req): axum::Json<TrackersRequest>,
+) -> Result<impl IntoResponse> {
+    state
+        .api
+        .api_torrent_action_add_trackers(idx, &req.trackers, req.tier)
+        .await
+        .map(axum::Json)
+}
+
+pub async fn h_torrent_action_remove_trackers(
+    State(state): State<ApiState>,
+    Path(idx): Path<TorrentIdOrHash>,
+    axum::Json(req): axum::Json<TrackersRequest>,
+) -> Result<impl IntoResponse> {
+    state
+        .api
+        .api_torrent_action_remove_trackers(idx, &req.trackers)
+        .await
+        .map(axum::Json)
+}
+
+#[derive(Deserialize)]
+pub struct ReplaceTrackerRequest {
+    old: String,
+    new: String,
+}
+
+pub async fn h_torrent_action_replace_tracker(
+    State(state): State<ApiState>,
+    Path(idx): Path<TorrentIdOrHash>,
+    axum::Json(req): axum::Json<ReplaceTrackerRequest>,
+) -> Result<impl IntoResponse> {
+    state
+        .api
+        .api_torrent_action_replace_tracker(idx, &req.old, &req.new)
+        .await
+        .map(axum::Json)
+}
+
+pub async fn h_torrent_action_update_seed_goals(
+    State(state): State<ApiState>,
+    Path(idx): Path<TorrentIdOrHash>,
+    axum::Json(req): axum::Json<SeedGoals>,
+) -> Result<impl IntoResponse> {
+    state
+        .api
+        .api_torrent_action_update_seed_goals(idx, req)
+        .await
+        .map(axum::Json)
+}
+
+pub async fn h_torrent_action_queue_move(
+    State(state): State<ApiState>,
+    Path((idx, movement)): Path<(TorrentIdOrHash, QueueMove)>,
+) -> Result<impl IntoResponse> {
+    state
+        .api
+        .api_torrent_action_queue_move(idx, movement)
+        .await
+        .map(axum::Json)
+}
+
 pub async fn h_session_stats(State(state): State<ApiState>) -> impl IntoResponse {
     axum::Json(state.api.api_session_stats())
 }
@@ -365,6 +463,8 @@ pub struct HttpCreateTorrentOptions {
     #[serde(default)]
     trackers: Vec<String>,
     name: Option<String>,
+    #[serde(default)]
+    version: TorrentVersion,
 }
 
 pub async fn h_create_torrent(
@@ -389,6 +489,7 @@ pub async fn h_create_torrent(
         name: opts.name.as_deref(),
         trackers: opts.trackers,
         piece_length: None,
+        version: opts.version,
     };
 
     let (torrent, handle) = state
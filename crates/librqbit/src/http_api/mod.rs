@@ -22,6 +22,7 @@ use crate::api::Result;
 
 mod handlers;
 mod timeout;
+mod tracker;
 #[cfg(feature = "webui")]
 mod webui;
 
@@ -117,6 +118,9 @@ impl HttpApi {
                 get(move || async move {
                     let mut metrics = handle.render();
                     session.stats_snapshot().as_prometheus(&mut metrics);
+                    if let Some(dht) = session.get_dht() {
+                        dht.stats().as_prometheus(&mut metrics);
+                    }
                     metrics
                 }),
             );
@@ -168,6 +172,11 @@ impl HttpApi {
             main_router = main_router.nest("/upnp", upnp_router);
         }
 
+        // BitTorrent clients can't do basic auth, so the tracker is outside of it.
+        if let Some(tracker) = state.api.session().tracker_server() {
+            main_router = main_router.merge(tracker::make_tracker_router(tracker.clone()));
+        }
+
         let app = main_router
             .layer(cors_layer)
             .layer(
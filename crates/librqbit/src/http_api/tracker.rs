@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::{ConnectInfo, RawQuery},
+    routing::get,
+};
+use librqbit_dualstack_sockets::WrappedSocketAddr;
+
+use crate::TrackerServer;
+
+pub fn make_tracker_router(tracker: Arc<TrackerServer>) -> Router {
+    let announce_tracker = tracker.clone();
+    Router::new()
+        .route(
+            "/announce",
+            get(
+                |ConnectInfo(addr): ConnectInfo<WrappedSocketAddr>, RawQuery(query): RawQuery| async move {
+                    (
+                        [("Content-Type", "text/plain")],
+                        announce_tracker.http_announce(query.as_deref().unwrap_or_default(), addr.0.ip()),
+                    )
+                },
+            ),
+        )
+        .route(
+            "/scrape",
+            get(|RawQuery(query): RawQuery| async move {
+                (
+                    [("Content-Type", "text/plain")],
+                    tracker.http_scrape(query.as_deref().unwrap_or_default()),
+                )
+            }),
+        )
+}
@@ -4,7 +4,7 @@ use serde::Deserialize;
 
 use crate::{
     api::ApiAddTorrentResponse,
-    http_api_types::{InitialPeers, TorrentAddQueryParams},
+    http_api_types::{FilePriorityList, InitialPeers, TorrentAddQueryParams},
     session::{AddTorrent, AddTorrentOptions},
 };
 
@@ -96,6 +96,7 @@ impl HttpApiClient {
                 overwrite: Some(opts.overwrite),
                 only_files_regex: opts.only_files_regex,
                 only_files: None,
+                file_priorities: opts.file_priorities.map(FilePriorityList),
                 output_folder: opts.output_folder,
                 sub_folder: opts.sub_folder,
                 list_only: Some(opts.list_only),
@@ -3,9 +3,10 @@ use std::{net::SocketAddr, str::FromStr, time::Duration};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-use crate::{AddTorrentOptions, PeerConnectionOptions};
+use crate::{AddTorrentOptions, FilePriority, PeerConnectionOptions, SeedGoalAction, SeedGoals};
 
 pub struct OnlyFiles(Vec<usize>);
+pub struct FilePriorityList(pub Vec<FilePriority>);
 pub struct InitialPeers(pub Vec<SocketAddr>);
 
 pub use crate::torrent_state::peer::stats::snapshot::{PeerStatsFilter, PeerStatsSnapshot};
@@ -17,12 +18,23 @@ pub struct TorrentAddQueryParams {
     pub sub_folder: Option<String>,
     pub only_files_regex: Option<String>,
     pub only_files: Option<OnlyFiles>,
+    // Comma-separated, one per file, e.g. "high,normal,skip".
+    pub file_priorities: Option<FilePriorityList>,
     pub peer_connect_timeout: Option<u64>,
     pub peer_read_write_timeout: Option<u64>,
     pub initial_peers: Option<InitialPeers>,
+    pub max_ratio: Option<f64>,
+    pub max_ratio_action: Option<SeedGoalAction>,
+    // Seconds.
+    pub max_seeding_time: Option<u64>,
+    pub max_seeding_time_action: Option<SeedGoalAction>,
+    // Seconds.
+    pub max_idle_time: Option<u64>,
+    pub max_idle_time_action: Option<SeedGoalAction>,
     // Will force interpreting the content as a URL.
     pub is_url: Option<bool>,
     pub list_only: Option<bool>,
+    pub retire_previous_versions: Option<bool>,
 }
 
 impl Serialize for OnlyFiles {
@@ -63,6 +75,30 @@ impl<'de> Deserialize<'de> for OnlyFiles {
     }
 }
 
+impl Serialize for FilePriorityList {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.iter().join(",").serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilePriorityList {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::IntoDeserializer;
+
+        let s = String::deserialize(deserializer)?;
+        s.split(',')
+            .map(|c| FilePriority::deserialize(c.into_deserializer()))
+            .collect::<Result<Vec<_>, D::Error>>()
+            .map(FilePriorityList)
+    }
+}
+
 impl<'de> Deserialize<'de> for InitialPeers {
     fn deserialize<D>(deserializer: D) -> std::prelude::v1::Result<Self, D::Error>
     where
@@ -97,15 +133,25 @@ impl TorrentAddQueryParams {
             overwrite: self.overwrite.unwrap_or(false),
             only_files_regex: self.only_files_regex,
             only_files: self.only_files.map(|o| o.0),
+            file_priorities: self.file_priorities.map(|p| p.0),
             output_folder: self.output_folder,
             sub_folder: self.sub_folder,
             list_only: self.list_only.unwrap_or(false),
+            retire_previous_versions: self.retire_previous_versions.unwrap_or(false),
             initial_peers: self.initial_peers.map(|i| i.0),
             peer_opts: Some(PeerConnectionOptions {
                 connect_timeout: self.peer_connect_timeout.map(Duration::from_secs),
                 read_write_timeout: self.peer_read_write_timeout.map(Duration::from_secs),
                 ..Default::default()
             }),
+            seed_goals: SeedGoals {
+                max_ratio: self.max_ratio,
+                max_ratio_action: self.max_ratio_action,
+                max_seeding_time: self.max_seeding_time.map(Duration::from_secs),
+                max_seeding_time_action: self.max_seeding_time_action,
+                max_idle_time: self.max_idle_time.map(Duration::from_secs),
+                max_idle_time_action: self.max_idle_time_action,
+            },
             ..Default::default()
         }
     }
@@ -51,6 +51,7 @@ mod dht_utils;
 mod error;
 pub mod file_info;
 mod file_ops;
+mod file_priority;
 #[cfg(feature = "http-api")]
 pub mod http_api;
 #[cfg(feature = "http-api-client")]
@@ -61,10 +62,13 @@ mod ip_ranges;
 pub mod limits;
 mod listen;
 mod merge_streams;
+mod mse;
 mod peer_connection;
 mod peer_info_reader;
 mod piece_tracker;
 mod read_buf;
+mod scrape;
+mod seed_goals;
 mod session;
 mod session_persistence;
 pub mod session_stats;
@@ -81,26 +85,35 @@ pub mod upnp_server_adapter;
 mod vectored_traits;
 #[cfg(feature = "watch")]
 pub mod watch;
+mod web_seed;
 
 pub use error::{Error, Result};
+pub use file_priority::FilePriority;
 
 pub use api::Api;
 pub use api_error::{ApiError, WithStatus, WithStatusError};
-pub use create_torrent_file::{CreateTorrentOptions, CreateTorrentResult, create_torrent};
+pub use create_torrent_file::{
+    CreateTorrentOptions, CreateTorrentResult, TorrentVersion, create_torrent,
+};
 pub use dht;
 pub use librqbit_core::spawn_utils::spawn as librqbit_spawn;
 pub use listen::{ListenerMode, ListenerOptions};
+pub use mse::EncryptionPolicy;
 pub use peer_connection::PeerConnectionOptions;
+pub use seed_goals::{SeedGoalAction, SeedGoals, SeedingStats};
 pub use session::{
-    AddTorrent, AddTorrentOptions, AddTorrentResponse, DhtSessionConfig, ListOnlyResponse,
-    SUPPORTED_SCHEMES, Session, SessionOptions, SessionPersistenceConfig,
+    AddTorrent, AddTorrentOptions, AddTorrentResponse, BuiltinTrackerOptions, DhtSessionConfig,
+    ListOnlyResponse, QueueLimits, QueueMove, SUPPORTED_SCHEMES, Session, SessionOptions,
+    SessionPersistenceConfig,
 };
 pub use stream_connect::ConnectionOptions;
 pub use torrent_state::{
     ManagedTorrent, ManagedTorrentShared, ManagedTorrentState, TorrentMetadata, TorrentStats,
     TorrentStatsState,
 };
+pub use tracker_comms::{ScrapeStats, TrackerServer, TrackerStatus};
 pub use type_aliases::FileInfos;
+pub use web_seed::WebSeed;
 
 pub use buffers::*;
 pub use clone_to_owned::CloneToOwned;
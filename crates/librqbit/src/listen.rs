@@ -10,7 +10,9 @@ use tokio::io::AsyncWrite;
 use tokio_util::sync::CancellationToken;
 use tracing::info;
 
-use crate::{stream_connect::ConnectionKind, vectored_traits::AsyncReadVectored};
+use crate::{
+    mse::EncryptionPolicy, stream_connect::ConnectionKind, vectored_traits::AsyncReadVectored,
+};
 
 pub(crate) struct ListenResult {
     pub tcp_socket: Option<TcpListener>,
@@ -19,6 +21,7 @@ pub(crate) struct ListenResult {
     pub addr: SocketAddr,
     pub announce_port: Option<u16>,
     pub max_pending_incoming_handshake_checks: usize,
+    pub encryption: EncryptionPolicy,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -57,6 +60,8 @@ pub struct ListenerOptions {
     pub announce_port: Option<u16>,
     pub ipv4_only: bool,
     pub max_pending_incoming_handshake_checks: usize,
+    // Message Stream Encryption for incoming connections.
+    pub encryption: EncryptionPolicy,
 }
 
 impl Default for ListenerOptions {
@@ -70,6 +75,7 @@ impl Default for ListenerOptions {
             announce_port: None,
             ipv4_only: false,
             max_pending_incoming_handshake_checks: DEFAULT_MAX_PENDING_INCOMING_HANDSHAKE_CHECKS,
+            encryption: EncryptionPolicy::Disabled,
         }
     }
 }
@@ -160,6 +166,7 @@ impl ListenerOptions {
             addr: listen_addr,
             enable_upnp_port_forwarding: self.enable_upnp_port_forwarding,
             max_pending_incoming_handshake_checks: self.max_pending_incoming_handshake_checks,
+            encryption: self.encryption,
         })
     }
 }
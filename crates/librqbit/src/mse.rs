@@ -0,0 +1,783 @@
+// Message Stream Encryption (MSE), also known as Protocol Encryption (PE).
+//
+// Not a BEP, but implemented by all major clients. Described at
+// https://wiki.vuze.com/w/Message_Stream_Encryption
+//
+// The handshake (A is the initiator, B the responder):
+// 1. A->B: Ya, PadA
+// 2. B->A: Yb, PadB
+// 3. A->B: HASH('req1', S), HASH('req2', SKEY) xor HASH('req3', S),
+//          ENCRYPT(VC, crypto_provide, len(PadC), PadC, len(IA)), ENCRYPT(IA)
+// 4. B->A: ENCRYPT(VC, crypto_select, len(PadD), PadD), ENCRYPT2(payload stream)
+// 5. A->B: ENCRYPT2(payload stream)
+//
+// Once done, the streams are wrapped into MseReader / MseWriter, which plug
+// into the regular peer connection pipeline.
+
+use std::{
+    io::IoSliceMut,
+    pin::Pin,
+    sync::LazyLock,
+    task::{Poll, ready},
+};
+
+use librqbit_core::hash_id::Id20;
+use num_bigint::BigUint;
+use rand::{Rng, RngExt};
+use serde::{Deserialize, Serialize};
+use sha1w::{ISha1, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    type_aliases::{BoxAsyncReadVectored, BoxAsyncWrite},
+    vectored_traits::AsyncReadVectored,
+};
+
+const DH_PRIME_HEX: &[u8] = b"FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+const DH_GENERATOR: u32 = 2;
+const DH_KEY_LEN: usize = 96;
+const DH_PRIVATE_KEY_LEN: usize = 20;
+
+const MAX_PAD_LEN: usize = 512;
+const VC: [u8; 8] = [0; 8];
+const RC4_DISCARD_LEN: usize = 1024;
+
+const CRYPTO_PLAINTEXT: u32 = 0x01;
+const CRYPTO_RC4: u32 = 0x02;
+
+// How plaintext BitTorrent handshake starts. Used to detect unencrypted incoming connections.
+const PLAINTEXT_HANDSHAKE_PREFIX: &[u8; 20] = b"\x13BitTorrent protocol";
+
+static DH_PRIME: LazyLock<BigUint> =
+    LazyLock::new(|| BigUint::parse_bytes(DH_PRIME_HEX, 16).unwrap());
+
+/// Whether to use Message Stream Encryption (a.k.a. Protocol Encryption) for peer connections.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionPolicy {
+    /// Plaintext only, the MSE handshake is never attempted.
+    #[default]
+    Disabled,
+    /// Try encrypting outgoing connections, fall back to plaintext if the peer doesn't support it.
+    /// Accept both encrypted and plaintext incoming connections.
+    Preferred,
+    /// Only encrypted connections are allowed.
+    Required,
+}
+
+impl EncryptionPolicy {
+    fn crypto_provide(self) -> u32 {
+        match self {
+            EncryptionPolicy::Disabled => CRYPTO_PLAINTEXT,
+            EncryptionPolicy::Preferred => CRYPTO_PLAINTEXT | CRYPTO_RC4,
+            EncryptionPolicy::Required => CRYPTO_RC4,
+        }
+    }
+
+    fn crypto_select(self, provided: u32) -> Option<u32> {
+        let common = self.crypto_provide() & provided;
+        if common & CRYPTO_RC4 != 0 {
+            Some(CRYPTO_RC4)
+        } else if common & CRYPTO_PLAINTEXT != 0 {
+            Some(CRYPTO_PLAINTEXT)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MseError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("invalid Diffie-Hellman public key")]
+    InvalidPublicKey,
+    #[error("couldn't find the synchronization point in the stream")]
+    SyncNotFound,
+    #[error("peer requested an info hash we don't have")]
+    UnknownInfoHash,
+    #[error("invalid verification constant")]
+    InvalidVc,
+    #[error("invalid padding length {0}")]
+    InvalidPadLength(u16),
+    #[error("no common crypto method, peer provided {0:#x}")]
+    NoCommonCryptoMethod(u32),
+    #[error("peer selected crypto method {0:#x} which we didn't provide")]
+    InvalidCryptoSelect(u32),
+    #[error("plaintext connections are not allowed")]
+    PlaintextNotAllowed,
+}
+
+#[derive(Clone)]
+struct Rc4 {
+    s: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    fn new(key: &[u8]) -> Self {
+        let mut s = [0u8; 256];
+        for (v, i) in s.iter_mut().zip(0..=u8::MAX) {
+            *v = i;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(s[i]).wrapping_add(key[i % key.len()]);
+            s.swap(i, j as usize);
+        }
+        Self { s, i: 0, j: 0 }
+    }
+
+    // MSE discards the first 1024 bytes of the keystream.
+    fn new_mse(key: &[u8]) -> Self {
+        let mut rc4 = Self::new(key);
+        rc4.apply(&mut [0u8; RC4_DISCARD_LEN]);
+        rc4
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for b in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.s[self.i as usize]);
+            self.s.swap(self.i as usize, self.j as usize);
+            let k = self.s[self.s[self.i as usize].wrapping_add(self.s[self.j as usize]) as usize];
+            *b ^= k;
+        }
+    }
+}
+
+struct DhKeys {
+    private: BigUint,
+    public: [u8; DH_KEY_LEN],
+}
+
+fn to_dh_key_bytes(n: &BigUint) -> [u8; DH_KEY_LEN] {
+    let bytes = n.to_bytes_be();
+    let mut out = [0u8; DH_KEY_LEN];
+    out[DH_KEY_LEN - bytes.len()..].copy_from_slice(&bytes);
+    out
+}
+
+impl DhKeys {
+    fn generate() -> Self {
+        let mut private = [0u8; DH_PRIVATE_KEY_LEN];
+        rand::rng().fill_bytes(&mut private);
+        let private = BigUint::from_bytes_be(&private);
+        let public = BigUint::from(DH_GENERATOR).modpow(&private, &DH_PRIME);
+        Self {
+            private,
+            public: to_dh_key_bytes(&public),
+        }
+    }
+
+    fn shared_secret(
+        &self,
+        remote_public: &[u8; DH_KEY_LEN],
+    ) -> Result<[u8; DH_KEY_LEN], MseError> {
+        let remote = BigUint::from_bytes_be(remote_public);
+        let one = BigUint::from(1u32);
+        if remote <= one || remote >= &*DH_PRIME - &one {
+            return Err(MseError::InvalidPublicKey);
+        }
+        Ok(to_dh_key_bytes(&remote.modpow(&self.private, &DH_PRIME)))
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut h = Sha1::new();
+    for p in parts {
+        h.update(p);
+    }
+    h.finish()
+}
+
+fn obfuscated_info_hash(info_hash: &Id20, s: &[u8]) -> [u8; 20] {
+    let req2 = hash(&[b"req2", &info_hash.0]);
+    let req3 = hash(&[b"req3", s]);
+    std::array::from_fn(|i| req2[i] ^ req3[i])
+}
+
+fn random_pad() -> Vec<u8> {
+    let mut pad = vec![0u8; rand::rng().random_range(0..=MAX_PAD_LEN)];
+    rand::rng().fill_bytes(&mut pad);
+    pad
+}
+
+// Read until "pattern" is found no further than "max_offset" bytes into the stream.
+// Returns the bytes that were read past the pattern.
+async fn sync_on(
+    read: &mut BoxAsyncReadVectored,
+    pattern: &[u8],
+    max_offset: usize,
+) -> Result<Vec<u8>, MseError> {
+    let mut buf = Vec::new();
+    let mut tmp = [0u8; 1024];
+    loop {
+        if let Some(pos) = buf.windows(pattern.len()).position(|w| w == pattern) {
+            if pos > max_offset {
+                return Err(MseError::SyncNotFound);
+            }
+            return Ok(buf.split_off(pos + pattern.len()));
+        }
+        if buf.len() >= max_offset + pattern.len() {
+            return Err(MseError::SyncNotFound);
+        }
+        let n = read.read(&mut tmp).await?;
+        if n == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        buf.extend_from_slice(&tmp[..n]);
+    }
+}
+
+async fn read_pad(reader: &mut MseReader) -> Result<(), MseError> {
+    let len = reader.read_u16().await?;
+    if len as usize > MAX_PAD_LEN {
+        return Err(MseError::InvalidPadLength(len));
+    }
+    let mut pad = vec![0u8; len as usize];
+    reader.read_exact(&mut pad).await?;
+    Ok(())
+}
+
+/// Run the MSE handshake as the connecting side.
+pub(crate) async fn initiate(
+    mut read: BoxAsyncReadVectored,
+    mut write: BoxAsyncWrite,
+    info_hash: Id20,
+    policy: EncryptionPolicy,
+) -> Result<(BoxAsyncReadVectored, BoxAsyncWrite), MseError> {
+    let keys = DhKeys::generate();
+    let mut msg = keys.public.to_vec();
+    msg.extend_from_slice(&random_pad());
+    write.write_all(&msg).await?;
+
+    let mut yb = [0u8; DH_KEY_LEN];
+    read.read_exact(&mut yb).await?;
+    let s = keys.shared_secret(&yb)?;
+
+    let mut encrypt = Rc4::new_mse(&hash(&[b"keyA", &s, &info_hash.0]));
+    let mut decrypt = Rc4::new_mse(&hash(&[b"keyB", &s, &info_hash.0]));
+
+    let crypto_provide = policy.crypto_provide();
+    let mut msg = Vec::with_capacity(20 + 20 + 8 + 4 + 2 + 2);
+    msg.extend_from_slice(&hash(&[b"req1", &s]));
+    msg.extend_from_slice(&obfuscated_info_hash(&info_hash, &s));
+    let encrypted_start = msg.len();
+    msg.extend_from_slice(&VC);
+    msg.extend_from_slice(&crypto_provide.to_be_bytes());
+    // len(PadC)
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    // len(IA). We send the BitTorrent handshake afterwards as a regular payload.
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    encrypt.apply(&mut msg[encrypted_start..]);
+    write.write_all(&msg).await?;
+
+    // VC is all zeroes, so encrypted VC is just the next 8 bytes of B's keystream.
+    let mut encrypted_vc = VC;
+    decrypt.apply(&mut encrypted_vc);
+    let raw = sync_on(&mut read, &encrypted_vc, MAX_PAD_LEN).await?;
+
+    let mut reader = MseReader {
+        inner: read,
+        rc4: Some(decrypt),
+        plain: Vec::new(),
+        raw,
+    };
+    let crypto_select = reader.read_u32().await?;
+    if crypto_select.count_ones() != 1 || crypto_select & crypto_provide == 0 {
+        return Err(MseError::InvalidCryptoSelect(crypto_select));
+    }
+    read_pad(&mut reader).await?;
+
+    let writer = if crypto_select == CRYPTO_RC4 {
+        MseWriter::new(write, Some(encrypt))
+    } else {
+        reader.rc4 = None;
+        MseWriter::new(write, None)
+    };
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+/// Run the MSE handshake as the accepting side.
+///
+/// Plaintext BitTorrent handshakes are detected and let through (unless the policy requires
+/// encryption), in which case the returned reader will yield the already consumed bytes first.
+pub(crate) async fn accept(
+    mut read: BoxAsyncReadVectored,
+    mut write: BoxAsyncWrite,
+    policy: EncryptionPolicy,
+    info_hashes: &[Id20],
+) -> Result<(BoxAsyncReadVectored, BoxAsyncWrite), MseError> {
+    if policy == EncryptionPolicy::Disabled {
+        return Ok((read, write));
+    }
+
+    let mut ya = [0u8; DH_KEY_LEN];
+    let prefix_len = PLAINTEXT_HANDSHAKE_PREFIX.len();
+    read.read_exact(&mut ya[..prefix_len]).await?;
+    if ya[..prefix_len] == PLAINTEXT_HANDSHAKE_PREFIX[..] {
+        if policy == EncryptionPolicy::Required {
+            return Err(MseError::PlaintextNotAllowed);
+        }
+        let reader = MseReader {
+            inner: read,
+            rc4: None,
+            plain: ya[..prefix_len].to_vec(),
+            raw: Vec::new(),
+        };
+        return Ok((Box::new(reader), write));
+    }
+    read.read_exact(&mut ya[prefix_len..]).await?;
+
+    let keys = DhKeys::generate();
+    let s = keys.shared_secret(&ya)?;
+    let mut msg = keys.public.to_vec();
+    msg.extend_from_slice(&random_pad());
+    write.write_all(&msg).await?;
+
+    let mut rest = sync_on(&mut read, &hash(&[b"req1", &s]), MAX_PAD_LEN).await?;
+    while rest.len() < 20 {
+        let mut tmp = [0u8; 20];
+        let n = read.read(&mut tmp).await?;
+        if n == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        rest.extend_from_slice(&tmp[..n]);
+    }
+    let raw = rest.split_off(20);
+    let info_hash = info_hashes
+        .iter()
+        .find(|ih| obfuscated_info_hash(ih, &s)[..] == rest[..])
+        .ok_or(MseError::UnknownInfoHash)?;
+
+    let decrypt = Rc4::new_mse(&hash(&[b"keyA", &s, &info_hash.0]));
+    let mut encrypt = Rc4::new_mse(&hash(&[b"keyB", &s, &info_hash.0]));
+
+    let mut reader = MseReader {
+        inner: read,
+        rc4: Some(decrypt),
+        plain: Vec::new(),
+        raw,
+    };
+    let mut vc = [0u8; 8];
+    reader.read_exact(&mut vc).await?;
+    if vc != VC {
+        return Err(MseError::InvalidVc);
+    }
+    let crypto_provide = reader.read_u32().await?;
+    read_pad(&mut reader).await?;
+    let ia_len = reader.read_u16().await?;
+    let mut ia = vec![0u8; ia_len as usize];
+    reader.read_exact(&mut ia).await?;
+
+    let crypto_select = policy
+        .crypto_select(crypto_provide)
+        .ok_or(MseError::NoCommonCryptoMethod(crypto_provide))?;
+
+    let mut msg = Vec::with_capacity(8 + 4 + 2);
+    msg.extend_from_slice(&VC);
+    msg.extend_from_slice(&crypto_select.to_be_bytes());
+    // len(PadD)
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    encrypt.apply(&mut msg);
+    write.write_all(&msg).await?;
+
+    reader.plain = ia;
+    let writer = if crypto_select == CRYPTO_RC4 {
+        MseWriter::new(write, Some(encrypt))
+    } else {
+        reader.rc4 = None;
+        MseWriter::new(write, None)
+    };
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+pub(crate) struct MseReader {
+    inner: BoxAsyncReadVectored,
+    rc4: Option<Rc4>,
+    // Already decrypted bytes, e.g. the initial payload or a sniffed plaintext handshake.
+    plain: Vec<u8>,
+    // Bytes over-read from the wire during the handshake, not decrypted yet.
+    raw: Vec<u8>,
+}
+
+impl MseReader {
+    fn read_buffered(&mut self, out: &mut [u8]) -> Option<usize> {
+        if !self.plain.is_empty() {
+            let n = out.len().min(self.plain.len());
+            out[..n].copy_from_slice(&self.plain[..n]);
+            self.plain.drain(..n);
+            return Some(n);
+        }
+        if !self.raw.is_empty() {
+            let n = out.len().min(self.raw.len());
+            out[..n].copy_from_slice(&self.raw[..n]);
+            self.raw.drain(..n);
+            if let Some(rc4) = self.rc4.as_mut() {
+                rc4.apply(&mut out[..n]);
+            }
+            return Some(n);
+        }
+        None
+    }
+}
+
+impl AsyncRead for MseReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(n) = this.read_buffered(buf.initialize_unfilled()) {
+            buf.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(rc4) = this.rc4.as_mut() {
+            rc4.apply(&mut buf.filled_mut()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncReadVectored for MseReader {
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        vec: &mut [IoSliceMut<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.plain.is_empty() || !this.raw.is_empty() {
+            let n = match vec.iter_mut().find(|s| !s.is_empty()) {
+                Some(s) => this.read_buffered(s).unwrap_or(0),
+                None => 0,
+            };
+            return Poll::Ready(Ok(n));
+        }
+        let rc4 = match this.rc4.as_mut() {
+            Some(rc4) => rc4,
+            None => return Pin::new(&mut this.inner).poll_read_vectored(cx, vec),
+        };
+        // Some readers (e.g. uTP) advance the slices they were given, so pass them reborrowed copies
+        // to know where to decrypt. The read buffer never uses more than 2 slices.
+        let n = {
+            let mut reborrowed = [IoSliceMut::new(&mut []), IoSliceMut::new(&mut [])];
+            for (dst, src) in reborrowed.iter_mut().zip(vec.iter_mut()) {
+                *dst = IoSliceMut::new(src);
+            }
+            ready!(Pin::new(&mut this.inner).poll_read_vectored(cx, &mut reborrowed))?
+        };
+        let mut remaining = n;
+        for s in vec.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            let len = remaining.min(s.len());
+            rc4.apply(&mut s[..len]);
+            remaining -= len;
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+pub(crate) struct MseWriter {
+    inner: BoxAsyncWrite,
+    rc4: Option<Rc4>,
+    // Scratch space for the encrypted copy of the caller's buffer.
+    encrypted: Vec<u8>,
+}
+
+impl MseWriter {
+    fn new(inner: BoxAsyncWrite, rc4: Option<Rc4>) -> Self {
+        Self {
+            inner,
+            rc4,
+            encrypted: Vec::new(),
+        }
+    }
+}
+
+impl AsyncWrite for MseWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let rc4 = match this.rc4.as_mut() {
+            Some(rc4) => rc4,
+            None => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        // The keystream can't be rewound, so encrypt with a copy of it and only advance the
+        // real one by what the socket accepted. Whatever wasn't written is encrypted again
+        // on the next call, whatever buffer the caller passes then.
+        let mut ahead = rc4.clone();
+        this.encrypted.clear();
+        this.encrypted.extend_from_slice(buf);
+        ahead.apply(&mut this.encrypted);
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.encrypted))?;
+        if n == buf.len() {
+            *rc4 = ahead;
+        } else {
+            rc4.apply(&mut this.encrypted[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::IoSliceMut, pin::Pin, sync::Arc, task::Poll};
+
+    use librqbit_core::hash_id::Id20;
+    use parking_lot::Mutex;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    use crate::{
+        type_aliases::{BoxAsyncReadVectored, BoxAsyncWrite},
+        vectored_traits::{AsyncReadVectored, AsyncReadVectoredExt, AsyncReadVectoredIntoCompat},
+    };
+
+    use super::{EncryptionPolicy, MseError, MseReader, MseWriter, Rc4, accept, initiate};
+
+    fn pipe() -> (
+        (BoxAsyncReadVectored, BoxAsyncWrite),
+        (BoxAsyncReadVectored, BoxAsyncWrite),
+    ) {
+        let (a, b) = tokio::io::duplex(4096);
+        let (ar, aw) = tokio::io::split(a);
+        let (br, bw) = tokio::io::split(b);
+        (
+            (Box::new(ar.into_vectored_compat()), Box::new(aw)),
+            (Box::new(br.into_vectored_compat()), Box::new(bw)),
+        )
+    }
+
+    #[test]
+    fn test_rc4_known_vector() {
+        let mut data = *b"Plaintext";
+        Rc4::new(b"Key").apply(&mut data);
+        assert_eq!(data, [0xbb, 0xf3, 0x16, 0xe8, 0xd9, 0x40, 0xaf, 0x0a, 0xd3]);
+    }
+
+    async fn roundtrip(
+        initiator: EncryptionPolicy,
+        responder: EncryptionPolicy,
+    ) -> Result<(), MseError> {
+        let info_hash = Id20::new([7; 20]);
+        let known = [Id20::new([1; 20]), info_hash];
+        let ((ar, aw), (br, bw)) = pipe();
+        let (a, b) = tokio::join!(
+            initiate(ar, aw, info_hash, initiator),
+            accept(br, bw, responder, &known),
+        );
+        let ((mut ar, mut aw), (mut br, mut bw)) = (a?, b?);
+
+        let payload = (0..=u8::MAX).cycle().take(10000).collect::<Vec<_>>();
+        let (_, _, a_received, b_received) = tokio::join!(
+            async { aw.write_all(&payload).await.unwrap() },
+            async { bw.write_all(&payload).await.unwrap() },
+            async {
+                let mut buf = vec![0u8; payload.len()];
+                ar.read_exact(&mut buf).await.unwrap();
+                buf
+            },
+            async {
+                let mut buf = vec![0u8; payload.len()];
+                br.read_exact(&mut buf).await.unwrap();
+                buf
+            },
+        );
+        assert_eq!(a_received, payload);
+        assert_eq!(b_received, payload);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mse_roundtrip() {
+        use EncryptionPolicy::*;
+        roundtrip(Required, Required).await.unwrap();
+        roundtrip(Preferred, Required).await.unwrap();
+        roundtrip(Required, Preferred).await.unwrap();
+        roundtrip(Preferred, Preferred).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mse_unknown_info_hash() {
+        let ((ar, aw), (br, bw)) = pipe();
+        let (a, b) = tokio::join!(
+            initiate(ar, aw, Id20::new([7; 20]), EncryptionPolicy::Required),
+            // The streams are dropped on error, so the initiator doesn't wait forever.
+            async { accept(br, bw, EncryptionPolicy::Required, &[Id20::new([1; 20])]).await },
+        );
+        assert!(matches!(b, Err(MseError::UnknownInfoHash)));
+        assert!(a.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mse_accept_plaintext() {
+        let mut handshake = b"\x13BitTorrent protocol".to_vec();
+        handshake.extend_from_slice(&[0u8; 48]);
+
+        let ((_, mut aw), (br, bw)) = pipe();
+        aw.write_all(&handshake).await.unwrap();
+        let (mut br, _) = accept(br, bw, EncryptionPolicy::Preferred, &[])
+            .await
+            .unwrap();
+        let mut received = vec![0u8; handshake.len()];
+        br.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, handshake);
+
+        let ((_, mut aw), (br, bw)) = pipe();
+        aw.write_all(&handshake).await.unwrap();
+        assert!(matches!(
+            accept(br, bw, EncryptionPolicy::Required, &[]).await,
+            Err(MseError::PlaintextNotAllowed)
+        ));
+    }
+
+    // Accepts at most "limit" bytes per write, and every other write is Pending.
+    struct ChoppyWriter {
+        written: Arc<Mutex<Vec<u8>>>,
+        limit: usize,
+        pending: bool,
+    }
+
+    impl AsyncWrite for ChoppyWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            this.pending = !this.pending;
+            if this.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = buf.len().min(this.limit);
+            this.written.lock().extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mse_writer_partial_writes() {
+        let plaintext = (0..=u8::MAX).cycle().take(1000).collect::<Vec<_>>();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = MseWriter::new(
+            Box::new(ChoppyWriter {
+                written: written.clone(),
+                limit: 7,
+                pending: false,
+            }),
+            Some(Rc4::new_mse(b"key")),
+        );
+
+        // A write left pending doesn't have to be retried with the same buffer.
+        let abandoned = std::future::poll_fn(|cx| {
+            Poll::Ready(Pin::new(&mut writer).poll_write(cx, b"abandoned"))
+        })
+        .await;
+        assert!(abandoned.is_pending());
+        writer.write_all(&plaintext).await.unwrap();
+
+        let mut decrypted = written.lock().clone();
+        Rc4::new_mse(b"key").apply(&mut decrypted);
+        assert_eq!(decrypted, plaintext);
+    }
+
+    // Mimics uTP, which advances the slices it reads into.
+    struct AdvancingReader(Vec<u8>);
+
+    impl AsyncRead for AdvancingReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            unreachable!("only vectored reads are used")
+        }
+    }
+
+    impl AsyncReadVectored for AdvancingReader {
+        fn poll_read_vectored(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            mut vec: &mut [IoSliceMut<'_>],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            let mut written = 0;
+            while let Some(s) = vec.first_mut() {
+                let len = s.len().min(this.0.len());
+                if len == 0 {
+                    break;
+                }
+                s[..len].copy_from_slice(&this.0[..len]);
+                this.0.drain(..len);
+                s.advance(len);
+                written += len;
+                if s.is_empty() {
+                    vec = &mut vec[1..];
+                }
+            }
+            Poll::Ready(Ok(written))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mse_reader_decrypts_vectored_reads() {
+        let plaintext = (0..=u8::MAX).cycle().take(100).collect::<Vec<_>>();
+        let mut encrypted = plaintext.clone();
+        Rc4::new_mse(b"key").apply(&mut encrypted);
+
+        let mut reader = MseReader {
+            inner: Box::new(AdvancingReader(encrypted)),
+            rc4: Some(Rc4::new_mse(b"key")),
+            plain: Vec::new(),
+            raw: Vec::new(),
+        };
+        let mut first = [0u8; 30];
+        let mut second = [0u8; 70];
+        let n = reader
+            .read_vectored(&mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)])
+            .await
+            .unwrap();
+        assert_eq!(n, 100);
+        assert_eq!(first[..], plaintext[..30]);
+        assert_eq!(second[..], plaintext[30..]);
+    }
+}
@@ -14,7 +14,7 @@ use librqbit_core::{
 };
 use parking_lot::RwLock;
 use peer_binary_protocol::{
-    Handshake, MAX_MSG_LEN, Message,
+    Handshake, Hashes, MAX_MSG_LEN, Message, Request,
     extended::{
         ExtendedMessage, PeerExtendedMessageIds, handshake::ExtendedHandshake,
         ut_metadata::UtMetadata, ut_pex::UtPex,
@@ -46,6 +46,11 @@ pub trait PeerConnectionHandler {
     fn should_transmit_have(&self, id: ValidPieceIndex) -> bool;
     fn on_uploaded_bytes(&self, bytes: u32);
     fn read_chunk(&self, chunk: &ChunkInfo, buf: &mut [u8]) -> anyhow::Result<()>;
+    // Whether a queued chunk may still be sent, e.g. the peer might have been choked since it
+    // requested it.
+    fn may_upload(&self, _chunk: &ChunkInfo) -> bool {
+        true
+    }
     fn update_my_extended_handshake(
         &self,
         _handshake: &mut ExtendedHandshake<ByteBuf>,
@@ -55,6 +60,10 @@ pub trait PeerConnectionHandler {
     fn client_name_and_version(&self) -> &str {
         crate::client_name_and_version()
     }
+    // Set the BEP 52 bit in our handshake.
+    fn advertise_v2(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -62,6 +71,7 @@ pub enum WriterRequest {
     Message(Message<'static>),
     UtMetadata(UtMetadata<ByteBufOwned>),
     UtPex(UtPex<ByteBufOwned>),
+    Hashes(Hashes<ByteBufOwned>),
     ReadChunkRequest(ChunkInfo),
     Disconnect(anyhow::Result<()>),
 }
@@ -112,6 +122,7 @@ pub(crate) async fn with_timeout<T>(
 
 struct ManagePeerArgs {
     handshake_supports_extended: bool,
+    handshake_supports_fast: bool,
     read_buf: ReadBuf,
     write_buf: Box<[u8; MAX_MSG_LEN]>,
     read: BoxAsyncReadVectored,
@@ -170,7 +181,10 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
         );
 
         let mut write_buf = Box::new([0u8; MAX_MSG_LEN]);
-        let handshake = Handshake::new(self.info_hash, self.peer_id);
+        let mut handshake = Handshake::new(self.info_hash, self.peer_id);
+        if self.handler.advertise_v2() {
+            handshake = handshake.with_v2();
+        }
         let hlen = handshake.serialize_unchecked_len(&mut *write_buf);
         with_timeout(
             "writing handshake",
@@ -183,13 +197,15 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
         .await?;
 
         let handshake_supports_extended = handshake.supports_extended();
+        let handshake_supports_fast = incoming.handshake.supports_fast();
 
         self.handler
-            .on_handshake(handshake, incoming.kind)
+            .on_handshake(incoming.handshake, incoming.kind)
             .map_err(Error::Anyhow)?;
 
         self.manage_peer(ManagePeerArgs {
             handshake_supports_extended,
+            handshake_supports_fast,
             read_buf: incoming.read_buf,
             write_buf,
             read: incoming.reader,
@@ -220,7 +236,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
         let (ckind, mut read, mut write) = with_timeout(
             "connecting",
             connect_timeout,
-            self.connector.connect(self.addr),
+            self.connector.connect(self.addr, self.info_hash),
         )
         .await?;
 
@@ -228,7 +244,10 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
             self.handler.on_connected(now.elapsed());
 
             let mut write_buf = Box::new([0u8; MAX_MSG_LEN]);
-            let handshake = Handshake::new(self.info_hash, self.peer_id);
+            let mut handshake = Handshake::new(self.info_hash, self.peer_id);
+            if self.handler.advertise_v2() {
+                handshake = handshake.with_v2();
+            }
             let hsz = handshake.serialize_unchecked_len(&mut *write_buf);
             with_timeout(
                 "writing",
@@ -242,6 +261,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
             let mut read_buf = ReadBuf::new();
             let h = read_buf.read_handshake(&mut read, rwtimeout).await?;
             let handshake_supports_extended = h.supports_extended();
+            let handshake_supports_fast = h.supports_fast();
             trace!(
                 peer_id=?h.peer_id,
                 decoded_id=?try_decode_peer_id(h.peer_id),
@@ -259,6 +279,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
 
             self.manage_peer(ManagePeerArgs {
                 handshake_supports_extended,
+                handshake_supports_fast,
                 read_buf,
                 write_buf,
                 read,
@@ -275,6 +296,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
     async fn manage_peer(&self, args: ManagePeerArgs) -> Result<()> {
         let ManagePeerArgs {
             handshake_supports_extended,
+            handshake_supports_fast,
             mut read_buf,
             mut write_buf,
             mut read,
@@ -294,6 +316,31 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
         let extended_handshake_ref = &extended_handshake;
         let supports_extended = handshake_supports_extended;
 
+        if self.handler.should_send_bitfield() {
+            let len = self
+                .handler
+                .serialize_bitfield_message_to_buf(&mut *write_buf)
+                .map_err(Error::Anyhow)?;
+            with_timeout(
+                "writing bitfield",
+                rwtimeout,
+                write.write_all(&write_buf[..len]).map_err(Error::Write),
+            )
+            .await?;
+            trace!("sent bitfield");
+        } else if handshake_supports_fast {
+            // BEP 6: with the fast extension one of bitfield, have all or have none
+            // MUST be the first message, so it goes before the extended handshake too.
+            let len = Message::HaveNone.serialize(&mut *write_buf, &Default::default)?;
+            with_timeout(
+                "writing have none",
+                rwtimeout,
+                write.write_all(&write_buf[..len]).map_err(Error::Write),
+            )
+            .await?;
+            trace!("sent have none");
+        }
+
         if supports_extended {
             let mut my_extended = ExtendedHandshake::new();
             my_extended.v = Some(ByteBuf(self.handler.client_name_and_version().as_bytes()));
@@ -318,29 +365,6 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
                 .keep_alive_interval
                 .unwrap_or_else(|| Duration::from_secs(120));
 
-            if self.handler.should_send_bitfield() {
-                let len = self
-                    .handler
-                    .serialize_bitfield_message_to_buf(&mut *write_buf)
-                    .map_err(Error::Anyhow)?;
-                with_timeout(
-                    "writing bitfield",
-                    rwtimeout,
-                    write.write_all(&write_buf[..len]).map_err(Error::Write),
-                )
-                .await?;
-                trace!("sent bitfield");
-            }
-
-            let len = Message::Unchoke.serialize(&mut *write_buf, &Default::default)?;
-            with_timeout(
-                "writing",
-                rwtimeout,
-                write.write_all(&write_buf[..len]).map_err(Error::Write),
-            )
-            .await?;
-            trace!("sent unchoke");
-
             let mut broadcast_closed = false;
 
             loop {
@@ -394,6 +418,19 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
                         Message::Extended(ExtendedMessage::UtPex(ut_pex.as_borrowed()))
                             .serialize(&mut *write_buf, ext_msg_ids)?
                     }
+                    WriterRequest::Hashes(hashes) => Message::Hashes(hashes.as_borrowed())
+                        .serialize(&mut *write_buf, ext_msg_ids)?,
+                    WriterRequest::ReadChunkRequest(chunk) if !self.handler.may_upload(&chunk) => {
+                        // Choking discards the peer's requests. BEP 6 requires rejecting them.
+                        if !handshake_supports_fast {
+                            trace!(?chunk, "dropping request of a choked peer");
+                            continue;
+                        }
+                        trace!(?chunk, "rejecting request of a choked peer");
+                        let request =
+                            Request::new(chunk.piece_index.get(), chunk.offset, chunk.size);
+                        Message::RejectRequest(request).serialize(&mut *write_buf, ext_msg_ids)?
+                    }
                     WriterRequest::ReadChunkRequest(chunk) => {
                         #[allow(unused_mut)]
                         let mut skip_reading_for_e2e_tests = false;
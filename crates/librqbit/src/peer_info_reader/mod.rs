@@ -1,24 +1,33 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
+use anyhow::Context;
 use bencode::from_bytes;
 use buffers::{ByteBuf, ByteBufOwned};
 use bytes::Bytes;
 use librqbit_core::{
     constants::CHUNK_SIZE,
-    hash_id::Id20,
+    hash_id::{Id20, Id32},
     lengths::{ChunkInfo, last_element_size},
+    merkle::{MAX_HASHES_PER_REQUEST, pad_hash, piece_layer_index, verify_hashes},
     torrent_metainfo::TorrentMetaV1Info,
 };
 use parking_lot::{Mutex, RwLock};
 use peer_binary_protocol::{
-    Handshake, Message,
+    Handshake, HashRequest, Hashes, Message,
     extended::{
         ExtendedMessage,
         handshake::ExtendedHandshake,
         ut_metadata::{UtMetadata, UtMetadataData},
     },
 };
-use sha1w::{ISha1, Sha1};
+use sha1w::{ISha1, ISha256, Sha1, Sha256};
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::trace;
 
@@ -30,25 +39,29 @@ use crate::{
     stream_connect::{ConnectionKind, StreamConnector},
 };
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn read_metainfo_from_peer(
     addr: SocketAddr,
     peer_id: Id20,
     info_hash: Id20,
+    info_hash_v2: Option<Id32>,
     peer_connection_options: Option<PeerConnectionOptions>,
     spawner: BlockingSpawner,
     connector: Arc<StreamConnector>,
     client_name_and_version: String,
 ) -> anyhow::Result<TorrentAndInfoBytes> {
-    let (result_tx, result_rx) = tokio::sync::oneshot::channel::<
-        Result<(TorrentMetaV1Info<ByteBufOwned>, ByteBufOwned), bencode::DeserializeError>,
-    >();
+    let (result_tx, result_rx) =
+        tokio::sync::oneshot::channel::<Result<TorrentAndInfoBytes, bencode::DeserializeError>>();
     let (writer_tx, writer_rx) = tokio::sync::mpsc::unbounded_channel::<WriterRequest>();
     let handler = Handler {
         addr,
         info_hash,
+        info_hash_v2,
         writer_tx,
         result_tx: Mutex::new(Some(result_tx)),
         locked: RwLock::new(None),
+        peer_supports_v2: AtomicBool::new(false),
+        pending_layers: Mutex::new(None),
         client_name_and_version,
     };
     let connection = PeerConnection::new(
@@ -112,6 +125,7 @@ impl HandlerLocked {
         &mut self,
         d: &UtMetadataData<ByteBuf>,
         info_hash: &Id20,
+        info_hash_v2: Option<&Id32>,
     ) -> anyhow::Result<bool> {
         let piece = d.piece();
         if piece as usize >= self.total_pieces {
@@ -135,9 +149,19 @@ impl HandlerLocked {
 
         if self.received_pieces.iter().all(|p| *p) {
             // check metadata
-            let mut hash = Sha1::new();
-            hash.update(&self.buffer);
-            if hash.finish() != info_hash.0 {
+            let valid = match info_hash_v2 {
+                Some(info_hash_v2) => {
+                    let mut hash = Sha256::new();
+                    hash.update(&self.buffer);
+                    hash.finish() == info_hash_v2.0
+                }
+                None => {
+                    let mut hash = Sha1::new();
+                    hash.update(&self.buffer);
+                    hash.finish() == info_hash.0
+                }
+            };
+            if !valid {
                 anyhow::bail!("info checksum invalid");
             }
             Ok(true)
@@ -147,11 +171,132 @@ impl HandlerLocked {
     }
 }
 
-pub type TorrentAndInfoBytes = (TorrentMetaV1Info<ByteBufOwned>, ByteBufOwned);
+/// The "piece layers" of a v2 torrent, by pieces root. Not verified yet.
+pub type RawPieceLayers = BTreeMap<ByteBufOwned, ByteBufOwned>;
+
+pub type TorrentAndInfoBytes = (
+    TorrentMetaV1Info<ByteBufOwned>,
+    ByteBufOwned,
+    Option<RawPieceLayers>,
+);
+
+// v2-only torrents can't be verified without piece layers, so they are requested from
+// the same peer once the metadata is received. Each response comes with the uncle hashes
+// proving it against the pieces root, so a peer sending bad hashes is dropped early.
+struct PendingPieceLayers {
+    info: TorrentMetaV1Info<ByteBufOwned>,
+    info_bytes: ByteBufOwned,
+    base_layer: u32,
+    // pieces root -> (number of pieces, layer being assembled)
+    layers: BTreeMap<Id32, (u32, Vec<u8>)>,
+    outstanding: HashSet<(Id32, u32)>,
+}
+
+impl PendingPieceLayers {
+    fn new(
+        info: TorrentMetaV1Info<ByteBufOwned>,
+        info_bytes: ByteBufOwned,
+    ) -> anyhow::Result<(Self, Vec<HashRequest>)> {
+        let piece_length = info.piece_length;
+        if piece_length == 0 || !piece_length.is_power_of_two() {
+            anyhow::bail!("invalid v2 piece length {piece_length}");
+        }
+        let base_layer = piece_layer_index(piece_length);
+        let mut layers = BTreeMap::new();
+        let mut requests = Vec::new();
+        for file in info.file_tree.iter().flat_map(|t| t.0.iter()) {
+            let pieces_root = match (&file.pieces_root, file.length > piece_length as u64) {
+                (Some(r), true) => Id32::from_bytes(r.as_ref())?,
+                _ => continue,
+            };
+            let pieces: u32 = file.length.div_ceil(piece_length as u64).try_into()?;
+            if layers
+                .insert(pieces_root, (pieces, vec![0u8; pieces as usize * 32]))
+                .is_some()
+            {
+                continue;
+            }
+            let width = pieces.next_power_of_two();
+            let length = width.min(MAX_HASHES_PER_REQUEST);
+            for index in (0..pieces).step_by(length as usize) {
+                requests.push(HashRequest {
+                    pieces_root,
+                    base_layer,
+                    index,
+                    length,
+                    proof_layers: (width / length).trailing_zeros(),
+                });
+            }
+        }
+        let outstanding = requests.iter().map(|r| (r.pieces_root, r.index)).collect();
+        Ok((
+            Self {
+                info,
+                info_bytes,
+                base_layer,
+                layers,
+                outstanding,
+            },
+            requests,
+        ))
+    }
+
+    fn record_hashes(&mut self, hashes: &Hashes<ByteBuf>) -> anyhow::Result<bool> {
+        let r = &hashes.request;
+        if r.base_layer != self.base_layer || !self.outstanding.remove(&(r.pieces_root, r.index)) {
+            anyhow::bail!("received unrequested hashes");
+        }
+        let (pieces, layer) = self
+            .layers
+            .get_mut(&r.pieces_root)
+            .context("received hashes for unknown file")?;
+        let width = pieces.next_power_of_two() as usize;
+        let length = r.length as usize;
+        let start = r.index as usize;
+        if length == 0 || length > width {
+            anyhow::bail!("received hashes of a wrong length");
+        }
+        let proof_layers = (width / length).trailing_zeros() as usize;
+        let received: Vec<Id32> = hashes.iter_hashes().collect();
+        if received.len() < length + proof_layers {
+            anyhow::bail!("received too few hashes");
+        }
+        let (received, proofs) = received.split_at(length);
+        if !verify_hashes(
+            &r.pieces_root,
+            received,
+            &proofs[..proof_layers],
+            start,
+            width,
+            pad_hash(self.base_layer),
+        ) {
+            anyhow::bail!("received hashes don't match the pieces root");
+        }
+        for (i, h) in received
+            .iter()
+            .take((*pieces as usize - start).min(length))
+            .enumerate()
+        {
+            let offset = (start + i) * 32;
+            layer[offset..offset + 32].copy_from_slice(&h.0);
+        }
+        Ok(self.outstanding.is_empty())
+    }
+
+    fn into_result(self) -> TorrentAndInfoBytes {
+        let layers = self
+            .layers
+            .into_iter()
+            .map(|(root, (_, layer))| (ByteBufOwned::from(&root.0[..]), ByteBufOwned::from(layer)))
+            .collect();
+        (self.info, self.info_bytes, Some(layers))
+    }
+}
 
 struct Handler {
     addr: SocketAddr,
     info_hash: Id20,
+    info_hash_v2: Option<Id32>,
     writer_tx: UnboundedSender<WriterRequest>,
     result_tx: Mutex<
         Option<
@@ -159,9 +304,48 @@ struct Handler {
         >,
     >,
     locked: RwLock<Option<HandlerLocked>>,
+    peer_supports_v2: AtomicBool,
+    pending_layers: Mutex<Option<PendingPieceLayers>>,
     client_name_and_version: String,
 }
 
+impl Handler {
+    fn send_result(
+        &self,
+        result: Result<TorrentAndInfoBytes, bencode::DeserializeError>,
+    ) -> anyhow::Result<()> {
+        self.result_tx
+            .lock()
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("oneshot is consumed"))?
+            .send(result)
+            .map_err(|_| anyhow::anyhow!("torrent info deserialized, but consumer closed"))
+    }
+
+    fn on_metadata(
+        &self,
+        info: TorrentMetaV1Info<ByteBufOwned>,
+        info_bytes: ByteBufOwned,
+    ) -> anyhow::Result<()> {
+        if info.has_v1() || !info.has_v2() {
+            return self.send_result(Ok((info, info_bytes, None)));
+        }
+        let (pending, requests) = PendingPieceLayers::new(info, info_bytes)?;
+        if requests.is_empty() {
+            return self.send_result(Ok(pending.into_result()));
+        }
+        if !self.peer_supports_v2.load(Ordering::Relaxed) {
+            anyhow::bail!("peer can't send piece layers of a v2-only torrent");
+        }
+        *self.pending_layers.lock() = Some(pending);
+        for r in requests {
+            self.writer_tx
+                .send(WriterRequest::Message(Message::HashRequest(r)))?;
+        }
+        Ok(())
+    }
+}
+
 impl PeerConnectionHandler for Handler {
     fn should_send_bitfield(&self) -> bool {
         false
@@ -172,6 +356,8 @@ impl PeerConnectionHandler for Handler {
     }
 
     fn on_handshake(&self, handshake: Handshake, _kind: ConnectionKind) -> anyhow::Result<()> {
+        self.peer_supports_v2
+            .store(handshake.supports_v2(), Ordering::Relaxed);
         if !handshake.supports_extended() {
             anyhow::bail!(
                 "this peer does not support extended handshaking, which is a prerequisite to download metadata"
@@ -183,35 +369,43 @@ impl PeerConnectionHandler for Handler {
     async fn on_received_message(&self, msg: Message<'_>) -> anyhow::Result<()> {
         trace!("{}: received message: {:?}", self.addr, msg);
 
-        if let Message::Extended(ExtendedMessage::UtMetadata(UtMetadata::Data(utdata))) = msg {
-            let piece_ready = self
-                .locked
-                .write()
-                .as_mut()
-                .unwrap()
-                .record_piece(&utdata, &self.info_hash)?;
-            if piece_ready {
-                let buf = Bytes::from(self.locked.write().take().unwrap().buffer);
-                let info = from_bytes::<TorrentMetaV1Info<ByteBuf>>(&buf)
-                    .map(|i| {
-                        use clone_to_owned::CloneToOwned;
-                        i.clone_to_owned(Some(&buf))
-                    })
-                    .map_err(|e| {
-                        trace!("error deserializing TorrentMetaV1Info: {e:#}");
-                        e.into_kind()
-                    })
-                    .map(|i| (i, ByteBufOwned(buf)));
-
-                self.result_tx
-                    .lock()
-                    .take()
-                    .ok_or_else(|| anyhow::anyhow!("oneshot is consumed"))?
-                    .send(info)
-                    .map_err(|_| {
-                        anyhow::anyhow!("torrent info deserialized, but consumer closed")
-                    })?;
+        match msg {
+            Message::Extended(ExtendedMessage::UtMetadata(UtMetadata::Data(utdata))) => {
+                let piece_ready = self.locked.write().as_mut().unwrap().record_piece(
+                    &utdata,
+                    &self.info_hash,
+                    self.info_hash_v2.as_ref(),
+                )?;
+                if piece_ready {
+                    let buf = Bytes::from(self.locked.write().take().unwrap().buffer);
+                    let info = from_bytes::<TorrentMetaV1Info<ByteBuf>>(&buf)
+                        .map(|i| {
+                            use clone_to_owned::CloneToOwned;
+                            i.clone_to_owned(Some(&buf))
+                        })
+                        .map_err(|e| {
+                            trace!("error deserializing TorrentMetaV1Info: {e:#}");
+                            e.into_kind()
+                        });
+                    match info {
+                        Ok(info) => self.on_metadata(info, ByteBufOwned(buf))?,
+                        Err(e) => self.send_result(Err(e))?,
+                    }
+                }
+            }
+            Message::Hashes(hashes) => {
+                let mut g = self.pending_layers.lock();
+                let pending = g.as_mut().context("received unrequested hashes")?;
+                if pending.record_hashes(&hashes)? {
+                    let pending = g.take().unwrap();
+                    drop(g);
+                    self.send_result(Ok(pending.into_result()))?;
+                }
+            }
+            Message::HashReject(r) => {
+                anyhow::bail!("peer rejected hash request for {:?}", r.pieces_root)
             }
+            _ => {}
         }
         Ok(())
     }
@@ -261,4 +455,8 @@ impl PeerConnectionHandler for Handler {
     fn client_name_and_version(&self) -> &str {
         &self.client_name_and_version
     }
+
+    fn advertise_v2(&self) -> bool {
+        self.info_hash_v2.is_some()
+    }
 }
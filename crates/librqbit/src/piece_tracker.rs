@@ -8,6 +8,8 @@
 //! - QUEUED (available to download)
 //! - IN_FLIGHT (currently being downloaded)
 //! - NOT_NEEDED (not selected for download)
+//!
+//! It also tracks how many live peers have each piece, to pick the rarest pieces first.
 
 use std::{
     collections::{HashMap, HashSet},
@@ -17,13 +19,19 @@ use std::{
 use buffers::ByteBuf;
 use librqbit_core::lengths::ValidPieceIndex;
 use peer_binary_protocol::Piece;
+use rand::RngExt;
 
 use crate::{
     chunk_tracker::{ChunkMarkingResult, ChunkTracker},
     file_info::FileInfo,
-    type_aliases::{FileInfos, FilePriorities, PeerHandle},
+    file_priority::FilePriority,
+    type_aliases::{BS, FileInfos, FilePriorities, PeerHandle},
 };
 
+/// Until we have this many pieces, queued pieces are picked at random rather than rarest-first.
+/// Rare pieces are slow to download, and we want something to trade with other peers quickly.
+const RANDOM_FIRST_PIECES: u64 = 4;
+
 /// Tracks a piece currently being downloaded.
 #[derive(Debug, Clone)]
 pub struct InflightPiece {
@@ -79,6 +87,8 @@ where
 pub struct PieceTracker {
     chunks: ChunkTracker,
     inflight: HashMap<ValidPieceIndex, InflightPiece>,
+    // How many live peers have each piece.
+    availability: Vec<u16>,
 }
 
 impl PieceTracker {
@@ -86,9 +96,11 @@ impl PieceTracker {
 
     /// Create a new PieceTracker wrapping the given ChunkTracker.
     pub fn new(chunks: ChunkTracker) -> Self {
+        let total_pieces = chunks.get_lengths().total_pieces() as usize;
         Self {
             chunks,
             inflight: HashMap::new(),
+            availability: vec![0; total_pieces],
         }
     }
 
@@ -115,7 +127,8 @@ impl PieceTracker {
     ///
     /// The acquisition strategy is:
     /// 1. Try to steal a piece from a peer that's 10x slower
-    /// 2. Try to reserve a piece from the queue (priority pieces first)
+    /// 2. Try to reserve a piece from the queue: priority pieces first, then the rarest
+    ///    queued piece (or a random one until we have a few pieces)
     /// 3. Try to steal a piece from a peer that's 3x slower
     ///
     /// If `Stolen` is returned, the caller MUST call `peers.on_steal()` to notify
@@ -142,17 +155,9 @@ impl PieceTracker {
             }
         }
 
-        // Then check naturally ordered queued pieces
-        // Note: iter_queued_pieces only returns pieces in queue_pieces (not in-flight)
-        let queued: Vec<_> = self
-            .chunks
-            .iter_queued_pieces(req.file_priorities, req.file_infos)
-            .collect();
-
-        for piece in queued {
-            if (req.peer_has_piece)(piece) {
-                return self.reserve_piece(piece, req.peer);
-            }
+        // Then pick from the queued pieces
+        if let Some(piece) = self.pick_queued_piece(&req) {
+            return self.reserve_piece(piece, req.peer);
         }
 
         // 3. Try steal with 3x threshold (moderately slow peer)
@@ -163,6 +168,57 @@ impl PieceTracker {
         AcquireResult::NoneAvailable
     }
 
+    /// Pick the rarest queued piece the peer has from the highest priority files, breaking
+    /// ties at random.
+    ///
+    /// Until we have a few pieces all queued pieces are considered equally rare, i.e. the
+    /// piece is picked at random.
+    fn pick_queued_piece<I, P, S>(&self, req: &AcquireRequest<I, P, S>) -> Option<ValidPieceIndex>
+    where
+        I: Iterator<Item = ValidPieceIndex>,
+        P: Fn(ValidPieceIndex) -> bool,
+        S: Fn(ValidPieceIndex) -> bool,
+    {
+        let lengths = self.chunks.get_lengths();
+        let random_first = self.chunks.get_hns().have_bytes
+            < RANDOM_FIRST_PIECES * lengths.default_piece_length() as u64;
+        let mut rng = rand::rng();
+
+        let mut best: Option<(FilePriority, u16, ValidPieceIndex)> = None;
+        let mut ties = 0u32;
+        // Note: iter_queued_pieces only returns pieces in queue_pieces (not in-flight)
+        for (priority, piece) in self
+            .chunks
+            .iter_queued_pieces(req.file_priorities, req.file_infos)
+            .filter(|(_, p)| (req.peer_has_piece)(*p))
+        {
+            // Files come sorted by priority, so nothing after this can beat the best piece.
+            if best.is_some_and(|(best_priority, _, _)| priority < best_priority) {
+                break;
+            }
+            let availability = if random_first {
+                0
+            } else {
+                self.availability[piece.get_usize()]
+            };
+            match best {
+                Some((_, best_availability, _)) if availability > best_availability => {}
+                // Reservoir sampling: each of the equally rare pieces is picked with the same probability.
+                Some((_, best_availability, _)) if availability == best_availability => {
+                    ties += 1;
+                    if rng.random_range(0..ties) == 0 {
+                        best = Some((priority, availability, piece));
+                    }
+                }
+                _ => {
+                    best = Some((priority, availability, piece));
+                    ties = 1;
+                }
+            }
+        }
+        best.map(|(_, _, piece)| piece)
+    }
+
     /// Reserve a piece: remove from queue, add to inflight.
     fn reserve_piece(&mut self, piece: ValidPieceIndex, peer: PeerHandle) -> AcquireResult {
         self.chunks.reserve_needed_piece(piece);
@@ -260,6 +316,52 @@ impl PieceTracker {
         count
     }
 
+    /// Release a single piece owned by a peer, e.g. when the peer rejected our request (BEP 6).
+    ///
+    /// Moves the piece from IN_FLIGHT back to QUEUED. Returns false if the piece
+    /// was not in-flight or was owned by someone else.
+    pub fn release_piece_owned_by(&mut self, piece: ValidPieceIndex, peer: PeerHandle) -> bool {
+        match self.inflight.get(&piece) {
+            Some(info) if info.peer == peer => {}
+            _ => return false,
+        }
+        self.inflight.remove(&piece);
+        self.chunks.mark_piece_broken_if_not_have(piece);
+        true
+    }
+
+    // === AVAILABILITY ===
+
+    /// Account for a peer having the pieces set in its bitfield.
+    pub fn add_peer_bitfield(&mut self, bitfield: &BS) {
+        for piece in bitfield.iter_ones() {
+            if let Some(a) = self.availability.get_mut(piece) {
+                *a = a.saturating_add(1);
+            }
+        }
+    }
+
+    /// Undo [`Self::add_peer_bitfield`], e.g. when the peer disconnects or sends a new bitfield.
+    pub fn remove_peer_bitfield(&mut self, bitfield: &BS) {
+        for piece in bitfield.iter_ones() {
+            if let Some(a) = self.availability.get_mut(piece) {
+                *a = a.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Account for a peer announcing a new piece (a "have" message).
+    pub fn add_peer_piece(&mut self, piece: ValidPieceIndex) {
+        let a = &mut self.availability[piece.get_usize()];
+        *a = a.saturating_add(1);
+    }
+
+    /// How many live peers have the piece.
+    #[cfg(test)]
+    pub fn availability(&self, piece: ValidPieceIndex) -> u16 {
+        self.availability[piece.get_usize()]
+    }
+
     // === QUERIES ===
 
     /// Get the inflight info for a piece, if it's currently being downloaded.
@@ -329,6 +431,11 @@ mod tests {
     /// Create a simple ChunkTracker for testing.
     /// Creates a torrent with the specified number of pieces, all selected.
     fn make_test_chunk_tracker(num_pieces: u32) -> ChunkTracker {
+        make_test_chunk_tracker_with_have(num_pieces, 0)
+    }
+
+    /// Same as [`make_test_chunk_tracker`], but the first `have_pieces` pieces are already downloaded.
+    fn make_test_chunk_tracker_with_have(num_pieces: u32, have_pieces: u32) -> ChunkTracker {
         // Create a simple single-file torrent
         let piece_length = 16384u32; // 16KB pieces
         let total_length = piece_length as u64 * num_pieces as u64;
@@ -337,8 +444,8 @@ mod tests {
 
         let bf_len = lengths.piece_bitfield_bytes();
 
-        // No pieces downloaded yet (empty have)
-        let have = BF::from_boxed_slice(vec![0u8; bf_len].into_boxed_slice());
+        let mut have = BF::from_boxed_slice(vec![0u8; bf_len].into_boxed_slice());
+        have[..have_pieces as usize].fill(true);
 
         // All pieces selected
         let mut selected = BF::from_boxed_slice(vec![0u8; bf_len].into_boxed_slice());
@@ -370,7 +477,9 @@ mod tests {
     }
 
     fn make_default_file_priorities(file_infos: &FileInfos) -> FilePriorities {
-        (0..file_infos.len()).collect()
+        (0..file_infos.len())
+            .map(|id| (id, FilePriority::Normal))
+            .collect()
     }
 
     #[test]
@@ -394,11 +503,11 @@ mod tests {
             priority_pieces: std::iter::empty(),
             file_priorities: &file_priorities,
             file_infos: &file_infos,
-            peer_has_piece: |_| true, // Peer has all pieces
+            peer_has_piece: |p| p.get() == 0,
             can_steal: |_| true,
         });
 
-        // Should reserve piece 0 (first in queue)
+        // Should reserve piece 0 (the only one the peer has)
         match result {
             AcquireResult::Reserved(piece) => {
                 assert_eq!(piece.get(), 0);
@@ -595,6 +704,50 @@ mod tests {
         assert!(!tracker.is_inflight(piece_a2));
     }
 
+    #[test]
+    fn test_release_single_piece_only_by_owner() {
+        let chunks = make_test_chunk_tracker(5);
+        let mut tracker = PieceTracker::new(chunks);
+
+        let file_infos = make_test_file_infos(5);
+        let file_priorities = make_default_file_priorities(&file_infos);
+
+        let piece = match tracker.acquire_piece(AcquireRequest {
+            peer: peer(1),
+            peer_avg_time: None,
+            priority_pieces: std::iter::empty(),
+            file_priorities: &file_priorities,
+            file_infos: &file_infos,
+            peer_has_piece: |_| true,
+            can_steal: |_| true,
+        }) {
+            AcquireResult::Reserved(p) => p,
+            _ => panic!("Expected Reserved"),
+        };
+
+        // Someone else can't release it.
+        assert!(!tracker.release_piece_owned_by(piece, peer(2)));
+        assert!(tracker.is_inflight(piece));
+
+        assert!(tracker.release_piece_owned_by(piece, peer(1)));
+        assert!(!tracker.is_inflight(piece));
+        assert!(!tracker.release_piece_owned_by(piece, peer(1)));
+
+        // Should be back in queue.
+        match tracker.acquire_piece(AcquireRequest {
+            peer: peer(2),
+            peer_avg_time: None,
+            priority_pieces: std::iter::empty(),
+            file_priorities: &file_priorities,
+            file_infos: &file_infos,
+            peer_has_piece: |p| p == piece,
+            can_steal: |_| true,
+        }) {
+            AcquireResult::Reserved(p) => assert_eq!(p, piece),
+            other => panic!("Expected piece to be re-reservable, got {other:?}"),
+        }
+    }
+
     #[test]
     fn test_into_chunks_requeues_inflight() {
         let chunks = make_test_chunk_tracker(5);
@@ -610,7 +763,7 @@ mod tests {
             priority_pieces: std::iter::empty(),
             file_priorities: &file_priorities,
             file_infos: &file_infos,
-            peer_has_piece: |_| true,
+            peer_has_piece: |p| p.get() == 0,
             can_steal: |_| true,
         });
         tracker.acquire_piece(AcquireRequest {
@@ -619,7 +772,7 @@ mod tests {
             priority_pieces: std::iter::empty(),
             file_priorities: &file_priorities,
             file_infos: &file_infos,
-            peer_has_piece: |_| true,
+            peer_has_piece: |p| p.get() == 1,
             can_steal: |_| true,
         });
 
@@ -636,7 +789,7 @@ mod tests {
             priority_pieces: std::iter::empty(),
             file_priorities: &file_priorities,
             file_infos: &file_infos,
-            peer_has_piece: |_| true,
+            peer_has_piece: |p| p.get() == 0,
             can_steal: |_| true,
         });
 
@@ -741,14 +894,14 @@ mod tests {
         let peer_a = peer(1);
         let peer_b = peer(2);
 
-        // Peer A reserves pieces 0 and 4 (first two in iteration order)
+        // Peer A reserves pieces 0 and 4
         let piece_0 = match tracker.acquire_piece(AcquireRequest {
             peer: peer_a,
             peer_avg_time: None,
             priority_pieces: std::iter::empty(),
             file_priorities: &file_priorities,
             file_infos: &file_infos,
-            peer_has_piece: |_| true,
+            peer_has_piece: |p| p.get() == 0,
             can_steal: |_| true,
         }) {
             AcquireResult::Reserved(p) => {
@@ -764,7 +917,7 @@ mod tests {
             priority_pieces: std::iter::empty(),
             file_priorities: &file_priorities,
             file_infos: &file_infos,
-            peer_has_piece: |_| true,
+            peer_has_piece: |p| p.get() == 4,
             can_steal: |_| true,
         }) {
             AcquireResult::Reserved(p) => {
@@ -801,4 +954,149 @@ mod tests {
             _ => panic!("Expected Stolen, got {:?}", result),
         }
     }
+
+    fn acquire_any(tracker: &mut PieceTracker, file_infos: &FileInfos) -> AcquireResult {
+        tracker.acquire_piece(AcquireRequest {
+            peer: peer(1),
+            peer_avg_time: None,
+            priority_pieces: std::iter::empty(),
+            file_priorities: &make_default_file_priorities(file_infos),
+            file_infos,
+            peer_has_piece: |_| true,
+            can_steal: |_| false,
+        })
+    }
+
+    fn bitfield(num_pieces: u32, has: impl Fn(usize) -> bool) -> BF {
+        let lengths = Lengths::new(16384 * num_pieces as u64, 16384).unwrap();
+        let mut bf =
+            BF::from_boxed_slice(vec![0u8; lengths.piece_bitfield_bytes()].into_boxed_slice());
+        for i in 0..num_pieces as usize {
+            bf.set(i, has(i));
+        }
+        bf
+    }
+
+    #[test]
+    fn test_availability_add_remove() {
+        let mut tracker = PieceTracker::new(make_test_chunk_tracker(10));
+        let piece = |i| {
+            tracker
+                .chunks
+                .get_lengths()
+                .validate_piece_index(i)
+                .unwrap()
+        };
+        let (p0, p1) = (piece(0), piece(1));
+
+        let even = bitfield(10, |i| i % 2 == 0);
+        tracker.add_peer_bitfield(&bitfield(10, |_| true));
+        tracker.add_peer_bitfield(&even);
+        tracker.add_peer_piece(p1);
+        assert_eq!(tracker.availability(p0), 2);
+        assert_eq!(tracker.availability(p1), 2);
+
+        tracker.remove_peer_bitfield(&even);
+        assert_eq!(tracker.availability(p0), 1);
+        assert_eq!(tracker.availability(p1), 2);
+
+        // Never goes below zero even if the accounting is off.
+        tracker.remove_peer_bitfield(&even);
+        tracker.remove_peer_bitfield(&even);
+        assert_eq!(tracker.availability(p0), 0);
+    }
+
+    #[test]
+    fn test_rarest_piece_picked_first() {
+        let mut tracker = PieceTracker::new(make_test_chunk_tracker_with_have(10, 4));
+        let file_infos = make_test_file_infos(10);
+
+        // Everyone has everything, but only one peer has piece 7 and two peers have piece 5.
+        for _ in 0..3 {
+            tracker.add_peer_bitfield(&bitfield(10, |i| i != 5 && i != 7));
+        }
+        tracker.add_peer_bitfield(&bitfield(10, |_| true));
+        tracker.add_peer_bitfield(&bitfield(10, |i| i == 5));
+
+        let mut picked = Vec::new();
+        while let AcquireResult::Reserved(p) = acquire_any(&mut tracker, &file_infos) {
+            picked.push(p.get());
+        }
+        assert_eq!(picked.len(), 6);
+        assert_eq!(picked[..2], [7, 5]);
+    }
+
+    #[test]
+    fn test_rarest_first_ties_are_random() {
+        let file_infos = make_test_file_infos(10);
+        let mut seen = HashSet::new();
+        for _ in 0..100 {
+            let mut tracker = PieceTracker::new(make_test_chunk_tracker_with_have(10, 4));
+            tracker.add_peer_bitfield(&bitfield(10, |i| i < 8));
+            match acquire_any(&mut tracker, &file_infos) {
+                AcquireResult::Reserved(p) => seen.insert(p.get()),
+                r => panic!("Expected Reserved, got {r:?}"),
+            };
+        }
+        // Pieces 8 and 9 are the rarest, and should be picked at random.
+        assert_eq!(seen, HashSet::from([8, 9]));
+    }
+
+    #[test]
+    fn test_random_first_until_enough_pieces() {
+        let file_infos = make_test_file_infos(10);
+        let mut seen = HashSet::new();
+        for _ in 0..200 {
+            let mut tracker = PieceTracker::new(make_test_chunk_tracker(10));
+            // Piece 9 is the rarest, but we have nothing yet so availability is ignored.
+            tracker.add_peer_bitfield(&bitfield(10, |i| i < 9));
+            match acquire_any(&mut tracker, &file_infos) {
+                AcquireResult::Reserved(p) => seen.insert(p.get()),
+                r => panic!("Expected Reserved, got {r:?}"),
+            };
+        }
+        assert!(seen.len() > 5, "expected random picks, got {seen:?}");
+    }
+
+    #[test]
+    fn test_higher_priority_files_picked_first() {
+        let piece_length = 16384u64;
+        let lengths = Lengths::new(piece_length * 10, 16384).unwrap();
+        let file = |name: &str, pieces: std::ops::Range<u32>| FileInfo {
+            relative_filename: name.into(),
+            offset_in_torrent: piece_length * pieces.start as u64,
+            len: piece_length * pieces.len() as u64,
+            piece_range: pieces,
+            attrs: Default::default(),
+        };
+        let file_infos = vec![file("a", 0..6), file("b", 6..10)];
+        let have = bitfield(10, |i| i < 4);
+        let selected = bitfield(10, |_| true);
+        let chunks = ChunkTracker::new(have.into_dyn(), selected, lengths, &file_infos).unwrap();
+        let mut tracker = PieceTracker::new(chunks);
+
+        // Piece 4 is the rarest, but it's in the lower priority file.
+        tracker.add_peer_bitfield(&bitfield(10, |_| true));
+        tracker.add_peer_bitfield(&bitfield(10, |i| i != 4));
+        let file_priorities = vec![(1, FilePriority::High), (0, FilePriority::Normal)];
+
+        let mut picked = Vec::new();
+        while let AcquireResult::Reserved(p) = tracker.acquire_piece(AcquireRequest {
+            peer: peer(1),
+            peer_avg_time: None,
+            priority_pieces: std::iter::empty(),
+            file_priorities: &file_priorities,
+            file_infos: &file_infos,
+            peer_has_piece: |_| true,
+            can_steal: |_| false,
+        }) {
+            picked.push(p.get());
+        }
+        assert_eq!(picked.len(), 6);
+        assert_eq!(
+            picked[..4].iter().copied().collect::<HashSet<_>>(),
+            HashSet::from([6, 7, 8, 9])
+        );
+        assert_eq!(picked[4], 4);
+    }
 }
@@ -54,19 +54,29 @@ impl ReadBuf {
         conn: &mut BoxAsyncReadVectored,
         timeout: Duration,
     ) -> Result<Handshake> {
-        self.len = with_timeout(
-            "reading",
-            timeout,
-            conn.read(&mut *self.buf).map_err(Error::ReadHandshake),
-        )
-        .await?;
-        if self.len == 0 {
-            return Err(Error::PeerDisconnectedReadingHandshake);
+        // The handshake may arrive in several reads, e.g. when it was partially consumed
+        // while sniffing for encryption.
+        loop {
+            let n = with_timeout(
+                "reading",
+                timeout,
+                conn.read(&mut self.buf[self.len..])
+                    .map_err(Error::ReadHandshake),
+            )
+            .await?;
+            if n == 0 {
+                return Err(Error::PeerDisconnectedReadingHandshake);
+            }
+            self.len += n;
+            match Handshake::deserialize(&self.buf[..self.len]) {
+                Ok((h, size)) => {
+                    self.advance(size);
+                    return Ok(h);
+                }
+                Err(MessageDeserializeError::NotEnoughData(..)) => continue,
+                Err(e) => return Err(Error::DeserializeHandshake(e)),
+            }
         }
-        let (h, size) =
-            Handshake::deserialize(&self.buf[..self.len]).map_err(Error::DeserializeHandshake)?;
-        self.advance(size);
-        Ok(h)
     }
 
     fn is_contiguous(&self) -> bool {
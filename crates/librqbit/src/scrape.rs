@@ -0,0 +1,116 @@
+// Periodic tracker and DHT (BEP 33) scrapes.
+//
+// All torrents are scraped, including paused and queued ones, so that dead torrents can be told
+// apart from healthy ones without starting them. Torrents sharing a tracker are scraped in
+// batches. Private torrents are never scraped through the DHT.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use librqbit_core::hash_id::Id20;
+use tracing::{debug, debug_span, trace};
+use tracker_comms::TrackerComms;
+
+use crate::{
+    ManagedTorrent, Session,
+    session::{TorrentId, swarm_info_hashes},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const SCRAPE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const MAX_CONCURRENT_TRACKERS: usize = 8;
+const MAX_CONCURRENT_DHT_SCRAPES: usize = 4;
+
+impl Session {
+    pub(crate) fn start_scraper(self: &Arc<Self>) {
+        self.spawn(debug_span!(parent: self.rs(), "scraper"), "scraper", {
+            let s = Arc::downgrade(self);
+            async move {
+                let mut last_scraped: HashMap<TorrentId, Instant> = HashMap::new();
+                let mut i = tokio::time::interval(CHECK_INTERVAL);
+                loop {
+                    i.tick().await;
+                    let s = s.upgrade().context("session is dead")?;
+                    s.scrape_due_torrents(&mut last_scraped).await;
+                }
+            }
+        })
+    }
+
+    async fn scrape_due_torrents(&self, last_scraped: &mut HashMap<TorrentId, Instant>) {
+        let now = Instant::now();
+        let torrents =
+            self.with_torrents(|it| it.map(|(id, t)| (id, t.clone())).collect::<Vec<_>>());
+        last_scraped.retain(|id, _| torrents.iter().any(|(tid, _)| tid == id));
+
+        let mut by_tracker: HashMap<url::Url, Vec<Arc<ManagedTorrent>>> = HashMap::new();
+        let mut dht_scrapes = Vec::new();
+        for (id, t) in torrents {
+            if last_scraped
+                .get(&id)
+                .is_some_and(|at| now - *at < SCRAPE_INTERVAL)
+            {
+                continue;
+            }
+            last_scraped.insert(id, now);
+            let is_private = t.with_metadata(|m| m.info.info().private).unwrap_or(false);
+            let tiers = self.effective_trackers(t.tracker_tiers(), is_private);
+            for tracker in tiers.into_iter().flatten() {
+                by_tracker.entry(tracker).or_default().push(t.clone());
+            }
+            if !is_private {
+                dht_scrapes.push(t);
+            }
+        }
+
+        futures::stream::iter(by_tracker)
+            .for_each_concurrent(MAX_CONCURRENT_TRACKERS, |(tracker, torrents)| async move {
+                let info_hashes = torrents
+                    .iter()
+                    .flat_map(|t| swarm_info_hashes(t.info_hash(), t.info_hash_v2()))
+                    .collect::<Vec<Id20>>();
+                match TrackerComms::scrape(
+                    &tracker,
+                    &info_hashes,
+                    &self.reqwest_client,
+                    &self.udp_tracker_client,
+                )
+                .await
+                {
+                    Ok(mut stats) => {
+                        trace!(%tracker, count = stats.len(), "scraped");
+                        for t in torrents {
+                            for h in swarm_info_hashes(t.info_hash(), t.info_hash_v2()) {
+                                if let Some(stats) = stats.remove(&h) {
+                                    t.update_scrape(&tracker, h, stats);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => debug!(%tracker, "error scraping: {e:#}"),
+                }
+            })
+            .await;
+
+        let Some(dht) = self.get_dht() else {
+            return;
+        };
+        futures::stream::iter(dht_scrapes)
+            .map(|t| async move {
+                let info_hashes =
+                    swarm_info_hashes(t.info_hash(), t.info_hash_v2()).collect::<Vec<_>>();
+                match dht.scrape_swarms(&info_hashes).await {
+                    Ok(estimate) => *t.dht_scrape.write() = Some(estimate),
+                    Err(e) => debug!(info_hash = ?t.info_hash(), "error scraping DHT: {e:#}"),
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_DHT_SCRAPES)
+            .collect::<()>()
+            .await
+    }
+}
@@ -0,0 +1,359 @@
+// Share ratio and seeding time goals.
+//
+// Once a finished torrent reaches one of its goals, the configured action is applied to it. Goals
+// are set per torrent, falling back to the session defaults for the ones that aren't set.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use tracing::{debug_span, info, warn};
+
+use crate::{Session, api::TorrentIdOrHash, session::TorrentId};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// How often the seeding stats of live torrents are saved, so they survive a crash.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// What to do with a torrent once it reaches a seeding goal.
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedGoalAction {
+    #[default]
+    Pause,
+    /// Remove the torrent from the session, keeping the files.
+    Forget,
+    /// Remove the torrent from the session together with its files.
+    Delete,
+}
+
+/// Seeding goals. Unset goals are not checked, unset actions default to pausing the torrent.
+#[serde_as]
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct SeedGoals {
+    /// Uploaded bytes divided by downloaded bytes. If the torrent was already downloaded when it
+    /// started, the size of the data we have is used instead of the downloaded bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ratio: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ratio_action: Option<SeedGoalAction>,
+
+    /// For how long to seed once the torrent is finished.
+    #[serde_as(as = "Option<serde_with::DurationSeconds>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_seeding_time: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_seeding_time_action: Option<SeedGoalAction>,
+
+    /// For how long to seed without uploading anything.
+    #[serde_as(as = "Option<serde_with::DurationSeconds>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_idle_time: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_idle_time_action: Option<SeedGoalAction>,
+}
+
+/// Totals used to check the seed goals. They add up across pauses and session restarts.
+#[serde_as]
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SeedingStats {
+    #[serde(default)]
+    pub uploaded_bytes: u64,
+    #[serde(default)]
+    pub downloaded_bytes: u64,
+    /// For how long the torrent was seeded.
+    #[serde_as(as = "serde_with::DurationSeconds")]
+    #[serde(default)]
+    pub seeding_time: Duration,
+    /// For how long the torrent was seeded since it last uploaded anything.
+    #[serde_as(as = "serde_with::DurationSeconds")]
+    #[serde(default)]
+    pub idle_time: Duration,
+}
+
+impl SeedingStats {
+    // Count the time seeded since the last check.
+    pub(crate) fn add_seeding_time(&mut self, elapsed: Duration, uploaded: bool) {
+        self.seeding_time += elapsed;
+        if uploaded {
+            self.idle_time = Duration::ZERO;
+        } else {
+            self.idle_time += elapsed;
+        }
+    }
+
+    // "have_bytes" is used instead of the downloaded bytes when the torrent was already
+    // (partially) there when it was added.
+    pub(crate) fn status(&self, have_bytes: u64) -> SeedingStatus {
+        let downloaded = self.downloaded_bytes.max(have_bytes);
+        SeedingStatus {
+            ratio: if downloaded == 0 {
+                0.
+            } else {
+                self.uploaded_bytes as f64 / downloaded as f64
+            },
+            seeding_time: self.seeding_time,
+            idle_time: self.idle_time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SeedingStatus {
+    pub ratio: f64,
+    pub seeding_time: Duration,
+    pub idle_time: Duration,
+}
+
+impl SeedGoals {
+    /// Fill the goals that aren't set from "defaults".
+    pub fn or(&self, defaults: &SeedGoals) -> SeedGoals {
+        SeedGoals {
+            max_ratio: self.max_ratio.or(defaults.max_ratio),
+            max_ratio_action: self.max_ratio_action.or(defaults.max_ratio_action),
+            max_seeding_time: self.max_seeding_time.or(defaults.max_seeding_time),
+            max_seeding_time_action: self
+                .max_seeding_time_action
+                .or(defaults.max_seeding_time_action),
+            max_idle_time: self.max_idle_time.or(defaults.max_idle_time),
+            max_idle_time_action: self.max_idle_time_action.or(defaults.max_idle_time_action),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_ratio.is_none() && self.max_seeding_time.is_none() && self.max_idle_time.is_none()
+    }
+
+    // If several goals are reached, the most destructive action wins.
+    pub(crate) fn reached(&self, status: &SeedingStatus) -> Option<(SeedGoalAction, &'static str)> {
+        [
+            (
+                self.max_ratio.is_some_and(|r| status.ratio >= r),
+                self.max_ratio_action,
+                "ratio",
+            ),
+            (
+                self.max_seeding_time
+                    .is_some_and(|t| status.seeding_time >= t),
+                self.max_seeding_time_action,
+                "seeding time",
+            ),
+            (
+                self.max_idle_time.is_some_and(|t| status.idle_time >= t),
+                self.max_idle_time_action,
+                "idle time",
+            ),
+        ]
+        .into_iter()
+        .filter(|(reached, _, _)| *reached)
+        .map(|(_, action, goal)| (action.unwrap_or_default(), goal))
+        .max_by_key(|(action, _)| *action)
+    }
+}
+
+// Tracks a live finished torrent between checks. The totals are kept on the torrent itself.
+struct SeedingTracker {
+    last_check: Instant,
+    last_uploaded_bytes: u64,
+}
+
+impl Session {
+    pub(crate) fn start_seed_goals_checker(self: &Arc<Self>) {
+        self.spawn(
+            debug_span!(parent: self.rs(), "seed_goals"),
+            "seed_goals",
+            {
+                let s = Arc::downgrade(self);
+                async move {
+                    let mut trackers: HashMap<TorrentId, SeedingTracker> = HashMap::new();
+                    let mut i = tokio::time::interval(CHECK_INTERVAL);
+                    let mut last_persisted = Instant::now();
+                    loop {
+                        i.tick().await;
+                        let s = s.upgrade().context("session is dead")?;
+                        s.check_seed_goals(&mut trackers).await;
+                        if last_persisted.elapsed() >= PERSIST_INTERVAL {
+                            s.persist_live_seeding_stats().await;
+                            last_persisted = Instant::now();
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    async fn check_seed_goals(&self, trackers: &mut HashMap<TorrentId, SeedingTracker>) {
+        let now = Instant::now();
+        let torrents =
+            self.with_torrents(|it| it.map(|(id, t)| (id, t.clone())).collect::<Vec<_>>());
+        trackers.retain(|id, _| torrents.iter().any(|(tid, _)| tid == id));
+
+        for (id, handle) in torrents {
+            let Some(live) = handle.live().filter(|l| l.is_finished()) else {
+                trackers.remove(&id);
+                continue;
+            };
+            let uploaded = live.get_uploaded_bytes();
+            let tracker = trackers.entry(id).or_insert_with(|| SeedingTracker {
+                last_check: now,
+                last_uploaded_bytes: uploaded,
+            });
+            handle.add_seeding_time(
+                now - tracker.last_check,
+                uploaded != tracker.last_uploaded_bytes,
+            );
+            tracker.last_check = now;
+            tracker.last_uploaded_bytes = uploaded;
+
+            let goals = handle.seed_goals().or(&self.seed_goals);
+            if goals.is_empty() {
+                continue;
+            }
+            let have_bytes = live.get_hns().map(|h| h.have_bytes).unwrap_or_default();
+            drop(live);
+            let status = handle.seeding_stats().status(have_bytes);
+
+            let Some((action, goal)) = goals.reached(&status) else {
+                continue;
+            };
+            info!(id, ?action, ?status, "torrent reached its {goal} goal");
+            trackers.remove(&id);
+            let res = match action {
+                SeedGoalAction::Pause => self.pause(&handle).await,
+                SeedGoalAction::Forget => self.delete(TorrentIdOrHash::Id(id), false).await,
+                SeedGoalAction::Delete => self.delete(TorrentIdOrHash::Id(id), true).await,
+            };
+            if let Err(e) = res {
+                warn!(id, ?action, "error applying seed goal action: {e:#}");
+            }
+        }
+    }
+
+    async fn persist_live_seeding_stats(&self) {
+        let torrents = self.with_torrents(|it| {
+            it.filter(|(_, t)| t.live().is_some())
+                .map(|(_, t)| t.clone())
+                .collect::<Vec<_>>()
+        });
+        for handle in torrents {
+            self.try_update_persistence_metadata(&handle).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{SeedGoalAction, SeedGoals, SeedingStats, SeedingStatus};
+
+    const STATUS: SeedingStatus = SeedingStatus {
+        ratio: 1.5,
+        seeding_time: Duration::from_secs(3600),
+        idle_time: Duration::from_secs(60),
+    };
+
+    #[test]
+    fn test_no_goals() {
+        assert!(SeedGoals::default().is_empty());
+        assert_eq!(SeedGoals::default().reached(&STATUS), None);
+    }
+
+    #[test]
+    fn test_goal_reached_with_default_action() {
+        let goals = SeedGoals {
+            max_ratio: Some(1.0),
+            max_idle_time: Some(Duration::from_secs(600)),
+            ..Default::default()
+        };
+        assert_eq!(
+            goals.reached(&STATUS),
+            Some((SeedGoalAction::Pause, "ratio"))
+        );
+
+        let goals = SeedGoals {
+            max_ratio: Some(2.0),
+            max_idle_time: Some(Duration::from_secs(600)),
+            ..Default::default()
+        };
+        assert_eq!(goals.reached(&STATUS), None);
+    }
+
+    #[test]
+    fn test_most_destructive_action_wins() {
+        let goals = SeedGoals {
+            max_ratio: Some(1.0),
+            max_ratio_action: Some(SeedGoalAction::Pause),
+            max_seeding_time: Some(Duration::from_secs(60)),
+            max_seeding_time_action: Some(SeedGoalAction::Delete),
+            max_idle_time: Some(Duration::from_secs(30)),
+            max_idle_time_action: Some(SeedGoalAction::Forget),
+        };
+        assert_eq!(
+            goals.reached(&STATUS),
+            Some((SeedGoalAction::Delete, "seeding time"))
+        );
+    }
+
+    #[test]
+    fn test_torrent_goals_override_defaults() {
+        let defaults = SeedGoals {
+            max_ratio: Some(2.0),
+            max_ratio_action: Some(SeedGoalAction::Forget),
+            max_idle_time: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let goals = SeedGoals {
+            max_ratio: Some(1.0),
+            ..Default::default()
+        }
+        .or(&defaults);
+        assert_eq!(goals.max_ratio, Some(1.0));
+        assert_eq!(goals.max_ratio_action, Some(SeedGoalAction::Forget));
+        assert_eq!(goals.max_idle_time, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_seeding_stats() {
+        let mut stats = SeedingStats {
+            uploaded_bytes: 300,
+            downloaded_bytes: 100,
+            ..Default::default()
+        };
+        stats.add_seeding_time(Duration::from_secs(10), false);
+        stats.add_seeding_time(Duration::from_secs(10), true);
+        stats.add_seeding_time(Duration::from_secs(5), false);
+        assert_eq!(stats.seeding_time, Duration::from_secs(25));
+        assert_eq!(stats.idle_time, Duration::from_secs(5));
+
+        assert_eq!(stats.status(0).ratio, 3.);
+        // Data we already had counts as downloaded.
+        assert_eq!(stats.status(200).ratio, 1.5);
+    }
+
+    #[test]
+    fn test_serde() {
+        let goals: SeedGoals = serde_json::from_str(
+            r#"{"max_ratio": 2.5, "max_seeding_time": 3600, "max_idle_time_action": "delete"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            goals,
+            SeedGoals {
+                max_ratio: Some(2.5),
+                max_seeding_time: Some(Duration::from_secs(3600)),
+                max_idle_time_action: Some(SeedGoalAction::Delete),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&goals).unwrap(),
+            r#"{"max_ratio":2.5,"max_seeding_time":3600,"max_idle_time_action":"delete"}"#
+        );
+    }
+}
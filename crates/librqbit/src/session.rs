@@ -2,17 +2,18 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
     io::Read,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     path::{Component, Path, PathBuf},
     sync::{
-        Arc,
+        Arc, Weak,
         atomic::{AtomicUsize, Ordering},
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    ApiError, CreateTorrentOptions, FileInfos, ManagedTorrent, ManagedTorrentShared,
+    ApiError, CreateTorrentOptions, FileInfos, FilePriority, ManagedTorrent, ManagedTorrentShared,
+    SeedGoals, SeedingStats,
     api::TorrentIdOrHash,
     api_error::WithStatus,
     bitv_factory::{BitVFactory, NonPersistentBitVFactory},
@@ -23,7 +24,8 @@ use crate::{
     limits::{Limits, LimitsConfig},
     listen::{Accept, ListenerOptions},
     merge_streams::merge_streams,
-    peer_connection::PeerConnectionOptions,
+    mse::{self, EncryptionPolicy},
+    peer_connection::{PeerConnectionOptions, with_timeout},
     read_buf::ReadBuf,
     session_persistence::{SessionPersistenceStore, json::JsonSessionPersistenceStore},
     session_stats::SessionStats,
@@ -37,8 +39,10 @@ use crate::{
     torrent_state::{
         ManagedTorrentHandle, ManagedTorrentLocked, ManagedTorrentOptions, ManagedTorrentState,
         TorrentMetadata, TorrentStateLive, initializing::TorrentStateInitializing,
+        normalize_tracker_tiers,
     },
     type_aliases::{BoxAsyncReadVectored, BoxAsyncWrite, PeerStream},
+    web_seed::WebSeed,
 };
 use anyhow::{Context, bail};
 use arc_swap::ArcSwapOption;
@@ -46,7 +50,9 @@ use bencode::bencode_serialize_to_writer;
 use buffers::{ByteBuf, ByteBufOwned};
 use bytes::Bytes;
 use clone_to_owned::CloneToOwned;
-use dht::{Dht, DhtBuilder, DhtConfig, DhtPersistenceConfig, Id20, PersistentDht, dht_listen_addr};
+use dht::{
+    Dht, DhtBuilder, DhtConfig, DhtPersistenceConfig, Id20, IsSeed, PersistentDht, dht_listen_addr,
+};
 use futures::{
     FutureExt, Stream, StreamExt, TryFutureExt,
     future::BoxFuture,
@@ -57,7 +63,9 @@ use itertools::Itertools;
 use librqbit_core::{
     crate_version,
     directories::get_configuration_directory,
-    magnet::Magnet,
+    hash_id::Id32,
+    magnet::{Magnet, MutableTorrentKey},
+    merkle::PieceLayers,
     peer_id::generate_azereus_style,
     spawn_utils::spawn_with_cancel,
     torrent_metainfo::{TorrentMetaV1Owned, ValidatedTorrentMetaV1Info},
@@ -70,10 +78,19 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::Notify;
 use tokio_util::sync::{CancellationToken, DropGuard};
 use tracing::{Instrument, debug, debug_span, error, info, trace, warn};
-use tracker_comms::{TrackerComms, UdpTrackerClient};
+use tracker_comms::{
+    AnnounceEvents, ScrapeStats, TrackerComms, TrackerCommsStatsState, TrackerServer,
+    TrackerServerOptions, TrackerStatus, TrackerStatuses, TrackerWhitelist, UdpTrackerClient,
+};
 
 pub const SUPPORTED_SCHEMES: [&str; 3] = ["http:", "https:", "magnet:"];
 
+// How long to wait for trackers to acknowledge "stopped" when a torrent stops.
+const STOPPED_ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// BEP 46: how often to check if a mutable torrent was updated.
+const MUTABLE_TORRENT_POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 pub type TorrentId = usize;
 
 struct ParsedTorrentFile {
@@ -115,11 +132,13 @@ pub struct Session {
     peer_id: Id20,
     announce_port: Option<u16>,
     listen_addr: Option<SocketAddr>,
+    incoming_encryption: EncryptionPolicy,
     dht: Option<Dht>,
     pub(crate) connector: Arc<StreamConnector>,
-    reqwest_client: reqwest::Client,
-    udp_tracker_client: UdpTrackerClient,
+    pub(crate) reqwest_client: reqwest::Client,
+    pub(crate) udp_tracker_client: UdpTrackerClient,
     disable_trackers: bool,
+    announce_to_all_trackers: bool,
 
     // Lifecycle management
     cancellation_token: CancellationToken,
@@ -133,13 +152,19 @@ pub struct Session {
     trackers: HashSet<url::Url>,
 
     lsd: Option<LocalServiceDiscovery>,
+    mutable_torrent_watches: RwLock<HashSet<MutableTorrentKey>>,
+    tracker_server: Option<Arc<TrackerServer>>,
 
     // Limits and throttling
     pub(crate) concurrent_initialize_semaphore: Arc<tokio::sync::Semaphore>,
     pub ratelimits: Limits,
+    // Defaults for torrents that don't override them.
+    pub(crate) seed_goals: SeedGoals,
+    queue: QueueLimits,
+    queue_notify: Arc<Notify>,
 
-    pub blocklist: IpRanges,
-    pub allowlist: Option<IpRanges>,
+    pub blocklist: Arc<IpRanges>,
+    pub allowlist: Option<Arc<IpRanges>>,
 
     // Monitoring / tracing / logging
     pub(crate) stats: Arc<SessionStats>,
@@ -150,6 +175,7 @@ pub struct Session {
     _disable_upload: bool,
     pub ipv4_only: bool,
     pub peer_limit: Option<usize>,
+    pub upload_slots: Option<usize>,
     client_name_and_version: String,
 }
 
@@ -250,6 +276,9 @@ pub struct AddTorrentOptions {
     /// An explicit list of file IDs to download.
     /// To see the file indices, run with "list_only".
     pub only_files: Option<Vec<usize>>,
+    /// Download priorities of all the files, indexed by file ID. Files with "skip" priority
+    /// are not downloaded, same as if they were left out of "only_files".
+    pub file_priorities: Option<Vec<FilePriority>>,
     /// Allow writing on top of existing files, including when resuming a torrent.
     /// You probably want to set it, however for safety it's not default.
     ///
@@ -283,14 +312,39 @@ pub struct AddTorrentOptions {
     /// Max concurrent connected peers.
     pub peer_limit: Option<usize>,
 
+    /// How many peers to upload to at the same time, not counting the optimistic unchoke.
+    /// If not set, session's default will be used.
+    pub upload_slots: Option<usize>,
+
+    /// Share ratio and seeding time goals. The ones not set here fall back to session's defaults.
+    #[serde(default)]
+    pub seed_goals: SeedGoals,
+
     /// This is used to restore the session from serialized state.
     pub preferred_id: Option<usize>,
 
+    /// Seeding totals from earlier runs of the torrent, counted towards the seed goals.
+    /// This is used to restore the session from serialized state.
+    #[serde(default)]
+    pub seeding_stats: SeedingStats,
+
+    /// Position in the session queue. If not set, the torrent goes to the bottom of the queue.
+    pub queue_position: Option<usize>,
+
     #[serde(skip)]
     pub storage_factory: Option<BoxStorageFactory>,
 
-    // Custom trackers
+    // Custom trackers, each one in its own tier after the torrent's trackers.
     pub trackers: Option<Vec<String>>,
+
+    /// If set, these tracker tiers are used instead of the ones from the torrent file or magnet
+    /// link. This is used to restore the session from serialized state.
+    pub tracker_tiers: Option<Vec<Vec<String>>>,
+
+    /// For BEP 46 mutable torrent magnet links: when the publisher points the link to a new
+    /// torrent, remove the previous one from the session. Its files are kept.
+    #[serde(default)]
+    pub retire_previous_versions: bool,
 }
 
 pub struct ListOnlyResponse {
@@ -390,6 +444,18 @@ impl SessionPersistenceConfig {
     }
 }
 
+// Applies the session blocklist and allowlist to DHT traffic too.
+struct DhtIpFilter {
+    blocklist: Arc<IpRanges>,
+    allowlist: Option<Arc<IpRanges>>,
+}
+
+impl dht::IpFilter for DhtIpFilter {
+    fn is_blocked(&self, ip: IpAddr) -> bool {
+        self.blocklist.has(ip) || self.allowlist.as_ref().is_some_and(|l| !l.has(ip))
+    }
+}
+
 /// Configuration for the DHT subsystem.
 /// Set to `None` in `SessionOptions::dht` to disable DHT entirely.
 pub struct DhtSessionConfig {
@@ -403,6 +469,8 @@ pub struct DhtSessionConfig {
     pub port: Option<u16>,
     /// Persistence behavior. If None, persistence is disabled.
     pub persistence: Option<DhtPersistenceConfig>,
+    /// Read-only mode (BEP 43): query the DHT, but don't answer queries from other nodes.
+    pub read_only: bool,
 }
 
 impl Default for DhtSessionConfig {
@@ -411,10 +479,61 @@ impl Default for DhtSessionConfig {
             bootstrap_addrs: None,
             port: None,
             persistence: Some(DhtPersistenceConfig::default()),
+            read_only: false,
         }
     }
 }
 
+/// Options for the built-in tracker.
+#[derive(Default, Debug, Clone)]
+pub struct BuiltinTrackerOptions {
+    /// Also serve the tracker over UDP on this address. Over HTTP, it's served by the HTTP API
+    /// at "/announce" and "/scrape".
+    pub udp_listen_addr: Option<SocketAddr>,
+    /// Only track the torrents that are in the session.
+    pub whitelist: bool,
+    /// How often peers should announce. Defaults to 10 minutes.
+    pub announce_interval: Option<Duration>,
+}
+
+/// Limits on how many torrents are active at the same time. Torrents that don't fit
+/// are queued, and started in the order of their queue positions as slots free up.
+///
+/// Unset limits are not checked. If none are set, all torrents start right away.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct QueueLimits {
+    pub max_active_downloads: Option<usize>,
+    pub max_active_seeds: Option<usize>,
+    /// Downloads and seeds combined.
+    pub max_active_torrents: Option<usize>,
+
+    /// Downloading torrents slower than this (bytes per second) don't count against the limits.
+    pub slow_download_threshold_bps: Option<u64>,
+    /// Seeding torrents slower than this (bytes per second) don't count against the limits.
+    pub slow_upload_threshold_bps: Option<u64>,
+    /// For how long a torrent needs to be active before it's checked for being slow.
+    /// Defaults to 1 minute.
+    pub slow_grace_period: Option<Duration>,
+}
+
+impl QueueLimits {
+    pub fn is_enabled(&self) -> bool {
+        self.max_active_downloads.is_some()
+            || self.max_active_seeds.is_some()
+            || self.max_active_torrents.is_some()
+    }
+}
+
+/// Where to move a torrent in the session queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueMove {
+    Up,
+    Down,
+    Top,
+    Bottom,
+}
+
 pub struct SessionOptions {
     /// DHT configuration. Set to None to disable DHT entirely.
     /// Defaults to DHT enabled with persistence.
@@ -427,6 +546,10 @@ pub struct SessionOptions {
     /// Disable tracker communication
     pub disable_trackers: bool,
 
+    /// Announce to all trackers of public torrents at once, instead of going through the tiers
+    /// one tracker at a time (BEP 12). On by default. Private torrents always use tiers.
+    pub announce_to_all_trackers: bool,
+
     /// Enable fastresume, to restore state quickly after restart.
     pub fastresume: bool,
 
@@ -467,6 +590,18 @@ pub struct SessionOptions {
     /// Default peer limit per torrent.
     pub peer_limit: Option<usize>,
 
+    /// Default number of upload slots per torrent.
+    pub upload_slots: Option<usize>,
+
+    /// Default share ratio and seeding time goals.
+    pub seed_goals: SeedGoals,
+
+    /// Limits on active downloads and seeds.
+    pub queue: QueueLimits,
+
+    /// Run a built-in tracker, for networks without DHT or other trackers.
+    pub tracker: Option<BuiltinTrackerOptions>,
+
     #[cfg(feature = "disable-upload")]
     pub disable_upload: bool,
 
@@ -487,6 +622,7 @@ impl Default for SessionOptions {
             dht: Some(DhtSessionConfig::default()),
             bind_device_name: None,
             disable_trackers: false,
+            announce_to_all_trackers: true,
             fastresume: false,
             persistence: None,
             peer_id: None,
@@ -502,6 +638,10 @@ impl Default for SessionOptions {
             allowlist_url: None,
             trackers: HashSet::new(),
             peer_limit: None,
+            upload_slots: None,
+            seed_goals: SeedGoals::default(),
+            queue: QueueLimits::default(),
+            tracker: None,
             #[cfg(feature = "disable-upload")]
             disable_upload: false,
             disable_local_service_discovery: false,
@@ -511,25 +651,62 @@ impl Default for SessionOptions {
     }
 }
 
-fn torrent_file_from_info_bytes(info_bytes: &[u8], trackers: &[url::Url]) -> anyhow::Result<Bytes> {
+fn torrent_file_from_info_bytes(
+    info_bytes: &[u8],
+    piece_layers: &PieceLayers,
+    tracker_tiers: &[Vec<url::Url>],
+) -> anyhow::Result<Bytes> {
     #[derive(Serialize)]
     struct Tmp<'a> {
         announce: &'a str,
         #[serde(rename = "announce-list")]
-        announce_list: &'a [&'a [url::Url]],
+        announce_list: &'a [Vec<url::Url>],
         info: bencode::raw_value::RawValue<&'a [u8]>,
+        #[serde(rename = "piece layers", skip_serializing_if = "Option::is_none")]
+        piece_layers: Option<&'a PieceLayers>,
     }
 
     let mut w = Vec::new();
     let v = Tmp {
         info: bencode::raw_value::RawValue(info_bytes),
-        announce: trackers.first().map(|s| s.as_str()).unwrap_or(""),
-        announce_list: &[trackers],
+        announce: tracker_tiers
+            .iter()
+            .flatten()
+            .next()
+            .map(|s| s.as_str())
+            .unwrap_or(""),
+        announce_list: tracker_tiers,
+        piece_layers: (!piece_layers.is_empty()).then_some(piece_layers),
     };
     bencode_serialize_to_writer(&v, &mut w)?;
     Ok(w.into())
 }
 
+// A magnet link may only name one of the info hashes of a hybrid torrent.
+fn complete_info_hashes(
+    metadata: &TorrentMetadata,
+    info_hash: Id20,
+    info_hash_v2: Option<Id32>,
+) -> (Id20, Option<Id32>) {
+    use sha1w::{ISha1, ISha256};
+
+    let info = metadata.info.info();
+    let info_hash_v2 = info_hash_v2.or_else(|| {
+        info.has_v2().then(|| {
+            let mut h = sha1w::Sha256::new();
+            h.update(&metadata.info_bytes);
+            Id32::new(h.finish())
+        })
+    });
+    let resolved_from_v2 = info_hash_v2.is_some_and(|h| h.truncate_for_dht() == info_hash);
+    if info.has_v1() && resolved_from_v2 {
+        let mut h = sha1w::Sha1::new();
+        h.update(&metadata.info_bytes);
+        return (Id20::new(h.finish()), info_hash_v2);
+    }
+    (info_hash, info_hash_v2)
+}
+
 pub(crate) struct CheckedIncomingConnection {
     pub kind: ConnectionKind,
     pub addr: SocketAddr,
@@ -541,8 +718,11 @@ pub(crate) struct CheckedIncomingConnection {
 
 struct InternalAddResult {
     info_hash: Id20,
+    info_hash_v2: Option<Id32>,
     metadata: Option<TorrentMetadata>,
-    trackers: Vec<url::Url>,
+    // Tracker tiers (BEP 12).
+    trackers: Vec<Vec<url::Url>>,
+    web_seeds: Vec<WebSeed>,
     name: Option<String>,
 }
 
@@ -603,15 +783,49 @@ impl Session {
                 None
             };
 
+            let blocklist = if let Some(blocklist_url) = opts.blocklist_url.take() {
+                info!(url = blocklist_url, "loading p2p blocklist");
+                let bl = IpRanges::load_from_url(&blocklist_url)
+                    .await
+                    .with_context(|| format!("error reading blocklist from {blocklist_url}"))?;
+                info!(len = bl.len(), "loaded blocklist");
+                Arc::new(bl)
+            } else {
+                Arc::new(IpRanges::default())
+            };
+
+            let allowlist = if let Some(allowlist_url) = opts.allowlist_url.take() {
+                info!(url = allowlist_url, "loading p2p allowlist");
+                let al = IpRanges::load_from_url(&allowlist_url)
+                    .await
+                    .with_context(|| format!("error reading allowlist from {allowlist_url}"))?;
+                info!(len = al.len(), "loaded allowlist");
+                Some(Arc::new(al))
+            } else {
+                None
+            };
+
+            let dht_ip_filter = (blocklist.len() > 0 || allowlist.is_some()).then(|| {
+                Arc::new(DhtIpFilter {
+                    blocklist: blocklist.clone(),
+                    allowlist: allowlist.clone(),
+                }) as Arc<dyn dht::IpFilter>
+            });
+
             let dht = if let Some(dht_config) = opts.dht.take() {
                 let dht = if let Some(persistence_config) = dht_config.persistence {
                     PersistentDht::create(
                         persistence_config,
                         dht_config.port,
                         opts.ipv4_only,
-                        dht_config.bootstrap_addrs,
-                        Some(token.clone()),
-                        bind_device.as_ref(),
+                        DhtConfig {
+                            bootstrap_addrs: dht_config.bootstrap_addrs,
+                            cancellation_token: Some(token.clone()),
+                            bind_device: bind_device.as_ref(),
+                            read_only: dht_config.read_only,
+                            ip_filter: dht_ip_filter,
+                            ..Default::default()
+                        },
                     )
                     .await
                     .context("error initializing persistent DHT")?
@@ -622,6 +836,8 @@ impl Session {
                         cancellation_token: Some(token.child_token()),
                         bind_device: bind_device.as_ref(),
                         listen_addr: Some(listen_addr),
+                        read_only: dht_config.read_only,
+                        ip_filter: dht_ip_filter,
                         ..Default::default()
                     })
                     .await
@@ -731,33 +947,16 @@ impl Session {
                     utp_socket: listen_result.as_ref().and_then(|l| l.utp_socket.clone()),
                     bind_device: bind_device.clone(),
                     ipv4_only: opts.ipv4_only,
+                    encryption: opts
+                        .connect
+                        .as_ref()
+                        .map(|c| c.encryption)
+                        .unwrap_or_default(),
                 })
                 .await
                 .context("error creating stream connector")?,
             );
 
-            let blocklist = if let Some(blocklist_url) = opts.blocklist_url {
-                info!(url = blocklist_url, "loading p2p blocklist");
-                let bl = IpRanges::load_from_url(&blocklist_url)
-                    .await
-                    .with_context(|| format!("error reading blocklist from {blocklist_url}"))?;
-                info!(len = bl.len(), "loaded blocklist");
-                bl
-            } else {
-                IpRanges::default()
-            };
-
-            let allowlist = if let Some(allowlist_url) = opts.allowlist_url {
-                info!(url = allowlist_url, "loading p2p allowlist");
-                let al = IpRanges::load_from_url(&allowlist_url)
-                    .await
-                    .with_context(|| format!("error reading allowlist from {allowlist_url}"))?;
-                info!(len = al.len(), "loaded allowlist");
-                Some(al)
-            } else {
-                None
-            };
-
             let udp_tracker_client = UdpTrackerClient::new(token.clone(), bind_device.as_ref())
                 .await
                 .context("error creating UDP tracker client")?;
@@ -777,7 +976,8 @@ impl Session {
                 }
             };
 
-            let session = Arc::new(Self {
+            let tracker_udp_listen_addr = opts.tracker.as_ref().and_then(|t| t.udp_listen_addr);
+            let session = Arc::new_cyclic(|session: &Weak<Self>| Self {
                 persistence,
                 bitv_factory,
                 peer_id,
@@ -791,6 +991,10 @@ impl Session {
                 cancellation_token: token,
                 announce_port: listen_result.as_ref().and_then(|l| l.announce_port),
                 listen_addr: listen_result.as_ref().map(|l| l.addr),
+                incoming_encryption: listen_result
+                    .as_ref()
+                    .map(|l| l.encryption)
+                    .unwrap_or_default(),
                 default_storage_factory: opts.default_storage_factory,
                 reqwest_client,
                 connector: stream_connector,
@@ -801,10 +1005,15 @@ impl Session {
                 )),
                 udp_tracker_client,
                 ratelimits: Limits::new(opts.ratelimits),
+                seed_goals: opts.seed_goals,
+                queue: opts.queue,
+                queue_notify: Arc::new(Notify::new()),
                 ipv4_only: opts.ipv4_only,
                 trackers: opts.trackers,
                 disable_trackers: opts.disable_trackers,
+                announce_to_all_trackers: opts.announce_to_all_trackers,
                 peer_limit: opts.peer_limit,
+                upload_slots: opts.upload_slots,
                 client_name_and_version,
 
                 #[cfg(feature = "disable-upload")]
@@ -812,8 +1021,47 @@ impl Session {
                 blocklist,
                 allowlist,
                 lsd,
+                mutable_torrent_watches: Default::default(),
+                tracker_server: opts.tracker.map(|tracker| {
+                    let session = session.clone();
+                    Arc::new(TrackerServer::new(TrackerServerOptions {
+                        announce_interval: tracker.announce_interval,
+                        whitelist: tracker.whitelist.then(|| -> TrackerWhitelist {
+                            // Hybrid torrents are tracked in both of their swarms.
+                            Box::new(move |info_hash| {
+                                session.upgrade().is_some_and(|s| {
+                                    s.with_torrents(|mut it| {
+                                        Iterator::any(&mut it, |(_, t)| {
+                                            t.matches_info_hash(*info_hash)
+                                        })
+                                    })
+                                })
+                            })
+                        }),
+                        ..Default::default()
+                    }))
+                }),
             });
 
+            if let (Some(tracker), Some(addr)) =
+                (session.tracker_server.clone(), tracker_udp_listen_addr)
+            {
+                let sock = librqbit_dualstack_sockets::UdpSocket::bind_udp(
+                    addr,
+                    librqbit_dualstack_sockets::BindOpts {
+                        device: bind_device.as_ref(),
+                        ..Default::default()
+                    },
+                )
+                .with_context(|| format!("error binding built-in tracker to UDP {addr}"))?;
+                info!(%addr, "serving the built-in tracker over UDP");
+                session.spawn(
+                    debug_span!(parent: session.rs(), "tracker_udp", %addr),
+                    "tracker_udp",
+                    async move { tracker.run_udp(sock).await },
+                );
+            }
+
             if let Some(mut listen) = listen_result {
                 if let Some(tcp) = listen.tcp_socket.take() {
                     let max_pending_incoming_handshake_checks =
@@ -881,9 +1129,20 @@ impl Session {
                                 Some(st) => {
                                     let (id, st) = st?;
                                     let span = add_torrent_span(st.info_hash());
+                                    let watch = st.mutable_watch();
                                     let (add_torrent, mut opts) = st.into_add_torrent()?;
                                     opts.preferred_id = Some(id);
-                                    let fut = session.add_torrent(add_torrent, Some(opts));
+                                    let s = session.clone();
+                                    let fut = async move {
+                                        let res = s.add_torrent(add_torrent, Some(opts)).await?;
+                                        // Keep polling mutable torrents for new versions.
+                                        if let Some(watch) = watch
+                                            && let Some(handle) = res.into_handle()
+                                        {
+                                            s.watch_mutable_torrent(watch, &handle);
+                                        }
+                                        anyhow::Ok(())
+                                    };
                                     let fut = fut.instrument(span);
                                     futs.push(fut);
                                 },
@@ -895,6 +1154,9 @@ impl Session {
             }
 
             session.start_speed_estimator_updater();
+            session.start_seed_goals_checker();
+            session.start_queue_manager();
+            session.start_scraper();
 
             Ok(session)
         }
@@ -905,7 +1167,7 @@ impl Session {
         self: Arc<Self>,
         addr: SocketAddr,
         kind: ConnectionKind,
-        mut reader: BoxAsyncReadVectored,
+        reader: BoxAsyncReadVectored,
         writer: BoxAsyncWrite,
     ) -> anyhow::Result<(Arc<TorrentStateLive>, CheckedIncomingConnection)> {
         let rwtimeout = self
@@ -929,6 +1191,29 @@ impl Session {
             bail!("Incoming ip {incoming_ip} is not in allowlist");
         }
 
+        let (mut reader, writer) = if self.incoming_encryption == EncryptionPolicy::Disabled {
+            (reader, writer)
+        } else {
+            let info_hashes = self
+                .db
+                .read()
+                .torrents
+                .values()
+                .flat_map(|t| {
+                    std::iter::once(t.info_hash())
+                        .chain(t.info_hash_v2().map(|h| h.truncate_for_dht()))
+                })
+                .collect::<Vec<_>>();
+            with_timeout(
+                "MSE handshake",
+                rwtimeout,
+                mse::accept(reader, writer, self.incoming_encryption, &info_hashes)
+                    .map_err(crate::Error::from),
+            )
+            .await
+            .context("error in MSE handshake")?
+        };
+
         let mut read_buf = ReadBuf::new();
         let h = read_buf
             .read_handshake(&mut reader, rwtimeout)
@@ -945,7 +1230,7 @@ impl Session {
             .read()
             .torrents
             .iter()
-            .find(|(_, t)| t.info_hash() == h.info_hash)
+            .find(|(_, t)| t.matches_info_hash(h.info_hash))
             .map(|(id, t)| (*id, t.clone()))
             .with_context(|| format!("didn't find a matching torrent {:?}", h.info_hash))?;
 
@@ -1002,7 +1287,12 @@ impl Session {
                         }
                     }
                 },
-                Some(Ok((live, checked))) = futs.next(), if !futs.is_empty() => {
+                // Errors are already logged above. They need to be matched here though, otherwise
+                // the branch gets disabled and the remaining checks stall until the next accept().
+                Some(res) = futs.next(), if !futs.is_empty() => {
+                    let Ok((live, checked)) = res else {
+                        continue;
+                    };
                     let (addr, kind) = (checked.addr, checked.kind);
                     if let Err(e) = live.add_incoming_peer(checked) {
                         warn!(?addr, ?kind, "error handing over incoming connection: {e:#}");
@@ -1051,6 +1341,11 @@ impl Session {
         spawn_with_cancel(span, name, self.cancellation_token.clone(), fut);
     }
 
+    /// The built-in tracker, if it's enabled.
+    pub fn tracker_server(&self) -> Option<&Arc<TrackerServer>> {
+        self.tracker_server.as_ref()
+    }
+
     pub(crate) fn rs(&self) -> Option<tracing::Id> {
         self.root_span.as_ref().and_then(|s| s.id())
     }
@@ -1064,11 +1359,19 @@ impl Session {
             .values()
             .cloned()
             .collect::<Vec<_>>();
+        let mut stopped_announces = Vec::new();
         for torrent in torrents {
+            stopped_announces.extend(self.stopped_announce(&torrent));
+            // Save the seeding stats while the live counters are there. Pausing would also
+            // persist the torrent as paused.
+            if torrent.live().is_some() {
+                self.try_update_persistence_metadata(&torrent).await;
+            }
             if let Err(e) = torrent.pause() {
                 debug!("error pausing torrent: {e:#}");
             }
         }
+        futures::future::join_all(stopped_announces).await;
         self.cancellation_token.cancel();
         // this sucks, but hopefully will be enough
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1091,13 +1394,36 @@ impl Session {
     ) -> BoxFuture<'a, anyhow::Result<AddTorrentResponse>> {
         async move {
             let mut opts = opts.unwrap_or_default();
+            let mut mutable_watch = None;
             let add_res = match add {
                 AddTorrent::Url(magnet) if magnet.starts_with("magnet:") || magnet.len() == 40 => {
                     let magnet = Magnet::parse(&magnet)
                         .context("provided path is not a valid magnet URL")?;
-                    let info_hash = magnet
+                    // v2-only swarms are keyed by the truncated v2 info hash.
+                    let mut info_hash = magnet
                         .as_id20()
-                        .context("magnet link didn't contain a BTv1 infohash")?;
+                        .or_else(|| magnet.as_id32().map(|h| h.truncate_for_dht()));
+                    if let Some(key) = magnet.as_mutable() {
+                        let mut seq = None;
+                        if info_hash.is_none() {
+                            let (s, ih) = self
+                                .resolve_mutable_torrent(key, None)
+                                .await?
+                                .context("mutable torrent not found in DHT")?;
+                            info!(info_hash = ?ih, seq = s, "resolved mutable torrent");
+                            seq = Some(s);
+                            info_hash = Some(ih);
+                        }
+                        mutable_watch = Some(MutableTorrentWatch {
+                            key: key.clone(),
+                            seq,
+                            trackers: magnet.trackers.clone(),
+                            retire_previous_versions: opts.retire_previous_versions,
+                            options: Default::default(),
+                        });
+                    }
+                    let info_hash =
+                        info_hash.context("magnet link didn't contain a BTv1 or BTv2 infohash")?;
                     if let Some(so) = magnet.get_select_only() {
                         // Only overwrite opts.only_files if user didn't specify
                         if opts.only_files.is_none() {
@@ -1107,11 +1433,21 @@ impl Session {
 
                     InternalAddResult {
                         info_hash,
-                        trackers: magnet
-                            .trackers
-                            .into_iter()
-                            .filter_map(|t| url::Url::parse(&t).ok())
-                            .collect(),
+                        info_hash_v2: magnet.as_id32(),
+                        // Magnet links have no tiers, so all trackers are in one.
+                        trackers: {
+                            let tier = magnet
+                                .trackers
+                                .into_iter()
+                                .filter_map(|t| url::Url::parse(&t).ok())
+                                .collect_vec();
+                            if tier.is_empty() {
+                                Vec::new()
+                            } else {
+                                vec![tier]
+                            }
+                        },
+                        web_seeds: Vec::new(),
                         metadata: None,
                         name: magnet.name,
                     }
@@ -1136,37 +1472,58 @@ impl Session {
 
                     let mut trackers = torrent
                         .meta
-                        .iter_announce()
-                        .unique()
-                        .filter_map(|tracker| match std::str::from_utf8(tracker.as_ref()) {
-                            Ok(url) => Some(url.to_owned()),
-                            Err(_) => {
-                                warn!("cannot parse tracker url as utf-8, ignoring");
-                                None
-                            }
+                        .announce_tiers()
+                        .into_iter()
+                        .map(|tier| {
+                            tier.into_iter()
+                                .filter_map(|tracker| match std::str::from_utf8(tracker.as_ref()) {
+                                    Ok(url) => Some(url.to_owned()),
+                                    Err(_) => {
+                                        warn!("cannot parse tracker url as utf-8, ignoring");
+                                        None
+                                    }
+                                })
+                                .collect::<Vec<_>>()
                         })
                         .collect::<Vec<_>>();
                     if let Some(custom_trackers) = opts.trackers.clone() {
-                        trackers.extend(custom_trackers);
+                        trackers.extend(custom_trackers.into_iter().map(|t| vec![t]));
                     }
 
+                    let web_seeds = WebSeed::from_torrent(&torrent.meta);
+                    let info = torrent.meta.info.data.validate()?;
+                    let piece_layers =
+                        PieceLayers::from_torrent(&info, torrent.meta.piece_layers.as_ref())?;
                     InternalAddResult {
                         info_hash: torrent.meta.info_hash,
+                        info_hash_v2: torrent.meta.info_hash_v2,
                         metadata: Some(TorrentMetadata::new(
-                            torrent.meta.info.data.validate()?,
+                            info,
                             torrent.torrent_bytes,
                             torrent.meta.info.raw_bytes.0,
+                            piece_layers,
                         )?),
-                        trackers: trackers
-                            .iter()
-                            .filter_map(|t| url::Url::parse(t).ok())
-                            .collect(),
+                        trackers: parse_tracker_tiers(&trackers),
+                        web_seeds,
                         name: None,
                     }
                 }
             };
 
-            self.add_torrent_internal(add_res, opts).await
+            if let Some(watch) = mutable_watch.as_mut() {
+                // New versions are added with the same options.
+                watch.options = serde_json::to_value(&opts)
+                    .context("error serializing options of mutable torrent")?;
+            }
+            let response = self.add_torrent_internal(add_res, opts).await?;
+            if let Some(watch) = mutable_watch
+                && let AddTorrentResponse::Added(_, handle)
+                | AddTorrentResponse::AlreadyManaged(_, handle) = &response
+            {
+                self.watch_mutable_torrent(watch, handle);
+                self.try_update_persistence_metadata(handle).await;
+            }
+            Ok(response)
         }
         .instrument(debug_span!(parent: self.rs(), "add_torrent"))
         .boxed()
@@ -1222,21 +1579,34 @@ impl Session {
     ) -> anyhow::Result<AddTorrentResponse> {
         let InternalAddResult {
             info_hash,
+            info_hash_v2,
             metadata,
             trackers,
+            web_seeds,
             name,
         } = add_res;
 
         let private = metadata.as_ref().is_some_and(|m| m.info.info().private);
+        let queued = !opts.paused && self.queue.is_enabled();
+        let trackers = normalize_tracker_tiers(match opts.tracker_tiers.take() {
+            Some(tiers) => parse_tracker_tiers(&tiers),
+            None => trackers,
+        });
+        let tracker_statuses = TrackerStatuses::default();
+        let announce_events = AnnounceEvents::default();
+        let trackers_tx = tokio::sync::watch::Sender::new(trackers.clone());
 
         let make_peer_rx = || {
             self.make_peer_rx(
                 info_hash,
-                trackers.clone(),
+                info_hash_v2,
+                trackers_tx.subscribe(),
                 !opts.paused && !opts.list_only,
                 opts.force_tracker_interval,
                 opts.initial_peers.clone().unwrap_or_default(),
                 private,
+                tracker_statuses.clone(),
+                announce_events.clone(),
             )
         };
 
@@ -1246,17 +1616,24 @@ impl Session {
             match metadata {
                 Some(metadata) => {
                     let mut peer_rx = None;
-                    if !opts.paused && !opts.list_only {
+                    if !opts.paused && !queued && !opts.list_only {
                         peer_rx = make_peer_rx();
                     }
                     (metadata, peer_rx)
                 }
                 None => {
-                    let peer_rx = make_peer_rx().context(
+                    // The peer stream is there even without trackers, as they can be added later.
+                    let can_resolve = self.dht.is_some()
+                        || self.lsd.is_some()
+                        || !self
+                            .effective_trackers(trackers.clone(), private)
+                            .is_empty()
+                        || opts.initial_peers.as_ref().is_some_and(|p| !p.is_empty());
+                    let peer_rx = make_peer_rx().filter(|_| can_resolve).context(
                         "no known way to resolve peers (no DHT, no trackers, no initial_peers)",
                     )?;
                     let resolved_magnet = self
-                        .resolve_magnet(info_hash, peer_rx, &trackers, opts.peer_opts)
+                        .resolve_magnet(info_hash, info_hash_v2, peer_rx, &trackers, opts.peer_opts)
                         .await?;
 
                     // Add back seen_peers into the peer stream, as we consumed some peers
@@ -1276,13 +1653,43 @@ impl Session {
 
         trace!("Torrent metadata: {:#?}", &metadata.info.info());
 
-        let only_files = compute_only_files(
+        let (info_hash, info_hash_v2) = complete_info_hashes(&metadata, info_hash, info_hash_v2);
+
+        let mut only_files = compute_only_files(
             &metadata.info,
             opts.only_files,
             opts.only_files_regex,
             opts.list_only,
         )?;
 
+        // Files not selected with only_files are skipped, and skipped files are not selected.
+        let file_priorities = match opts.file_priorities {
+            Some(mut priorities) => {
+                let file_count = metadata.file_infos.len();
+                if priorities.len() != file_count {
+                    bail!(
+                        "expected {file_count} file priorities, got {}",
+                        priorities.len()
+                    );
+                }
+                for (id, priority) in priorities.iter_mut().enumerate() {
+                    if only_files.as_ref().is_some_and(|o| !o.contains(&id)) {
+                        *priority = FilePriority::Skip;
+                    }
+                }
+                only_files = Some(
+                    priorities
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, p)| **p != FilePriority::Skip)
+                        .map(|(id, _)| id)
+                        .collect(),
+                );
+                Some(priorities)
+            }
+            None => None,
+        };
+
         let output_folder = match (opts.output_folder, opts.sub_folder) {
             (None, None) => self.output_folder.join(
                 self.get_default_subfolder_for_torrent(&metadata.info, name.as_deref())?
@@ -1326,7 +1733,7 @@ impl Session {
         let (managed_torrent, metadata) = {
             let mut g = self.db.write();
             if let Some((id, handle)) = g.torrents.iter().find_map(|(eid, t)| {
-                if t.info_hash() == info_hash || *eid == id {
+                if t.matches_info_hash(info_hash) || *eid == id {
                     Some((*eid, t.clone()))
                 } else {
                     None
@@ -1335,6 +1742,14 @@ impl Session {
                 return Ok(AddTorrentResponse::AlreadyManaged(id, handle));
             }
 
+            let queue_position = opts.queue_position.unwrap_or_else(|| {
+                g.torrents
+                    .values()
+                    .map(|t| t.queue_position() + 1)
+                    .max()
+                    .unwrap_or_default()
+            });
+
             let span = debug_span!(parent: self.rs(), "torrent", id);
             let peer_opts = self.merge_peer_opts(opts.peer_opts);
             let metadata = Arc::new(metadata);
@@ -1342,7 +1757,8 @@ impl Session {
                 id,
                 span,
                 info_hash,
-                trackers: trackers.into_iter().collect(),
+                info_hash_v2,
+                web_seeds,
                 spawner: self.spawner.clone(),
                 peer_id: self.peer_id,
                 storage_factory,
@@ -1355,6 +1771,9 @@ impl Session {
                     ratelimits: opts.ratelimits,
                     initial_peers: opts.initial_peers.clone().unwrap_or_default(),
                     peer_limit: opts.peer_limit.or(self.peer_limit),
+                    // The session default is looked up when needed, so that only the
+                    // torrent's own setting is persisted.
+                    upload_slots: opts.upload_slots,
                     #[cfg(feature = "disable-upload")]
                     _disable_upload: self._disable_upload,
                 },
@@ -1362,6 +1781,7 @@ impl Session {
                 session: Arc::downgrade(self),
                 magnet_name: name,
                 client_name_and_version: self.client_name_and_version.clone(),
+                trackers: trackers_tx,
             });
 
             let initializing = Arc::new(TorrentStateInitializing::new(
@@ -1375,9 +1795,18 @@ impl Session {
             let handle = Arc::new(ManagedTorrent {
                 locked: RwLock::new(ManagedTorrentLocked {
                     paused: opts.paused,
+                    queued: false,
+                    queue_position,
                     state: ManagedTorrentState::Initializing(initializing),
                     only_files,
+                    file_priorities,
+                    seed_goals: opts.seed_goals,
+                    seeding_stats: opts.seeding_stats,
                 }),
+                tracker_statuses,
+                announce_events,
+                mutable_watch: RwLock::new(None),
+                dht_scrape: RwLock::new(None),
                 state_change_notify: Notify::new(),
                 shared: minfo,
                 metadata: ArcSwapOption::new(Some(metadata.clone())),
@@ -1396,9 +1825,16 @@ impl Session {
 
         let _e = managed_torrent.shared.span.clone().entered();
 
-        managed_torrent
-            .start(peer_rx, opts.paused)
-            .context("error starting torrent")?;
+        if queued {
+            managed_torrent
+                .start_queued()
+                .context("error starting torrent")?;
+            self.queue_notify.notify_one();
+        } else {
+            managed_torrent
+                .start(peer_rx, opts.paused)
+                .context("error starting torrent")?;
+        }
 
         if let Some(name) = metadata.info.name() {
             info!(?name, "added torrent");
@@ -1420,6 +1856,144 @@ impl Session {
         }
     }
 
+    // BEP 46: look up the current info hash of a mutable torrent, if it's newer than min_seq.
+    async fn resolve_mutable_torrent(
+        &self,
+        key: &MutableTorrentKey,
+        min_seq: Option<i64>,
+    ) -> anyhow::Result<Option<(i64, Id20)>> {
+        #[derive(Deserialize)]
+        struct MutableTorrentValue {
+            ih: Id20,
+        }
+
+        let dht = self
+            .dht
+            .as_ref()
+            .context("mutable torrent magnet links need DHT")?;
+        let Some(item) = dht
+            .get_mutable(key.public_key, key.salt.clone().into(), min_seq)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let value: MutableTorrentValue = bencode::from_bytes(&item.value)
+            .map_err(|e| e.into_anyhow())
+            .context("invalid mutable torrent value")?;
+        Ok(Some((item.seq, value.ih)))
+    }
+
+    // Poll the DHT for new versions of a mutable torrent, adding them when they appear. The watch
+    // is kept on the torrent of the current version, so that it's persisted with it.
+    pub(crate) fn watch_mutable_torrent(
+        self: &Arc<Self>,
+        watch: MutableTorrentWatch,
+        handle: &ManagedTorrentHandle,
+    ) {
+        let key = watch.key.clone();
+        *handle.mutable_watch.write() = Some(watch);
+        if !self.mutable_torrent_watches.write().insert(key.clone()) {
+            // Already watching.
+            return;
+        }
+        let mut current = handle.id();
+        let session = Arc::downgrade(self);
+        self.spawn(
+            debug_span!(parent: self.rs(), "mutable_torrent", id = current),
+            "mutable_torrent",
+            async move {
+                loop {
+                    tokio::time::sleep(MUTABLE_TORRENT_POLL_INTERVAL).await;
+                    let Some(session) = session.upgrade() else {
+                        return Ok(());
+                    };
+                    let Some((handle, mut watch)) =
+                        session.get(TorrentIdOrHash::Id(current)).and_then(|h| {
+                            let watch = h.mutable_watch()?;
+                            Some((h, watch))
+                        })
+                    else {
+                        // The torrent was removed, stop watching.
+                        session.mutable_torrent_watches.write().remove(&key);
+                        return Ok(());
+                    };
+                    let (seq, info_hash) =
+                        match session.resolve_mutable_torrent(&watch.key, watch.seq).await {
+                            Ok(Some(r)) => r,
+                            Ok(None) => continue,
+                            Err(e) => {
+                                debug!("error resolving mutable torrent: {e:#}");
+                                continue;
+                            }
+                        };
+                    watch.seq = Some(seq);
+                    match session
+                        .update_mutable_torrent(&handle, watch, info_hash)
+                        .await
+                    {
+                        Ok(Some(new)) => current = new.id(),
+                        Ok(None) => {}
+                        Err(e) => warn!("error updating mutable torrent: {e:#}"),
+                    }
+                }
+            },
+        );
+    }
+
+    // Move the watch of a mutable torrent to the version "info_hash" resolved to "watch.seq",
+    // adding it if it's new. Returns the new version's handle. If adding fails, the watch is
+    // left as it was, so that the version is resolved again on the next poll.
+    pub(crate) async fn update_mutable_torrent(
+        self: &Arc<Self>,
+        handle: &ManagedTorrentHandle,
+        watch: MutableTorrentWatch,
+        info_hash: Id20,
+    ) -> anyhow::Result<Option<ManagedTorrentHandle>> {
+        if info_hash == handle.info_hash() {
+            *handle.mutable_watch.write() = Some(watch);
+            self.try_update_persistence_metadata(handle).await;
+            return Ok(None);
+        }
+
+        info!(
+            ?info_hash,
+            seq = watch.seq,
+            "mutable torrent was updated, adding the new version"
+        );
+        let magnet = Magnet::from_id20(info_hash, watch.trackers.clone(), None);
+        let new = self
+            .add_torrent(
+                AddTorrent::from_url(magnet.to_string()),
+                Some(watch.new_version_options()),
+            )
+            .await
+            .context("error adding the new version")?
+            .into_handle()
+            .context("the new version wasn't added")?;
+        if new.id() == handle.id() {
+            *handle.mutable_watch.write() = Some(watch);
+            self.try_update_persistence_metadata(handle).await;
+            return Ok(None);
+        }
+
+        // The watch moves to the new version.
+        let retire = watch.retire_previous_versions;
+        *handle.mutable_watch.write() = None;
+        *new.mutable_watch.write() = Some(watch);
+        self.try_update_persistence_metadata(&new).await;
+        if retire {
+            if let Err(e) = self.delete(TorrentIdOrHash::Id(handle.id()), false).await {
+                warn!(
+                    id = handle.id(),
+                    "error removing the previous version: {e:#}"
+                );
+            }
+        } else {
+            self.try_update_persistence_metadata(handle).await;
+        }
+        Ok(Some(new))
+    }
+
     pub async fn delete(&self, id: TorrentIdOrHash, delete_files: bool) -> anyhow::Result<()> {
         let id = match id {
             TorrentIdOrHash::Id(id) => id,
@@ -1444,6 +2018,7 @@ impl Session {
             .remove(&id)
             .with_context(|| format!("torrent with id {id} did not exist"))?;
 
+        self.spawn_stopped_announce(&removed);
         if let Err(e) = removed.pause() {
             debug!("error pausing torrent before deletion: {e:#}")
         }
@@ -1504,6 +2079,7 @@ impl Session {
             }
         };
 
+        self.queue_notify.notify_one();
         info!(id, "deleted torrent");
         Ok(())
     }
@@ -1516,84 +2092,285 @@ impl Session {
         let is_private = t.with_metadata(|m| m.info.info().private).unwrap_or(false);
         self.make_peer_rx(
             t.info_hash(),
-            t.shared().trackers.iter().cloned().collect(),
+            t.info_hash_v2(),
+            t.shared().trackers.subscribe(),
             announce,
             t.shared().options.force_tracker_interval,
             t.shared().options.initial_peers.clone(),
             is_private,
+            t.tracker_statuses.clone(),
+            t.announce_events.clone(),
         )
     }
 
+    /// The status of each tracker the torrent announces to. Hybrid torrents have a status for
+    /// each of their swarms.
+    pub fn tracker_statuses(&self, t: &ManagedTorrent) -> Vec<TrackerStatus> {
+        let is_private = t.with_metadata(|m| m.info.info().private).unwrap_or(false);
+        self.effective_trackers(t.tracker_tiers(), is_private)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(tier, urls)| urls.into_iter().map(move |url| (tier, url)))
+            .flat_map(|(tier, url)| {
+                swarm_info_hashes(t.info_hash(), t.info_hash_v2())
+                    .map(move |h| (tier, url.clone(), h))
+            })
+            .map(|(tier, url, info_hash)| {
+                let status = t
+                    .tracker_statuses
+                    .get(&url, info_hash)
+                    .unwrap_or_else(|| TrackerStatus::new(url, info_hash));
+                TrackerStatus { tier, ..status }
+            })
+            .collect()
+    }
+
+    // The tracker tiers to talk to for a torrent, taking the session settings into account.
+    // Session-wide trackers are used for public torrents only, each in its own tier.
+    pub(crate) fn effective_trackers(
+        &self,
+        mut tiers: Vec<Vec<url::Url>>,
+        is_private: bool,
+    ) -> Vec<Vec<url::Url>> {
+        if self.disable_trackers {
+            return Vec::new();
+        }
+        if !is_private {
+            tiers.extend(
+                self.trackers
+                    .iter()
+                    .sorted_by_key(|t| t.as_str())
+                    .map(|t| vec![t.clone()]),
+            );
+        }
+        normalize_tracker_tiers(tiers)
+    }
+
+    // Whether we have everything selected in the torrent, for DHT announces.
+    fn is_seed_fn(self: &Arc<Self>, info_hash: Id20) -> IsSeed {
+        let session = Arc::downgrade(self);
+        Arc::new(move || {
+            session.upgrade().is_some_and(|session| {
+                PeerRxTorrentInfo { info_hash, session }
+                    .torrent()
+                    .and_then(|t| t.live())
+                    .is_some_and(|live| live.is_finished())
+            })
+        })
+    }
+
+    fn effective_tracker_urls(&self, t: &ManagedTorrent) -> Vec<url::Url> {
+        let is_private = t.with_metadata(|m| m.info.info().private).unwrap_or(false);
+        self.effective_trackers(t.tracker_tiers(), is_private)
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
     // Get a peer stream from both DHT and trackers.
+    #[allow(clippy::too_many_arguments)]
     fn make_peer_rx(
         self: &Arc<Self>,
         info_hash: Id20,
-        mut trackers: Vec<url::Url>,
+        info_hash_v2: Option<Id32>,
+        trackers: tokio::sync::watch::Receiver<Vec<Vec<url::Url>>>,
         announce: bool,
         force_tracker_interval: Option<Duration>,
         initial_peers: Vec<SocketAddr>,
         is_private: bool,
+        tracker_statuses: TrackerStatuses,
+        announce_events: AnnounceEvents,
     ) -> Option<PeerStream> {
-        let dht_rx = if is_private {
-            None
-        } else {
-            self.dht.as_ref().map(|dht| {
-                dht.get_peers(info_hash, if announce { self.announce_port } else { None })
-            })
-        };
+        let mut swarm_rx: Option<BoxStream<'static, SocketAddr>> = None;
+        for info_hash in swarm_info_hashes(info_hash, info_hash_v2) {
+            let dht_rx = if is_private {
+                None
+            } else {
+                self.dht
+                    .as_ref()
+                    .map(|dht| match self.announce_port.filter(|_| announce) {
+                        Some(port) => dht.get_peers_announcing_seed(
+                            info_hash,
+                            port,
+                            self.is_seed_fn(info_hash),
+                        ),
+                        None => dht.get_peers(info_hash, None),
+                    })
+            };
 
-        let lsd_rx = if is_private {
-            None
-        } else {
-            self.lsd.as_ref().map(|lsd| {
-                lsd.announce(info_hash, if announce { self.announce_port } else { None })
-            })
-        };
+            let lsd_rx = if is_private {
+                None
+            } else {
+                self.lsd.as_ref().map(|lsd| {
+                    lsd.announce(info_hash, if announce { self.announce_port } else { None })
+                })
+            };
 
-        if self.disable_trackers {
-            trackers.clear();
-        }
+            // Trackers can be added later, so this is set even if there are none yet.
+            let tracker_rx = (!self.disable_trackers).then(|| {
+                self.make_tracker_rx(
+                    info_hash,
+                    trackers.clone(),
+                    is_private,
+                    force_tracker_interval,
+                    tracker_statuses.clone(),
+                    announce_events.clone(),
+                )
+            });
 
-        if is_private && trackers.len() > 1 {
-            warn!(
-                ?info_hash,
-                "private trackers are not fully implemented, so using only the first tracker"
+            swarm_rx = merge_two_optional_streams(
+                swarm_rx,
+                merge_two_optional_streams(merge_two_optional_streams(dht_rx, tracker_rx), lsd_rx),
             );
-            trackers.truncate(1);
-        } else if !self.disable_trackers {
-            trackers.extend(self.trackers.iter().cloned());
         }
 
-        let tracker_rx_stats = PeerRxTorrentInfo {
-            info_hash,
-            session: self.clone(),
-        };
-        let tracker_rx = TrackerComms::start(
-            info_hash,
-            self.peer_id,
-            trackers.into_iter().collect(),
-            Box::new(tracker_rx_stats),
-            force_tracker_interval,
-            self.announce_port().unwrap_or(4240),
-            self.reqwest_client.clone(),
-            self.udp_tracker_client.clone(),
-        );
-
         let initial_peers_rx = if initial_peers.is_empty() {
             None
         } else {
             Some(futures::stream::iter(initial_peers))
         };
-        merge_two_optional_streams(
-            merge_two_optional_streams(
-                merge_two_optional_streams(dht_rx, tracker_rx),
-                initial_peers_rx,
-            ),
-            lsd_rx,
-        )
+        merge_two_optional_streams(swarm_rx, initial_peers_rx)
+    }
+
+    // Announce to the torrent's trackers, updating the announces every time the tracker list
+    // changes. When announcing to all trackers, each one has its own task, so only the trackers
+    // that were added or removed are touched. With tiers, the order matters, so the one tiered
+    // task restarts.
+    fn make_tracker_rx(
+        self: &Arc<Self>,
+        info_hash: Id20,
+        mut trackers: tokio::sync::watch::Receiver<Vec<Vec<url::Url>>>,
+        is_private: bool,
+        force_tracker_interval: Option<Duration>,
+        tracker_statuses: TrackerStatuses,
+        announce_events: AnnounceEvents,
+    ) -> BoxStream<'static, SocketAddr> {
+        let session = Arc::downgrade(self);
+        async_stream::stream! {
+            // The running announce tasks by the tiers they announce to. Dropping the guard stops
+            // the task.
+            let mut running: HashMap<Vec<Vec<url::Url>>, DropGuard> = HashMap::new();
+            let mut comms = futures::stream::SelectAll::new();
+            loop {
+                {
+                    let Some(session) = session.upgrade() else {
+                        break;
+                    };
+                    let tiers =
+                        session.effective_trackers(trackers.borrow_and_update().clone(), is_private);
+                    tracker_statuses.set_tiers(info_hash, &tiers);
+                    let announce_to_all = session.announce_to_all_trackers && !is_private;
+                    let wanted = if announce_to_all {
+                        tiers.into_iter().flatten().map(|url| vec![vec![url]]).collect_vec()
+                    } else {
+                        vec![tiers]
+                    };
+                    running.retain(|tiers, _| wanted.contains(tiers));
+                    for tiers in wanted {
+                        if running.contains_key(&tiers) {
+                            continue;
+                        }
+                        let tracker_rx_stats = PeerRxTorrentInfo {
+                            info_hash,
+                            session: session.clone(),
+                        };
+                        let Some(stream) = TrackerComms::start(
+                            info_hash,
+                            session.peer_id,
+                            tiers.clone(),
+                            announce_to_all,
+                            Box::new(tracker_rx_stats),
+                            force_tracker_interval,
+                            session.announce_port().unwrap_or(4240),
+                            session.reqwest_client.clone(),
+                            session.udp_tracker_client.clone(),
+                            tracker_statuses.clone(),
+                            announce_events.clone(),
+                        ) else {
+                            continue;
+                        };
+                        let token = CancellationToken::new();
+                        comms.push(stream.take_until(token.clone().cancelled_owned()).boxed());
+                        running.insert(tiers, token.drop_guard());
+                    }
+                }
+                loop {
+                    tokio::select! {
+                        Some(addr) = comms.next(), if !comms.is_empty() => yield addr,
+                        changed = trackers.changed() => {
+                            if changed.is_err() {
+                                return;
+                            }
+                            debug!(?info_hash, "trackers changed, updating announces");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+
+    // Announce "stopped" for a torrent that is about to stop, which must be called while it's
+    // still live. None if the torrent isn't live, so there is nothing to announce.
+    fn stopped_announce(
+        &self,
+        handle: &ManagedTorrentHandle,
+    ) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let trackers = self.effective_tracker_urls(handle);
+        self.stopped_announce_to(handle, trackers)
+    }
+
+    // Announce "stopped" to some of the trackers of a live torrent, e.g. the ones removed from it.
+    fn stopped_announce_to(
+        &self,
+        handle: &ManagedTorrentHandle,
+        trackers: Vec<url::Url>,
+    ) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let stats = tracker_comms_stats(handle);
+        if !matches!(stats.torrent_state, TrackerCommsStatsState::Live) {
+            return None;
+        }
+        // The next announce to these begins with "started" again.
+        for url in trackers.iter() {
+            handle.announce_events.forget(url);
+        }
+        if trackers.is_empty() {
+            return None;
+        }
+        let announces = swarm_info_hashes(handle.info_hash(), handle.info_hash_v2())
+            .map(|info_hash| {
+                TrackerComms::announce_stopped(
+                    info_hash,
+                    self.peer_id,
+                    trackers.clone(),
+                    stats.clone(),
+                    self.announce_port().unwrap_or(4240),
+                    self.reqwest_client.clone(),
+                    self.udp_tracker_client.clone(),
+                    STOPPED_ANNOUNCE_TIMEOUT,
+                )
+            })
+            .collect_vec();
+        Some(futures::future::join_all(announces).map(|_| ()))
+    }
+
+    fn spawn_stopped_announce(&self, handle: &ManagedTorrentHandle) {
+        let trackers = self.effective_tracker_urls(handle);
+        self.spawn_stopped_announce_to(handle, trackers);
+    }
+
+    fn spawn_stopped_announce_to(&self, handle: &ManagedTorrentHandle, trackers: Vec<url::Url>) {
+        if let Some(announce) = self.stopped_announce_to(handle, trackers) {
+            self.spawn(
+                debug_span!(parent: self.rs(), "announce_stopped", id = handle.id()),
+                "announce_stopped",
+                announce.map(Ok::<_, anyhow::Error>),
+            );
+        }
     }
 
-    async fn try_update_persistence_metadata(&self, handle: &ManagedTorrentHandle) {
+    pub(crate) async fn try_update_persistence_metadata(&self, handle: &ManagedTorrentHandle) {
         if let Some(p) = self.persistence.as_ref()
             && let Err(e) = p.update_metadata(handle.id(), handle).await
         {
@@ -1602,18 +2379,61 @@ impl Session {
     }
 
     pub async fn pause(&self, handle: &ManagedTorrentHandle) -> anyhow::Result<()> {
+        self.spawn_stopped_announce(handle);
         handle.pause()?;
         self.try_update_persistence_metadata(handle).await;
+        self.queue_notify.notify_one();
         Ok(())
     }
 
+    /// Resume the torrent. If queueing is enabled, the torrent is started once it
+    /// gets a slot in the queue.
     pub async fn unpause(self: &Arc<Self>, handle: &ManagedTorrentHandle) -> anyhow::Result<()> {
-        let peer_rx = self.make_peer_rx_managed_torrent(handle, true);
-        handle.start(peer_rx, false)?;
+        if self.queue.is_enabled() {
+            handle.start_queued()?;
+            self.queue_notify.notify_one();
+        } else {
+            let peer_rx = self.make_peer_rx_managed_torrent(handle, true);
+            handle.start(peer_rx, false)?;
+        }
         self.try_update_persistence_metadata(handle).await;
         Ok(())
     }
 
+    /// Move the torrent within the session queue.
+    pub async fn move_in_queue(
+        &self,
+        handle: &ManagedTorrentHandle,
+        movement: QueueMove,
+    ) -> anyhow::Result<()> {
+        let changed = {
+            let db = self.db.read();
+            let mut order = db.torrents.values().cloned().collect::<Vec<_>>();
+            order.sort_by_key(|t| (t.queue_position(), t.id()));
+            let idx = order
+                .iter()
+                .position(|t| Arc::ptr_eq(t, handle))
+                .context("torrent not found in the session")?;
+            move_queue_item(&mut order, idx, movement);
+
+            // Renumber the whole queue, this also closes the gaps left by removed torrents.
+            order
+                .into_iter()
+                .enumerate()
+                .filter(|(pos, t)| t.queue_position() != *pos)
+                .map(|(pos, t)| {
+                    t.set_queue_position(pos);
+                    t
+                })
+                .collect::<Vec<_>>()
+        };
+        for t in changed {
+            self.try_update_persistence_metadata(&t).await;
+        }
+        self.queue_notify.notify_one();
+        Ok(())
+    }
+
     pub async fn update_only_files(
         self: &Arc<Self>,
         handle: &ManagedTorrentHandle,
@@ -1624,6 +2444,99 @@ impl Session {
         Ok(())
     }
 
+    pub async fn update_file_priorities(
+        &self,
+        handle: &ManagedTorrentHandle,
+        file_priorities: &[FilePriority],
+    ) -> anyhow::Result<()> {
+        handle.update_file_priorities(file_priorities)?;
+        self.try_update_persistence_metadata(handle).await;
+        Ok(())
+    }
+
+    pub async fn update_seed_goals(
+        &self,
+        handle: &ManagedTorrentHandle,
+        seed_goals: SeedGoals,
+    ) -> anyhow::Result<()> {
+        handle.update_seed_goals(seed_goals);
+        self.try_update_persistence_metadata(handle).await;
+        Ok(())
+    }
+
+    /// Add trackers to the torrent. The announces restart without restarting the torrent.
+    /// If "tier" isn't set, each tracker is added in a new tier after the existing ones.
+    pub async fn add_trackers(
+        &self,
+        handle: &ManagedTorrentHandle,
+        trackers: &[url::Url],
+        tier: Option<usize>,
+    ) -> anyhow::Result<()> {
+        check_tracker_urls(trackers)?;
+        if let Some(tier) = tier
+            && tier > handle.tracker_tiers().len()
+        {
+            bail!("tier {tier} doesn't exist");
+        }
+        self.update_trackers(handle, |tiers| match tier {
+            Some(tier) if tier < tiers.len() => tiers[tier].extend(trackers.iter().cloned()),
+            Some(_) => tiers.push(trackers.to_vec()),
+            None => tiers.extend(trackers.iter().map(|t| vec![t.clone()])),
+        })
+        .await;
+        Ok(())
+    }
+
+    pub async fn remove_trackers(
+        &self,
+        handle: &ManagedTorrentHandle,
+        trackers: &[url::Url],
+    ) -> anyhow::Result<()> {
+        self.update_trackers(handle, |tiers| {
+            for tier in tiers.iter_mut() {
+                tier.retain(|u| !trackers.contains(u));
+            }
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Replace a tracker keeping its position in the list, e.g. when the tracker moved to another
+    /// domain.
+    pub async fn replace_tracker(
+        &self,
+        handle: &ManagedTorrentHandle,
+        old: &url::Url,
+        new: url::Url,
+    ) -> anyhow::Result<()> {
+        check_tracker_urls(std::slice::from_ref(&new))?;
+        if !handle.trackers().contains(old) {
+            bail!("torrent doesn't have tracker {old}");
+        }
+        self.update_trackers(handle, |tiers| {
+            for u in tiers.iter_mut().flatten().filter(|u| *u == old) {
+                *u = new.clone();
+            }
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn update_trackers(
+        &self,
+        handle: &ManagedTorrentHandle,
+        f: impl FnOnce(&mut Vec<Vec<url::Url>>),
+    ) {
+        let old = self.effective_tracker_urls(handle);
+        if handle.update_trackers(f) {
+            info!(id = handle.id(), tiers = ?handle.tracker_tiers(), "updated trackers");
+            let new = self.effective_tracker_urls(handle);
+            let removed = old.into_iter().filter(|u| !new.contains(u)).collect_vec();
+            self.spawn_stopped_announce_to(handle, removed);
+            self.try_update_persistence_metadata(handle).await;
+        }
+    }
+
     pub fn listen_addr(&self) -> Option<SocketAddr> {
         self.listen_addr
     }
@@ -1635,13 +2548,15 @@ impl Session {
     async fn resolve_magnet(
         self: &Arc<Self>,
         info_hash: Id20,
+        info_hash_v2: Option<Id32>,
         peer_rx: PeerStream,
-        trackers: &[url::Url],
+        tracker_tiers: &[Vec<url::Url>],
         peer_opts: Option<PeerConnectionOptions>,
     ) -> anyhow::Result<ResolveMagnetResult> {
         match read_metainfo_from_peer_receiver(
             self.peer_id,
             info_hash,
+            info_hash_v2,
             Default::default(),
             peer_rx,
             Some(self.merge_peer_opts(peer_opts)),
@@ -1653,16 +2568,23 @@ impl Session {
             ReadMetainfoResult::Found {
                 info,
                 info_bytes,
+                piece_layers,
                 rx,
                 seen,
             } => {
                 trace!(?info, "received result from DHT");
                 let info = info.validate()?;
+                let piece_layers = PieceLayers::from_torrent(&info, piece_layers.as_ref())?;
                 Ok(ResolveMagnetResult {
                     metadata: TorrentMetadata::new(
                         info,
-                        torrent_file_from_info_bytes(info_bytes.as_ref(), trackers)?,
+                        torrent_file_from_info_bytes(
+                            info_bytes.as_ref(),
+                            &piece_layers,
+                            tracker_tiers,
+                        )?,
                         info_bytes.0,
+                        piece_layers,
                     )?,
                     peer_rx: rx,
                     seen_peers: {
@@ -1722,12 +2644,65 @@ impl Session {
     }
 }
 
+// BEP 46: a mutable torrent that is polled for new versions.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MutableTorrentWatch {
+    pub key: MutableTorrentKey,
+    // The sequence number of the current version, if known.
+    pub seq: Option<i64>,
+    pub trackers: Vec<String>,
+    pub retire_previous_versions: bool,
+    // The options the torrent was added with, for adding its new versions.
+    pub options: serde_json::Value,
+}
+
+impl MutableTorrentWatch {
+    pub(crate) fn new_version_options(&self) -> AddTorrentOptions {
+        let opts = match serde_json::from_value::<AddTorrentOptions>(self.options.clone()) {
+            Ok(opts) => opts,
+            Err(e) => {
+                warn!("error deserializing options of mutable torrent: {e:#}");
+                Default::default()
+            }
+        };
+        // Only the settings are carried over, not the state of the previous version.
+        AddTorrentOptions {
+            list_only: false,
+            preferred_id: None,
+            seeding_stats: Default::default(),
+            queue_position: None,
+            tracker_tiers: None,
+            ..opts
+        }
+    }
+}
+
 pub(crate) struct ResolveMagnetResult {
     pub metadata: TorrentMetadata,
     pub peer_rx: PeerStream,
     pub seen_peers: Vec<SocketAddr>,
 }
 
+fn parse_tracker_tiers(tiers: &[Vec<String>]) -> Vec<Vec<url::Url>> {
+    tiers
+        .iter()
+        .map(|tier| {
+            tier.iter()
+                .filter_map(|t| url::Url::parse(t).ok())
+                .collect()
+        })
+        .collect()
+}
+
+fn check_tracker_urls(trackers: &[url::Url]) -> anyhow::Result<()> {
+    for t in trackers {
+        if !matches!(t.scheme(), "http" | "https" | "udp") {
+            bail!("unsupported tracker URL {t}, expected http, https or udp");
+        }
+    }
+    Ok(())
+}
+
 fn remove_files_and_dirs(infos: &FileInfos, files: &dyn TorrentStorage) {
     let mut all_dirs = HashSet::new();
     for (id, fi) in infos.iter().enumerate() {
@@ -1762,46 +2737,261 @@ fn remove_files_and_dirs(infos: &FileInfos, files: &dyn TorrentStorage) {
     }
 }
 
+const QUEUE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_SLOW_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+// A torrent managed by the session queue.
+#[derive(Debug, Clone, Copy)]
+struct QueueEntry {
+    id: TorrentId,
+    seeding: bool,
+    active: bool,
+    // Active but too slow to count against the limits.
+    slow: bool,
+    scrape: Option<ScrapeStats>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct QueuePlan {
+    start: Vec<TorrentId>,
+    queue: Vec<TorrentId>,
+}
+
+// Leechers per seeder according to the tracker scrape, scaled to keep some precision.
+fn seeding_need(scrape: Option<ScrapeStats>) -> u64 {
+    scrape
+        .map(|s| u64::from(s.leechers) * 1000 / (u64::from(s.seeders) + 1))
+        .unwrap_or_default()
+}
+
+// Decide which torrents to start and which to queue. "entries" must be sorted by queue
+// position, and torrents higher in the queue take the slots first. The exception are seeds,
+// which take the slots in the order of how much their swarms need them.
+fn plan_queue(limits: &QueueLimits, entries: &[QueueEntry]) -> QueuePlan {
+    let mut entries = entries.to_vec();
+    let seed_slots = (0..entries.len())
+        .filter(|i| entries[*i].seeding)
+        .collect::<Vec<_>>();
+    let mut seeds = seed_slots.iter().map(|i| entries[*i]).collect::<Vec<_>>();
+    // Stable, so ties keep the queue order.
+    seeds.sort_by_key(|e| std::cmp::Reverse(seeding_need(e.scrape)));
+    for (slot, e) in seed_slots.into_iter().zip(seeds) {
+        entries[slot] = e;
+    }
+
+    let fits = |limit: Option<usize>, count: usize| limit.is_none_or(|l| count < l);
+    let mut plan = QueuePlan::default();
+    let (mut downloads, mut seeds) = (0, 0);
+    for e in entries.iter() {
+        if e.active && e.slow {
+            continue;
+        }
+        let has_slot = fits(limits.max_active_torrents, downloads + seeds)
+            && if e.seeding {
+                fits(limits.max_active_seeds, seeds)
+            } else {
+                fits(limits.max_active_downloads, downloads)
+            };
+        if has_slot {
+            if e.seeding {
+                seeds += 1;
+            } else {
+                downloads += 1;
+            }
+            if !e.active {
+                plan.start.push(e.id);
+            }
+        } else if e.active {
+            plan.queue.push(e.id);
+        }
+    }
+    plan
+}
+
+fn move_queue_item<T>(order: &mut Vec<T>, idx: usize, movement: QueueMove) {
+    let item = order.remove(idx);
+    let new_idx = match movement {
+        QueueMove::Up => idx.saturating_sub(1),
+        QueueMove::Down => (idx + 1).min(order.len()),
+        QueueMove::Top => 0,
+        QueueMove::Bottom => order.len(),
+    };
+    order.insert(new_idx, item);
+}
+
+impl Session {
+    fn start_queue_manager(self: &Arc<Self>) {
+        if !self.queue.is_enabled() {
+            return;
+        }
+        self.spawn(
+            debug_span!(parent: self.rs(), "queue_manager"),
+            "queue_manager",
+            {
+                let s = Arc::downgrade(self);
+                let notify = self.queue_notify.clone();
+                async move {
+                    let mut active_since = HashMap::new();
+                    let mut i = tokio::time::interval(QUEUE_CHECK_INTERVAL);
+                    loop {
+                        tokio::select! {
+                            _ = i.tick() => {},
+                            _ = notify.notified() => {},
+                        }
+                        let s = s.upgrade().context("session is dead")?;
+                        s.update_queue(&mut active_since);
+                    }
+                }
+            },
+        )
+    }
+
+    // active_since tracks when the queue first saw each torrent live, so that
+    // torrents which just started aren't considered slow.
+    fn update_queue(self: &Arc<Self>, active_since: &mut HashMap<TorrentId, Instant>) {
+        let now = Instant::now();
+        let grace_period = self
+            .queue
+            .slow_grace_period
+            .unwrap_or(DEFAULT_SLOW_GRACE_PERIOD);
+        let mut torrents = self.with_torrents(|it| it.map(|(_, t)| t.clone()).collect::<Vec<_>>());
+        torrents.sort_by_key(|t| (t.queue_position(), t.id()));
+
+        let mut still_active = HashMap::new();
+        let mut entries = Vec::new();
+        for t in torrents.iter() {
+            let id = t.id();
+            if t.is_paused() {
+                continue;
+            }
+            if let Some(live) = t.live() {
+                let seeding = live.is_finished();
+                let since = *active_since.entry(id).or_insert(now);
+                still_active.insert(id, since);
+                let (speed, threshold) = if seeding {
+                    (
+                        live.up_speed_estimator().bps(),
+                        self.queue.slow_upload_threshold_bps,
+                    )
+                } else {
+                    (
+                        live.down_speed_estimator().bps(),
+                        self.queue.slow_download_threshold_bps,
+                    )
+                };
+                entries.push(QueueEntry {
+                    id,
+                    seeding,
+                    active: true,
+                    slow: now - since >= grace_period && threshold.is_some_and(|t| speed < t),
+                    scrape: t.scrape(),
+                });
+            } else if t.is_queued() {
+                // Only torrents that finished the initial check can be started.
+                if let Ok(seeding) = t.with_chunk_tracker(|c| c.is_finished()) {
+                    entries.push(QueueEntry {
+                        id,
+                        seeding,
+                        active: false,
+                        slow: false,
+                        scrape: t.scrape(),
+                    });
+                }
+            }
+        }
+        *active_since = still_active;
+
+        let plan = plan_queue(&self.queue, &entries);
+        let get = |id: TorrentId| torrents.iter().find(|t| t.id() == id);
+        for t in plan.queue.into_iter().filter_map(get) {
+            self.spawn_stopped_announce(t);
+            match t.queue() {
+                Ok(()) => debug!(id = t.id(), "queued torrent"),
+                Err(e) => warn!(id = t.id(), "error queueing torrent: {e:#}"),
+            }
+        }
+        for t in plan.start.into_iter().filter_map(get) {
+            let peer_rx = self.make_peer_rx_managed_torrent(t, true);
+            match t.start(peer_rx, false) {
+                Ok(()) => debug!(id = t.id(), "started torrent from the queue"),
+                Err(e) => warn!(id = t.id(), "error starting queued torrent: {e:#}"),
+            }
+        }
+    }
+}
+
 // Ad adapter for converting stats into the format that tracker_comms accepts.
 struct PeerRxTorrentInfo {
     info_hash: Id20,
     session: Arc<Session>,
 }
 
-impl tracker_comms::TorrentStatsProvider for PeerRxTorrentInfo {
-    fn get(&self) -> tracker_comms::TrackerCommsStats {
-        let mt = self.session.with_torrents(|torrents| {
+impl PeerRxTorrentInfo {
+    fn torrent(&self) -> Option<ManagedTorrentHandle> {
+        self.session.with_torrents(|torrents| {
             for (_, mt) in torrents {
-                if mt.info_hash() == self.info_hash {
+                if mt.matches_info_hash(self.info_hash) {
                     return Some(mt.clone());
                 }
             }
             None
-        });
-        let mt = match mt {
-            Some(mt) => mt,
+        })
+    }
+}
+
+impl tracker_comms::TorrentStatsProvider for PeerRxTorrentInfo {
+    fn get(&self) -> tracker_comms::TrackerCommsStats {
+        match self.torrent() {
+            Some(mt) => tracker_comms_stats(&mt),
             None => {
                 trace!(info_hash=?self.info_hash, "can't find torrent in the session, using default stats");
-                return Default::default();
+                Default::default()
             }
-        };
-        let stats = mt.stats();
-
-        use crate::torrent_state::stats::TorrentStatsState as TS;
-        use tracker_comms::TrackerCommsStatsState as S;
-
-        tracker_comms::TrackerCommsStats {
-            downloaded_bytes: stats.progress_bytes,
-            total_bytes: stats.total_bytes,
-            uploaded_bytes: stats.uploaded_bytes,
-            torrent_state: match stats.state {
-                TS::Initializing { .. } => S::Initializing,
-                TS::Live => S::Live,
-                TS::Paused => S::Paused,
-                TS::Error => S::None,
-            },
         }
     }
+
+    fn wait_until_completed(&self) -> BoxFuture<'_, ()> {
+        async move {
+            if let Some(mt) = self.torrent()
+                && mt.wait_until_completed().await.is_ok()
+            {
+                return;
+            }
+            futures::future::pending().await
+        }
+        .boxed()
+    }
+}
+
+// Hybrid torrents are in two swarms, the v2 one is keyed by the truncated v2 info hash.
+pub(crate) fn swarm_info_hashes(
+    info_hash: Id20,
+    info_hash_v2: Option<Id32>,
+) -> impl Iterator<Item = Id20> {
+    std::iter::once(info_hash).chain(
+        info_hash_v2
+            .map(|h| h.truncate_for_dht())
+            .filter(move |h| *h != info_hash),
+    )
+}
+
+fn tracker_comms_stats(mt: &ManagedTorrent) -> tracker_comms::TrackerCommsStats {
+    let stats = mt.stats();
+
+    use crate::torrent_state::stats::TorrentStatsState as TS;
+    use tracker_comms::TrackerCommsStatsState as S;
+
+    tracker_comms::TrackerCommsStats {
+        downloaded_bytes: stats.progress_bytes,
+        total_bytes: stats.total_bytes,
+        uploaded_bytes: stats.uploaded_bytes,
+        torrent_state: match stats.state {
+            TS::Initializing { .. } => S::Initializing,
+            TS::Live => S::Live,
+            TS::Paused => S::Paused,
+            TS::Error => S::None,
+        },
+    }
 }
 
 #[cfg(test)]
@@ -1810,14 +3000,24 @@ mod tests {
     use itertools::Itertools;
     use librqbit_core::torrent_metainfo::{TorrentMetaV1, torrent_from_bytes};
 
-    use super::torrent_file_from_info_bytes;
+    use tracker_comms::ScrapeStats;
+
+    use super::{
+        QueueEntry, QueueLimits, QueueMove, QueuePlan, move_queue_item, plan_queue,
+        torrent_file_from_info_bytes,
+    };
 
     #[test]
     fn test_torrent_file_from_info_and_bytes() {
-        fn get_trackers(info: &TorrentMetaV1<ByteBuf>) -> Vec<url::Url> {
-            info.iter_announce()
-                .filter_map(|t| std::str::from_utf8(t.as_ref()).ok().map(|t| t.to_owned()))
-                .filter_map(|t| t.parse().ok())
+        fn get_trackers(info: &TorrentMetaV1<ByteBuf>) -> Vec<Vec<url::Url>> {
+            info.announce_tiers()
+                .into_iter()
+                .map(|tier| {
+                    tier.into_iter()
+                        .filter_map(|t| std::str::from_utf8(t.as_ref()).ok())
+                        .filter_map(|t| t.parse().ok())
+                        .collect_vec()
+                })
                 .collect_vec()
         }
 
@@ -1826,11 +3026,143 @@ mod tests {
         let parsed = torrent_from_bytes(&orig_full_torrent[..]).unwrap();
         let parsed_trackers = get_trackers(&parsed);
 
-        let generated_torrent =
-            torrent_file_from_info_bytes(parsed.info.raw_bytes.as_ref(), &parsed_trackers).unwrap();
+        let generated_torrent = torrent_file_from_info_bytes(
+            parsed.info.raw_bytes.as_ref(),
+            &Default::default(),
+            &parsed_trackers,
+        )
+        .unwrap();
         let generated_parsed = torrent_from_bytes(generated_torrent.as_ref()).unwrap();
         assert_eq!(parsed.info_hash, generated_parsed.info_hash);
         assert_eq!(parsed.info, generated_parsed.info);
         assert_eq!(parsed_trackers, get_trackers(&generated_parsed));
     }
+
+    fn entry(id: usize, seeding: bool, active: bool, slow: bool) -> QueueEntry {
+        QueueEntry {
+            id,
+            seeding,
+            active,
+            slow,
+            scrape: None,
+        }
+    }
+
+    #[test]
+    fn test_queue_seeds_needed_most_take_slots_first() {
+        let limits = QueueLimits {
+            max_active_seeds: Some(2),
+            ..Default::default()
+        };
+        let with_scrape = |id: usize, active: bool, seeders: u32, leechers: u32| QueueEntry {
+            scrape: Some(ScrapeStats {
+                seeders,
+                leechers,
+                completed: 0,
+            }),
+            ..entry(id, true, active, false)
+        };
+        let entries = [
+            with_scrape(0, true, 100, 1),
+            entry(1, false, false, false),
+            with_scrape(2, false, 0, 10),
+            entry(3, true, true, false),
+            with_scrape(4, false, 1, 10),
+        ];
+        assert_eq!(
+            plan_queue(&limits, &entries),
+            QueuePlan {
+                start: vec![2, 1, 4],
+                queue: vec![0, 3],
+            }
+        );
+    }
+
+    #[test]
+    fn test_queue_starts_in_queue_order() {
+        let limits = QueueLimits {
+            max_active_downloads: Some(2),
+            max_active_seeds: Some(1),
+            ..Default::default()
+        };
+        let entries = [
+            entry(0, false, true, false),
+            entry(1, true, false, false),
+            entry(2, false, false, false),
+            entry(3, true, false, false),
+            entry(4, false, false, false),
+        ];
+        assert_eq!(
+            plan_queue(&limits, &entries),
+            QueuePlan {
+                start: vec![1, 2],
+                queue: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_queue_higher_positions_take_slots_first() {
+        let limits = QueueLimits {
+            max_active_torrents: Some(2),
+            ..Default::default()
+        };
+        let entries = [
+            entry(0, false, false, false),
+            entry(1, true, true, false),
+            entry(2, false, true, false),
+        ];
+        assert_eq!(
+            plan_queue(&limits, &entries),
+            QueuePlan {
+                start: vec![0],
+                queue: vec![2],
+            }
+        );
+    }
+
+    #[test]
+    fn test_queue_slow_torrents_dont_count() {
+        let limits = QueueLimits {
+            max_active_downloads: Some(1),
+            ..Default::default()
+        };
+        let entries = [
+            entry(0, false, true, true),
+            entry(1, false, true, false),
+            entry(2, false, false, false),
+        ];
+        assert_eq!(
+            plan_queue(&limits, &entries),
+            QueuePlan {
+                start: vec![],
+                queue: vec![],
+            }
+        );
+
+        // Not active torrents can't be slow.
+        let entries = [entry(0, false, true, true), entry(1, false, false, true)];
+        assert_eq!(
+            plan_queue(&limits, &entries),
+            QueuePlan {
+                start: vec![1],
+                queue: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_move_queue_item() {
+        let check = |idx: usize, movement: QueueMove, expected: [u8; 4]| {
+            let mut order = vec![0, 1, 2, 3];
+            move_queue_item(&mut order, idx, movement);
+            assert_eq!(order, expected, "idx={idx}, movement={movement:?}");
+        };
+        check(2, QueueMove::Up, [0, 2, 1, 3]);
+        check(0, QueueMove::Up, [0, 1, 2, 3]);
+        check(1, QueueMove::Down, [0, 2, 1, 3]);
+        check(3, QueueMove::Down, [0, 1, 2, 3]);
+        check(2, QueueMove::Top, [2, 0, 1, 3]);
+        check(1, QueueMove::Bottom, [0, 2, 3, 1]);
+    }
 }
@@ -19,7 +19,9 @@ use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::{debug, trace, warn};
 
-use super::{SerializedTorrent, SessionPersistenceStore};
+use super::{
+    SerializedTorrent, SessionPersistenceStore, serialize_mutable_watch, tracker_tiers_to_strings,
+};
 
 #[derive(Serialize, Deserialize, Default)]
 struct SerializedSessionDatabase {
@@ -136,18 +138,20 @@ impl JsonSessionPersistenceStore {
         }
 
         let st = SerializedTorrent {
-            trackers: torrent
-                .shared()
-                .trackers
-                .iter()
-                .map(|u| u.to_string())
-                .collect(),
+            trackers: torrent.trackers().iter().map(|u| u.to_string()).collect(),
             info_hash: torrent.info_hash(),
             // we don't serialize this here, but to a file instead.
             torrent_bytes: Default::default(),
             only_files: torrent.only_files().clone(),
+            file_priorities: torrent.file_priorities(),
             is_paused: torrent.is_paused(),
             output_folder: torrent.shared().options.output_folder.clone(),
+            seed_goals: torrent.seed_goals(),
+            seeding_stats: torrent.seeding_stats(),
+            queue_position: Some(torrent.queue_position()),
+            tracker_tiers: Some(tracker_tiers_to_strings(torrent)),
+            upload_slots: torrent.shared().options.upload_slots,
+            mutable_watch: serialize_mutable_watch(torrent),
         };
 
         let torrent_bytes = torrent
@@ -9,13 +9,16 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use futures::stream::BoxStream;
 use librqbit_core::Id20;
-use librqbit_core::magnet::Magnet;
+use librqbit_core::magnet::{Magnet, MutableTorrentKey};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
-    AddTorrent, AddTorrentOptions, bitv_factory::BitVFactory, session::TorrentId,
+    AddTorrent, AddTorrentOptions, FilePriority, SeedGoals, SeedingStats,
+    bitv_factory::BitVFactory,
+    session::{MutableTorrentWatch, TorrentId},
     torrent_state::ManagedTorrentHandle,
 };
+use tracing::warn;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SerializedTorrent {
@@ -29,13 +32,91 @@ pub struct SerializedTorrent {
     trackers: HashSet<String>,
     output_folder: PathBuf,
     only_files: Option<Vec<usize>>,
+    #[serde(default)]
+    file_priorities: Option<Vec<FilePriority>>,
     is_paused: bool,
+    #[serde(default)]
+    seed_goals: SeedGoals,
+    #[serde(default)]
+    seeding_stats: SeedingStats,
+    #[serde(default)]
+    queue_position: Option<usize>,
+    // Tracker tiers (BEP 12). "trackers" is kept for older versions and for magnets.
+    #[serde(default)]
+    tracker_tiers: Option<Vec<Vec<String>>>,
+    #[serde(default)]
+    upload_slots: Option<usize>,
+    #[serde(default)]
+    mutable_watch: Option<SerializedMutableWatch>,
+}
+
+// BEP 46 mutable torrent watch, see MutableTorrentWatch.
+#[derive(Serialize, Deserialize, Clone)]
+struct SerializedMutableWatch {
+    // Hex-encoded.
+    public_key: String,
+    // Hex-encoded.
+    salt: String,
+    seq: Option<i64>,
+    #[serde(default)]
+    trackers: Vec<String>,
+    #[serde(default)]
+    retire_previous_versions: bool,
+    #[serde(default)]
+    options: serde_json::Value,
+}
+
+impl From<MutableTorrentWatch> for SerializedMutableWatch {
+    fn from(w: MutableTorrentWatch) -> Self {
+        Self {
+            public_key: hex::encode(w.key.public_key),
+            salt: hex::encode(&w.key.salt),
+            seq: w.seq,
+            trackers: w.trackers,
+            retire_previous_versions: w.retire_previous_versions,
+            options: w.options,
+        }
+    }
+}
+
+impl SerializedMutableWatch {
+    fn into_watch(self) -> anyhow::Result<MutableTorrentWatch> {
+        let mut public_key = [0u8; 32];
+        hex::decode_to_slice(&self.public_key, &mut public_key)
+            .context("invalid public key of mutable torrent")?;
+        Ok(MutableTorrentWatch {
+            key: MutableTorrentKey {
+                public_key,
+                salt: hex::decode(&self.salt).context("invalid salt of mutable torrent")?,
+            },
+            seq: self.seq,
+            trackers: self.trackers,
+            retire_previous_versions: self.retire_previous_versions,
+            options: self.options,
+        })
+    }
+}
+
+fn serialize_mutable_watch(torrent: &ManagedTorrentHandle) -> Option<SerializedMutableWatch> {
+    torrent.mutable_watch().map(Into::into)
 }
 
 impl SerializedTorrent {
     pub fn info_hash(&self) -> &Id20 {
         &self.info_hash
     }
+
+    // The torrent is the current version of a mutable torrent, which needs to be watched.
+    pub(crate) fn mutable_watch(&self) -> Option<MutableTorrentWatch> {
+        let watch = self.mutable_watch.clone()?;
+        match watch.into_watch() {
+            Ok(watch) => Some(watch),
+            Err(e) => {
+                warn!(info_hash = ?self.info_hash, "not watching mutable torrent: {e:#}");
+                None
+            }
+        }
+    }
     pub fn into_add_torrent(self) -> anyhow::Result<(AddTorrent<'static>, AddTorrentOptions)> {
         let add_torrent = if !self.torrent_bytes.is_empty() {
             AddTorrent::TorrentFileBytes(self.torrent_bytes)
@@ -58,6 +139,12 @@ impl SerializedTorrent {
                     .to_owned(),
             ),
             only_files: self.only_files,
+            file_priorities: self.file_priorities,
+            seed_goals: self.seed_goals,
+            seeding_stats: self.seeding_stats,
+            queue_position: self.queue_position,
+            tracker_tiers: self.tracker_tiers,
+            upload_slots: self.upload_slots,
             overwrite: true,
             ..Default::default()
         };
@@ -66,6 +153,14 @@ impl SerializedTorrent {
     }
 }
 
+fn tracker_tiers_to_strings(torrent: &ManagedTorrentHandle) -> Vec<Vec<String>> {
+    torrent
+        .tracker_tiers()
+        .iter()
+        .map(|tier| tier.iter().map(|u| u.to_string()).collect())
+        .collect()
+}
+
 // TODO: make this info_hash first, ID-second.
 #[async_trait]
 pub trait SessionPersistenceStore: core::fmt::Debug + Send + Sync + BitVFactory {
@@ -10,7 +10,9 @@ use librqbit_core::{Id20, spawn_utils::spawn};
 use sqlx::{Pool, Postgres};
 use tracing::debug_span;
 
-use super::{SerializedTorrent, SessionPersistenceStore};
+use super::{
+    SerializedTorrent, SessionPersistenceStore, serialize_mutable_watch, tracker_tiers_to_strings,
+};
 
 #[derive(Debug)]
 pub struct PostgresSessionStorage {
@@ -26,6 +28,18 @@ struct TorrentsTableRecord {
     output_folder: String,
     only_files: Option<Vec<i32>>,
     is_paused: bool,
+    // JSON-serialized list of FilePriority.
+    file_priorities: Option<String>,
+    // JSON-serialized SeedGoals.
+    seed_goals: Option<String>,
+    queue_position: Option<i32>,
+    // JSON-serialized list of tracker tiers.
+    tracker_tiers: Option<String>,
+    // JSON-serialized SeedingStats.
+    seeding_stats: Option<String>,
+    upload_slots: Option<i32>,
+    // JSON-serialized SerializedMutableWatch.
+    mutable_watch: Option<String>,
 }
 
 impl TorrentsTableRecord {
@@ -40,7 +54,26 @@ impl TorrentsTableRecord {
                 only_files: self
                     .only_files
                     .map(|v| v.into_iter().map(|v| v as usize).collect()),
+                file_priorities: self
+                    .file_priorities
+                    .and_then(|s| serde_json::from_str(&s).ok()),
                 is_paused: self.is_paused,
+                seed_goals: self
+                    .seed_goals
+                    .and_then(|s| serde_json::from_str(&s).ok())
+                    .unwrap_or_default(),
+                queue_position: self.queue_position.map(|p| p as usize),
+                tracker_tiers: self
+                    .tracker_tiers
+                    .and_then(|s| serde_json::from_str(&s).ok()),
+                seeding_stats: self
+                    .seeding_stats
+                    .and_then(|s| serde_json::from_str(&s).ok())
+                    .unwrap_or_default(),
+                upload_slots: self.upload_slots.map(|s| s as usize),
+                mutable_watch: self
+                    .mutable_watch
+                    .and_then(|s| serde_json::from_str(&s).ok()),
             },
         ))
     }
@@ -80,6 +113,13 @@ impl PostgresSessionStorage {
         );
 
         exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS have_bitfield BYTEA");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS seed_goals TEXT");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS queue_position INTEGER");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS file_priorities TEXT");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS tracker_tiers TEXT");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS seeding_stats TEXT");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS upload_slots INTEGER");
+        exec!("ALTER TABLE torrents ADD COLUMN IF NOT EXISTS mutable_watch TEXT");
 
         Ok(Self { pool })
     }
@@ -102,8 +142,8 @@ impl SessionPersistenceStore for PostgresSessionStorage {
             .as_ref()
             .map(|i| i.torrent_bytes.clone())
             .unwrap_or_default();
-        let q = "INSERT INTO torrents (id, info_hash, torrent_bytes, trackers, output_folder, only_files, is_paused)
-        VALUES($1, $2, $3, $4, $5, $6, $7)
+        let q = "INSERT INTO torrents (id, info_hash, torrent_bytes, trackers, output_folder, only_files, is_paused, seed_goals, queue_position, file_priorities, tracker_tiers, seeding_stats, upload_slots, mutable_watch)
+        VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
         ON CONFLICT(id) DO NOTHING";
         sqlx::query(q)
             .bind::<i32>(id.try_into()?)
@@ -111,8 +151,7 @@ impl SessionPersistenceStore for PostgresSessionStorage {
             .bind(torrent_bytes.as_ref())
             .bind(
                 torrent
-                    .shared()
-                    .trackers
+                    .trackers()
                     .iter()
                     .map(|t| t.to_string())
                     .collect::<Vec<_>>(),
@@ -132,6 +171,29 @@ impl SessionPersistenceStore for PostgresSessionStorage {
                     .collect::<Vec<i32>>()
             }))
             .bind(torrent.is_paused())
+            .bind(serde_json::to_string(&torrent.seed_goals())?)
+            .bind::<i32>(torrent.queue_position().try_into()?)
+            .bind(
+                torrent
+                    .file_priorities()
+                    .map(|p| serde_json::to_string(&p))
+                    .transpose()?,
+            )
+            .bind(serde_json::to_string(&tracker_tiers_to_strings(torrent))?)
+            .bind(serde_json::to_string(&torrent.seeding_stats())?)
+            .bind(
+                torrent
+                    .shared()
+                    .options
+                    .upload_slots
+                    .map(i32::try_from)
+                    .transpose()?,
+            )
+            .bind(
+                serialize_mutable_watch(torrent)
+                    .map(|w| serde_json::to_string(&w))
+                    .transpose()?,
+            )
             .execute(&self.pool)
             .await
             .context("error executing INSERT INTO torrents")?;
@@ -163,17 +225,41 @@ impl SessionPersistenceStore for PostgresSessionStorage {
         id: TorrentId,
         torrent: &ManagedTorrentHandle,
     ) -> anyhow::Result<()> {
-        sqlx::query("UPDATE torrents SET only_files = $1, is_paused = $2 WHERE id = $3")
-            .bind(torrent.only_files().map(|v| {
-                v.into_iter()
-                    .filter_map(|f| f.try_into().ok())
-                    .collect::<Vec<i32>>()
-            }))
-            .bind(torrent.is_paused())
-            .bind::<i32>(id.try_into()?)
-            .execute(&self.pool)
-            .await
-            .context("error executing UPDATE torrents")?;
+        sqlx::query(
+            "UPDATE torrents SET only_files = $1, is_paused = $2, seed_goals = $3, queue_position = $4, file_priorities = $5, trackers = $6, tracker_tiers = $7, seeding_stats = $8, mutable_watch = $9 WHERE id = $10",
+        )
+        .bind(torrent.only_files().map(|v| {
+            v.into_iter()
+                .filter_map(|f| f.try_into().ok())
+                .collect::<Vec<i32>>()
+        }))
+        .bind(torrent.is_paused())
+        .bind(serde_json::to_string(&torrent.seed_goals())?)
+        .bind::<i32>(torrent.queue_position().try_into()?)
+        .bind(
+            torrent
+                .file_priorities()
+                .map(|p| serde_json::to_string(&p))
+                .transpose()?,
+        )
+        .bind(
+            torrent
+                .trackers()
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .bind(serde_json::to_string(&tracker_tiers_to_strings(torrent))?)
+        .bind(serde_json::to_string(&torrent.seeding_stats())?)
+        .bind(
+            serialize_mutable_watch(torrent)
+                .map(|w| serde_json::to_string(&w))
+                .transpose()?,
+        )
+        .bind::<i32>(id.try_into()?)
+        .execute(&self.pool)
+        .await
+        .context("error executing UPDATE torrents")?;
         Ok(())
     }
 
@@ -1,6 +1,8 @@
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::{Context, bail};
+use futures::TryFutureExt;
+use librqbit_core::hash_id::Id20;
 use librqbit_dualstack_sockets::ConnectOpts;
 use librqbit_utp::{BindDevice, UtpSocketUdp};
 use serde::Serialize;
@@ -8,10 +10,14 @@ use tracing::debug;
 
 use crate::{
     Error, PeerConnectionOptions, Result,
+    mse::{self, EncryptionPolicy},
+    peer_connection::with_timeout,
     type_aliases::{BoxAsyncReadVectored, BoxAsyncWrite},
     vectored_traits::AsyncReadVectoredIntoCompat,
 };
 
+const MSE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, Copy, Serialize)]
 pub enum ConnectionKind {
     #[serde(rename = "tcp")]
@@ -20,6 +26,9 @@ pub enum ConnectionKind {
     Utp,
     #[serde(rename = "socks")]
     Socks,
+    // HTTP web seed (BEP 19 / BEP 17), a virtual peer.
+    #[serde(rename = "webseed")]
+    WebSeed,
 }
 
 impl std::fmt::Display for ConnectionKind {
@@ -28,6 +37,7 @@ impl std::fmt::Display for ConnectionKind {
             ConnectionKind::Tcp => f.write_str("tcp"),
             ConnectionKind::Utp => f.write_str("uTP"),
             ConnectionKind::Socks => f.write_str("socks"),
+            ConnectionKind::WebSeed => f.write_str("webseed"),
         }
     }
 }
@@ -39,6 +49,8 @@ pub struct ConnectionOptions {
     // TCP outgoing connections are enabled by default
     pub enable_tcp: bool,
     pub peer_opts: Option<PeerConnectionOptions>,
+    // Message Stream Encryption for outgoing connections.
+    pub encryption: EncryptionPolicy,
 }
 
 impl Default for ConnectionOptions {
@@ -47,6 +59,7 @@ impl Default for ConnectionOptions {
             enable_tcp: true,
             proxy_url: None,
             peer_opts: None,
+            encryption: EncryptionPolicy::Disabled,
         }
     }
 }
@@ -65,6 +78,7 @@ pub(crate) struct StreamConnectorArgs {
     pub utp_socket: Option<Arc<UtpSocketUdp>>,
     pub bind_device: Option<BindDevice>,
     pub ipv4_only: bool,
+    pub encryption: EncryptionPolicy,
 }
 
 impl SocksProxyConfig {
@@ -133,6 +147,7 @@ pub(crate) struct StreamConnector {
     utp_socket: Option<Arc<librqbit_utp::UtpSocketUdp>>,
     stats: ConnectStatsAtomic,
     ipv4_only: bool,
+    encryption: EncryptionPolicy,
 }
 
 impl StreamConnector {
@@ -158,12 +173,14 @@ impl StreamConnector {
             bind_device: config.bind_device,
             stats: Default::default(),
             ipv4_only: config.ipv4_only,
+            encryption: config.encryption,
         })
     }
 
     fn get_stat(&self, kind: ConnectionKind, is_v6: bool) -> &SingleStatAtomic {
         let stat = match kind {
-            ConnectionKind::Tcp => &self.stats.tcp,
+            // Web seeds talk HTTP over TCP.
+            ConnectionKind::Tcp | ConnectionKind::WebSeed => &self.stats.tcp,
             ConnectionKind::Utp => &self.stats.utp,
             ConnectionKind::Socks => &self.stats.socks,
         };
@@ -210,6 +227,39 @@ impl StreamConnector {
     pub async fn connect(
         &self,
         addr: SocketAddr,
+        info_hash: Id20,
+    ) -> Result<(ConnectionKind, BoxAsyncReadVectored, BoxAsyncWrite)> {
+        let (kind, read, write) = self.connect_plaintext(addr).await?;
+        if self.encryption == EncryptionPolicy::Disabled {
+            return Ok((kind, read, write));
+        }
+
+        let res = with_timeout(
+            "MSE handshake",
+            MSE_HANDSHAKE_TIMEOUT,
+            mse::initiate(read, write, info_hash, self.encryption).map_err(Error::from),
+        )
+        .await;
+        match res {
+            Ok((read, write)) => {
+                debug!(?addr, "MSE handshake done");
+                Ok((kind, read, write))
+            }
+            // Most clients not supporting MSE will just disconnect, so reconnect in plaintext.
+            Err(e) if self.encryption == EncryptionPolicy::Preferred => {
+                debug!(
+                    ?addr,
+                    "MSE handshake failed, reconnecting in plaintext: {e:#}"
+                );
+                self.connect_plaintext(addr).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn connect_plaintext(
+        &self,
+        addr: SocketAddr,
     ) -> Result<(ConnectionKind, BoxAsyncReadVectored, BoxAsyncWrite)> {
         if addr.port() == 0 {
             return Err(Error::Anyhow(anyhow::anyhow!(
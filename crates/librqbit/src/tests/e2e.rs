@@ -1,7 +1,6 @@
 use std::{net::Ipv4Addr, time::Duration};
 
 use anyhow::{Context, bail};
-use librqbit_core::magnet::Magnet;
 use rand::RngExt;
 use tokio::{
     spawn,
@@ -11,7 +10,7 @@ use tracing::{Instrument, error, error_span, info};
 
 use crate::{
     AddTorrentOptions, AddTorrentResponse, ConnectionOptions, ListenerMode, Session,
-    SessionOptions, SessionPersistenceConfig, create_torrent,
+    SessionOptions, SessionPersistenceConfig, TorrentVersion, create_torrent,
     listen::ListenerOptions,
     spawn_utils::BlockingSpawner,
     tests::test_util::{
@@ -22,15 +21,20 @@ use crate::{
 
 #[tokio::test(flavor = "multi_thread")]
 async fn test_e2e_download_tcp() {
-    _test_e2e_download_timeout_and_cleanups(ListenerMode::TcpOnly).await
+    _test_e2e_download_timeout_and_cleanups(ListenerMode::TcpOnly, TorrentVersion::V1).await
 }
 
 #[tokio::test(flavor = "multi_thread")]
 async fn test_e2e_download_utp() {
-    _test_e2e_download_timeout_and_cleanups(ListenerMode::UtpOnly).await
+    _test_e2e_download_timeout_and_cleanups(ListenerMode::UtpOnly, TorrentVersion::V1).await
 }
 
-async fn _test_e2e_download_timeout_and_cleanups(mode: ListenerMode) {
+#[tokio::test(flavor = "multi_thread")]
+async fn test_e2e_download_v2_tcp() {
+    _test_e2e_download_timeout_and_cleanups(ListenerMode::TcpOnly, TorrentVersion::V2).await
+}
+
+async fn _test_e2e_download_timeout_and_cleanups(mode: ListenerMode, version: TorrentVersion) {
     let timeout = std::env::var("E2E_TIMEOUT")
         .ok()
         .and_then(|v| v.parse().ok())
@@ -39,7 +43,7 @@ async fn _test_e2e_download_timeout_and_cleanups(mode: ListenerMode) {
     let drop_checks = DropChecks::default();
     tokio::time::timeout(
         Duration::from_secs(timeout),
-        _test_e2e_download(mode, &drop_checks),
+        _test_e2e_download(mode, version, &drop_checks),
     )
     .await
     .context("test_e2e_download timed out")
@@ -51,7 +55,7 @@ async fn _test_e2e_download_timeout_and_cleanups(mode: ListenerMode) {
     drop_checks.check().unwrap();
 }
 
-async fn _test_e2e_download(mode: ListenerMode, drop_checks: &DropChecks) {
+async fn _test_e2e_download(mode: ListenerMode, version: TorrentVersion, drop_checks: &DropChecks) {
     setup_test_logging();
     match crate::try_increase_nofile_limit() {
         Ok(limit) => info!(limit, "increased ulimit"),
@@ -73,6 +77,7 @@ async fn _test_e2e_download(mode: ListenerMode, drop_checks: &DropChecks) {
         dbg!(tempdir.path()),
         crate::CreateTorrentOptions {
             piece_length: Some(piece_length),
+            version,
             ..Default::default()
         },
         &BlockingSpawner::new(1),
@@ -199,7 +204,7 @@ async fn _test_e2e_download(mode: ListenerMode, drop_checks: &DropChecks) {
         .and_then(|v| v.parse().ok())
         .unwrap_or(1usize);
 
-    let magnet = Magnet::from_id20(torrent_file.info_hash(), Vec::new(), None).to_string();
+    let magnet = torrent_file.as_magnet().to_string();
 
     // 3. Start a client with the initial peers, and download the file.
     for _ in 0..client_iters {
@@ -0,0 +1,153 @@
+use std::{net::Ipv4Addr, path::PathBuf, time::Duration};
+
+use anyhow::{Context, bail};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tracing::info;
+
+use crate::{
+    AddTorrent, CreateTorrentOptions, Session, create_torrent,
+    spawn_utils::BlockingSpawner,
+    tests::test_util::{create_default_random_dir_with_torrents, setup_test_logging},
+    torrent_state::peer::stats::snapshot::{PeerStatsFilter, PeerStatsFilterState},
+};
+
+// A minimal HTTP server supporting range requests. The first path segment (the torrent name) is
+// skipped, the rest is the path of the file relative to "root".
+async fn serve_web_seed_connection(root: PathBuf, conn: TcpStream) -> anyhow::Result<()> {
+    let mut conn = BufReader::new(conn);
+    let mut request_line = String::new();
+    conn.read_line(&mut request_line).await?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .context("bad request line")?
+        .to_owned();
+
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        conn.read_line(&mut line).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(r) = line.strip_prefix("range: bytes=") {
+            let (start, end) = r.split_once('-').context("bad range")?;
+            range = Some(start.parse::<usize>()?..end.parse::<usize>()? + 1);
+        }
+    }
+
+    let relative = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .context("expected torrent name in path")?
+        .1;
+    let data = std::fs::read(root.join(relative))?;
+    let (status, body) = match range {
+        Some(r) if r.end <= data.len() => ("206 Partial Content", &data[r]),
+        Some(_) => bail!("bad range"),
+        None => ("200 OK", &data[..]),
+    };
+    let conn = conn.get_mut();
+    conn.write_all(
+        format!(
+            "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            body.len()
+        )
+        .as_bytes(),
+    )
+    .await?;
+    conn.write_all(body).await?;
+    conn.shutdown().await?;
+    Ok(())
+}
+
+async fn e2e_web_seed() -> anyhow::Result<()> {
+    setup_test_logging();
+    let files = create_default_random_dir_with_torrents(3, 50_000, Some("test_e2e_web_seed"));
+    let mut torrent = create_torrent(
+        files.path(),
+        CreateTorrentOptions {
+            piece_length: Some(16384),
+            ..Default::default()
+        },
+        &BlockingSpawner::new(1),
+    )
+    .await?;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let server_addr = listener.local_addr()?;
+    let root = files.path().to_owned();
+    let server = tokio::spawn(async move {
+        loop {
+            let (conn, _) = listener.accept().await?;
+            tokio::spawn(serve_web_seed_connection(root.clone(), conn));
+        }
+        #[allow(unreachable_code)]
+        Ok::<_, anyhow::Error>(())
+    });
+
+    torrent.meta.url_list = vec![format!("http://{server_addr}/").into_bytes().into()];
+
+    let client_dir = TempDir::with_prefix("test_e2e_web_seed_client")?;
+    let client_session = Session::new_with_opts(
+        client_dir.path().into(),
+        crate::SessionOptions {
+            dht: None,
+            persistence: None,
+            listen: None,
+            disable_local_service_discovery: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let handle = client_session
+        .add_torrent(
+            AddTorrent::from_bytes(torrent.as_bytes()?),
+            Some(crate::AddTorrentOptions {
+                output_folder: Some(client_dir.path().to_str().unwrap().to_owned()),
+                ..Default::default()
+            }),
+        )
+        .await?
+        .into_handle()
+        .context("expected a handle")?;
+
+    handle.wait_until_completed().await?;
+    info!("downloaded the torrent from the web seed");
+
+    let peer_stats = handle
+        .live()
+        .context("expected torrent to be live")?
+        .per_peer_stats_snapshot(PeerStatsFilter {
+            state: PeerStatsFilterState::All,
+        });
+    let web_seed_stats = peer_stats
+        .peers
+        .get(&format!("http://{server_addr}/"))
+        .context("expected web seed in per-peer stats")?;
+    assert_eq!(web_seed_stats.counters.fetched_bytes, 150_000);
+
+    for f in 0..3 {
+        let name = format!("{f}.data");
+        let expected = std::fs::read(files.path().join(&name))?;
+        let actual = std::fs::read(client_dir.path().join(&name))?;
+        if expected != actual {
+            bail!("contents of {name} differ");
+        }
+    }
+
+    server.abort();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_e2e_web_seed() -> anyhow::Result<()> {
+    timeout(Duration::from_secs(30), e2e_web_seed()).await?
+}
@@ -1,4 +1,7 @@
 mod e2e;
 mod e2e_another_local_client;
 mod e2e_stream;
+mod e2e_web_seed;
+mod session_persistence;
 pub mod test_util;
+mod tracker_server;
@@ -0,0 +1,349 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use librqbit_core::{hash_id::Id20, magnet::MutableTorrentKey};
+use tempfile::TempDir;
+use tokio::time::timeout;
+
+use crate::{
+    AddTorrent, AddTorrentOptions, CreateTorrentOptions, SeedingStats, Session, SessionOptions,
+    SessionPersistenceConfig,
+    api::TorrentIdOrHash,
+    create_torrent,
+    session::MutableTorrentWatch,
+    spawn_utils::BlockingSpawner,
+    tests::test_util::{create_default_random_dir_with_torrents, setup_test_logging},
+    torrent_state::ManagedTorrentHandle,
+};
+
+async fn new_session(root: &Path) -> Arc<Session> {
+    Session::new_with_opts(
+        root.join("out"),
+        SessionOptions {
+            dht: None,
+            persistence: Some(SessionPersistenceConfig::Json {
+                folder: Some(root.join("session")),
+            }),
+            listen: None,
+            disable_local_service_discovery: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap()
+}
+
+// Adds a torrent of a few random files we already have.
+async fn add_seeded_torrent(
+    session: &Arc<Session>,
+    data: &TempDir,
+    opts: AddTorrentOptions,
+) -> ManagedTorrentHandle {
+    let torrent = create_torrent(
+        data.path(),
+        CreateTorrentOptions::default(),
+        &BlockingSpawner::new(1),
+    )
+    .await
+    .unwrap();
+    session
+        .add_torrent(
+            AddTorrent::TorrentFileBytes(torrent.as_bytes().unwrap()),
+            Some(AddTorrentOptions {
+                overwrite: true,
+                output_folder: Some(data.path().to_str().unwrap().to_owned()),
+                ..opts
+            }),
+        )
+        .await
+        .unwrap()
+        .into_handle()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_seeding_stats_survive_restart() {
+    setup_test_logging();
+    let root = TempDir::with_prefix("rqbit_session_persistence").unwrap();
+    let data = create_default_random_dir_with_torrents(2, 100_000, None);
+
+    let earlier = SeedingStats {
+        uploaded_bytes: 1000,
+        downloaded_bytes: 500,
+        seeding_time: Duration::from_secs(3600),
+        idle_time: Duration::from_secs(60),
+    };
+    let session = new_session(root.path()).await;
+    let handle = add_seeded_torrent(
+        &session,
+        &data,
+        AddTorrentOptions {
+            seeding_stats: earlier,
+            ..Default::default()
+        },
+    )
+    .await;
+    timeout(Duration::from_secs(30), handle.wait_until_completed())
+        .await
+        .unwrap()
+        .unwrap();
+    handle.add_seeding_time(Duration::from_secs(10), false);
+    let id = handle.id();
+
+    // Pausing keeps the totals.
+    session.pause(&handle).await.unwrap();
+    let expected = SeedingStats {
+        seeding_time: Duration::from_secs(3610),
+        idle_time: Duration::from_secs(70),
+        ..earlier
+    };
+    assert_eq!(handle.seeding_stats(), expected);
+    session.unpause(&handle).await.unwrap();
+    timeout(Duration::from_secs(30), handle.wait_until_completed())
+        .await
+        .unwrap()
+        .unwrap();
+    handle.add_seeding_time(Duration::from_secs(5), true);
+    let expected = SeedingStats {
+        seeding_time: Duration::from_secs(3615),
+        idle_time: Duration::ZERO,
+        ..earlier
+    };
+
+    session.stop().await;
+    drop(handle);
+    drop(session);
+
+    let session = new_session(root.path()).await;
+    let handle = session.get(TorrentIdOrHash::Id(id)).unwrap();
+    assert_eq!(handle.seeding_stats(), expected);
+    session.stop().await;
+}
+
+#[tokio::test]
+async fn test_tracker_edits_survive_restart() {
+    setup_test_logging();
+    let root = TempDir::with_prefix("rqbit_session_persistence").unwrap();
+    let data = create_default_random_dir_with_torrents(1, 10_000, None);
+    let url = |s: &str| url::Url::parse(s).unwrap();
+    let [a, b, c, d] = ["a", "b", "c", "d"].map(|h| url(&format!("http://{h}.invalid/announce")));
+
+    let session = new_session(root.path()).await;
+    let handle = add_seeded_torrent(
+        &session,
+        &data,
+        AddTorrentOptions {
+            paused: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let id = handle.id();
+    assert!(handle.tracker_tiers().is_empty());
+
+    // Without a tier, each tracker goes into its own tier.
+    session
+        .add_trackers(&handle, &[a.clone(), b.clone()], None)
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.tracker_tiers(),
+        vec![vec![a.clone()], vec![b.clone()]]
+    );
+
+    // Into an existing tier, or a new one right after the last.
+    session
+        .add_trackers(&handle, std::slice::from_ref(&c), Some(0))
+        .await
+        .unwrap();
+    session
+        .add_trackers(&handle, std::slice::from_ref(&d), Some(2))
+        .await
+        .unwrap();
+    assert!(
+        session
+            .add_trackers(&handle, std::slice::from_ref(&a), Some(4))
+            .await
+            .is_err()
+    );
+    assert!(
+        session
+            .add_trackers(&handle, &[url("ftp://e.invalid/")], None)
+            .await
+            .is_err()
+    );
+    assert_eq!(
+        handle.tracker_tiers(),
+        vec![vec![a.clone(), c.clone()], vec![b.clone()], vec![d.clone()]]
+    );
+
+    // Empty tiers go away.
+    session
+        .remove_trackers(&handle, std::slice::from_ref(&b))
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.tracker_tiers(),
+        vec![vec![a.clone(), c.clone()], vec![d.clone()]]
+    );
+
+    // Replacing keeps the position.
+    let moved = url("https://a2.invalid/announce");
+    session
+        .replace_tracker(&handle, &a, moved.clone())
+        .await
+        .unwrap();
+    assert!(
+        session
+            .replace_tracker(&handle, &a, b.clone())
+            .await
+            .is_err()
+    );
+    let expected = vec![vec![moved, c], vec![d]];
+    assert_eq!(handle.tracker_tiers(), expected);
+    assert_eq!(handle.shared().trackers(), expected.concat());
+
+    session.stop().await;
+    drop(handle);
+    drop(session);
+
+    let session = new_session(root.path()).await;
+    let handle = session.get(TorrentIdOrHash::Id(id)).unwrap();
+    assert_eq!(handle.tracker_tiers(), expected);
+    session.stop().await;
+}
+
+#[tokio::test]
+async fn test_upload_slots_survive_restart() {
+    setup_test_logging();
+    let root = TempDir::with_prefix("rqbit_session_persistence").unwrap();
+    let data = create_default_random_dir_with_torrents(1, 10_000, None);
+
+    let session = new_session(root.path()).await;
+    let handle = add_seeded_torrent(
+        &session,
+        &data,
+        AddTorrentOptions {
+            paused: true,
+            upload_slots: Some(7),
+            ..Default::default()
+        },
+    )
+    .await;
+    let id = handle.id();
+    session.stop().await;
+    drop(handle);
+    drop(session);
+
+    let session = new_session(root.path()).await;
+    let handle = session.get(TorrentIdOrHash::Id(id)).unwrap();
+    assert_eq!(handle.shared().options.upload_slots, Some(7));
+    session.stop().await;
+}
+
+#[tokio::test]
+async fn test_mutable_torrent_watch_survives_restart() {
+    setup_test_logging();
+    let root = TempDir::with_prefix("rqbit_session_persistence").unwrap();
+    let data = create_default_random_dir_with_torrents(2, 10_000, None);
+
+    let session = new_session(root.path()).await;
+    let handle = add_seeded_torrent(
+        &session,
+        &data,
+        AddTorrentOptions {
+            paused: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let id = handle.id();
+    let options = AddTorrentOptions {
+        output_folder: Some("/downloads/mutable".to_owned()),
+        only_files: Some(vec![1]),
+        upload_slots: Some(3),
+        preferred_id: Some(id),
+        ..Default::default()
+    };
+    let watch = MutableTorrentWatch {
+        key: MutableTorrentKey {
+            public_key: [7; 32],
+            salt: b"salt".to_vec(),
+        },
+        seq: Some(42),
+        trackers: vec!["http://a.invalid/announce".to_owned()],
+        retire_previous_versions: true,
+        options: serde_json::to_value(&options).unwrap(),
+    };
+    session.watch_mutable_torrent(watch.clone(), &handle);
+    session.try_update_persistence_metadata(&handle).await;
+
+    session.stop().await;
+    drop(handle);
+    drop(session);
+
+    let session = new_session(root.path()).await;
+    let handle = session.get(TorrentIdOrHash::Id(id)).unwrap();
+    assert_eq!(handle.mutable_watch(), Some(watch.clone()));
+
+    // New versions get the settings the torrent was added with, but not its state.
+    let opts = watch.new_version_options();
+    assert_eq!(opts.output_folder, options.output_folder);
+    assert_eq!(opts.only_files, options.only_files);
+    assert_eq!(opts.upload_slots, Some(3));
+    assert_eq!(opts.preferred_id, None);
+    session.stop().await;
+}
+
+#[tokio::test]
+async fn test_mutable_torrent_failed_update_is_retried() {
+    setup_test_logging();
+    let root = TempDir::with_prefix("rqbit_session_persistence").unwrap();
+    let data = create_default_random_dir_with_torrents(2, 10_000, None);
+
+    let session = new_session(root.path()).await;
+    let handle = add_seeded_torrent(
+        &session,
+        &data,
+        AddTorrentOptions {
+            paused: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let watch = MutableTorrentWatch {
+        key: MutableTorrentKey {
+            public_key: [7; 32],
+            salt: Vec::new(),
+        },
+        seq: Some(1),
+        trackers: Vec::new(),
+        retire_previous_versions: true,
+        options: serde_json::to_value(AddTorrentOptions::default()).unwrap(),
+    };
+    session.watch_mutable_torrent(watch.clone(), &handle);
+
+    // Without DHT and trackers the new version can't be added, so the watch stays at the old
+    // version to resolve the new one again.
+    let new_version = MutableTorrentWatch {
+        seq: Some(2),
+        ..watch.clone()
+    };
+    assert!(
+        session
+            .update_mutable_torrent(&handle, new_version.clone(), Id20::new([9; 20]))
+            .await
+            .is_err()
+    );
+    assert_eq!(handle.mutable_watch(), Some(watch));
+
+    // A new seq of the same version only advances the watch.
+    assert!(
+        session
+            .update_mutable_torrent(&handle, new_version.clone(), handle.info_hash())
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(handle.mutable_watch(), Some(new_version));
+    session.stop().await;
+}
@@ -0,0 +1,84 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use librqbit_core::hash_id::Id20;
+use tempfile::TempDir;
+
+use crate::{
+    AddTorrent, AddTorrentOptions, BuiltinTrackerOptions, CreateTorrentOptions, Session,
+    SessionOptions, TorrentVersion, create_torrent,
+    spawn_utils::BlockingSpawner,
+    tests::test_util::{create_default_random_dir_with_torrents, setup_test_logging},
+};
+
+fn announce_query(info_hash: Id20) -> String {
+    let info_hash = info_hash
+        .0
+        .iter()
+        .map(|b| format!("%{b:02X}"))
+        .collect::<String>();
+    format!(
+        "info_hash={info_hash}&peer_id={}&port=6881&uploaded=0&downloaded=0&left=0&compact=1",
+        "%01".repeat(20)
+    )
+}
+
+#[tokio::test]
+async fn test_whitelist_tracks_both_swarms_of_hybrid_torrents() {
+    setup_test_logging();
+    let root = TempDir::with_prefix("rqbit_tracker_server").unwrap();
+    let data = create_default_random_dir_with_torrents(2, 10_000, None);
+
+    let session = Session::new_with_opts(
+        root.path().join("out"),
+        SessionOptions {
+            dht: None,
+            persistence: None,
+            listen: None,
+            disable_local_service_discovery: true,
+            tracker: Some(BuiltinTrackerOptions {
+                whitelist: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let torrent = create_torrent(
+        data.path(),
+        CreateTorrentOptions {
+            version: TorrentVersion::Hybrid,
+            ..Default::default()
+        },
+        &BlockingSpawner::new(1),
+    )
+    .await
+    .unwrap();
+    let handle = session
+        .add_torrent(
+            AddTorrent::TorrentFileBytes(torrent.as_bytes().unwrap()),
+            Some(AddTorrentOptions {
+                paused: true,
+                overwrite: true,
+                output_folder: Some(data.path().to_str().unwrap().to_owned()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap()
+        .into_handle()
+        .unwrap();
+
+    let tracker = session.tracker_server().unwrap();
+    let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let not_tracked = |info_hash| {
+        let body = tracker.http_announce(&announce_query(info_hash), ip);
+        String::from_utf8_lossy(&body).contains("torrent is not tracked here")
+    };
+    let v2 = handle.info_hash_v2().unwrap().truncate_for_dht();
+    assert!(!not_tracked(handle.info_hash()));
+    assert!(!not_tracked(v2));
+    assert!(not_tracked(Id20::new([9; 20])));
+    session.stop().await;
+}
@@ -106,6 +106,7 @@ impl TorrentStateInitializing {
             .block_in_place_with_semaphore(|| {
                 let fo = crate::file_ops::FileOps::new(
                     &self.metadata.info,
+                    &self.metadata.piece_layers,
                     &self.files,
                     &self.metadata.file_infos,
                 );
@@ -217,8 +218,13 @@ impl TorrentStateInitializing {
                     .shared
                     .spawner
                     .block_in_place_with_semaphore(|| {
-                        FileOps::new(&self.metadata.info, &self.files, &self.metadata.file_infos)
-                            .initial_check(&self.checked_bytes, &self.pause_requested)
+                        FileOps::new(
+                            &self.metadata.info,
+                            &self.metadata.piece_layers,
+                            &self.files,
+                            &self.metadata.file_infos,
+                        )
+                        .initial_check(&self.checked_bytes, &self.pause_requested)
                     })
                     .await?;
                 bitv_factory
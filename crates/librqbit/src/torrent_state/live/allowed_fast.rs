@@ -0,0 +1,84 @@
+// BEP 6 allowed fast set generation.
+//
+// The set is a deterministic function of the peer's IP, the info hash and the number
+// of pieces, so both sides could compute it. We send it to peers that have nothing yet,
+// so that they can bootstrap by requesting those pieces even while choked.
+
+use std::net::IpAddr;
+
+use librqbit_core::hash_id::Id20;
+use sha1w::{ISha1, Sha1};
+
+// Recommended by BEP 6.
+pub const ALLOWED_FAST_SET_SIZE: u32 = 10;
+
+pub fn allowed_fast_set(ip: IpAddr, info_hash: Id20, total_pieces: u32, k: u32) -> Vec<u32> {
+    if total_pieces == 0 {
+        return Vec::new();
+    }
+    let k = k.min(total_pieces) as usize;
+
+    // BEP 6 only defines the algorithm for IPv4 (masking to /24). For IPv6 we mask to /48 which
+    // is what a typical end-user allocation looks like.
+    let mut x = Vec::with_capacity(36);
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => x.extend_from_slice(&(u32::from(ip) & 0xFFFFFF00).to_be_bytes()),
+        IpAddr::V6(ip) => {
+            x.extend_from_slice(&ip.octets()[..6]);
+            x.extend_from_slice(&[0u8; 10]);
+        }
+    }
+    x.extend_from_slice(&info_hash.0);
+
+    let mut result = Vec::with_capacity(k);
+    while result.len() < k {
+        let mut h = Sha1::new();
+        h.update(&x);
+        let digest = h.finish();
+        for chunk in digest.chunks_exact(4) {
+            if result.len() >= k {
+                break;
+            }
+            let index = u32::from_be_bytes(chunk.try_into().unwrap()) % total_pieces;
+            if !result.contains(&index) {
+                result.push(index);
+            }
+        }
+        x.clear();
+        x.extend_from_slice(&digest);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use librqbit_core::hash_id::Id20;
+
+    use super::allowed_fast_set;
+
+    // Test vectors from BEP 6.
+    #[test]
+    fn test_allowed_fast_set_bep6_vectors() {
+        let ip = IpAddr::V4(Ipv4Addr::new(80, 4, 4, 200));
+        let info_hash = Id20::new([0xaa; 20]);
+        assert_eq!(
+            allowed_fast_set(ip, info_hash, 1313, 7),
+            vec![1059, 431, 808, 1217, 287, 376, 1188]
+        );
+        assert_eq!(
+            allowed_fast_set(ip, info_hash, 1313, 9),
+            vec![1059, 431, 808, 1217, 287, 376, 1188, 353, 508]
+        );
+    }
+
+    #[test]
+    fn test_allowed_fast_set_small_torrent() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let mut set = allowed_fast_set(ip, Id20::new([1; 20]), 3, 10);
+        set.sort_unstable();
+        assert_eq!(set, vec![0, 1, 2]);
+        assert!(allowed_fast_set(ip, Id20::new([1; 20]), 0, 10).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+// The choker decides which interested peers we upload to.
+//
+// Every CHOKE_ROUND_INTERVAL the peers that gave us the most data during the last round (tit-for-tat)
+// are unchoked while we are downloading. When seeding, the peers we uploaded to the fastest are
+// unchoked instead, so that the bandwidth goes to peers that can take it. On top of the regular
+// upload slots, one random peer is optimistically unchoked, rotating every OPTIMISTIC_UNCHOKE_ROUNDS
+// rounds, so that new peers get a chance to prove themselves.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use rand::{RngExt, seq::SliceRandom};
+use tracing::{debug, trace};
+
+use crate::{stream_connect::ConnectionKind, type_aliases::PeerHandle};
+
+use super::{TorrentStateLive, peer::LivePeerState};
+
+pub(crate) const DEFAULT_UPLOAD_SLOTS: usize = 4;
+const CHOKE_ROUND_INTERVAL: Duration = Duration::from_secs(10);
+const OPTIMISTIC_UNCHOKE_ROUNDS: u64 = 3;
+
+#[derive(Debug, Clone, Copy)]
+struct ChokerCandidate {
+    handle: PeerHandle,
+    // Bytes received from the peer during the last round.
+    downloaded: u64,
+    // Bytes sent to the peer during the last round.
+    uploaded: u64,
+}
+
+// Returns the peers to unchoke, and the optimistically unchoked peer, which is kept if it's still a
+// candidate. Pass None as "optimistic" to pick a new one.
+fn select_peers_to_unchoke(
+    mut candidates: Vec<ChokerCandidate>,
+    upload_slots: usize,
+    seeding: bool,
+    optimistic: Option<PeerHandle>,
+) -> (HashSet<PeerHandle>, Option<PeerHandle>) {
+    // Shuffle first so that peers with equal rates get their turn.
+    candidates.shuffle(&mut rand::rng());
+    if seeding {
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.uploaded));
+    } else {
+        candidates.sort_by_key(|c| std::cmp::Reverse((c.downloaded, c.uploaded)));
+    }
+
+    let split = upload_slots.min(candidates.len());
+    let (regular, rest) = candidates.split_at(split);
+    let mut unchoke: HashSet<PeerHandle> = regular.iter().map(|c| c.handle).collect();
+
+    let optimistic = match optimistic {
+        Some(h) if rest.iter().any(|c| c.handle == h) => Some(h),
+        _ if rest.is_empty() => None,
+        _ => Some(rest[rand::rng().random_range(0..rest.len())].handle),
+    };
+    unchoke.extend(optimistic);
+    (unchoke, optimistic)
+}
+
+// Web seeds and peers that don't want anything from us can't use an upload slot.
+fn is_choker_candidate(live: &LivePeerState, total_pieces: usize) -> bool {
+    !matches!(live.connection_kind, ConnectionKind::WebSeed)
+        && live.peer_interested
+        && !live.has_full_torrent(total_pieces)
+}
+
+impl TorrentStateLive {
+    fn upload_slots(&self) -> usize {
+        self.shared
+            .options
+            .upload_slots
+            .or_else(|| self.shared.session.upgrade()?.upload_slots)
+            .unwrap_or(DEFAULT_UPLOAD_SLOTS)
+    }
+
+    pub(super) async fn task_choker(self: Arc<Self>) -> crate::Result<()> {
+        if self.shared.options.disable_upload() {
+            return Ok(());
+        }
+        let total_pieces = self.lengths.total_pieces() as usize;
+        let mut interval = tokio::time::interval(CHOKE_ROUND_INTERVAL);
+        let mut last_counters: HashMap<PeerHandle, (u64, u64)> = HashMap::new();
+        let mut optimistic = None;
+
+        for round in 0u64.. {
+            interval.tick().await;
+            let seeding = self.is_finished();
+
+            let mut candidates = Vec::new();
+            let mut counters = HashMap::new();
+            for pe in self.peers.states.iter() {
+                let Some(live) = pe.value().get_live() else {
+                    continue;
+                };
+                let c = &pe.value().stats.counters;
+                let current = (
+                    c.fetched_bytes.load(Ordering::Relaxed),
+                    c.uploaded_bytes.load(Ordering::Relaxed),
+                );
+                let (fetched, uploaded) = last_counters.get(pe.key()).copied().unwrap_or(current);
+                counters.insert(*pe.key(), current);
+                if is_choker_candidate(live, total_pieces) {
+                    candidates.push(ChokerCandidate {
+                        handle: *pe.key(),
+                        downloaded: current.0.saturating_sub(fetched),
+                        uploaded: current.1.saturating_sub(uploaded),
+                    });
+                }
+            }
+            last_counters = counters;
+
+            if round.is_multiple_of(OPTIMISTIC_UNCHOKE_ROUNDS) {
+                optimistic = None;
+            }
+            let unchoke;
+            (unchoke, optimistic) =
+                select_peers_to_unchoke(candidates, self.upload_slots(), seeding, optimistic);
+            trace!(round, seeding, ?unchoke, ?optimistic, "choker round");
+
+            for mut pe in self.peers.states.iter_mut() {
+                let handle = *pe.key();
+                if let Some(live) = pe.value_mut().get_live_mut()
+                    && !matches!(live.connection_kind, ConnectionKind::WebSeed)
+                {
+                    live.set_am_choking(!unchoke.contains(&handle));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Unchoke a peer that just became interested right away if there's a free upload slot, rather
+    // than making it wait for the next choker round.
+    pub(super) fn unchoke_if_slot_available(&self, handle: PeerHandle) {
+        if self.shared.options.disable_upload() {
+            return;
+        }
+        let unchoked = self
+            .peers
+            .states
+            .iter()
+            .filter(|pe| pe.value().get_live().is_some_and(|l| !l.am_choking))
+            .count();
+        if unchoked >= self.upload_slots() {
+            return;
+        }
+        let total_pieces = self.lengths.total_pieces() as usize;
+        self.peers
+            .with_live_mut(handle, "unchoke_if_slot_available", |live| {
+                if is_choker_candidate(live, total_pieces) {
+                    debug!("unchoking newly interested peer");
+                    live.set_am_choking(false);
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+    };
+
+    use super::{ChokerCandidate, select_peers_to_unchoke};
+    use crate::type_aliases::PeerHandle;
+
+    fn peer(id: u8) -> PeerHandle {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, id)), 6881)
+    }
+
+    fn candidates() -> Vec<ChokerCandidate> {
+        (1..=6)
+            .map(|id| ChokerCandidate {
+                handle: peer(id),
+                // Peer 6 sends us the most, peer 1 the least.
+                downloaded: id as u64 * 1000,
+                // Peer 1 takes the most from us, peer 6 the least.
+                uploaded: (7 - id) as u64 * 1000,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_leeching_unchokes_best_uploaders_to_us() {
+        let (unchoke, optimistic) = select_peers_to_unchoke(candidates(), 2, false, None);
+        let optimistic = optimistic.unwrap();
+        assert!([1, 2, 3, 4].map(peer).contains(&optimistic));
+        assert_eq!(unchoke, HashSet::from([peer(6), peer(5), optimistic]));
+    }
+
+    #[test]
+    fn test_seeding_unchokes_fastest_downloaders() {
+        let (unchoke, optimistic) = select_peers_to_unchoke(candidates(), 2, true, None);
+        let optimistic = optimistic.unwrap();
+        assert!([3, 4, 5, 6].map(peer).contains(&optimistic));
+        assert_eq!(unchoke, HashSet::from([peer(1), peer(2), optimistic]));
+    }
+
+    #[test]
+    fn test_optimistic_unchoke_is_kept_until_rotated() {
+        let (_, optimistic) = select_peers_to_unchoke(candidates(), 2, false, Some(peer(1)));
+        assert_eq!(optimistic, Some(peer(1)));
+
+        // Peer 6 already has a regular slot, so a different peer is picked.
+        let (unchoke, optimistic) = select_peers_to_unchoke(candidates(), 2, false, Some(peer(6)));
+        assert_ne!(optimistic, Some(peer(6)));
+        assert_eq!(unchoke.len(), 3);
+
+        // Optimistic unchokes are random.
+        let seen: HashSet<_> = (0..100)
+            .filter_map(|_| select_peers_to_unchoke(candidates(), 2, false, None).1)
+            .collect();
+        assert!(seen.len() > 1);
+    }
+
+    #[test]
+    fn test_fewer_candidates_than_slots() {
+        let (unchoke, optimistic) =
+            select_peers_to_unchoke(candidates()[..2].to_vec(), 4, false, None);
+        assert_eq!(unchoke, HashSet::from([peer(1), peer(2)]));
+        assert_eq!(optimistic, None);
+    }
+}
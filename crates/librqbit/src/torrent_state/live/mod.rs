@@ -23,6 +23,13 @@
 // - "peer_chunk_requester" - this continuously sends requests for chunks to the peer.
 //   it may steal chunks/pieces from other peers.
 //
+// Choker task:
+// - every 10 seconds decides which interested peers we upload to (tit-for-tat while downloading, upload rate
+//   while seeding), plus one optimistic unchoke rotating every 30 seconds.
+//
+// Each web seed (HTTP source of the torrent's data) has one "web_seed" task. It acts as a virtual peer
+// that has all pieces, and downloads the pieces it reserves over HTTP.
+//
 // ## Peer lifecycle
 // State transitions:
 // - queued (initial state) -> connected
@@ -39,9 +46,12 @@
 // > so don't lock them both at the same time at all, or at the worst lock them in the
 // > same order (peers one first, then the global one).
 
+mod allowed_fast;
+mod choker;
 pub mod peer;
 pub mod peers;
 pub mod stats;
+mod web_seed;
 
 use std::{
     borrow::Cow,
@@ -49,7 +59,7 @@ use std::{
     net::{IpAddr, SocketAddr},
     num::NonZeroU32,
     sync::{
-        Arc,
+        Arc, OnceLock,
         atomic::{AtomicBool, AtomicU64, Ordering},
     },
     time::{Duration, Instant},
@@ -68,7 +78,7 @@ use librqbit_core::{
 };
 use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use peer_binary_protocol::{
-    Handshake, Message, Piece, Request,
+    Handshake, HashRequest, Hashes, MAX_HASHES_IN_MSG, Message, Piece, Request,
     extended::{
         self, ExtendedMessage,
         handshake::ExtendedHandshake,
@@ -87,6 +97,7 @@ use crate::{
     Error,
     chunk_tracker::{ChunkMarkingResult, ChunkTracker, HaveNeededSelected},
     file_ops::FileOps,
+    file_priority::{FilePriority, file_download_order},
     limits::Limits,
     peer_connection::{
         PeerConnection, PeerConnectionHandler, PeerConnectionOptions, WriterRequest,
@@ -97,6 +108,7 @@ use crate::{
     stream_connect::ConnectionKind,
     torrent_state::{peer::Peer, utils::atomic_inc},
     type_aliases::{BF, FilePriorities, FileStorage, PeerHandle},
+    web_seed::WebSeed,
 };
 
 use self::{
@@ -219,6 +231,7 @@ pub struct TorrentStateLive {
 impl TorrentStateLive {
     pub(crate) fn new(
         paused: TorrentStatePaused,
+        file_priorities: Option<&[FilePriority]>,
         fatal_errors_tx: tokio::sync::oneshot::Sender<anyhow::Error>,
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<Arc<Self>> {
@@ -235,19 +248,7 @@ impl TorrentStateLive {
         let have_bytes = paused.chunk_tracker.get_hns().have_bytes;
         let lengths = *paused.chunk_tracker.get_lengths();
 
-        // TODO: make it configurable
-        let file_priorities = {
-            let mut pri = (0..paused.metadata.file_infos.len()).collect::<Vec<usize>>();
-            // sort by filename, cause many torrents have random sort order.
-            pri.sort_unstable_by_key(|id| {
-                paused
-                    .metadata
-                    .file_infos
-                    .get(*id)
-                    .map(|fi| fi.relative_filename.as_path())
-            });
-            pri
-        };
+        let file_priorities = file_download_order(&paused.metadata.file_infos, file_priorities);
 
         let (have_broadcast_tx, _) = tokio::sync::broadcast::channel(128);
 
@@ -338,6 +339,20 @@ impl TorrentStateLive {
             format!("[{}]upload_scheduler", state.shared.id),
             state.clone().task_upload_scheduler(ratelimit_upload_rx),
         );
+
+        state.spawn(
+            debug_span!(parent: state.shared.span.clone(), "choker"),
+            format!("[{}]choker", state.shared.id),
+            state.clone().task_choker(),
+        );
+
+        for (index, web_seed) in state.shared.web_seeds.iter().enumerate() {
+            state.spawn(
+                debug_span!(parent: state.shared.span.clone(), "web_seed", url = %web_seed.url()),
+                format!("[{}][url={}]web_seed", state.shared.id, web_seed.url()),
+                state.clone().task_web_seed(index, web_seed.clone()),
+            );
+        }
         Ok(state)
     }
 
@@ -480,6 +495,8 @@ impl TorrentStateLive {
             tx,
             counters,
             first_message_received: AtomicBool::new(false),
+            supports_fast: AtomicBool::new(false),
+            allowed_fast_set: OnceLock::new(),
             cancel_token: self.cancellation_token.child_token(),
             client_name_and_version: self.shared.client_name_and_version().to_owned(),
         };
@@ -489,9 +506,10 @@ impl TorrentStateLive {
             read_write_timeout: self.shared.options.peer_read_write_timeout,
             ..Default::default()
         };
+        // Answer with the info hash the peer used, it's the v2 one for v2 swarm peers of hybrids.
         let peer_connection = PeerConnection::new(
             checked_peer.addr,
-            self.shared.info_hash,
+            checked_peer.handshake.info_hash,
             self.shared.peer_id,
             &handler,
             Some(options),
@@ -544,6 +562,8 @@ impl TorrentStateLive {
             tx,
             counters,
             first_message_received: AtomicBool::new(false),
+            supports_fast: AtomicBool::new(false),
+            allowed_fast_set: OnceLock::new(),
             cancel_token: state.cancellation_token.child_token(),
             client_name_and_version: state.shared.client_name_and_version().to_owned(),
         };
@@ -673,7 +693,12 @@ impl TorrentStateLive {
         self.shared.peer_id
     }
     pub(crate) fn file_ops(&self) -> FileOps<'_> {
-        FileOps::new(&self.metadata.info, &*self.files, &self.metadata.file_infos)
+        FileOps::new(
+            &self.metadata.info,
+            &self.metadata.piece_layers,
+            &*self.files,
+            &self.metadata.file_infos,
+        )
     }
 
     pub(crate) fn lock_read(
@@ -752,11 +777,19 @@ impl TorrentStateLive {
                 .states
                 .iter()
                 .filter(|e| filter.state.matches(e.value().get_state()))
-                .map(|e| (e.key().to_string(), e.value().into()))
+                .map(|e| (self.peer_display_name(*e.key()), e.value().into()))
                 .collect(),
         }
     }
 
+    // Web seeds are shown by their URL, other peers by their address.
+    fn peer_display_name(&self, handle: PeerHandle) -> String {
+        WebSeed::index_from_peer_handle(handle)
+            .and_then(|idx| self.shared.web_seeds.get(idx))
+            .map(|web_seed| web_seed.url().to_string())
+            .unwrap_or_else(|| handle.to_string())
+    }
+
     pub async fn wait_until_completed(&self) {
         if self.is_finished() {
             return;
@@ -801,10 +834,19 @@ impl TorrentStateLive {
         Err(res)
     }
 
-    pub(crate) fn update_only_files(&self, only_files: &HashSet<usize>) -> anyhow::Result<()> {
+    // Update the selected files, and the file download order if there are priorities, at once.
+    pub(crate) fn update_only_files(
+        &self,
+        only_files: &HashSet<usize>,
+        file_priorities: Option<&[FilePriority]>,
+    ) -> anyhow::Result<()> {
         let mut g = self.lock_write("update_only_files");
         let pt = g.get_pieces_mut()?;
         let hns = pt.update_only_files(&self.metadata.file_infos, only_files)?;
+        if let Some(file_priorities) = file_priorities {
+            g.file_priorities =
+                file_download_order(&self.metadata.file_infos, Some(file_priorities));
+        }
         if !hns.finished() {
             self.reconnect_all_not_needed_peers();
         }
@@ -888,15 +930,33 @@ impl TorrentStateLive {
                 && l.has_full_torrent(self.lengths.total_pieces() as usize)
             {
                 let prev = pe.value_mut().set_not_needed(&self.peers);
-                let _ = prev
-                    .take_live_no_counters()
-                    .unwrap()
-                    .tx
-                    .send(WriterRequest::Disconnect(Ok(())));
+                let live = prev.take_live_no_counters().unwrap();
+                if let Ok(pieces) = self
+                    .lock_write("disconnect_all_peers_that_have_full_torrent")
+                    .get_pieces_mut()
+                {
+                    pieces.remove_peer_bitfield(&live.bitfield);
+                }
+                let _ = live.tx.send(WriterRequest::Disconnect(Ok(())));
             }
         }
     }
 
+    /// Replace the peer's bitfield, updating piece availability accordingly.
+    fn update_peer_bitfield(&self, handle: PeerHandle, bitfield: BF) -> Option<()> {
+        self.peers
+            .with_live_mut(handle, "update_peer_bitfield", |live| {
+                if let Ok(pieces) = self
+                    .lock_write("update_piece_availability")
+                    .get_pieces_mut()
+                {
+                    pieces.remove_peer_bitfield(&live.bitfield);
+                    pieces.add_peer_bitfield(&bitfield);
+                }
+                live.bitfield = bitfield;
+            })
+    }
+
     pub(crate) fn reconnect_all_not_needed_peers(&self) {
         self.peers
             .states
@@ -1030,6 +1090,11 @@ struct PeerHandler {
 
     first_message_received: AtomicBool,
 
+    // If both sides set the BEP 6 fast extension bit in the handshake.
+    supports_fast: AtomicBool,
+    // The BEP 6 allowed fast set for the peer, computed once on first use.
+    allowed_fast_set: OnceLock<Vec<u32>>,
+
     cancel_token: CancellationToken,
 
     client_name_and_version: String,
@@ -1049,8 +1114,10 @@ impl PeerConnectionHandler for &'_ PeerHandler {
     async fn on_received_message(&self, message: Message<'_>) -> anyhow::Result<()> {
         // The first message must be "bitfield", but if it's not sent,
         // assume the bitfield is all zeroes and was sent.
-        if !matches!(&message, Message::Bitfield(..))
-            && !self.first_message_received.swap(true, Ordering::Relaxed)
+        if !matches!(
+            &message,
+            Message::Bitfield(..) | Message::HaveAll | Message::HaveNone
+        ) && !self.first_message_received.swap(true, Ordering::Relaxed)
         {
             self.on_bitfield_notify.notify_waiters();
         }
@@ -1074,12 +1141,37 @@ impl PeerConnectionHandler for &'_ PeerHandler {
                 trace!("keepalive received");
             }
             Message::Have(h) => self.on_have(h),
-            Message::NotInterested => {
-                trace!("received \"not interested\", but we don't process it yet")
-            }
+            Message::NotInterested => self.on_peer_not_interested(),
             Message::Cancel(_) => {
                 trace!("received \"cancel\", but we don't process it yet")
             }
+            Message::HaveAll => {
+                self.check_fast("have_all")?;
+                self.on_have_all();
+            }
+            Message::HaveNone => {
+                self.check_fast("have_none")?;
+                self.on_have_none();
+            }
+            Message::SuggestPiece(index) => {
+                self.check_fast("suggest_piece")?;
+                trace!(index, "received \"suggest piece\", ignoring");
+            }
+            Message::RejectRequest(request) => {
+                self.check_fast("reject_request")?;
+                self.on_reject_request(request)
+                    .context("on_reject_request")?;
+            }
+            Message::AllowedFast(index) => {
+                self.check_fast("allowed_fast")?;
+                self.on_allowed_fast(index);
+            }
+            Message::HashRequest(request) => {
+                self.on_hash_request(request).context("on_hash_request")?;
+            }
+            Message::HashReject(request) => {
+                trace!(?request, "received \"hash reject\", ignoring");
+            }
             Message::Extended(ExtendedMessage::UtMetadata(UtMetadata::Request(
                 metadata_piece_id,
             ))) => {
@@ -1122,13 +1214,24 @@ impl PeerConnectionHandler for &'_ PeerHandler {
 
     fn serialize_bitfield_message_to_buf(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
         let g = self.state.lock_read("serialize_bitfield_message_to_buf");
-        let msg = Message::Bitfield(ByteBuf(g.get_chunks()?.get_have_pieces().as_bytes()));
+        let chunks = g.get_chunks()?;
+        let msg = if self.supports_fast.load(Ordering::Relaxed)
+            && chunks.get_hns().have_bytes == self.state.lengths.total_length()
+        {
+            // BEP 6: no point sending a large bitfield if we are a seeder.
+            Message::HaveAll
+        } else {
+            Message::Bitfield(ByteBuf(chunks.get_have_pieces().as_bytes()))
+        };
         let len = msg.serialize(buf, &Default::default)?;
         trace!("sending: {:?}, length={}", &msg, len);
         Ok(len)
     }
 
     fn on_handshake(&self, handshake: Handshake, ckind: ConnectionKind) -> anyhow::Result<()> {
+        // Our own handshake always advertises the fast extension.
+        self.supports_fast
+            .store(handshake.supports_fast(), Ordering::Relaxed);
         self.state.set_peer_live(self.addr, handshake, ckind);
         Ok(())
     }
@@ -1217,6 +1320,10 @@ impl PeerConnectionHandler for &'_ PeerHandler {
         self.state.get_approx_have_bytes() > 0
     }
 
+    fn may_upload(&self, chunk: &ChunkInfo) -> bool {
+        !self.am_choking() || self.is_allowed_fast(chunk.piece_index)
+    }
+
     fn should_transmit_have(&self, id: ValidPieceIndex) -> bool {
         if self.state.shared.options.disable_upload() {
             return false;
@@ -1248,6 +1355,10 @@ impl PeerConnectionHandler for &'_ PeerHandler {
     fn client_name_and_version(&self) -> &str {
         &self.client_name_and_version
     }
+
+    fn advertise_v2(&self) -> bool {
+        self.state.metadata.info.info().has_v2()
+    }
 }
 
 impl PeerHandler {
@@ -1275,7 +1386,9 @@ impl PeerHandler {
 
                 // Release all pieces owned by this peer (fixes the bug where pieces
                 // could be in both queue_pieces AND inflight_pieces after peer death)
-                let released = g.get_pieces_mut()?.release_pieces_owned_by(self.addr);
+                let pieces = g.get_pieces_mut()?;
+                pieces.remove_peer_bitfield(&live.bitfield);
+                let released = pieces.release_pieces_owned_by(self.addr);
                 if released > 0 {
                     trace!(
                         "peer dead, released {} in-flight pieces back to queue",
@@ -1412,7 +1525,8 @@ impl PeerHandler {
     ///
     /// Returns the piece index to download, or None if no pieces are available.
     fn acquire_next_piece(&self) -> crate::Result<Option<ValidPieceIndex>> {
-        if self.is_choked() {
+        let choked = self.is_choked();
+        if choked && !self.has_allowed_fast_pieces() {
             debug!("we are choked, can't acquire piece");
             return Ok(None);
         }
@@ -1427,6 +1541,7 @@ impl PeerHandler {
                 let mut g = self.state.lock_write("acquire_next_piece");
 
                 let bf = &live.bitfield;
+                let allowed_fast = &live.allowed_fast_pieces;
                 // Extract references to disjoint fields
                 let TorrentStateLocked {
                     pieces,
@@ -1440,7 +1555,10 @@ impl PeerHandler {
                     priority_pieces: self.state.streams.iter_next_pieces(&self.state.lengths),
                     file_priorities,
                     file_infos: &self.state.metadata.file_infos,
-                    peer_has_piece: |p| bf.get(p.get() as usize).map(|v| *v) == Some(true),
+                    peer_has_piece: |p| {
+                        bf.get(p.get() as usize).map(|v| *v) == Some(true)
+                            && (!choked || allowed_fast.contains(&p))
+                    },
                     can_steal: |p| {
                         self.state.per_piece_locks[p.get_usize()]
                             .try_write()
@@ -1475,6 +1593,9 @@ impl PeerHandler {
 
     fn on_download_request(&self, request: Request) -> anyhow::Result<()> {
         if self.state.torrent().options.disable_upload() {
+            if self.supports_fast.load(Ordering::Relaxed) {
+                return self.reject_request(request);
+            }
             anyhow::bail!("upload disabled, but peer requested a piece")
         }
 
@@ -1502,12 +1623,26 @@ impl PeerHandler {
             }
         };
 
+        if self.am_choking() && !self.is_allowed_fast(piece_index) {
+            if self.supports_fast.load(Ordering::Relaxed) {
+                trace!(?chunk_info, "rejecting request while choking the peer");
+                return self.reject_request(request);
+            }
+            // Requests can race with our choke message, so this is not an error.
+            trace!(?chunk_info, "ignoring request while choking the peer");
+            return Ok(());
+        }
+
         if !self
             .state
             .lock_read("is_chunk_ready_to_upload")
             .get_chunks()?
             .is_chunk_ready_to_upload(&chunk_info)
         {
+            if self.supports_fast.load(Ordering::Relaxed) {
+                debug!(?chunk_info, "rejecting request for a chunk we don't have");
+                return self.reject_request(request);
+            }
             anyhow::bail!(
                 "got request for a chunk that is not ready to upload. chunk {:?}",
                 chunk_info
@@ -1520,6 +1655,180 @@ impl PeerHandler {
         Ok(())
     }
 
+    fn am_choking(&self) -> bool {
+        self.state
+            .peers
+            .with_live(self.addr, |live| live.am_choking)
+            .unwrap_or(true)
+    }
+
+    // Whether the piece is in the allowed fast set we'd send to this peer, i.e. it may be requested
+    // while choked.
+    fn is_allowed_fast(&self, piece: ValidPieceIndex) -> bool {
+        self.supports_fast.load(Ordering::Relaxed) && self.allowed_fast_set().contains(&piece.get())
+    }
+
+    fn allowed_fast_set(&self) -> &[u32] {
+        self.allowed_fast_set.get_or_init(|| {
+            allowed_fast::allowed_fast_set(
+                self.addr.ip(),
+                self.state.shared.info_hash,
+                self.state.lengths.total_pieces(),
+                allowed_fast::ALLOWED_FAST_SET_SIZE,
+            )
+        })
+    }
+
+    fn reject_request(&self, request: Request) -> anyhow::Result<()> {
+        self.tx
+            .send(WriterRequest::Message(Message::RejectRequest(request)))
+            .context("error sending reject request: channel closed")?;
+        Ok(())
+    }
+
+    fn on_hash_request(&self, request: HashRequest) -> anyhow::Result<()> {
+        let metadata = &self.state.metadata;
+        let hashes = metadata
+            .piece_layers
+            .answer_request(
+                &request.pieces_root,
+                metadata.info.info().piece_length,
+                request.base_layer,
+                request.index,
+                request.length,
+                request.proof_layers,
+            )
+            .filter(|h| h.len() <= MAX_HASHES_IN_MSG);
+        let msg = match hashes {
+            Some(hashes) => WriterRequest::Hashes(Hashes {
+                request,
+                hashes: ByteBufOwned::from(hashes.iter().flat_map(|h| h.0).collect::<Vec<u8>>()),
+            }),
+            None => {
+                debug!(?request, "rejecting hash request");
+                WriterRequest::Message(Message::HashReject(request))
+            }
+        };
+        self.tx
+            .send(msg)
+            .context("error sending hashes: channel closed")?;
+        Ok(())
+    }
+
+    fn check_fast(&self, msg: &'static str) -> anyhow::Result<()> {
+        if !self.supports_fast.load(Ordering::Relaxed) {
+            anyhow::bail!("peer sent {msg}, but the fast extension wasn't negotiated");
+        }
+        Ok(())
+    }
+
+    fn on_have_all(&self) {
+        let mut bf = make_piece_bitfield(&self.state.lengths);
+        bf[..self.state.lengths.total_pieces() as usize].fill(true);
+        debug!("peer has full torrent");
+        self.state.update_peer_bitfield(self.addr, bf);
+        self.on_bitfield_notify.notify_waiters();
+    }
+
+    fn on_have_none(&self) {
+        self.state
+            .update_peer_bitfield(self.addr, make_piece_bitfield(&self.state.lengths));
+        self.on_bitfield_notify.notify_waiters();
+        self.send_allowed_fast_set();
+    }
+
+    // Let a peer that has nothing yet bootstrap by requesting a few pieces while choked.
+    fn send_allowed_fast_set(&self) {
+        if !self.supports_fast.load(Ordering::Relaxed) || self.state.shared.options.disable_upload()
+        {
+            return;
+        }
+        let g = self.state.lock_read("send_allowed_fast_set");
+        let Ok(chunks) = g.get_chunks() else {
+            return;
+        };
+        for piece in self
+            .allowed_fast_set()
+            .iter()
+            .filter_map(|p| self.state.lengths.validate_piece_index(*p))
+            .filter(|p| chunks.is_piece_have(*p))
+        {
+            if self
+                .tx
+                .send(WriterRequest::Message(Message::AllowedFast(piece.get())))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    fn on_allowed_fast(&self, index: u32) {
+        let Some(piece) = self.state.lengths.validate_piece_index(index) else {
+            debug!(
+                index,
+                "received allowed fast for an invalid piece, ignoring"
+            );
+            return;
+        };
+        trace!(index, "peer allowed us to request piece while choked");
+        self.state
+            .peers
+            .with_live_mut(self.addr, "on_allowed_fast", |live| {
+                live.allowed_fast_pieces.insert(piece);
+            });
+        self.notify_request_slots_changed();
+    }
+
+    fn on_reject_request(&self, request: Request) -> anyhow::Result<()> {
+        let chunk_info = self
+            .state
+            .lengths
+            .validate_piece_index(request.index)
+            .and_then(|p| {
+                self.state
+                    .lengths
+                    .chunk_info_from_received_data(p, request.begin, request.length)
+            })
+            .with_context(|| format!("peer rejected an invalid request {request:?}"))?;
+        let piece = chunk_info.piece_index;
+
+        let expected = self
+            .state
+            .peers
+            .with_live_mut(self.addr, "on_reject_request", |live| {
+                match live.remove_inflight_request(&chunk_info) {
+                    RemoveInflightRequestResult::Expected => {
+                        // The rest of the piece is unlikely to be served either.
+                        live.cancel_inflight_requests_for_piece(piece);
+                        Ok(true)
+                    }
+                    RemoveInflightRequestResult::LateCanceled => Ok(false),
+                    RemoveInflightRequestResult::Unexpected => {
+                        anyhow::bail!("peer rejected a request we never sent: {request:?}")
+                    }
+                }
+            })
+            .context("peer not found")??;
+
+        if !expected {
+            return Ok(());
+        }
+
+        // Release the piece right away so that other peers can pick it up instead of waiting
+        // for the steal timeout.
+        let released = self
+            .state
+            .lock_write("on_reject_request")
+            .get_pieces_mut()?
+            .release_piece_owned_by(piece, self.addr);
+        debug!(%piece, released, "peer rejected our request");
+        if released {
+            self.state.new_pieces_notify.notify_waiters();
+        }
+        Ok(())
+    }
+
     fn on_have(&self, have: u32) {
         self.state
             .peers
@@ -1530,7 +1839,15 @@ impl PeerHandler {
                     live.bitfield = make_piece_bitfield(&self.state.lengths);
                 }
                 match live.bitfield.get_mut(have as usize) {
-                    Some(mut v) => *v = true,
+                    Some(mut v) => {
+                        let had = v.replace(true);
+                        if !had
+                            && let Some(piece) = self.state.lengths.validate_piece_index(have)
+                            && let Ok(pieces) = self.state.lock_write("on_have").get_pieces_mut()
+                        {
+                            pieces.add_peer_piece(piece);
+                        }
+                    }
                     None => {
                         warn!(
                             id = self.state.shared.id,
@@ -1569,8 +1886,12 @@ impl PeerHandler {
         {
             debug!("peer has full torrent");
         }
-        self.state.peers.update_bitfield(self.addr, bf);
+        let is_empty = bf.not_any();
+        self.state.update_peer_bitfield(self.addr, bf);
         self.on_bitfield_notify.notify_waiters();
+        if is_empty {
+            self.send_allowed_fast_set();
+        }
         Ok(())
     }
 
@@ -1753,6 +2074,13 @@ impl PeerHandler {
     fn on_peer_interested(&self) {
         trace!("peer is interested");
         self.state.peers.mark_peer_interested(self.addr, true);
+        self.state.unchoke_if_slot_available(self.addr);
+    }
+
+    fn on_peer_not_interested(&self) {
+        trace!("peer is not interested");
+        // The upload slot is given to someone else on the next choker round.
+        self.state.peers.mark_peer_interested(self.addr, false);
     }
 
     fn on_i_am_unchoked(&self) {
@@ -2044,6 +2372,13 @@ impl PeerHandler {
         self.lock_flow_control("is_choked").i_am_choked
     }
 
+    fn has_allowed_fast_pieces(&self) -> bool {
+        self.state
+            .peers
+            .with_live(self.addr, |live| !live.allowed_fast_pieces.is_empty())
+            .unwrap_or(false)
+    }
+
     fn requested_inflight_count(&self) -> Option<usize> {
         self.state
             .peers
@@ -2056,7 +2391,7 @@ impl PeerHandler {
             (flow.i_am_choked, flow.request_window)
         };
 
-        if i_am_choked {
+        if i_am_choked && !self.has_allowed_fast_pieces() {
             return false;
         }
 
@@ -52,6 +52,19 @@ impl Peer {
         }
     }
 
+    pub fn new_live_for_web_seed(addr: SocketAddr, tx: PeerTx, counters: &PeerStates) -> Self {
+        let state = PeerState::Live(LivePeerState::new_web_seed(tx));
+        for counter in [&counters.session_stats, &counters.stats] {
+            counter.inc(&state);
+        }
+        Self {
+            addr,
+            state,
+            stats: Default::default(),
+            outgoing_address: None,
+        }
+    }
+
     pub fn new_with_outgoing_address(addr: SocketAddr) -> Self {
         Self {
             addr,
@@ -243,6 +256,11 @@ impl Peer {
         }
     }
 
+    pub fn web_seed_to_live(&mut self, tx: PeerTx, counters: &PeerStates) -> &mut LivePeerState {
+        self.set_state(PeerState::Live(LivePeerState::new_web_seed(tx)), counters);
+        self.get_live_mut().unwrap()
+    }
+
     pub fn set_not_needed(&mut self, counters: &PeerStates) -> PeerState {
         self.set_state(PeerState::NotNeeded, counters)
     }
@@ -257,9 +275,15 @@ pub(crate) struct LivePeerState {
 
     pub peer_interested: bool,
 
+    // Whether we are choking the peer. Change it through set_am_choking() so that the peer is told.
+    pub am_choking: bool,
+
     // This is used to track the pieces the peer has.
     pub bitfield: BF,
 
+    // BEP 6: pieces the peer allows us to request even while it's choking us.
+    pub allowed_fast_pieces: HashSet<ValidPieceIndex>,
+
     // When the peer sends us data this is used to track if we asked for it.
     inflight_requests: HashSet<InflightRequest>,
 
@@ -287,7 +311,9 @@ impl LivePeerState {
             peer_id,
             client_name: None,
             peer_interested: initial_interested,
+            am_choking: true,
             bitfield: BF::default(),
+            allowed_fast_pieces: Default::default(),
             inflight_requests: Default::default(),
             late_cancelled_request_tolerance: 0,
             request_slots_changed: Default::default(),
@@ -296,10 +322,29 @@ impl LivePeerState {
         }
     }
 
+    // Web seeds are never interested, and their bitfield is set once they are live.
+    fn new_web_seed(tx: PeerTx) -> Self {
+        Self::new(Id20::default(), tx, false, ConnectionKind::WebSeed)
+    }
+
     pub fn has_full_torrent(&self, total_pieces: usize) -> bool {
         self.bitfield.get(0..total_pieces).is_some_and(|s| s.all())
     }
 
+    /// Choke or unchoke the peer, sending it a message if the state changed.
+    pub fn set_am_choking(&mut self, choking: bool) {
+        if self.am_choking == choking {
+            return;
+        }
+        self.am_choking = choking;
+        let msg = if choking {
+            Message::Choke
+        } else {
+            Message::Unchoke
+        };
+        let _ = self.tx.send(WriterRequest::Message(msg));
+    }
+
     pub fn request_slots_changed(&self) -> Arc<Notify> {
         self.request_slots_changed.clone()
     }
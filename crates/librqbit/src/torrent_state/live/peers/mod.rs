@@ -7,12 +7,15 @@ use parking_lot::RwLock;
 use crate::{
     Error,
     torrent_state::utils::{TimedExistence, atomic_inc},
-    type_aliases::{BF, PeerHandle},
+    type_aliases::PeerHandle,
 };
 
 use self::stats::{AggregatePeerStats, AggregatePeerStatsAtomic};
 
-use super::peer::{LivePeerState, Peer, PeerRx, PeerState, PeerTx};
+use super::peer::{
+    LivePeerState, Peer, PeerRx, PeerState, PeerTx,
+    stats::atomic::PeerCountersAtomic as AtomicPeerCounters,
+};
 
 pub mod stats;
 
@@ -53,6 +56,24 @@ impl PeerStates {
             }
         }
     }
+    /// Mark the web seed live, adding it if it wasn't seen yet.
+    pub fn mark_web_seed_live(&self, handle: PeerHandle, tx: PeerTx) -> Arc<AtomicPeerCounters> {
+        use dashmap::mapref::entry::Entry;
+        let peer = match self.states.entry(handle) {
+            Entry::Occupied(occ) => {
+                let mut peer = occ.into_ref();
+                peer.web_seed_to_live(tx, self);
+                peer
+            }
+            Entry::Vacant(vac) => {
+                atomic_inc(&self.stats.seen);
+                atomic_inc(&self.session_stats.seen);
+                vac.insert(Peer::new_live_for_web_seed(handle, tx, self))
+            }
+        };
+        peer.stats.counters.clone()
+    }
+
     pub fn with_peer<R>(&self, addr: PeerHandle, f: impl FnOnce(&Peer) -> R) -> Option<R> {
         self.states.get(&addr).map(|e| f(e.value()))
     }
@@ -111,12 +132,6 @@ impl PeerStates {
         })
     }
 
-    pub fn update_bitfield(&self, handle: PeerHandle, bitfield: BF) -> Option<()> {
-        self.with_live_mut(handle, "update_bitfield", |live| {
-            live.bitfield = bitfield;
-        })
-    }
-
     pub fn mark_peer_connecting(&self, h: PeerHandle) -> crate::Result<(PeerRx, PeerTx)> {
         let rx = self
             .with_peer_mut(h, "mark_peer_connecting", |peer| {
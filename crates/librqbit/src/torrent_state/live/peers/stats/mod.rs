@@ -16,6 +16,7 @@ gen_stats!(AggregatePeerStatsAtomic AggregatePeerStats, [
     live_tcp u32,
     live_utp u32,
     live_socks u32,
+    live_webseed u32,
     seen u32,
     dead u32,
     not_needed u32,
@@ -38,6 +39,7 @@ impl AggregatePeerStatsAtomic {
             ConnectionKind::Tcp => &self.live_tcp,
             ConnectionKind::Utp => &self.live_utp,
             ConnectionKind::Socks => &self.live_socks,
+            ConnectionKind::WebSeed => &self.live_webseed,
         }
     }
 
@@ -0,0 +1,173 @@
+// Web seeds are virtual peers. They have all the pieces, never choke us and never request anything.
+//
+// Each web seed has one task that reserves pieces through the same PieceTracker logic as the
+// regular peers, downloads them over HTTP and processes them chunk by chunk as if they were
+// received from a peer.
+
+use std::{
+    num::NonZeroU32,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use peer_binary_protocol::Piece;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{debug, warn};
+
+use crate::{
+    Error,
+    web_seed::{WebSeed, fetch_piece},
+};
+
+use super::{PeerFlowControl, PeerHandler, TorrentStateLive, make_piece_bitfield};
+
+impl TorrentStateLive {
+    pub(super) async fn task_web_seed(
+        self: Arc<Self>,
+        index: usize,
+        web_seed: WebSeed,
+    ) -> crate::Result<()> {
+        let handle = WebSeed::peer_handle(index);
+        let client = self
+            .shared
+            .session
+            .upgrade()
+            .ok_or(Error::SessionDestroyed)?
+            .reqwest_client
+            .clone();
+
+        loop {
+            if self.is_finished_and_no_active_streams() {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+
+            let (tx, mut rx) = unbounded_channel();
+            let counters = self.peers.mark_web_seed_live(handle, tx.clone());
+            let mut bitfield = make_piece_bitfield(&self.lengths);
+            bitfield[..self.lengths.total_pieces() as usize].fill(true);
+            self.update_peer_bitfield(handle, bitfield);
+
+            let handler = PeerHandler {
+                addr: handle,
+                // Not re-queued through the peer adder on errors, this task retries it instead.
+                incoming: true,
+                on_bitfield_notify: Default::default(),
+                flow_control: Mutex::new(PeerFlowControl {
+                    i_am_choked: false,
+                    ..Default::default()
+                }),
+                state: self.clone(),
+                tx,
+                counters,
+                first_message_received: AtomicBool::new(true),
+                supports_fast: AtomicBool::new(false),
+                allowed_fast_set: Default::default(),
+                cancel_token: self.cancellation_token.child_token(),
+                client_name_and_version: self.shared.client_name_and_version().to_owned(),
+            };
+            let _token_guard = handler.cancel_token.clone().drop_guard();
+
+            // Nothing sent to a web seed needs an answer. Cancellations of stolen pieces are
+            // already reflected in its inflight requests.
+            let drain = async { while rx.recv().await.is_some() {} };
+
+            let res = tokio::select! {
+                r = handler.task_web_seed_requester(&client, &web_seed) => r,
+                _ = drain => Ok(()),
+            };
+
+            match res {
+                Ok(()) => {
+                    handler.on_peer_died(None)?;
+                    continue;
+                }
+                Err(e) => {
+                    debug!("error downloading from web seed: {e:#}");
+                    handler.on_peer_died(Some(Error::Anyhow(e)))?;
+                }
+            }
+
+            let backoff = self
+                .peers
+                .with_peer_mut(handle, "web_seed_backoff", |p| p.stats.backoff.next())
+                .flatten();
+            match backoff {
+                Some(dur) => tokio::time::sleep(dur).await,
+                None => {
+                    warn!(
+                        id = self.shared.id,
+                        info_hash = ?self.shared.info_hash,
+                        url = %web_seed.url(),
+                        "giving up on web seed, backoff exhausted"
+                    );
+                    self.peers.drop_peer(handle);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl PeerHandler {
+    async fn task_web_seed_requester(
+        &self,
+        client: &reqwest::Client,
+        web_seed: &WebSeed,
+    ) -> anyhow::Result<()> {
+        let lengths = &self.state.lengths;
+        loop {
+            if self.state.is_finished_and_no_active_streams() {
+                debug!("nothing left to download, stopping web seed");
+                return Ok(());
+            }
+
+            let new_piece_notify = self.state.new_pieces_notify.notified();
+            let piece = match self.acquire_next_piece()? {
+                Some(piece) => piece,
+                None => {
+                    let _ = tokio::time::timeout(Duration::from_secs(5), new_piece_notify).await;
+                    continue;
+                }
+            };
+
+            // Track the chunks as requested, so that on_received_piece() accepts them, and so that
+            // they get cancelled if someone steals the piece while we are downloading it.
+            for chunk in lengths.iter_chunk_infos(piece) {
+                if self
+                    .state
+                    .peers
+                    .with_live_mut(self.addr, "add chunk request", |live| {
+                        live.add_inflight_request(chunk)
+                    })
+                    .is_none()
+                {
+                    return Ok(());
+                }
+            }
+
+            for chunk in lengths.iter_chunk_infos(piece) {
+                let len = NonZeroU32::new(chunk.size).unwrap();
+                self.state.ratelimits.prepare_for_download(len).await?;
+                if let Some(session) = self.state.torrent().session.upgrade() {
+                    session.ratelimits.prepare_for_download(len).await?;
+                }
+            }
+
+            let requests = web_seed.requests_for_piece(
+                self.state.shared.info_hash,
+                &self.state.metadata.info,
+                &self.state.metadata.file_infos,
+                piece,
+            )?;
+            let data = fetch_piece(client, &requests, lengths.piece_length(piece).into()).await?;
+
+            for chunk in lengths.iter_chunk_infos(piece) {
+                let block = &data[chunk.offset as usize..][..chunk.size as usize];
+                self.on_received_piece(Piece::from_data(piece.get(), chunk.offset, block))
+                    .await?;
+            }
+        }
+    }
+}
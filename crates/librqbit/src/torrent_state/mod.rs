@@ -18,10 +18,12 @@ use anyhow::bail;
 use arc_swap::ArcSwapOption;
 use buffers::ByteBufOwned;
 use bytes::Bytes;
+use dht::ScrapeEstimate;
 use futures::FutureExt;
 use futures::future::BoxFuture;
-use librqbit_core::hash_id::Id20;
+use librqbit_core::hash_id::{Id20, Id32};
 use librqbit_core::lengths::Lengths;
+use librqbit_core::merkle::PieceLayers;
 
 use librqbit_core::spawn_utils::spawn_with_cancel;
 use librqbit_core::torrent_metainfo::ValidatedTorrentMetaV1Info;
@@ -36,11 +38,16 @@ use tracing::debug;
 use tracing::debug_span;
 use tracing::trace;
 use tracing::warn;
+use tracker_comms::{AnnounceEvents, ScrapeStats, TrackerStatuses};
 
+use crate::SeedGoals;
+use crate::SeedingStats;
 use crate::Session;
 use crate::chunk_tracker::ChunkTracker;
 use crate::file_info::FileInfo;
+use crate::file_priority::FilePriority;
 use crate::limits::LimitsConfig;
+use crate::session::MutableTorrentWatch;
 use crate::session::TorrentId;
 use crate::spawn_utils::BlockingSpawner;
 use crate::storage::BoxStorageFactory;
@@ -48,6 +55,7 @@ use crate::stream_connect::StreamConnector;
 use crate::torrent_state::stats::LiveStats;
 use crate::type_aliases::FileInfos;
 use crate::type_aliases::PeerStream;
+use crate::web_seed::WebSeed;
 
 use initializing::TorrentStateInitializing;
 
@@ -103,8 +111,27 @@ pub(crate) struct ManagedTorrentLocked {
     //
     // This should change only on "unpause".
     pub(crate) paused: bool,
+    // Paused by the session queue rather than by the user. Such torrents are
+    // started by the queue once a slot frees up.
+    pub(crate) queued: bool,
+    // Position in the session queue, lower positions are started first.
+    pub(crate) queue_position: usize,
     pub(crate) state: ManagedTorrentState,
     pub(crate) only_files: Option<Vec<usize>>,
+    // Indexed by file id. If set, files with "skip" priority are exactly the ones not in only_files.
+    pub(crate) file_priorities: Option<Vec<FilePriority>>,
+    // Overrides of the session's default seed goals.
+    pub(crate) seed_goals: SeedGoals,
+    // Totals up to the current live state, which keeps its own byte counters.
+    pub(crate) seeding_stats: SeedingStats,
+}
+
+impl ManagedTorrentLocked {
+    // Called when the live state goes away, as its counters go with it.
+    fn add_live_counters(&mut self, live: &TorrentStateLive) {
+        self.seeding_stats.uploaded_bytes += live.get_uploaded_bytes();
+        self.seeding_stats.downloaded_bytes += live.get_downloaded_bytes();
+    }
 }
 
 #[derive(Default)]
@@ -117,6 +144,8 @@ pub(crate) struct ManagedTorrentOptions {
     pub ratelimits: LimitsConfig,
     pub initial_peers: Vec<SocketAddr>,
     pub peer_limit: Option<usize>,
+    // The torrent's own setting, if not set the session's default is used.
+    pub upload_slots: Option<usize>,
     #[cfg(feature = "disable-upload")]
     pub _disable_upload: bool,
 }
@@ -139,6 +168,8 @@ pub struct TorrentMetadata {
     pub torrent_bytes: Bytes,
     pub info_bytes: Bytes,
     pub file_infos: FileInfos,
+    // BEP 52 piece hashes. Only populated for v2 and hybrid torrents.
+    pub piece_layers: PieceLayers,
 }
 
 impl TorrentMetadata {
@@ -146,6 +177,7 @@ impl TorrentMetadata {
         info: ValidatedTorrentMetaV1Info<ByteBufOwned>,
         torrent_bytes: Bytes,
         info_bytes: Bytes,
+        piece_layers: PieceLayers,
     ) -> anyhow::Result<Self> {
         let file_infos = info
             .iter_file_details_ext()
@@ -165,6 +197,7 @@ impl TorrentMetadata {
             torrent_bytes,
             info_bytes,
             file_infos,
+            piece_layers,
         })
     }
 
@@ -181,8 +214,11 @@ impl TorrentMetadata {
 pub struct ManagedTorrentShared {
     pub id: TorrentId,
     pub info_hash: Id20,
+    // Set for v2 and hybrid torrents.
+    pub info_hash_v2: Option<Id32>,
     pub(crate) spawner: BlockingSpawner,
-    pub trackers: HashSet<url::Url>,
+    // BEP 19 and BEP 17 web seeds from the torrent file.
+    pub web_seeds: Vec<WebSeed>,
     pub peer_id: Id20,
     pub span: tracing::Span,
     pub(crate) options: ManagedTorrentOptions,
@@ -194,12 +230,25 @@ pub struct ManagedTorrentShared {
     pub(crate) magnet_name: Option<String>,
 
     pub(crate) client_name_and_version: String,
+
+    // Can be changed at runtime, the tracker announces are updated on every change.
+    // Tracker tiers (BEP 12).
+    pub(crate) trackers: tokio::sync::watch::Sender<Vec<Vec<url::Url>>>,
 }
 
 impl ManagedTorrentShared {
     pub(crate) fn client_name_and_version(&self) -> &str {
         &self.client_name_and_version
     }
+
+    /// All trackers, tier by tier.
+    pub fn trackers(&self) -> Vec<url::Url> {
+        self.trackers.borrow().iter().flatten().cloned().collect()
+    }
+
+    pub fn tracker_tiers(&self) -> Vec<Vec<url::Url>> {
+        self.trackers.borrow().clone()
+    }
 }
 
 pub struct ManagedTorrent {
@@ -209,6 +258,14 @@ pub struct ManagedTorrent {
     pub metadata: ArcSwapOption<TorrentMetadata>,
     pub(crate) state_change_notify: Notify,
     pub(crate) locked: RwLock<ManagedTorrentLocked>,
+    // Updated by the tracker announce tasks and the session scraper.
+    pub(crate) tracker_statuses: TrackerStatuses,
+    // What was announced to each tracker, kept across the restarts of the announces.
+    pub(crate) announce_events: AnnounceEvents,
+    // Set on the current version of a mutable torrent (BEP 46).
+    pub(crate) mutable_watch: RwLock<Option<MutableTorrentWatch>>,
+    // Updated by the session scraper.
+    pub(crate) dht_scrape: RwLock<Option<ScrapeEstimate>>,
 }
 
 impl ManagedTorrent {
@@ -249,10 +306,99 @@ impl ManagedTorrent {
         self.shared.info_hash
     }
 
+    pub fn info_hash_v2(&self) -> Option<Id32> {
+        self.shared.info_hash_v2
+    }
+
+    /// If peers may refer to this torrent by this info hash. Hybrid torrents are known
+    /// both by the v1 and by the truncated v2 info hash.
+    pub(crate) fn matches_info_hash(&self, info_hash: Id20) -> bool {
+        self.shared.info_hash == info_hash
+            || self
+                .shared
+                .info_hash_v2
+                .is_some_and(|v2| v2.truncate_for_dht() == info_hash)
+    }
+
     pub fn only_files(&self) -> Option<Vec<usize>> {
         self.locked.read().only_files.clone()
     }
 
+    /// Per-file priorities, if they were ever set. Otherwise all the files in only_files
+    /// have normal priority.
+    pub fn file_priorities(&self) -> Option<Vec<FilePriority>> {
+        self.locked.read().file_priorities.clone()
+    }
+
+    /// The seed goals set for this torrent. Goals that aren't set fall back to the session's defaults.
+    pub fn seed_goals(&self) -> SeedGoals {
+        self.locked.read().seed_goals
+    }
+
+    pub(crate) fn update_seed_goals(&self, seed_goals: SeedGoals) {
+        self.locked.write().seed_goals = seed_goals;
+    }
+
+    /// Uploaded and downloaded bytes and seeding times, including earlier runs of the torrent.
+    pub fn seeding_stats(&self) -> SeedingStats {
+        let g = self.locked.read();
+        let mut stats = g.seeding_stats;
+        if let ManagedTorrentState::Live(live) = &g.state {
+            stats.uploaded_bytes += live.get_uploaded_bytes();
+            stats.downloaded_bytes += live.get_downloaded_bytes();
+        }
+        stats
+    }
+
+    pub(crate) fn add_seeding_time(&self, elapsed: Duration, uploaded: bool) {
+        self.locked
+            .write()
+            .seeding_stats
+            .add_seeding_time(elapsed, uploaded);
+    }
+
+    /// All trackers, tier by tier.
+    pub fn trackers(&self) -> Vec<url::Url> {
+        self.shared.trackers()
+    }
+
+    pub(crate) fn mutable_watch(&self) -> Option<MutableTorrentWatch> {
+        self.mutable_watch.read().clone()
+    }
+
+    pub fn tracker_tiers(&self) -> Vec<Vec<url::Url>> {
+        self.shared.tracker_tiers()
+    }
+
+    // Returns true if the trackers changed.
+    pub(crate) fn update_trackers(&self, f: impl FnOnce(&mut Vec<Vec<url::Url>>)) -> bool {
+        self.shared.trackers.send_if_modified(|tiers| {
+            let old = tiers.clone();
+            f(tiers);
+            *tiers = normalize_tracker_tiers(std::mem::take(tiers));
+            *tiers != old
+        })
+    }
+
+    /// Swarm counts from the tracker and swarm that know about the most peers.
+    pub fn scrape(&self) -> Option<ScrapeStats> {
+        self.tracker_statuses
+            .snapshot()
+            .into_iter()
+            .filter_map(|s| s.scrape)
+            .max_by_key(|s| u64::from(s.seeders) + u64::from(s.leechers))
+    }
+
+    pub(crate) fn update_scrape(&self, tracker: &url::Url, info_hash: Id20, stats: ScrapeStats) {
+        self.tracker_statuses
+            .update(tracker, info_hash, |s| s.scrape = Some(stats));
+    }
+
+    /// Swarm size estimated with the latest DHT scrape (BEP 33).
+    pub fn dht_scrape(&self) -> Option<ScrapeEstimate> {
+        *self.dht_scrape.read()
+    }
+
     pub fn with_state<R>(&self, f: impl FnOnce(&ManagedTorrentState) -> R) -> R {
         f(&self.locked.read().state)
     }
@@ -302,6 +448,7 @@ impl ManagedTorrent {
 
         match g.state.take() {
             ManagedTorrentState::Live(live) => {
+                g.add_live_counters(&live);
                 if let Err(err) = live.pause() {
                     warn!(
                         id = self.shared.id,
@@ -417,7 +564,12 @@ impl ManagedTorrent {
                     }
                     let paused = g.state.take().assert_paused();
                     let (tx, rx) = tokio::sync::oneshot::channel();
-                    let live = TorrentStateLive::new(paused, tx, token.clone())?;
+                    let live = TorrentStateLive::new(
+                        paused,
+                        g.file_priorities.as_deref(),
+                        tx,
+                        token.clone(),
+                    )?;
                     g.state = ManagedTorrentState::Live(live.clone());
                     t.state_change_notify.notify_waiters();
 
@@ -455,6 +607,7 @@ impl ManagedTorrent {
             .context("session is dead, cannot start torrent")?;
         let mut g = self.locked.write();
         g.paused = start_paused;
+        g.queued = false;
         let cancellation_token = session.cancellation_token().child_token();
 
         _start(
@@ -471,24 +624,66 @@ impl ManagedTorrent {
         self.locked.read().paused
     }
 
+    /// If the torrent is waiting for a slot in the session queue.
+    pub fn is_queued(&self) -> bool {
+        self.locked.read().queued
+    }
+
+    pub fn queue_position(&self) -> usize {
+        self.locked.read().queue_position
+    }
+
+    pub(crate) fn set_queue_position(&self, position: usize) {
+        self.locked.write().queue_position = position;
+    }
+
+    /// Initialize the torrent (check file integrity), but leave starting it to the session queue.
+    pub(crate) fn start_queued(self: &Arc<Self>) -> anyhow::Result<()> {
+        self.start(None, true)?;
+        let mut g = self.locked.write();
+        g.paused = false;
+        g.queued = true;
+        Ok(())
+    }
+
     /// Pause the torrent if it's live.
     pub(crate) fn pause(&self) -> anyhow::Result<()> {
+        self.pause_impl(false)
+    }
+
+    /// Pause the torrent to free up a slot in the session queue. Unlike [Self::pause]
+    /// this doesn't change the intent, so the queue will start the torrent again later.
+    pub(crate) fn queue(&self) -> anyhow::Result<()> {
+        self.pause_impl(true)
+    }
+
+    fn pause_impl(&self, queued: bool) -> anyhow::Result<()> {
         let mut g = self.locked.write();
         match &g.state {
             ManagedTorrentState::Live(live) => {
+                let live = live.clone();
                 let paused = live.pause()?;
+                g.add_live_counters(&live);
                 g.state = ManagedTorrentState::Paused(paused);
-                g.paused = true;
+                g.paused = !queued;
+                g.queued = queued;
                 self.state_change_notify.notify_waiters();
                 Ok(())
             }
             ManagedTorrentState::Initializing(init) => {
                 let init = init.clone();
-                g.paused = true;
+                g.paused = !queued;
+                g.queued = queued;
                 init.request_pause();
                 self.state_change_notify.notify_waiters();
                 Ok(())
             }
+            ManagedTorrentState::Paused(_) if g.queued && !queued => {
+                // Waiting in the queue, just make sure the queue won't start it.
+                g.paused = true;
+                g.queued = false;
+                Ok(())
+            }
             ManagedTorrentState::Paused(_) => {
                 bail!("torrent is already paused");
             }
@@ -515,11 +710,15 @@ impl ManagedTorrent {
             progress_bytes: 0,
             uploaded_bytes: 0,
             finished: false,
+            queued: false,
+            queue_position: 0,
             live: None,
         };
 
         {
             let g = self.locked.read();
+            resp.queued = g.queued;
+            resp.queue_position = g.queue_position;
             match &g.state {
                 ManagedTorrentState::Initializing(i) => {
                     resp.state = S::Initializing { paused: g.paused };
@@ -615,6 +814,16 @@ impl ManagedTorrent {
     // Returns true if needed to unpause torrent.
     // This is just implementation detail - it's easier to pause/unpause than to tinker with internals.
     pub(crate) fn update_only_files(&self, only_files: &HashSet<usize>) -> anyhow::Result<()> {
+        self.update_selection(only_files, None)
+    }
+
+    // Update the selected files together with the file priorities: the given ones, or else the
+    // current ones with "skip" kept in sync with the selection. Both are applied under one lock.
+    fn update_selection(
+        &self,
+        only_files: &HashSet<usize>,
+        file_priorities: Option<&[FilePriority]>,
+    ) -> anyhow::Result<()> {
         let metadata = self.metadata.load();
         let metadata = metadata.as_ref().context("torrent is not resolved")?;
         let file_count = metadata.file_infos.len();
@@ -629,6 +838,20 @@ impl ManagedTorrent {
         // if paused, need to update chunk tracker
 
         let mut g = self.locked.write();
+        let file_priorities = match file_priorities {
+            Some(p) => Some(p.to_vec()),
+            // Keep "skip" priorities in sync with the new selection.
+            None => g.file_priorities.clone().map(|mut priorities| {
+                for (id, priority) in priorities.iter_mut().enumerate() {
+                    match (only_files.contains(&id), *priority) {
+                        (false, _) => *priority = FilePriority::Skip,
+                        (true, FilePriority::Skip) => *priority = FilePriority::Normal,
+                        _ => {}
+                    }
+                }
+                priorities
+            }),
+        };
         match &mut g.state {
             ManagedTorrentState::Initializing(_) => bail!("can't update initializing torrent"),
             ManagedTorrentState::Error(_) => {}
@@ -637,13 +860,36 @@ impl ManagedTorrent {
                 p.update_only_files(only_files)?;
             }
             ManagedTorrentState::Live(l) => {
-                l.update_only_files(only_files)?;
+                l.update_only_files(only_files, file_priorities.as_deref())?;
             }
         };
 
         g.only_files = Some(only_files.iter().copied().collect());
+        if file_priorities.is_some() {
+            g.file_priorities = file_priorities;
+        }
         Ok(())
     }
+
+    pub(crate) fn update_file_priorities(
+        &self,
+        file_priorities: &[FilePriority],
+    ) -> anyhow::Result<()> {
+        let file_count = self.with_metadata(|m| m.file_infos.len())?;
+        if file_priorities.len() != file_count {
+            bail!(
+                "expected {file_count} file priorities, got {}",
+                file_priorities.len()
+            );
+        }
+        let only_files = file_priorities
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| **p != FilePriority::Skip)
+            .map(|(id, _)| id)
+            .collect();
+        self.update_selection(&only_files, Some(file_priorities))
+    }
 }
 
 pub type ManagedTorrentHandle = Arc<ManagedTorrent>;
@@ -680,6 +926,20 @@ fn spawn_fatal_errors_receiver(
     );
 }
 
+// Remove duplicate trackers, keeping the first occurrence, and empty tiers.
+pub(crate) fn normalize_tracker_tiers(tiers: Vec<Vec<url::Url>>) -> Vec<Vec<url::Url>> {
+    let mut seen = HashSet::new();
+    tiers
+        .into_iter()
+        .map(|tier| {
+            tier.into_iter()
+                .filter(|t| seen.insert(t.clone()))
+                .collect::<Vec<_>>()
+        })
+        .filter(|tier| !tier.is_empty())
+        .collect()
+}
+
 fn spawn_peer_adder(live: &Arc<TorrentStateLive>, mut peer_rx: PeerStream) {
     live.spawn(
         debug_span!(parent: live.torrent().span.clone(), "external_peer_adder"),
@@ -81,6 +81,9 @@ pub struct TorrentStats {
     pub uploaded_bytes: u64,
     pub total_bytes: u64,
     pub finished: bool,
+    /// Waiting for a slot in the session queue.
+    pub queued: bool,
+    pub queue_position: usize,
     pub live: Option<LiveStats>,
 }
 
@@ -154,6 +157,8 @@ mod tests {
             uploaded_bytes: 0,
             total_bytes: 100,
             finished: false,
+            queued: false,
+            queue_position: 0,
             live: None,
         }
     }
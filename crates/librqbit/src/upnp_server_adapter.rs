@@ -417,6 +417,8 @@ mod tests {
                     sha1: None,
                     symlink_path: None,
                     private: false,
+                    meta_version: None,
+                    file_tree: None,
                 },
                 raw_bytes: Default::default(),
             },
@@ -426,7 +428,11 @@ mod tests {
             publisher: None,
             publisher_url: None,
             creation_date: None,
+            url_list: vec![],
+            httpseeds: vec![],
+            piece_layers: None,
             info_hash: Id20::default(),
+            info_hash_v2: None,
         }
     }
 
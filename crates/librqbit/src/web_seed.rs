@@ -0,0 +1,428 @@
+// Web seeds: HTTP servers hosting the torrent's content. They are used as virtual peers.
+//
+// - BEP 19 ("url-list") seeds serve the files themselves, pieces are fetched with byte range requests.
+// - BEP 17 ("httpseeds") seeds serve whole pieces by their index.
+
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    ops::Range,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use buffers::ByteBufOwned;
+use itertools::Itertools;
+use librqbit_core::{
+    hash_id::Id20,
+    lengths::ValidPieceIndex,
+    torrent_metainfo::{FileIteratorName, TorrentMetaV1, ValidatedTorrentMetaV1Info},
+};
+use reqwest::{StatusCode, header};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::type_aliases::{FileInfos, PeerHandle};
+
+// Limits how many virtual peers a single torrent can have.
+const MAX_WEB_SEEDS: usize = 32;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Web seeds get synthetic peer handles from the IPv6 discard prefix (RFC 6666) with port 0.
+// They never clash with real peers and are never connected to.
+const HANDLE_PREFIX: [u16; 7] = [0x100, 0, 0, 0, 0, 0, 0];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WebSeed {
+    /// BEP 19 (GetRight style).
+    UrlList(Url),
+    /// BEP 17 (Hoffman style).
+    HttpSeed(Url),
+}
+
+/// A single HTTP request needed to fetch (a part of) a piece.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum WebSeedRequest {
+    /// Fetch the whole resource.
+    Full { url: Url, len: u64 },
+    /// Fetch a byte range of the resource.
+    Range { url: Url, range: Range<u64> },
+    /// Padding that isn't stored anywhere. It's all zeroes.
+    Zeroes { len: u64 },
+}
+
+impl WebSeed {
+    pub fn url(&self) -> &Url {
+        match self {
+            WebSeed::UrlList(url) | WebSeed::HttpSeed(url) => url,
+        }
+    }
+
+    pub(crate) fn from_torrent<BufType: AsRef<[u8]>>(
+        meta: &TorrentMetaV1<BufType>,
+    ) -> Vec<WebSeed> {
+        let parse = |url: &BufType| -> Option<Url> {
+            let url = match std::str::from_utf8(url.as_ref()) {
+                Ok("") => return None,
+                Ok(url) => url,
+                Err(_) => {
+                    warn!("cannot parse web seed url as utf-8, ignoring");
+                    return None;
+                }
+            };
+            match Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+                Ok(url) => {
+                    debug!(%url, "ignoring web seed with unsupported scheme");
+                    None
+                }
+                Err(e) => {
+                    debug!(url, "error parsing web seed url: {e:#}");
+                    None
+                }
+            }
+        };
+        let mut seeds = meta
+            .url_list
+            .iter()
+            .filter_map(parse)
+            .map(WebSeed::UrlList)
+            .chain(
+                meta.httpseeds
+                    .iter()
+                    .filter_map(parse)
+                    .map(WebSeed::HttpSeed),
+            )
+            .unique()
+            .collect_vec();
+        if seeds.len() > MAX_WEB_SEEDS {
+            warn!(
+                count = seeds.len(),
+                "too many web seeds, using first {MAX_WEB_SEEDS}"
+            );
+            seeds.truncate(MAX_WEB_SEEDS);
+        }
+        seeds
+    }
+
+    /// The synthetic peer handle of the web seed at this index.
+    pub(crate) fn peer_handle(index: usize) -> PeerHandle {
+        let [a, b, c, d, e, f, g] = HANDLE_PREFIX;
+        let index = u16::try_from(index).expect("web seed count is bounded by MAX_WEB_SEEDS");
+        SocketAddr::new(Ipv6Addr::new(a, b, c, d, e, f, g, index).into(), 0)
+    }
+
+    /// The index of the web seed if this is a synthetic web seed handle.
+    pub(crate) fn index_from_peer_handle(handle: PeerHandle) -> Option<usize> {
+        match handle {
+            SocketAddr::V6(addr) if addr.port() == 0 => {
+                let [prefix @ .., index] = addr.ip().segments();
+                (prefix == HANDLE_PREFIX).then_some(index as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// The HTTP requests that together return the contents of the piece, in order.
+    pub(crate) fn requests_for_piece(
+        &self,
+        info_hash: Id20,
+        info: &ValidatedTorrentMetaV1Info<ByteBufOwned>,
+        file_infos: &FileInfos,
+        piece: ValidPieceIndex,
+    ) -> anyhow::Result<Vec<WebSeedRequest>> {
+        let lengths = info.lengths();
+        let piece_len = lengths.piece_length(piece) as u64;
+        let base = match self {
+            WebSeed::HttpSeed(url) => {
+                let mut url = url.clone();
+                let query = format!(
+                    "info_hash={}&piece={}",
+                    urlencoding::encode_binary(&info_hash.0),
+                    piece.get()
+                );
+                let query = match url.query() {
+                    Some(existing) if !existing.is_empty() => format!("{existing}&{query}"),
+                    _ => query,
+                };
+                url.set_query(Some(&query));
+                return Ok(vec![WebSeedRequest::Full {
+                    url,
+                    len: piece_len,
+                }]);
+            }
+            WebSeed::UrlList(url) => url,
+        };
+
+        let piece_start = lengths.piece_offset(piece);
+        let piece_end = piece_start + piece_len;
+        let mut requests = Vec::new();
+        for (fi, fd) in file_infos.iter().zip(info.iter_file_details()) {
+            let file_start = fi.offset_in_torrent;
+            let file_end = file_start + fi.len;
+            if fi.len == 0 || file_end <= piece_start || file_start >= piece_end {
+                continue;
+            }
+            let range =
+                piece_start.max(file_start) - file_start..piece_end.min(file_end) - file_start;
+            if fi.attrs.padding {
+                requests.push(WebSeedRequest::Zeroes {
+                    len: range.end - range.start,
+                });
+                continue;
+            }
+            requests.push(WebSeedRequest::Range {
+                url: file_url(base, info.name().as_deref(), &fd.filename)?,
+                range,
+            });
+        }
+        Ok(requests)
+    }
+}
+
+// BEP 19: a URL ending with a slash is a directory, and the torrent name is appended to it.
+// A URL without the slash points directly to the file of a single-file torrent.
+fn file_url(
+    base: &Url,
+    torrent_name: Option<&str>,
+    filename: &FileIteratorName<'_, ByteBufOwned>,
+) -> anyhow::Result<Url> {
+    if filename.is_single_file() && !base.path().ends_with('/') {
+        return Ok(base.clone());
+    }
+    let mut url = base.clone();
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .ok()
+            .with_context(|| format!("web seed url {base} cannot be a base"))?;
+        segments.pop_if_empty();
+        if !filename.is_single_file()
+            && let Some(name) = torrent_name
+        {
+            segments.push(name);
+        }
+        segments.extend(filename.iter_components());
+    }
+    Ok(url)
+}
+
+/// Download the data of a piece with the given requests.
+pub(crate) async fn fetch_piece(
+    client: &reqwest::Client,
+    requests: &[WebSeedRequest],
+    piece_len: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(piece_len.try_into()?);
+    for request in requests {
+        let (url, len, range_header) = match request {
+            WebSeedRequest::Zeroes { len } => {
+                buf.resize(buf.len() + usize::try_from(*len)?, 0);
+                continue;
+            }
+            WebSeedRequest::Full { url, len } => (url, *len, None),
+            WebSeedRequest::Range { url, range } => (
+                url,
+                range.end - range.start,
+                Some(format!("bytes={}-{}", range.start, range.end - 1)),
+            ),
+        };
+
+        let mut http_request = client.get(url.clone());
+        if let Some(range) = range_header {
+            http_request = http_request.header(header::RANGE, range);
+        }
+        let mut response = tokio::time::timeout(READ_TIMEOUT, http_request.send())
+            .await
+            .with_context(|| format!("{url}: timeout"))?
+            .with_context(|| format!("{url}: error sending request"))?;
+
+        let status = response.status();
+        match (request, status) {
+            (WebSeedRequest::Full { .. }, StatusCode::OK)
+            | (WebSeedRequest::Range { .. }, StatusCode::PARTIAL_CONTENT) => {}
+            // The server ignored the range and sent the whole file. Its prefix is still what we need.
+            (WebSeedRequest::Range { range, .. }, StatusCode::OK) if range.start == 0 => {}
+            _ => bail!("{url}: unexpected HTTP status {status}"),
+        }
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+                .await
+                .with_context(|| format!("{url}: timeout"))?
+                .with_context(|| format!("{url}: error reading response"))?
+                .with_context(|| format!("{url}: response is {remaining} bytes too short"))?;
+            let take = usize::try_from(remaining)
+                .unwrap_or(usize::MAX)
+                .min(chunk.len());
+            buf.extend_from_slice(&chunk[..take]);
+            remaining -= take as u64;
+        }
+    }
+    if buf.len() as u64 != piece_len {
+        bail!("expected {piece_len} bytes, fetched {}", buf.len());
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use buffers::ByteBufOwned;
+    use librqbit_core::{
+        hash_id::Id20,
+        torrent_metainfo::{TorrentMetaV1File, TorrentMetaV1Info},
+    };
+    use url::Url;
+
+    use crate::{file_info::FileInfo, type_aliases::FileInfos};
+
+    use super::{WebSeed, WebSeedRequest};
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn file_infos(
+        info: &librqbit_core::torrent_metainfo::ValidatedTorrentMetaV1Info<ByteBufOwned>,
+    ) -> FileInfos {
+        info.iter_file_details_ext()
+            .map(|fd| FileInfo {
+                relative_filename: fd.details.filename.to_pathbuf(),
+                offset_in_torrent: fd.offset,
+                piece_range: fd.pieces,
+                len: fd.details.len,
+                attrs: fd.details.attrs(),
+            })
+            .collect()
+    }
+
+    fn multi_file_info() -> TorrentMetaV1Info<ByteBufOwned> {
+        let file = |path: &[&str], length: u64, attr: Option<&str>| TorrentMetaV1File {
+            length,
+            path: path.iter().map(|p| p.as_bytes().into()).collect(),
+            attr: attr.map(|a| a.as_bytes().into()),
+            sha1: None,
+            symlink_path: None,
+        };
+        TorrentMetaV1Info {
+            name: Some(b"my torrent".as_ref().into()),
+            pieces: vec![0u8; 20 * 3].into(),
+            piece_length: 16384,
+            files: Some(vec![
+                file(&["a.txt"], 10000, None),
+                file(&[".pad", "6384"], 6384, Some("p")),
+                file(&["dir", "b c.txt"], 20000, None),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_peer_handle_roundtrip() {
+        for idx in [0, 1, 31] {
+            let handle = WebSeed::peer_handle(idx);
+            assert_eq!(WebSeed::index_from_peer_handle(handle), Some(idx));
+        }
+        let real: SocketAddr = (Ipv4Addr::LOCALHOST, 0).into();
+        assert_eq!(WebSeed::index_from_peer_handle(real), None);
+        let real: SocketAddr = "[100::1]:6881".parse().unwrap();
+        assert_eq!(WebSeed::index_from_peer_handle(real), None);
+    }
+
+    #[test]
+    fn test_url_list_multi_file() {
+        let info = multi_file_info().validate().unwrap();
+        let file_infos = file_infos(&info);
+        let lengths = *info.lengths();
+        let reqs = |seed: &WebSeed, piece: u32| {
+            seed.requests_for_piece(
+                Id20::default(),
+                &info,
+                &file_infos,
+                lengths.validate_piece_index(piece).unwrap(),
+            )
+            .unwrap()
+        };
+
+        for base in ["http://example.com/files/", "http://example.com/files"] {
+            let seed = WebSeed::UrlList(url(base));
+            assert_eq!(
+                reqs(&seed, 0),
+                vec![
+                    WebSeedRequest::Range {
+                        url: url("http://example.com/files/my%20torrent/a.txt"),
+                        range: 0..10000
+                    },
+                    WebSeedRequest::Zeroes { len: 6384 },
+                ]
+            );
+            assert_eq!(
+                reqs(&seed, 2),
+                vec![WebSeedRequest::Range {
+                    url: url("http://example.com/files/my%20torrent/dir/b%20c.txt"),
+                    range: 16384..20000
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_url_list_single_file() {
+        let info = TorrentMetaV1Info::<ByteBufOwned> {
+            name: Some(b"file.iso".as_ref().into()),
+            pieces: vec![0u8; 20 * 2].into(),
+            piece_length: 16384,
+            length: Some(20000),
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+        let file_infos = file_infos(&info);
+        let piece = info.lengths().validate_piece_index(1).unwrap();
+
+        for (base, expected) in [
+            (
+                "http://example.com/mirror/",
+                "http://example.com/mirror/file.iso",
+            ),
+            (
+                "http://example.com/other.iso",
+                "http://example.com/other.iso",
+            ),
+        ] {
+            let seed = WebSeed::UrlList(url(base));
+            assert_eq!(
+                seed.requests_for_piece(Id20::default(), &info, &file_infos, piece)
+                    .unwrap(),
+                vec![WebSeedRequest::Range {
+                    url: url(expected),
+                    range: 16384..20000
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_http_seed() {
+        let info = multi_file_info().validate().unwrap();
+        let file_infos = file_infos(&info);
+        let piece = info.lengths().validate_piece_index(1).unwrap();
+        let mut info_hash = Id20::default();
+        info_hash.0[0] = 0xab;
+
+        let seed = WebSeed::HttpSeed(url("http://example.com/seed?key=1"));
+        assert_eq!(
+            seed.requests_for_piece(info_hash, &info, &file_infos, piece)
+                .unwrap(),
+            vec![WebSeedRequest::Full {
+                url: url(
+                    "http://example.com/seed?key=1&info_hash=%AB%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00&piece=1"
+                ),
+                len: 16384
+            }]
+        );
+    }
+}
@@ -9,9 +9,12 @@ export interface TorrentFile {
   components: string[];
   length: number;
   included: boolean;
+  priority?: FilePriority;
   attributes: TorrentFileAttributes;
 }
 
+export type FilePriority = "skip" | "low" | "normal" | "high";
+
 export interface TorrentFileAttributes {
   symlink: boolean;
   hidden: boolean;
@@ -26,6 +29,35 @@ export interface TorrentDetails {
   files: Array<TorrentFile>;
   total_pieces?: number;
   output_folder: string;
+  scrape?: ScrapeStats;
+  dht_scrape?: ScrapeEstimate;
+  trackers?: Array<TrackerStatus>;
+}
+
+// Timestamps are in seconds since the UNIX epoch.
+export interface TrackerStatus {
+  url: string;
+  info_hash: string;
+  tier: number;
+  last_announce: number | null;
+  next_announce: number | null;
+  peers_returned: number;
+  last_error: string | null;
+  last_warning: string | null;
+  scrape: ScrapeStats | null;
+}
+
+// Swarm counts from the latest tracker scrape.
+export interface ScrapeStats {
+  seeders: number;
+  leechers: number;
+  completed: number;
+}
+
+// Swarm size estimated with a DHT scrape (BEP 33).
+export interface ScrapeEstimate {
+  seeders: number;
+  leechers: number;
 }
 
 // Interface for torrent list item (from bulk /torrents?with_stats=true endpoint)
@@ -37,6 +69,8 @@ export interface TorrentListItem {
   output_folder: string;
   total_pieces: number;
   stats?: TorrentStats;
+  scrape?: ScrapeStats;
+  dht_scrape?: ScrapeEstimate;
 }
 
 export interface AddTorrentResponse {
@@ -64,7 +98,7 @@ export interface AggregatePeerStats {
   not_needed: number;
 }
 
-export type ConnectionKind = "tcp" | "utp" | "socks";
+export type ConnectionKind = "tcp" | "utp" | "socks" | "webseed";
 
 export interface PeerCounters {
   incoming_connections: number;
@@ -172,6 +206,8 @@ export interface TorrentStats {
   progress_bytes: number;
   finished: boolean;
   initializing_paused?: boolean;
+  queued?: boolean;
+  queue_position?: number;
   total_bytes: number;
   live: LiveTorrentStats | null;
 }
@@ -197,6 +233,7 @@ export interface AddTorrentOptions {
   paused?: boolean;
   only_files_regex?: string | null;
   only_files?: number[] | null;
+  file_priorities?: FilePriority[] | null;
   overwrite?: boolean;
   list_only?: boolean;
   output_folder?: string | null;
@@ -8,11 +8,20 @@ use crate::hash_id::{Id20, Id32};
 pub struct Magnet {
     id20: Option<Id20>,
     id32: Option<Id32>,
+    mutable: Option<MutableTorrentKey>,
     pub trackers: Vec<String>,
     pub name: Option<String>,
     select_only: Option<Vec<usize>>,
 }
 
+/// BEP 46: the DHT key of a mutable torrent, which points to its current info hash.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MutableTorrentKey {
+    /// ed25519 public key of the publisher.
+    pub public_key: [u8; 32],
+    pub salt: Vec<u8>,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum MagnetError {
     #[error("magnet link has neither v1 (btih) nor v2 (btmh) info hash")]
@@ -27,6 +36,11 @@ impl Magnet {
     pub fn as_id32(&self) -> Option<Id32> {
         self.id32
     }
+
+    /// The BEP 46 mutable torrent key ("xs=urn:btpk:"), if it's a mutable torrent link.
+    pub fn as_mutable(&self) -> Option<&MutableTorrentKey> {
+        self.mutable.as_ref()
+    }
     pub fn get_select_only(&self) -> Option<Vec<usize>> {
         self.select_only.clone()
     }
@@ -35,6 +49,7 @@ impl Magnet {
         Self {
             id20: Some(id20),
             id32: None,
+            mutable: None,
             trackers,
             name: None,
             select_only,
@@ -53,6 +68,7 @@ impl Magnet {
         Ok(Self {
             id20,
             id32,
+            mutable: None,
             trackers,
             name: None,
             select_only,
@@ -67,6 +83,7 @@ impl Magnet {
             return Ok(Magnet {
                 id20: Some(id20),
                 id32: None,
+                mutable: None,
                 name: None,
                 trackers: vec![],
                 select_only: None,
@@ -79,6 +96,8 @@ impl Magnet {
         let mut info_hash_found = false;
         let mut id20: Option<Id20> = None;
         let mut id32: Option<Id32> = None;
+        let mut public_key: Option<[u8; 32]> = None;
+        let mut salt = Vec::new();
         let mut name: Option<String> = None;
         let mut trackers = Vec::<String>::new();
         let mut files = Vec::<usize>::new();
@@ -97,6 +116,17 @@ impl Magnet {
                         anyhow::bail!("expected xt to start with btih or btmh");
                     }
                 }
+                "xs" => {
+                    // Other "xs" (exact source) links aren't supported, but don't make the link
+                    // invalid.
+                    if let Some(pk) = value.as_ref().strip_prefix("urn:btpk:") {
+                        public_key = Some(Id32::from_str(pk)?.0);
+                        info_hash_found = true;
+                    }
+                }
+                "s" => {
+                    salt = hex::decode(value.as_ref()).context("salt (s) must be hex")?;
+                }
                 "tr" => trackers.push(value.into()),
                 "dn" if !value.is_empty() => name = Some(value.into_owned()),
                 "dn" => {}
@@ -129,6 +159,7 @@ impl Magnet {
             true => Ok(Magnet {
                 id20,
                 id32,
+                mutable: public_key.map(|public_key| MutableTorrentKey { public_key, salt }),
                 trackers,
                 name,
                 select_only: if files.is_empty() { None } else { Some(files) },
@@ -163,6 +194,14 @@ impl std::fmt::Display for Magnet {
             write_ampersand(f)?;
             write!(f, "xt=urn:btmh:1220{}", id32.as_string(),)?;
         }
+        if let Some(mutable) = &self.mutable {
+            write_ampersand(f)?;
+            write!(f, "xs=urn:btpk:{}", hex::encode(mutable.public_key))?;
+            if !mutable.salt.is_empty() {
+                write_ampersand(f)?;
+                write!(f, "s={}", hex::encode(&mutable.salt))?;
+            }
+        }
         for tracker in self.trackers.iter() {
             write_ampersand(f)?;
             write!(f, "tr={tracker}")?;
@@ -187,7 +226,7 @@ impl std::fmt::Display for Magnet {
 mod tests {
     use std::str::FromStr;
 
-    use crate::Id20;
+    use crate::{Id20, hash_id::Id32};
 
     use super::Magnet;
 
@@ -211,6 +250,24 @@ mod tests {
         assert!(m.as_id32() == Some(info_hash));
     }
 
+    #[test]
+    fn test_parse_magnet_mutable() {
+        let magnet = "magnet:?xs=urn:btpk:8543d3e6115f0f98c944077a4493dcd543e49c739fd998550a1f614ab36ed63e&s=6e&tr=http://example.com/announce";
+        let m = Magnet::parse(magnet).unwrap();
+        assert_eq!(m.as_id20(), None);
+        let mutable = m.as_mutable().unwrap();
+        assert_eq!(
+            Id32::new(mutable.public_key),
+            Id32::from_str("8543d3e6115f0f98c944077a4493dcd543e49c739fd998550a1f614ab36ed63e")
+                .unwrap()
+        );
+        assert_eq!(mutable.salt, b"n");
+        assert_eq!(m.to_string(), magnet);
+
+        assert!(Magnet::parse("magnet:?xs=urn:btpk:8543d3e6&s=6e").is_err());
+        assert!(Magnet::parse("magnet:?xs=http://example.com/file.torrent").is_err());
+    }
+
     #[test]
     fn test_magnet_to_string() {
         let id20 = Id20::from_str("a621779b5e3d486e127c3efbca9b6f8d135f52e5").unwrap();
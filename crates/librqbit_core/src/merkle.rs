@@ -0,0 +1,465 @@
+// BEP 52 merkle trees.
+//
+// Every file is split into 16 KiB blocks, each hashed with SHA-256. Those leaves are combined
+// pairwise up to the "pieces root". The layer where each hash covers exactly one piece is the
+// "piece layer". Leaves past the end of the file are all zeroes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use buffers::ByteBuf;
+use serde::ser::SerializeMap;
+use sha1w::ISha256;
+
+use crate::{Error, hash_id::Id32, torrent_metainfo::ValidatedTorrentMetaV1Info};
+
+pub const MERKLE_BLOCK_SIZE: u32 = 16384;
+
+/// Maximum "length" of a hash request.
+pub const MAX_HASHES_PER_REQUEST: u32 = 512;
+
+pub fn hash_block(data: &[u8]) -> Id32 {
+    let mut h = sha1w::Sha256::new();
+    h.update(data);
+    Id32::new(h.finish())
+}
+
+pub fn hash_pair(left: &Id32, right: &Id32) -> Id32 {
+    let mut h = sha1w::Sha256::new();
+    h.update(&left.0);
+    h.update(&right.0);
+    Id32::new(h.finish())
+}
+
+/// The root of a subtree of 2^levels zero leaves.
+pub fn pad_hash(levels: u32) -> Id32 {
+    let mut pad = Id32::default();
+    for _ in 0..levels {
+        pad = hash_pair(&pad, &pad);
+    }
+    pad
+}
+
+/// Compute the root of a tree with "width" (a power of two) leaves, of which the first
+/// hashes.len() are given and the rest are "pad".
+pub fn root(hashes: &[Id32], width: usize, pad: Id32) -> Id32 {
+    debug_assert!(width.is_power_of_two() && width >= hashes.len());
+    let mut layer = hashes.to_vec();
+    let mut pad = pad;
+    let mut width = width;
+    while width > 1 {
+        if layer.len() % 2 == 1 {
+            layer.push(pad);
+        }
+        layer = layer
+            .chunks_exact(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        pad = hash_pair(&pad, &pad);
+        width /= 2;
+    }
+    layer.first().copied().unwrap_or(pad)
+}
+
+/// How many layers above the leaves the piece layer is.
+pub fn piece_layer_index(piece_length: u32) -> u32 {
+    (piece_length / MERKLE_BLOCK_SIZE).trailing_zeros()
+}
+
+/// Compute the pieces root from the piece layer of a file.
+pub fn root_from_piece_layer(layer: &[Id32], piece_length: u32) -> Id32 {
+    root(
+        layer,
+        layer.len().next_power_of_two(),
+        pad_hash(piece_layer_index(piece_length)),
+    )
+}
+
+/// Uncle hashes proving hashes[index..index+length] of "layer", bottom to top, excluding
+/// the root. "pad" is the hash used for the missing nodes of this layer.
+///
+/// Returns None if the range is not a valid aligned subtree.
+pub fn proof_hashes(
+    layer: &[Id32],
+    pad: Id32,
+    index: usize,
+    length: usize,
+    proof_layers: usize,
+) -> Option<Vec<Id32>> {
+    if !length.is_power_of_two() || !index.is_multiple_of(length) || index >= layer.len() {
+        return None;
+    }
+    let width = layer.len().next_power_of_two();
+    if index + length > width {
+        return None;
+    }
+
+    // Collapse the layer to the level where one node covers the requested range.
+    let mut current: Vec<Id32> = layer.to_vec();
+    let mut pad = pad;
+    let mut step = 1;
+    while step < length {
+        current = collapse(&current, &pad);
+        pad = hash_pair(&pad, &pad);
+        step *= 2;
+    }
+
+    let mut proofs = Vec::new();
+    let mut node = index / length;
+    let mut level_width = width / length;
+    while level_width > 1 && proofs.len() < proof_layers {
+        let sibling = node ^ 1;
+        proofs.push(current.get(sibling).copied().unwrap_or(pad));
+        current = collapse(&current, &pad);
+        pad = hash_pair(&pad, &pad);
+        node /= 2;
+        level_width /= 2;
+    }
+    Some(proofs)
+}
+
+/// Check hashes received for the aligned range starting at "index" of a layer against the
+/// root, using the uncle hashes that came with them (bottom to top). "width" is the padded
+/// width of the layer and "pad" the hash used for its missing nodes.
+pub fn verify_hashes(
+    root_hash: &Id32,
+    hashes: &[Id32],
+    proofs: &[Id32],
+    index: usize,
+    width: usize,
+    pad: Id32,
+) -> bool {
+    let length = hashes.len();
+    if !length.is_power_of_two() || !index.is_multiple_of(length) || index + length > width {
+        return false;
+    }
+    let mut current = root(hashes, length, pad);
+    let mut node = index / length;
+    let mut level_width = width / length;
+    for proof in proofs {
+        if level_width == 1 {
+            return false;
+        }
+        current = if node.is_multiple_of(2) {
+            hash_pair(&current, proof)
+        } else {
+            hash_pair(proof, &current)
+        };
+        node /= 2;
+        level_width /= 2;
+    }
+    level_width == 1 && current == *root_hash
+}
+
+fn collapse(layer: &[Id32], pad: &Id32) -> Vec<Id32> {
+    layer
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(pad)))
+        .collect()
+}
+
+/// Verified piece layers of a v2 torrent, by pieces root.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct PieceLayers(HashMap<Id32, Box<[Id32]>>);
+
+impl PieceLayers {
+    /// Validate the "piece layers" dictionary of a .torrent file against the info.
+    ///
+    /// Only v2-only torrents require them, as hybrid ones can be verified with v1 hashes.
+    pub fn from_torrent<BufType: AsRef<[u8]>>(
+        info: &ValidatedTorrentMetaV1Info<BufType>,
+        piece_layers: Option<&BTreeMap<BufType, BufType>>,
+    ) -> crate::Result<Self> {
+        let required = !info.info().has_v1();
+        let piece_length = info.info().piece_length;
+        let piece_layers = match piece_layers {
+            Some(l) => l,
+            None if !required => return Ok(Default::default()),
+            None => {
+                if info.v2_files().iter().any(|f| f.len > piece_length as u64) {
+                    return Err(Error::V2MissingPieceLayers);
+                }
+                return Ok(Default::default());
+            }
+        };
+
+        let by_root: HashMap<&[u8], &[u8]> = piece_layers
+            .iter()
+            .map(|(k, v)| (k.as_ref(), v.as_ref()))
+            .collect();
+
+        let mut layers = HashMap::new();
+        for file in info.v2_files() {
+            let pieces_root = match file.pieces_root {
+                Some(r) => r,
+                None => continue,
+            };
+            let layer = by_root.get(&pieces_root.0[..]);
+            if file.len <= piece_length as u64 {
+                if layer.is_some() {
+                    return Err(Error::V2SmallFileShouldNotHavePieceLayers);
+                }
+                continue;
+            }
+            let layer = match layer {
+                Some(layer) => *layer,
+                None if required => {
+                    return Err(Error::V2MissingPieceLayersEntry(pieces_root.as_string()));
+                }
+                None => continue,
+            };
+            let layer = Self::verify_layer(pieces_root, file.len, piece_length, layer)?;
+            layers.insert(pieces_root, layer);
+        }
+        Ok(Self(layers))
+    }
+
+    /// Parse and verify the piece layer of one file.
+    pub fn verify_layer(
+        pieces_root: Id32,
+        file_len: u64,
+        piece_length: u32,
+        layer: &[u8],
+    ) -> crate::Result<Box<[Id32]>> {
+        if !layer.len().is_multiple_of(32) {
+            return Err(Error::V2PieceLayersWrongSize {
+                expected: 32,
+                actual: layer.len() % 32,
+            });
+        }
+        let expected = file_len.div_ceil(piece_length as u64) as usize;
+        if layer.len() / 32 != expected {
+            return Err(Error::V2PieceLayerCountMismatch {
+                expected,
+                actual: layer.len() / 32,
+            });
+        }
+        let layer: Box<[Id32]> = layer
+            .chunks_exact(32)
+            .map(|c| Id32::new(c.try_into().unwrap()))
+            .collect();
+        if root_from_piece_layer(&layer, piece_length) != pieces_root {
+            return Err(Error::V2PieceLayersRootMismatch);
+        }
+        Ok(layer)
+    }
+
+    pub fn get(&self, pieces_root: &Id32) -> Option<&[Id32]> {
+        self.0.get(pieces_root).map(|l| &l[..])
+    }
+
+    pub fn insert(&mut self, pieces_root: Id32, layer: Box<[Id32]>) {
+        self.0.insert(pieces_root, layer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Answer a BEP 52 hash request: the requested hashes followed by the uncle hashes.
+    ///
+    /// Only layers at or above the piece layer can be served, as block hashes aren't stored.
+    pub fn answer_request(
+        &self,
+        pieces_root: &Id32,
+        piece_length: u32,
+        base_layer: u32,
+        index: u32,
+        length: u32,
+        proof_layers: u32,
+    ) -> Option<Vec<Id32>> {
+        let piece_layer = piece_layer_index(piece_length);
+        if base_layer < piece_layer || !(2..=MAX_HASHES_PER_REQUEST).contains(&length) {
+            return None;
+        }
+        let mut layer = self.get(pieces_root)?.to_vec();
+        let mut pad = pad_hash(piece_layer);
+        for _ in piece_layer..base_layer {
+            if layer.len() == 1 {
+                return None;
+            }
+            layer = collapse(&layer, &pad);
+            pad = hash_pair(&pad, &pad);
+        }
+
+        let (index, length) = (index as usize, length as usize);
+        let mut result = proof_hashes(&layer, pad, index, length, proof_layers as usize)?;
+        let hashes = (index..index + length).map(|i| layer.get(i).copied().unwrap_or(pad));
+        result.splice(0..0, hashes);
+        Some(result)
+    }
+}
+
+// Serializes into the "piece layers" dictionary of a .torrent file.
+impl serde::Serialize for PieceLayers {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let sorted: BTreeMap<&Id32, Vec<u8>> = self
+            .0
+            .iter()
+            .map(|(k, v)| (k, v.iter().flat_map(|h| h.0).collect()))
+            .collect();
+        let mut map = serializer.serialize_map(Some(sorted.len()))?;
+        for (k, v) in sorted {
+            map.serialize_entry(&ByteBuf(&k.0), &ByteBuf(&v))?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Id32> {
+        (0..n).map(|i| hash_block(&[i as u8; 10])).collect()
+    }
+
+    #[test]
+    fn test_pad_hash() {
+        assert_eq!(pad_hash(0), Id32::default());
+        let zero = Id32::default();
+        assert_eq!(pad_hash(1), hash_pair(&zero, &zero));
+        assert_eq!(
+            pad_hash(2),
+            hash_pair(&hash_pair(&zero, &zero), &hash_pair(&zero, &zero))
+        );
+    }
+
+    #[test]
+    fn test_root_with_padding() {
+        let l = leaves(3);
+        let zero = Id32::default();
+        let expected = hash_pair(&hash_pair(&l[0], &l[1]), &hash_pair(&l[2], &zero));
+        assert_eq!(root(&l, 4, zero), expected);
+        assert_eq!(root(&l[..1], 1, zero), l[0]);
+    }
+
+    #[test]
+    fn test_root_from_piece_layer_matches_leaves() {
+        // 4 blocks per piece, 10 blocks -> 3 pieces.
+        let piece_length = MERKLE_BLOCK_SIZE * 4;
+        let l = leaves(10);
+        let layer: Vec<Id32> = l.chunks(4).map(|c| root(c, 4, Id32::default())).collect();
+        assert_eq!(
+            root_from_piece_layer(&layer, piece_length),
+            root(&l, 16, Id32::default())
+        );
+    }
+
+    #[test]
+    fn test_proof_hashes() {
+        let l = leaves(5);
+        let zero = Id32::default();
+        let full_root = root(&l, 8, zero);
+
+        // Prove l[2..4]: need hash(l0, l1), then the right half.
+        let proofs = proof_hashes(&l, zero, 2, 2, 10).unwrap();
+        assert_eq!(proofs.len(), 2);
+        let mut node = hash_pair(&hash_pair(&l[0], &l[1]), &hash_pair(&l[2], &l[3]));
+        assert_eq!(proofs[0], hash_pair(&l[0], &l[1]));
+        node = hash_pair(&node, &proofs[1]);
+        assert_eq!(node, full_root);
+
+        assert_eq!(proof_hashes(&l, zero, 2, 2, 1).unwrap().len(), 1);
+        assert!(proof_hashes(&l, zero, 1, 2, 0).is_none());
+        assert!(proof_hashes(&l, zero, 0, 3, 0).is_none());
+    }
+
+    #[test]
+    fn test_verify_hashes() {
+        let piece_length = MERKLE_BLOCK_SIZE * 2;
+        let layer = leaves(5);
+        let pieces_root = root_from_piece_layer(&layer, piece_length);
+        let pad = pad_hash(1);
+
+        let proofs = proof_hashes(&layer, pad, 4, 2, 2).unwrap();
+        assert!(verify_hashes(
+            &pieces_root,
+            &[layer[4], pad],
+            &proofs,
+            4,
+            8,
+            pad
+        ));
+        assert!(!verify_hashes(
+            &pieces_root,
+            &[layer[3], pad],
+            &proofs,
+            4,
+            8,
+            pad
+        ));
+        // Missing an uncle.
+        assert!(!verify_hashes(
+            &pieces_root,
+            &[layer[4], pad],
+            &proofs[..1],
+            4,
+            8,
+            pad
+        ));
+
+        let all: Vec<Id32> = layer.iter().copied().chain([pad; 3]).collect();
+        assert!(verify_hashes(&pieces_root, &all, &[], 0, 8, pad));
+    }
+
+    #[test]
+    fn test_answer_request() {
+        let piece_length = MERKLE_BLOCK_SIZE * 2;
+        let layer = leaves(3);
+        let pieces_root = root_from_piece_layer(&layer, piece_length);
+        let mut layers = PieceLayers::default();
+        layers.insert(pieces_root, layer.clone().into());
+
+        let pad = pad_hash(1);
+        let answer = layers
+            .answer_request(&pieces_root, piece_length, 1, 0, 4, 0)
+            .unwrap();
+        assert_eq!(answer, vec![layer[0], layer[1], layer[2], pad]);
+
+        let answer = layers
+            .answer_request(&pieces_root, piece_length, 1, 2, 2, 5)
+            .unwrap();
+        assert_eq!(answer, vec![layer[2], pad, hash_pair(&layer[0], &layer[1])]);
+
+        // Block hashes aren't stored.
+        assert!(
+            layers
+                .answer_request(&pieces_root, piece_length, 0, 0, 2, 0)
+                .is_none()
+        );
+        assert!(
+            layers
+                .answer_request(&Id32::default(), piece_length, 1, 0, 2, 0)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_verify_layer() {
+        let piece_length = MERKLE_BLOCK_SIZE * 2;
+        let layer = leaves(3);
+        let pieces_root = root_from_piece_layer(&layer, piece_length);
+        let bytes: Vec<u8> = layer.iter().flat_map(|h| h.0).collect();
+        let file_len = piece_length as u64 * 2 + 1;
+
+        let parsed =
+            PieceLayers::verify_layer(pieces_root, file_len, piece_length, &bytes).unwrap();
+        assert_eq!(&parsed[..], &layer[..]);
+
+        assert!(matches!(
+            PieceLayers::verify_layer(pieces_root, file_len, piece_length, &bytes[1..]),
+            Err(Error::V2PieceLayersWrongSize { .. })
+        ));
+        assert!(matches!(
+            PieceLayers::verify_layer(pieces_root, file_len, piece_length, &bytes[32..]),
+            Err(Error::V2PieceLayerCountMismatch { .. })
+        ));
+        assert!(matches!(
+            PieceLayers::verify_layer(Id32::default(), file_len, piece_length, &bytes),
+            Err(Error::V2PieceLayersRootMismatch)
+        ));
+    }
+}
@@ -4,11 +4,27 @@ use bytes::Bytes;
 use clone_to_owned::CloneToOwned;
 use encoding_rs::Encoding;
 use itertools::Either;
+use serde::{
+    Deserializer, Serializer,
+    de::{DeserializeSeed, MapAccess, SeqAccess, Visitor, value::BorrowedBytesDeserializer},
+    ser::SerializeMap,
+};
 use serde_derive::{Deserialize, Serialize};
-use std::{borrow::Cow, collections::HashSet, iter::once, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    iter::once,
+    marker::PhantomData,
+    path::PathBuf,
+};
 use tracing::debug;
 
-use crate::{Error, hash_id::Id20, lengths::Lengths};
+use crate::{
+    Error,
+    constants::CHUNK_SIZE,
+    hash_id::{Id20, Id32},
+    lengths::Lengths,
+};
 
 pub type TorrentMetaV1Borrowed<'a> = TorrentMetaV1<ByteBuf<'a>>;
 pub type TorrentMetaV1Owned = TorrentMetaV1<ByteBufOwned>;
@@ -22,6 +38,8 @@ pub struct ParsedTorrent<BufType> {
 }
 
 /// Parse torrent metainfo from bytes (includes info_hash).
+///
+/// For v2-only torrents, info_hash is the truncated v2 info hash.
 #[cfg(any(feature = "sha1-ring", feature = "sha1-crypto-hash"))]
 pub fn torrent_from_bytes<'de>(
     buf: &'de [u8],
@@ -30,11 +48,21 @@ pub fn torrent_from_bytes<'de>(
         .inspect_err(|e| tracing::trace!("error deserializing torrent: {e:#}"))
         .map_err(|e| e.into_kind())?;
 
-    use sha1w::ISha1;
+    use sha1w::{ISha1, ISha256};
 
-    let mut digest = sha1w::Sha1::new();
-    digest.update(t.info.raw_bytes.as_ref());
-    t.info_hash = Id20::new(digest.finish());
+    if t.info.data.has_v2() {
+        let mut digest = sha1w::Sha256::new();
+        digest.update(t.info.raw_bytes.as_ref());
+        let info_hash_v2 = Id32::new(digest.finish());
+        t.info_hash_v2 = Some(info_hash_v2);
+        t.info_hash = info_hash_v2.truncate_for_dht();
+    }
+
+    if t.info.data.has_v1() {
+        let mut digest = sha1w::Sha1::new();
+        digest.update(t.info.raw_bytes.as_ref());
+        t.info_hash = Id20::new(digest.finish());
+    }
     Ok(t)
 }
 
@@ -42,8 +70,16 @@ fn is_false(b: &bool) -> bool {
     !*b
 }
 
+fn is_empty_buf<BufType: AsRef<[u8]>>(b: &BufType) -> bool {
+    b.as_ref().is_empty()
+}
+
 /// A parsed .torrent file.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound(
+    serialize = "BufType: serde::Serialize + AsRef<[u8]>",
+    deserialize = "BufType: serde::Deserialize<'de> + AsRef<[u8]> + Clone + Default + Ord"
+))]
 pub struct TorrentMetaV1<BufType> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub announce: Option<BufType>,
@@ -67,8 +103,37 @@ pub struct TorrentMetaV1<BufType> {
     #[serde(rename = "creation date", skip_serializing_if = "Option::is_none")]
     pub creation_date: Option<usize>,
 
+    // BEP 19 web seeds. Either a single URL or a list of them.
+    #[serde(
+        rename = "url-list",
+        default = "Vec::new",
+        deserialize_with = "deserialize_one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub url_list: Vec<BufType>,
+
+    // BEP 17 HTTP seeds.
+    #[serde(
+        default = "Vec::new",
+        deserialize_with = "deserialize_one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub httpseeds: Vec<BufType>,
+
+    // BEP 52: pieces root -> concatenated piece hashes, for files larger than one piece.
+    #[serde(
+        rename = "piece layers",
+        default = "none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub piece_layers: Option<BTreeMap<BufType, BufType>>,
+
     #[serde(skip)]
     pub info_hash: Id20,
+
+    // SHA-256 of the info dict, present for v2 and hybrid torrents.
+    #[serde(skip)]
+    pub info_hash_v2: Option<Id32>,
 }
 
 impl<BufType> TorrentMetaV1<BufType> {
@@ -78,13 +143,32 @@ impl<BufType> TorrentMetaV1<BufType> {
         }
         itertools::Either::Right(self.announce.iter())
     }
+
+    /// Tracker tiers (BEP 12). Without an announce-list, "announce" is the only tier.
+    pub fn announce_tiers(&self) -> Vec<Vec<&BufType>> {
+        if self.announce_list.iter().flatten().next().is_some() {
+            return self
+                .announce_list
+                .iter()
+                .filter(|tier| !tier.is_empty())
+                .map(|tier| tier.iter().collect())
+                .collect();
+        }
+        self.announce.iter().map(|a| vec![a]).collect()
+    }
 }
 
 /// Main torrent information, shared by .torrent files and magnet link contents.
 #[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(bound(
+    serialize = "BufType: serde::Serialize + AsRef<[u8]>",
+    deserialize = "BufType: serde::Deserialize<'de> + AsRef<[u8]> + Clone + Default"
+))]
 pub struct TorrentMetaV1Info<BufType> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<BufType>,
+    // Absent in v2-only torrents.
+    #[serde(default, skip_serializing_if = "is_empty_buf")]
     pub pieces: BufType,
     #[serde(rename = "piece length")]
     pub piece_length: u32,
@@ -112,6 +196,20 @@ pub struct TorrentMetaV1Info<BufType> {
 
     #[serde(skip_serializing_if = "is_false", default)]
     pub private: bool,
+
+    // BEP 52
+    #[serde(
+        rename = "meta version",
+        default = "none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub meta_version: Option<u32>,
+    #[serde(
+        rename = "file tree",
+        default = "none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub file_tree: Option<TorrentMetaV2FileTree<BufType>>,
 }
 
 #[derive(Clone, Copy)]
@@ -124,6 +222,8 @@ pub struct FileIteratorName<'a, BufType> {
 pub enum FileIteratorNameData<'a, BufType> {
     Single(Option<&'a BufType>),
     Tree(&'a [BufType]),
+    // Padding between files of a v2-only torrent, not present in the metainfo.
+    Padding,
 }
 
 impl<BufType> std::fmt::Debug for FileIteratorName<'_, BufType>
@@ -159,6 +259,11 @@ where
         self.iter_components().map(|c| c.into_owned()).collect()
     }
 
+    /// Whether this is the file of a single-file torrent, named after the torrent itself.
+    pub fn is_single_file(&self) -> bool {
+        matches!(self.data, FileIteratorNameData::Single(_))
+    }
+
     /// Convert path components into a PathBuf, for use with local FS.
     pub fn to_pathbuf(&self) -> PathBuf {
         let mut buf = PathBuf::new();
@@ -183,6 +288,7 @@ where
                 return Either::Left(once(&b"torrent-content"[..]));
             }
             FileIteratorNameData::Single(Some(name)) => Either::Left(once((*name).as_ref())),
+            FileIteratorNameData::Padding => return Either::Left(once(&b".pad"[..])),
             FileIteratorNameData::Tree(t) => Either::Right(t.iter().map(|bb| bb.as_ref())),
         };
         Either::Right(it)
@@ -205,6 +311,9 @@ pub struct FileDetails<'a, BufType> {
     attr: Option<&'a BufType>,
     pub sha1: Option<&'a BufType>,
     pub symlink_path: Option<&'a [BufType]>,
+
+    // v2-only torrents align files to piece boundaries, this is the gap after a file.
+    synthetic_padding: bool,
 }
 
 impl<BufType> FileDetails<'_, BufType>
@@ -212,6 +321,12 @@ where
     BufType: AsRef<[u8]>,
 {
     pub fn attrs(&self) -> FileDetailsAttrs {
+        if self.synthetic_padding {
+            return FileDetailsAttrs {
+                padding: true,
+                ..Default::default()
+            };
+        }
         let attrs = match self.attr {
             Some(attrs) => attrs,
             None => return FileDetailsAttrs::default(),
@@ -245,11 +360,24 @@ impl<BufType> FileDetailsExt<'_, BufType> {
     }
 }
 
+/// Location of a BEP 52 file in the torrent's flat byte space.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V2FileInfo {
+    /// Index of the file in iter_file_details().
+    pub file_index: usize,
+    pub offset: u64,
+    pub len: u64,
+    /// None for empty files.
+    pub pieces_root: Option<Id32>,
+    pub pieces: std::ops::Range<u32>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ValidatedTorrentMetaV1Info<BufType> {
     encoding: &'static Encoding,
     lengths: Lengths,
     info: TorrentMetaV1Info<BufType>,
+    v2_files: Vec<V2FileInfo>,
 }
 
 impl<BufType: AsRef<[u8]>> ValidatedTorrentMetaV1Info<BufType> {
@@ -269,6 +397,18 @@ impl<BufType: AsRef<[u8]>> ValidatedTorrentMetaV1Info<BufType> {
         &self.lengths
     }
 
+    /// BEP 52 files, in file tree order. Empty for v1-only torrents.
+    pub fn v2_files(&self) -> &[V2FileInfo] {
+        &self.v2_files
+    }
+
+    /// The BEP 52 file the piece belongs to. Only meaningful for v2-only torrents,
+    /// as in hybrid ones the (v1) pieces may span several files.
+    pub fn v2_file_for_piece(&self, piece: u32) -> Option<&V2FileInfo> {
+        let idx = self.v2_files.partition_point(|f| f.pieces.end <= piece);
+        self.v2_files.get(idx).filter(|f| f.pieces.contains(&piece))
+    }
+
     pub fn name_or_else<'a, DefaultT: Into<Cow<'a, str>>>(
         &'a self,
         default: impl Fn() -> DefaultT,
@@ -282,6 +422,73 @@ impl<BufType: AsRef<[u8]>> ValidatedTorrentMetaV1Info<BufType> {
         self.info.iter_file_details_raw(self.encoding).unwrap()
     }
 
+    // Map the file tree onto the flat layout. For hybrid torrents this checks that the v1 file
+    // list (minus padding) describes the same files.
+    fn compute_v2_files(&self) -> crate::Result<Vec<V2FileInfo>> {
+        let tree = match self.info.file_tree.as_ref() {
+            Some(tree) => tree,
+            None => return Ok(Vec::new()),
+        };
+
+        for file in tree.0.iter() {
+            if file.path.is_empty() {
+                return Err(Error::V2FileTreeRootIsFile);
+            }
+            for bit in file.path.iter().map(|b| b.as_ref()) {
+                match bit {
+                    b"" | b"." => return Err(Error::V2FileTreeDotComponent),
+                    b".." => return Err(Error::BadTorrentPathTraversal),
+                    _ => {}
+                }
+            }
+            match (
+                file.length,
+                file.pieces_root.as_ref().map(|r| r.as_ref().len()),
+            ) {
+                (0, None) => {}
+                (0, Some(_)) => return Err(Error::V2ZeroLengthFileHasPiecesRoot),
+                (_, Some(32)) => {}
+                (_, _) => return Err(Error::V2SmallFileMissingPiecesRoot),
+            }
+        }
+
+        let mut flat_files = self
+            .iter_file_details_ext()
+            .enumerate()
+            .filter(|(_, fd)| !fd.details.attrs().padding);
+
+        let mut result = Vec::with_capacity(tree.0.len());
+        for file in tree.0.iter() {
+            let (file_index, fd) = flat_files.next().ok_or_else(|| {
+                Error::V2HybridFileListMismatch("v1 file list has fewer files".into())
+            })?;
+            if fd.details.len != file.length {
+                return Err(Error::V2HybridFileListMismatch(format!(
+                    "length of {} differs: {} vs {}",
+                    fd.details.filename, fd.details.len, file.length
+                )));
+            }
+            result.push(V2FileInfo {
+                file_index,
+                offset: fd.offset,
+                len: file.length,
+                pieces_root: file
+                    .pieces_root
+                    .as_ref()
+                    .map(|r| Id32::from_bytes(r.as_ref()))
+                    .transpose()
+                    .map_err(|_| Error::V2SmallFileMissingPiecesRoot)?,
+                pieces: fd.pieces,
+            });
+        }
+        if flat_files.next().is_some() {
+            return Err(Error::V2HybridFileListMismatch(
+                "v1 file list has more files".into(),
+            ));
+        }
+        Ok(result)
+    }
+
     pub fn iter_file_lengths(&self) -> impl Iterator<Item = u64> + '_ {
         self.iter_file_details().map(|d| d.len)
     }
@@ -305,12 +512,16 @@ impl<BufType: AsRef<[u8]>> ValidatedTorrentMetaV1Info<BufType> {
 
 impl<BufType: AsRef<[u8]>> TorrentMetaV1Info<BufType> {
     pub fn validate(self) -> crate::Result<ValidatedTorrentMetaV1Info<BufType>> {
+        if self.meta_version.is_some() || self.file_tree.is_some() {
+            self.validate_v2_header()?;
+        }
         let lengths = Lengths::from_torrent(&self)?;
         let encoding = self.detect_encoding();
-        let validated = ValidatedTorrentMetaV1Info {
+        let mut validated = ValidatedTorrentMetaV1Info {
             encoding,
             lengths,
             info: self,
+            v2_files: Vec::new(),
         };
 
         // Ensure:
@@ -340,21 +551,54 @@ impl<BufType: AsRef<[u8]>> TorrentMetaV1Info<BufType> {
             return Err(Error::BadTorrentNoFiles);
         }
 
+        // Padding files never hit the disk, so their names may repeat.
         let mut unique_filenames = HashSet::<PathBuf>::new();
+        let mut seen_non_padding_files = 0;
         for fd in validated.iter_file_details() {
             let pb = fd.filename.to_pathbuf();
             if pb.as_os_str().is_empty() {
                 return Err(Error::BadTorrentFileNoName);
             }
+            if fd.attrs().padding {
+                continue;
+            }
+            seen_non_padding_files += 1;
             unique_filenames.insert(pb);
         }
-        if unique_filenames.len() != seen_files {
+        if unique_filenames.len() != seen_non_padding_files {
             return Err(Error::BadTorrentDuplicateFilenames);
         }
 
+        validated.v2_files = validated.compute_v2_files()?;
+
         Ok(validated)
     }
 
+    /// If the info dict has v1 "pieces" (v1 or hybrid torrent).
+    pub fn has_v1(&self) -> bool {
+        self.length.is_some() || self.files.is_some()
+    }
+
+    /// If the info dict has a BEP 52 "file tree" (v2 or hybrid torrent).
+    pub fn has_v2(&self) -> bool {
+        self.file_tree.is_some()
+    }
+
+    fn validate_v2_header(&self) -> crate::Result<()> {
+        match (self.meta_version, self.file_tree.as_ref()) {
+            (None, _) => return Err(Error::V2MissingMetaVersion),
+            (Some(2), Some(_)) => {}
+            (Some(2), None) => return Err(Error::V2MissingFileTree),
+            (Some(v), _) => return Err(Error::V2UnsupportedMetaVersion(v)),
+        }
+        if !self.piece_length.is_power_of_two() || self.piece_length < CHUNK_SIZE {
+            return Err(Error::V2InvalidPieceLength(self.piece_length));
+        }
+        Ok(())
+    }
+}
+
+impl<BufType: AsRef<[u8]>> TorrentMetaV1Info<BufType> {
     pub fn get_hash(&self, piece: u32) -> Option<&[u8]> {
         let start = piece as usize * 20;
         let end = start + 20;
@@ -381,6 +625,12 @@ impl<BufType: AsRef<[u8]>> TorrentMetaV1Info<BufType> {
             }
         }
 
+        for file in self.file_tree.iter().flat_map(|t| t.0.iter()) {
+            for component in file.path.iter() {
+                encdetect.feed(component.as_ref(), false);
+            }
+        }
+
         encdetect.guess(None, chardetng::Utf8Detection::Allow)
     }
 
@@ -388,9 +638,9 @@ impl<BufType: AsRef<[u8]>> TorrentMetaV1Info<BufType> {
         &self,
         encoding: &'static Encoding,
     ) -> crate::Result<impl Iterator<Item = FileDetails<'_, BufType>>> {
-        match (self.length, self.files.as_ref()) {
+        match (self.length, self.files.as_ref(), self.file_tree.as_ref()) {
             // Single-file
-            (Some(length), None) => Ok(Either::Left(once(FileDetails {
+            (Some(length), None, _) => Ok(Either::Left(Either::Left(once(FileDetails {
                 filename: FileIteratorName {
                     encoding,
                     data: FileIteratorNameData::Single(self.name.as_ref()),
@@ -399,23 +649,80 @@ impl<BufType: AsRef<[u8]>> TorrentMetaV1Info<BufType> {
                 attr: self.attr.as_ref(),
                 sha1: self.sha1.as_ref(),
                 symlink_path: self.symlink_path.as_deref(),
-            }))),
+                synthetic_padding: false,
+            })))),
 
             // Multi-file
-            (None, Some(files)) => {
+            (None, Some(files), _) => {
                 if files.is_empty() {
                     return Err(Error::BadTorrentMultiFileEmpty);
                 }
-                Ok(Either::Right(files.iter().map(move |f| FileDetails {
-                    filename: FileIteratorName {
-                        encoding,
-                        data: FileIteratorNameData::Tree(&f.path),
+                Ok(Either::Left(Either::Right(files.iter().map(move |f| {
+                    FileDetails {
+                        filename: FileIteratorName {
+                            encoding,
+                            data: FileIteratorNameData::Tree(&f.path),
+                        },
+                        len: f.length,
+                        attr: f.attr.as_ref(),
+                        sha1: f.sha1.as_ref(),
+                        symlink_path: f.symlink_path.as_deref(),
+                        synthetic_padding: false,
+                    }
+                }))))
+            }
+
+            // v2-only. Every file starts at a piece boundary, so pad all but the last one.
+            (None, None, Some(tree)) => {
+                if self.piece_length == 0 {
+                    return Err(Error::V2InvalidPieceLength(self.piece_length));
+                }
+                let piece_length = self.piece_length as u64;
+                let last_non_empty = tree.0.iter().rposition(|f| f.length > 0);
+                // A single-file torrent has one entry in the tree, named like the torrent.
+                let is_single = match (tree.0.as_slice(), self.name.as_ref()) {
+                    ([f], Some(name)) => {
+                        matches!(f.path.as_slice(), [p] if p.as_ref() == name.as_ref())
+                    }
+                    _ => false,
+                };
+                Ok(Either::Right(tree.0.iter().enumerate().flat_map(
+                    move |(idx, f)| {
+                        let padding_len = match last_non_empty {
+                            Some(last) if idx < last => {
+                                (piece_length - f.length % piece_length) % piece_length
+                            }
+                            _ => 0,
+                        };
+                        let file = FileDetails {
+                            filename: FileIteratorName {
+                                encoding,
+                                data: if is_single {
+                                    FileIteratorNameData::Single(self.name.as_ref())
+                                } else {
+                                    FileIteratorNameData::Tree(&f.path)
+                                },
+                            },
+                            len: f.length,
+                            attr: f.attr.as_ref(),
+                            sha1: None,
+                            symlink_path: None,
+                            synthetic_padding: false,
+                        };
+                        let padding = (padding_len > 0).then_some(FileDetails {
+                            filename: FileIteratorName {
+                                encoding,
+                                data: FileIteratorNameData::Padding,
+                            },
+                            len: padding_len,
+                            attr: None,
+                            sha1: None,
+                            symlink_path: None,
+                            synthetic_padding: true,
+                        });
+                        once(file).chain(padding)
                     },
-                    len: f.length,
-                    attr: f.attr.as_ref(),
-                    sha1: f.sha1.as_ref(),
-                    symlink_path: f.symlink_path.as_deref(),
-                })))
+                )))
             }
             _ => Err(Error::BadTorrentBothSingleAndMultiFile),
         }
@@ -426,6 +733,47 @@ const fn none<T>() -> Option<T> {
     None
 }
 
+// Deserializes a string or a list of strings into a list.
+fn deserialize_one_or_many<'de, D, BufType>(deserializer: D) -> Result<Vec<BufType>, D::Error>
+where
+    D: Deserializer<'de>,
+    BufType: serde::Deserialize<'de>,
+{
+    struct OneOrManyVisitor<BufType>(PhantomData<BufType>);
+
+    impl<'de, BufType> Visitor<'de> for OneOrManyVisitor<BufType>
+    where
+        BufType: serde::Deserialize<'de>,
+    {
+        type Value = Vec<BufType>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a byte string or a list of byte strings")
+        }
+
+        fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            let value = BufType::deserialize(BorrowedBytesDeserializer::<E>::new(v))?;
+            Ok(vec![value])
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut values = Vec::new();
+            while let Some(value) = seq.next_element()? {
+                values.push(value);
+            }
+            Ok(values)
+        }
+    }
+
+    deserializer.deserialize_any(OneOrManyVisitor(PhantomData))
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct TorrentMetaV1File<BufType> {
     pub length: u64,
@@ -443,6 +791,197 @@ pub struct TorrentMetaV1File<BufType> {
     pub symlink_path: Option<Vec<BufType>>,
 }
 
+/// A file from a BEP 52 "file tree".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentMetaV2File<BufType> {
+    pub path: Vec<BufType>,
+    pub length: u64,
+    pub pieces_root: Option<BufType>,
+    pub attr: Option<BufType>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct FileTreeLeaf<BufType> {
+    length: u64,
+    #[serde(
+        rename = "pieces root",
+        default = "none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pieces_root: Option<BufType>,
+    #[serde(default = "none", skip_serializing_if = "Option::is_none")]
+    attr: Option<BufType>,
+}
+
+/// A BEP 52 "file tree", flattened into a list of files in tree order.
+///
+/// The nested dictionaries are sorted by key, so this order is the canonical file order
+/// of the torrent. Files must be kept in this order for serialization to work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentMetaV2FileTree<BufType>(pub Vec<TorrentMetaV2File<BufType>>);
+
+struct FileTreeDirVisitor<'a, BufType> {
+    prefix: Vec<BufType>,
+    files: &'a mut Vec<TorrentMetaV2File<BufType>>,
+}
+
+impl<'de, BufType> Visitor<'de> for FileTreeDirVisitor<'_, BufType>
+where
+    BufType: serde::Deserialize<'de> + AsRef<[u8]> + Clone,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a BEP 52 file tree dictionary")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let FileTreeDirVisitor { prefix, files } = self;
+        while let Some(key) = map.next_key::<BufType>()? {
+            if key.as_ref().is_empty() {
+                let leaf: FileTreeLeaf<BufType> = map.next_value()?;
+                files.push(TorrentMetaV2File {
+                    path: prefix.clone(),
+                    length: leaf.length,
+                    pieces_root: leaf.pieces_root,
+                    attr: leaf.attr,
+                });
+            } else {
+                let mut prefix = prefix.clone();
+                prefix.push(key);
+                map.next_value_seed(FileTreeDirVisitor {
+                    prefix,
+                    files: &mut *files,
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, BufType> DeserializeSeed<'de> for FileTreeDirVisitor<'_, BufType>
+where
+    BufType: serde::Deserialize<'de> + AsRef<[u8]> + Clone,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, BufType> serde::Deserialize<'de> for TorrentMetaV2FileTree<BufType>
+where
+    BufType: serde::Deserialize<'de> + AsRef<[u8]> + Clone,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut files = Vec::new();
+        FileTreeDirVisitor {
+            prefix: Vec::new(),
+            files: &mut files,
+        }
+        .deserialize(deserializer)?;
+        Ok(Self(files))
+    }
+}
+
+// Serializes files sharing the first "depth" path components as a directory.
+struct FileTreeDir<'a, BufType> {
+    files: &'a [TorrentMetaV2File<BufType>],
+    depth: usize,
+}
+
+impl<BufType: AsRef<[u8]>> serde::Serialize for FileTreeDir<'_, BufType> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        let mut rest = self.files;
+        while let Some(first) = rest.first() {
+            match first.path.get(self.depth) {
+                None => {
+                    map.serialize_entry(
+                        &ByteBuf(b""),
+                        &FileTreeLeaf {
+                            length: first.length,
+                            pieces_root: first.pieces_root.as_ref().map(|r| ByteBuf(r.as_ref())),
+                            attr: first.attr.as_ref().map(|a| ByteBuf(a.as_ref())),
+                        },
+                    )?;
+                    rest = &rest[1..];
+                }
+                Some(name) => {
+                    let count = rest
+                        .iter()
+                        .take_while(|f| {
+                            f.path.get(self.depth).map(|c| c.as_ref()) == Some(name.as_ref())
+                        })
+                        .count();
+                    map.serialize_entry(
+                        &ByteBuf(name.as_ref()),
+                        &FileTreeDir {
+                            files: &rest[..count],
+                            depth: self.depth + 1,
+                        },
+                    )?;
+                    rest = &rest[count..];
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+impl<BufType: AsRef<[u8]>> serde::Serialize for TorrentMetaV2FileTree<BufType> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        FileTreeDir {
+            files: &self.0,
+            depth: 0,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<BufType> CloneToOwned for TorrentMetaV2File<BufType>
+where
+    BufType: CloneToOwned,
+{
+    type Target = TorrentMetaV2File<<BufType as CloneToOwned>::Target>;
+
+    fn clone_to_owned(&self, within_buffer: Option<&Bytes>) -> Self::Target {
+        TorrentMetaV2File {
+            path: self.path.clone_to_owned(within_buffer),
+            length: self.length,
+            pieces_root: self.pieces_root.clone_to_owned(within_buffer),
+            attr: self.attr.clone_to_owned(within_buffer),
+        }
+    }
+}
+
+impl<BufType> CloneToOwned for TorrentMetaV2FileTree<BufType>
+where
+    BufType: CloneToOwned,
+{
+    type Target = TorrentMetaV2FileTree<<BufType as CloneToOwned>::Target>;
+
+    fn clone_to_owned(&self, within_buffer: Option<&Bytes>) -> Self::Target {
+        TorrentMetaV2FileTree(self.0.clone_to_owned(within_buffer))
+    }
+}
+
 impl<BufType> CloneToOwned for TorrentMetaV1File<BufType>
 where
     BufType: CloneToOwned,
@@ -478,6 +1017,8 @@ where
             sha1: self.sha1.clone_to_owned(within_buffer),
             symlink_path: self.symlink_path.clone_to_owned(within_buffer),
             private: self.private,
+            meta_version: self.meta_version,
+            file_tree: self.file_tree.clone_to_owned(within_buffer),
         }
     }
 }
@@ -485,6 +1026,7 @@ where
 impl<BufType> CloneToOwned for TorrentMetaV1<BufType>
 where
     BufType: CloneToOwned,
+    <BufType as CloneToOwned>::Target: Ord,
 {
     type Target = TorrentMetaV1<<BufType as CloneToOwned>::Target>;
 
@@ -499,7 +1041,11 @@ where
             publisher: self.publisher.clone_to_owned(within_buffer),
             publisher_url: self.publisher_url.clone_to_owned(within_buffer),
             creation_date: self.creation_date,
+            url_list: self.url_list.clone_to_owned(within_buffer),
+            httpseeds: self.httpseeds.clone_to_owned(within_buffer),
+            piece_layers: self.piece_layers.clone_to_owned(within_buffer),
             info_hash: self.info_hash,
+            info_hash_v2: self.info_hash_v2,
         }
     }
 }
@@ -577,4 +1123,194 @@ mod tests {
         let torrent: TorrentMetaV1Borrowed = from_bytes(buf).unwrap();
         assert!(torrent.info.data.private);
     }
+
+    #[test]
+    fn test_url_list_single_or_list() {
+        let with_extra_keys = |extra: &[u8]| {
+            let mut buf = TORRENT_BYTES[..TORRENT_BYTES.len() - 1].to_vec();
+            buf.extend_from_slice(extra);
+            buf.push(b'e');
+            buf
+        };
+
+        let buf = with_extra_keys(b"8:url-list19:http://example.com/");
+        let torrent: TorrentMetaV1Borrowed = from_bytes(&buf).unwrap();
+        assert_eq!(torrent.url_list, vec![ByteBuf(b"http://example.com/")]);
+        assert!(torrent.httpseeds.is_empty());
+
+        let buf = with_extra_keys(
+            b"8:url-listl15:http://a.com/x/15:http://b.com/y/e9:httpseedsl17:http://c.com/seede",
+        );
+        let torrent: TorrentMetaV1Borrowed = from_bytes(&buf).unwrap();
+        assert_eq!(
+            torrent.url_list,
+            vec![ByteBuf(b"http://a.com/x/"), ByteBuf(b"http://b.com/y/")]
+        );
+        assert_eq!(torrent.httpseeds, vec![ByteBuf(b"http://c.com/seed")]);
+
+        let mut writer = Vec::new();
+        bencode::bencode_serialize_to_writer(&torrent, &mut writer).unwrap();
+        let deserialized: TorrentMetaV1Borrowed = from_bytes(&writer).unwrap();
+        assert_eq!(deserialized.url_list, torrent.url_list);
+        assert_eq!(deserialized.httpseeds, torrent.httpseeds);
+    }
+
+    fn v2_file(path: &[&str], length: u64) -> TorrentMetaV2File<ByteBufOwned> {
+        TorrentMetaV2File {
+            path: path.iter().map(|p| p.as_bytes().into()).collect(),
+            length,
+            pieces_root: (length > 0).then(|| vec![length as u8; 32].into()),
+            attr: None,
+        }
+    }
+
+    fn v2_info(files: Vec<TorrentMetaV2File<ByteBufOwned>>) -> TorrentMetaV1Info<ByteBufOwned> {
+        TorrentMetaV1Info {
+            name: Some(b"test".as_ref().into()),
+            piece_length: 16384,
+            meta_version: Some(2),
+            file_tree: Some(TorrentMetaV2FileTree(files)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_v2_file_tree_serialize_deserialize() {
+        let info = v2_info(vec![
+            v2_file(&["a"], 10),
+            v2_file(&["dir", "b"], 0),
+            v2_file(&["dir", "c"], 20000),
+            v2_file(&["dir", "sub", "d"], 5),
+        ]);
+        let mut buf = Vec::new();
+        bencode::bencode_serialize_to_writer(&info, &mut buf).unwrap();
+        assert!(
+            memchr::memmem::find(&buf, b"9:file treed1:ad0:d6:lengthi10e11:pieces root32:")
+                .is_some()
+        );
+        assert!(memchr::memmem::find(&buf, b"6:pieces").is_none());
+
+        let deserialized = from_bytes::<TorrentMetaV1Info<ByteBuf>>(&buf).unwrap();
+        assert_eq!(deserialized.clone_to_owned(None), info);
+        assert!(deserialized.has_v2());
+        assert!(!deserialized.has_v1());
+    }
+
+    #[test]
+    fn test_v2_only_layout_has_padding() {
+        let info = v2_info(vec![
+            v2_file(&["a"], 10),
+            v2_file(&["b"], 0),
+            v2_file(&["c"], 20000),
+            v2_file(&["d"], 0),
+        ])
+        .validate()
+        .unwrap();
+
+        let files = info
+            .iter_file_details()
+            .map(|fd| (fd.filename.to_string(), fd.len, fd.attrs().padding))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                ("a".to_owned(), 10, false),
+                (".pad".to_owned(), 16374, true),
+                ("b".to_owned(), 0, false),
+                ("c".to_owned(), 20000, false),
+                ("d".to_owned(), 0, false),
+            ]
+        );
+        assert_eq!(info.lengths().total_length(), 16384 + 20000);
+
+        let v2_files = info.v2_files();
+        assert_eq!(v2_files.len(), 4);
+        assert_eq!(v2_files[2].file_index, 3);
+        assert_eq!(v2_files[2].offset, 16384);
+        assert_eq!(v2_files[2].pieces, 1..3);
+        assert_eq!(v2_files[3].pieces_root, None);
+        assert_eq!(info.v2_file_for_piece(0).unwrap().file_index, 0);
+        assert_eq!(info.v2_file_for_piece(2).unwrap().file_index, 3);
+        assert!(info.v2_file_for_piece(3).is_none());
+    }
+
+    #[test]
+    fn test_v2_single_file_is_named_like_torrent() {
+        let info = v2_info(vec![v2_file(&["test"], 10)]).validate().unwrap();
+        let files = info.iter_file_details().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].filename.is_single_file());
+        assert_eq!(files[0].filename.to_string(), "test");
+
+        let info = v2_info(vec![v2_file(&["other"], 10)]).validate().unwrap();
+        let files = info.iter_file_details().collect::<Vec<_>>();
+        assert!(!files[0].filename.is_single_file());
+    }
+
+    #[test]
+    fn test_v2_validation_errors() {
+        let mut info = v2_info(vec![v2_file(&["a"], 10)]);
+        info.meta_version = Some(3);
+        assert!(matches!(
+            info.validate(),
+            Err(Error::V2UnsupportedMetaVersion(3))
+        ));
+
+        let mut info = v2_info(vec![v2_file(&["a"], 10)]);
+        info.piece_length = 1000;
+        assert!(matches!(
+            info.validate(),
+            Err(Error::V2InvalidPieceLength(1000))
+        ));
+
+        let mut file = v2_file(&["a"], 10);
+        file.pieces_root = None;
+        assert!(matches!(
+            v2_info(vec![file]).validate(),
+            Err(Error::V2SmallFileMissingPiecesRoot)
+        ));
+
+        assert!(matches!(
+            v2_info(vec![v2_file(&["..", "a"], 10)]).validate(),
+            Err(Error::BadTorrentPathTraversal)
+        ));
+    }
+
+    #[test]
+    fn test_hybrid_file_list_must_match() {
+        let mut info = v2_info(vec![v2_file(&["a"], 10), v2_file(&["b"], 20)]);
+        info.pieces = vec![0u8; 40].into();
+        info.files = Some(vec![
+            TorrentMetaV1File {
+                length: 10,
+                path: vec![b"a".as_ref().into()],
+                attr: None,
+                sha1: None,
+                symlink_path: None,
+            },
+            TorrentMetaV1File {
+                length: 16374,
+                path: vec![b".pad".as_ref().into(), b"16374".as_ref().into()],
+                attr: Some(b"p".as_ref().into()),
+                sha1: None,
+                symlink_path: None,
+            },
+            TorrentMetaV1File {
+                length: 20,
+                path: vec![b"b".as_ref().into()],
+                attr: None,
+                sha1: None,
+                symlink_path: None,
+            },
+        ]);
+        let validated = info.clone().validate().unwrap();
+        assert_eq!(validated.v2_files()[1].file_index, 2);
+        assert_eq!(validated.v2_files()[1].offset, 16384);
+
+        info.files.as_mut().unwrap()[2].length = 21;
+        assert!(matches!(
+            info.validate(),
+            Err(Error::V2HybridFileListMismatch(_))
+        ));
+    }
 }
@@ -12,7 +12,11 @@ use byteorder::{BE, ByteOrder};
 use bytes::Bytes;
 use clone_to_owned::CloneToOwned;
 use extended::PeerExtendedMessageIds;
-use librqbit_core::{constants::CHUNK_SIZE, hash_id::Id20, lengths::ChunkInfo};
+use librqbit_core::{
+    constants::CHUNK_SIZE,
+    hash_id::{Id20, Id32},
+    lengths::ChunkInfo,
+};
 use serde_derive::{Deserialize, Serialize};
 
 pub use crate::double_buf::DoubleBufHelper;
@@ -25,14 +29,25 @@ const PREAMBLE_LEN: usize = INTEGER_LEN + MSGID_LEN;
 const PIECE_MESSAGE_PREAMBLE_LEN: usize = PREAMBLE_LEN + INTEGER_LEN * 2;
 pub const PIECE_MESSAGE_DEFAULT_LEN: usize = PIECE_MESSAGE_PREAMBLE_LEN + CHUNK_SIZE as usize;
 
-// extended message ut_metadata request is the largest known message.
 const MAX_MSG_LEN_LEN_JUST_IN_CASE_EXTRA: usize = 64;
-pub const MAX_MSG_LEN: usize = PREAMBLE_LEN
+const MAX_UT_METADATA_MSG_LEN: usize = PREAMBLE_LEN
     + 1
     + b"d8:msg_typei1e5:piecei42e10:total_sizei16384ee".len()
     + CHUNK_SIZE as usize
     + MAX_MSG_LEN_LEN_JUST_IN_CASE_EXTRA;
 
+const HASH_REQUEST_LEN: usize = 32 + INTEGER_LEN * 4;
+// BEP 52 allows requesting up to 512 hashes. Proofs can't be deeper than 64 layers.
+pub const MAX_HASHES_IN_MSG: usize = 512 + 64;
+const MAX_HASHES_MSG_LEN: usize = PREAMBLE_LEN + HASH_REQUEST_LEN + MAX_HASHES_IN_MSG * 32;
+
+// "hashes" and extended ut_metadata messages are the largest known messages.
+pub const MAX_MSG_LEN: usize = if MAX_HASHES_MSG_LEN > MAX_UT_METADATA_MSG_LEN {
+    MAX_HASHES_MSG_LEN
+} else {
+    MAX_UT_METADATA_MSG_LEN
+};
+
 const PSTR_BT1: &str = "BitTorrent protocol";
 
 type MsgId = u8;
@@ -46,7 +61,17 @@ const MSGID_BITFIELD: MsgId = 5;
 const MSGID_REQUEST: MsgId = 6;
 const MSGID_PIECE: MsgId = 7;
 const MSGID_CANCEL: MsgId = 8;
+// BEP 6 - Fast Extension
+const MSGID_SUGGEST_PIECE: MsgId = 13;
+const MSGID_HAVE_ALL: MsgId = 14;
+const MSGID_HAVE_NONE: MsgId = 15;
+const MSGID_REJECT_REQUEST: MsgId = 16;
+const MSGID_ALLOWED_FAST: MsgId = 17;
 const MSGID_EXTENDED: MsgId = 20;
+// BEP 52
+const MSGID_HASH_REQUEST: MsgId = 21;
+const MSGID_HASHES: MsgId = 22;
+const MSGID_HASH_REJECT: MsgId = 23;
 
 pub const EXTENDED_UT_METADATA_KEY: &[u8] = b"ut_metadata";
 pub const MY_EXTENDED_UT_METADATA: u8 = 3;
@@ -68,7 +93,15 @@ impl MsgIdDebug {
             MSGID_REQUEST => "request",
             MSGID_PIECE => "piece",
             MSGID_CANCEL => "cancel",
+            MSGID_SUGGEST_PIECE => "suggest_piece",
+            MSGID_HAVE_ALL => "have_all",
+            MSGID_HAVE_NONE => "have_none",
+            MSGID_REJECT_REQUEST => "reject_request",
+            MSGID_ALLOWED_FAST => "allowed_fast",
             MSGID_EXTENDED => "extended",
+            MSGID_HASH_REQUEST => "hash_request",
+            MSGID_HASHES => "hashes",
+            MSGID_HASH_REJECT => "hash_reject",
             _ => return None,
         };
         Some(n)
@@ -229,6 +262,16 @@ pub enum Message<'a> {
     NotInterested,
     Piece(Piece<ByteBuf<'a>>),
     Extended(ExtendedMessage<ByteBuf<'a>>),
+    // BEP 6 - Fast Extension. Only valid if both sides set the fast bit in the handshake.
+    SuggestPiece(u32),
+    HaveAll,
+    HaveNone,
+    RejectRequest(Request),
+    AllowedFast(u32),
+    // BEP 52. Only valid if both sides set the v2 bit in the handshake.
+    HashRequest(HashRequest),
+    Hashes(Hashes<ByteBuf<'a>>),
+    HashReject(HashRequest),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -271,12 +314,15 @@ impl Message<'_> {
         }
 
         match self {
-            Message::Request(request) | Message::Cancel(request) => {
+            Message::Request(request)
+            | Message::Cancel(request)
+            | Message::RejectRequest(request) => {
                 const TOTAL_LEN: usize = PREAMBLE_LEN + INTEGER_LEN * 3;
                 check_len!(TOTAL_LEN);
                 let msg_id = match self {
                     Message::Request(..) => MSGID_REQUEST,
                     Message::Cancel(..) => MSGID_CANCEL,
+                    Message::RejectRequest(..) => MSGID_REJECT_REQUEST,
                     _ => unsafe { unreachable_unchecked() },
                 };
                 write_preamble!((INTEGER_LEN * 3) as u32, msg_id);
@@ -291,13 +337,20 @@ impl Message<'_> {
                 out[PREAMBLE_LEN..PREAMBLE_LEN + block_len].copy_from_slice(b.as_ref());
                 Ok(total_len)
             }
-            Message::Choke | Message::Unchoke | Message::Interested | Message::NotInterested => {
+            Message::Choke
+            | Message::Unchoke
+            | Message::Interested
+            | Message::NotInterested
+            | Message::HaveAll
+            | Message::HaveNone => {
                 check_len!(PREAMBLE_LEN);
                 let msg_id = match self {
                     Message::Choke => MSGID_CHOKE,
                     Message::Unchoke => MSGID_UNCHOKE,
                     Message::Interested => MSGID_INTERESTED,
                     Message::NotInterested => MSGID_NOT_INTERESTED,
+                    Message::HaveAll => MSGID_HAVE_ALL,
+                    Message::HaveNone => MSGID_HAVE_NONE,
                     _ => unsafe { unreachable_unchecked() },
                 };
                 write_preamble!(0, msg_id);
@@ -317,9 +370,15 @@ impl Message<'_> {
                 out[0..4].copy_from_slice(&0u32.to_be_bytes());
                 Ok(4)
             }
-            Message::Have(v) => {
+            Message::Have(v) | Message::SuggestPiece(v) | Message::AllowedFast(v) => {
                 check_len!(PREAMBLE_LEN + INTEGER_LEN);
-                write_preamble!(INTEGER_LEN as u32, MSGID_HAVE);
+                let msg_id = match self {
+                    Message::Have(..) => MSGID_HAVE,
+                    Message::SuggestPiece(..) => MSGID_SUGGEST_PIECE,
+                    Message::AllowedFast(..) => MSGID_ALLOWED_FAST,
+                    _ => unsafe { unreachable_unchecked() },
+                };
+                write_preamble!(INTEGER_LEN as u32, msg_id);
                 out[5..9].copy_from_slice(&v.to_be_bytes());
                 Ok(9)
             }
@@ -329,6 +388,28 @@ impl Message<'_> {
                 write_preamble!(msg_len as u32, MSGID_EXTENDED);
                 Ok(PREAMBLE_LEN + msg_len)
             }
+            Message::HashRequest(request) | Message::HashReject(request) => {
+                check_len!(PREAMBLE_LEN + HASH_REQUEST_LEN);
+                let msg_id = match self {
+                    Message::HashRequest(..) => MSGID_HASH_REQUEST,
+                    Message::HashReject(..) => MSGID_HASH_REJECT,
+                    _ => unsafe { unreachable_unchecked() },
+                };
+                write_preamble!(HASH_REQUEST_LEN as u32, msg_id);
+                Ok(PREAMBLE_LEN + request.serialize_unchecked_len(&mut out[PREAMBLE_LEN..]))
+            }
+            Message::Hashes(hashes) => {
+                let hashes_len = hashes.hashes.as_ref().len();
+                let total_len = PREAMBLE_LEN + HASH_REQUEST_LEN + hashes_len;
+                check_len!(total_len);
+                write_preamble!((HASH_REQUEST_LEN + hashes_len) as u32, MSGID_HASHES);
+                let offset = PREAMBLE_LEN
+                    + hashes
+                        .request
+                        .serialize_unchecked_len(&mut out[PREAMBLE_LEN..]);
+                out[offset..total_len].copy_from_slice(hashes.hashes.as_ref());
+                Ok(total_len)
+            }
         }
     }
 }
@@ -398,10 +479,23 @@ impl Message<'_> {
                 check_msg_len!(0);
                 Ok((Message::NotInterested, total_len))
             }
-            MSGID_HAVE => {
+            MSGID_HAVE_ALL => {
+                check_msg_len!(0);
+                Ok((Message::HaveAll, total_len))
+            }
+            MSGID_HAVE_NONE => {
+                check_msg_len!(0);
+                Ok((Message::HaveNone, total_len))
+            }
+            MSGID_HAVE | MSGID_SUGGEST_PIECE | MSGID_ALLOWED_FAST => {
                 check_msg_len!(4);
-                let have = buf.read_u32_be().unwrap();
-                Ok((Message::Have(have), total_len))
+                let index = buf.read_u32_be().unwrap();
+                let msg = match msg_id {
+                    MSGID_HAVE => Message::Have(index),
+                    MSGID_SUGGEST_PIECE => Message::SuggestPiece(index),
+                    _ => Message::AllowedFast(index),
+                };
+                Ok((msg, total_len))
             }
             MSGID_BITFIELD => {
                 check_msg_len!(min 1);
@@ -411,7 +505,7 @@ impl Message<'_> {
                     .ok_or(MessageDeserializeError::NeedContiguous)?;
                 Ok((Message::Bitfield(ByteBuf::from(data)), total_len))
             }
-            MSGID_REQUEST | MSGID_CANCEL => {
+            MSGID_REQUEST | MSGID_CANCEL | MSGID_REJECT_REQUEST => {
                 check_msg_len!(12);
                 const I32: usize = 4;
                 const I32_3: usize = I32 * 3;
@@ -421,10 +515,10 @@ impl Message<'_> {
                     begin: BE::read_u32(&req[I32..I32 * 2]),
                     length: BE::read_u32(&req[I32 * 2..I32 * 3]),
                 };
-                let req = if msg_id == MSGID_REQUEST {
-                    Message::Request(request)
-                } else {
-                    Message::Cancel(request)
+                let req = match msg_id {
+                    MSGID_REQUEST => Message::Request(request),
+                    MSGID_CANCEL => Message::Cancel(request),
+                    _ => Message::RejectRequest(request),
                 };
                 Ok((req, total_len))
             }
@@ -455,6 +549,37 @@ impl Message<'_> {
                     total_len,
                 ))
             }
+            MSGID_HASH_REQUEST | MSGID_HASH_REJECT => {
+                check_msg_len!(48);
+                let request = HashRequest::deserialize(&buf.consume::<HASH_REQUEST_LEN>().unwrap());
+                let msg = match msg_id {
+                    MSGID_HASH_REQUEST => Message::HashRequest(request),
+                    _ => Message::HashReject(request),
+                };
+                Ok((msg, total_len))
+            }
+            MSGID_HASHES => {
+                check_msg_len!(min 48);
+                let hashes_len = msg_len - HASH_REQUEST_LEN;
+                if !hashes_len.is_multiple_of(32) {
+                    return Err(MessageDeserializeError::IncorrectMsgLen {
+                        received: len_prefix - 1,
+                        expected: (HASH_REQUEST_LEN + hashes_len.next_multiple_of(32)) as u32,
+                        msg_id: MsgIdDebug(msg_id),
+                    });
+                }
+                let request = HashRequest::deserialize(&buf.consume::<HASH_REQUEST_LEN>().unwrap());
+                let hashes = buf
+                    .get_contiguous(hashes_len)
+                    .ok_or(MessageDeserializeError::NeedContiguous)?;
+                Ok((
+                    Message::Hashes(Hashes {
+                        request,
+                        hashes: ByteBuf::from(hashes),
+                    }),
+                    total_len,
+                ))
+            }
             MSGID_EXTENDED => Ok((
                 Message::Extended(ExtendedMessage::deserialize(buf.with_max_len(msg_len))?),
                 PREAMBLE_LEN + msg_len,
@@ -464,7 +589,7 @@ impl Message<'_> {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Handshake {
     pub reserved: u64,
     pub info_hash: Id20,
@@ -478,6 +603,8 @@ impl Handshake {
         let mut reserved: u64 = 0;
         // supports extended messaging
         reserved |= 1 << 20;
+        // supports BEP 6 fast extension
+        reserved |= 1 << 2;
 
         Handshake {
             reserved,
@@ -510,6 +637,20 @@ impl Handshake {
         self.reserved.to_be_bytes()[5] & 0x10 > 0
     }
 
+    pub fn supports_fast(&self) -> bool {
+        self.reserved.to_be_bytes()[7] & 0x04 > 0
+    }
+
+    /// Advertise BEP 52 support. Only set for torrents that have a v2 info hash.
+    pub fn with_v2(mut self) -> Self {
+        self.reserved |= 1 << 4;
+        self
+    }
+
+    pub fn supports_v2(&self) -> bool {
+        self.reserved.to_be_bytes()[7] & 0x10 > 0
+    }
+
     #[must_use]
     pub fn serialize_unchecked_len(&self, buf: &mut [u8]) -> usize {
         debug_assert_eq!(PSTR_BT1.len(), 19);
@@ -546,6 +687,63 @@ impl Request {
     }
 }
 
+/// BEP 52 request for "length" merkle tree hashes of the file with "pieces_root", starting at
+/// "index" in "base_layer" (0 = 16KiB blocks), plus "proof_layers" levels of uncle hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashRequest {
+    pub pieces_root: Id32,
+    pub base_layer: u32,
+    pub index: u32,
+    pub length: u32,
+    pub proof_layers: u32,
+}
+
+impl HashRequest {
+    fn deserialize(b: &[u8; HASH_REQUEST_LEN]) -> Self {
+        Self {
+            pieces_root: Id32::new(b[0..32].try_into().unwrap()),
+            base_layer: BE::read_u32(&b[32..36]),
+            index: BE::read_u32(&b[36..40]),
+            length: BE::read_u32(&b[40..44]),
+            proof_layers: BE::read_u32(&b[44..48]),
+        }
+    }
+
+    pub fn serialize_unchecked_len(&self, buf: &mut [u8]) -> usize {
+        buf[0..32].copy_from_slice(&self.pieces_root.0);
+        buf[32..36].copy_from_slice(&self.base_layer.to_be_bytes());
+        buf[36..40].copy_from_slice(&self.index.to_be_bytes());
+        buf[40..44].copy_from_slice(&self.length.to_be_bytes());
+        buf[44..48].copy_from_slice(&self.proof_layers.to_be_bytes());
+        HASH_REQUEST_LEN
+    }
+}
+
+/// BEP 52 "hashes" message: the requested hashes followed by the uncle hashes, bottom to top.
+#[derive(Debug, Clone)]
+pub struct Hashes<B> {
+    pub request: HashRequest,
+    pub hashes: B,
+}
+
+impl Hashes<ByteBufOwned> {
+    pub fn as_borrowed(&self) -> Hashes<ByteBuf<'_>> {
+        Hashes {
+            request: self.request,
+            hashes: ByteBuf(&self.hashes.0),
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> Hashes<B> {
+    pub fn iter_hashes(&self) -> impl Iterator<Item = Id32> + '_ {
+        self.hashes
+            .as_ref()
+            .chunks_exact(32)
+            .map(|c| Id32::new(c.try_into().unwrap()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Context;
@@ -575,6 +773,10 @@ mod tests {
         let (de, dlen) = Handshake::deserialize(&buf).unwrap();
         assert_eq!(dlen, len);
         assert_eq!(se, de);
+        assert!(de.supports_extended());
+        assert!(de.supports_fast());
+        assert!(!de.supports_v2());
+        assert!(se.with_v2().supports_v2());
     }
 
     #[test]
@@ -774,6 +976,8 @@ mod tests {
             MSGID_UNCHOKE,
             MSGID_INTERESTED,
             MSGID_NOT_INTERESTED,
+            MSGID_HAVE_ALL,
+            MSGID_HAVE_NONE,
         ] {
             buf[0..4].copy_from_slice(&1u32.to_be_bytes());
             buf[4] = msgid;
@@ -784,7 +988,9 @@ mod tests {
                     (MSGID_CHOKE, Message::Choke)
                     | (MSGID_UNCHOKE, Message::Unchoke)
                     | (MSGID_INTERESTED, Message::Interested)
-                    | (MSGID_NOT_INTERESTED, Message::NotInterested) => {}
+                    | (MSGID_NOT_INTERESTED, Message::NotInterested)
+                    | (MSGID_HAVE_ALL, Message::HaveAll)
+                    | (MSGID_HAVE_NONE, Message::HaveNone) => {}
                     (msgid, msg) => panic!("msgid={msgid}, msg={msg:?}"),
                 }
                 assert_eq!(len, 5);
@@ -795,4 +1001,125 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_fast_piece_index_messages() {
+        let mut buf = [0u8; 100];
+
+        for msgid in [MSGID_SUGGEST_PIECE, MSGID_ALLOWED_FAST] {
+            buf[0..4].copy_from_slice(&5u32.to_be_bytes());
+            buf[4] = msgid;
+            buf[5..9].copy_from_slice(&42u32.to_be_bytes());
+            for split_point in 0..buf.len() {
+                let (first, second) = buf.split_at(split_point);
+                let (msg, len) = Message::deserialize(first, second).unwrap();
+                match (msgid, &msg) {
+                    (MSGID_SUGGEST_PIECE, Message::SuggestPiece(42))
+                    | (MSGID_ALLOWED_FAST, Message::AllowedFast(42)) => {}
+                    (msgid, msg) => panic!("msgid={msgid}, msg={msg:?}"),
+                }
+                assert_eq!(len, 9);
+                let mut tmp = [0u8; 100];
+                let slen = msg.serialize(&mut tmp, &|| Default::default()).unwrap();
+                assert_eq!(slen, len);
+                assert_eq!(buf[..len], tmp[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reject_request() {
+        let msg = Message::RejectRequest(Request::new(1, 16384, 16384));
+        let mut buf = [0u8; 100];
+        let len = msg.serialize(&mut buf, &|| Default::default()).unwrap();
+        assert_eq!(len, 17);
+        assert_eq!(buf[4], MSGID_REJECT_REQUEST);
+
+        for split_point in 0..buf.len() {
+            let (first, second) = buf.split_at(split_point);
+            let (msg, dlen) = Message::deserialize(first, second).unwrap();
+            let request = match msg {
+                Message::RejectRequest(req) => req,
+                other => panic!("expected reject request got {other:?}"),
+            };
+            assert_eq!(dlen, len);
+            assert_eq!(request.index, 1);
+            assert_eq!(request.begin, 16384);
+            assert_eq!(request.length, 16384);
+        }
+    }
+
+    #[test]
+    fn test_hash_request_and_reject() {
+        let request = HashRequest {
+            pieces_root: Id32::new([7u8; 32]),
+            base_layer: 2,
+            index: 512,
+            length: 512,
+            proof_layers: 3,
+        };
+        for msg in [Message::HashRequest(request), Message::HashReject(request)] {
+            let mut buf = [0u8; 100];
+            let len = msg.serialize(&mut buf, &|| Default::default()).unwrap();
+            assert_eq!(len, 53);
+            for split_point in 0..buf.len() {
+                let (first, second) = buf.split_at(split_point);
+                let (de, dlen) = Message::deserialize(first, second).unwrap();
+                assert_eq!(dlen, len);
+                match (&msg, de) {
+                    (Message::HashRequest(..), Message::HashRequest(r))
+                    | (Message::HashReject(..), Message::HashReject(r)) => assert_eq!(r, request),
+                    (_, de) => panic!("unexpected {de:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hashes() {
+        let request = HashRequest {
+            pieces_root: Id32::new([7u8; 32]),
+            base_layer: 0,
+            index: 0,
+            length: 2,
+            proof_layers: 1,
+        };
+        let hashes = [[1u8; 32], [2u8; 32], [3u8; 32]].concat();
+        let msg = Message::Hashes(Hashes {
+            request,
+            hashes: ByteBuf(&hashes),
+        });
+        let mut buf = [0u8; 200];
+        let len = msg.serialize(&mut buf, &|| Default::default()).unwrap();
+        assert_eq!(len, 5 + 48 + 96);
+
+        let (de, dlen) = Message::deserialize(&buf, &[]).unwrap();
+        assert_eq!(dlen, len);
+        let de = match de {
+            Message::Hashes(h) => h,
+            other => panic!("unexpected {other:?}"),
+        };
+        assert_eq!(de.request, request);
+        assert_eq!(
+            de.iter_hashes().collect::<Vec<_>>(),
+            vec![
+                Id32::new([1u8; 32]),
+                Id32::new([2u8; 32]),
+                Id32::new([3u8; 32])
+            ]
+        );
+
+        // Hashes must be contiguous.
+        assert!(matches!(
+            Message::deserialize(&buf[..60], &buf[60..]),
+            Err(MessageDeserializeError::NeedContiguous)
+        ));
+
+        // Partial hashes are invalid.
+        buf[0..4].copy_from_slice(&(1u32 + 48 + 95).to_be_bytes());
+        assert!(matches!(
+            Message::deserialize(&buf, &[]),
+            Err(MessageDeserializeError::IncorrectMsgLen { .. })
+        ));
+    }
 }
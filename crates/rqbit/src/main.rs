@@ -12,9 +12,10 @@ use anyhow::{Context, bail};
 use clap::{CommandFactory, Parser, ValueEnum};
 use clap_complete::Shell;
 use librqbit::{
-    AddTorrent, AddTorrentOptions, AddTorrentResponse, Api, ConnectionOptions,
-    CreateTorrentOptions, DhtSessionConfig, ListOnlyResponse, ListenerMode, ListenerOptions,
-    PeerConnectionOptions, Session, SessionOptions, SessionPersistenceConfig, TorrentStatsState,
+    AddTorrent, AddTorrentOptions, AddTorrentResponse, Api, BuiltinTrackerOptions,
+    ConnectionOptions, CreateTorrentOptions, DhtSessionConfig, EncryptionPolicy, ListOnlyResponse,
+    ListenerMode, ListenerOptions, PeerConnectionOptions, QueueLimits, SeedGoalAction, SeedGoals,
+    Session, SessionOptions, SessionPersistenceConfig, TorrentStatsState, TorrentVersion,
     dht::DhtPersistenceConfig,
     http_api::{HttpApi, HttpApiOptions},
     librqbit_spawn,
@@ -37,6 +38,60 @@ enum LogLevel {
     Error,
 }
 
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum Encryption {
+    #[default]
+    Disabled,
+    Preferred,
+    Required,
+}
+
+impl From<Encryption> for EncryptionPolicy {
+    fn from(value: Encryption) -> Self {
+        match value {
+            Encryption::Disabled => EncryptionPolicy::Disabled,
+            Encryption::Preferred => EncryptionPolicy::Preferred,
+            Encryption::Required => EncryptionPolicy::Required,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum ShareTorrentVersion {
+    #[default]
+    V1,
+    V2,
+    Hybrid,
+}
+
+impl From<ShareTorrentVersion> for TorrentVersion {
+    fn from(value: ShareTorrentVersion) -> Self {
+        match value {
+            ShareTorrentVersion::V1 => TorrentVersion::V1,
+            ShareTorrentVersion::V2 => TorrentVersion::V2,
+            ShareTorrentVersion::Hybrid => TorrentVersion::Hybrid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum SeedGoalActionArg {
+    #[default]
+    Pause,
+    Forget,
+    Delete,
+}
+
+impl From<SeedGoalActionArg> for SeedGoalAction {
+    fn from(value: SeedGoalActionArg) -> Self {
+        match value {
+            SeedGoalActionArg::Pause => SeedGoalAction::Pause,
+            SeedGoalActionArg::Forget => SeedGoalAction::Forget,
+            SeedGoalActionArg::Delete => SeedGoalAction::Delete,
+        }
+    }
+}
+
 #[cfg(not(target_os = "windows"))]
 fn parse_umask(value: &str) -> anyhow::Result<libc::mode_t> {
     fn parse_oct_digit(d: u8) -> Option<libc::mode_t> {
@@ -141,6 +196,11 @@ struct Opts {
     #[arg(long = "dht-bootstrap-addrs", env = "RQBIT_DHT_BOOTSTRAP")]
     dht_bootstrap_addrs: Option<String>,
 
+    /// Query the DHT without answering queries from other nodes (BEP 43).
+    /// Useful on metered connections.
+    #[arg(long = "dht-read-only", env = "RQBIT_DHT_READ_ONLY")]
+    dht_read_only: bool,
+
     /// The connect timeout, e.g. 1s, 1.5s, 100ms etc.
     #[arg(long = "peer-connect-timeout", value_parser = parse_duration::parse, default_value="2s", env="RQBIT_PEER_CONNECT_TIMEOUT")]
     peer_connect_timeout: Duration,
@@ -153,6 +213,59 @@ struct Opts {
     #[arg(long = "peer-limit", env = "RQBIT_PEER_LIMIT")]
     peer_limit: Option<usize>,
 
+    /// How many peers to upload to at the same time per torrent, not counting the optimistic
+    /// unchoke. Defaults to 4.
+    #[arg(long = "upload-slots", env = "RQBIT_UPLOAD_SLOTS")]
+    upload_slots: Option<usize>,
+
+    /// Default share ratio (uploaded / downloaded) after which finished torrents stop seeding.
+    #[arg(long = "max-ratio", env = "RQBIT_MAX_RATIO")]
+    max_ratio: Option<f64>,
+
+    /// Default time finished torrents are seeded for, e.g. 12h, 7days.
+    #[arg(long = "max-seeding-time", value_parser = parse_duration::parse, env = "RQBIT_MAX_SEEDING_TIME")]
+    max_seeding_time: Option<Duration>,
+
+    /// Default time finished torrents are seeded for without uploading anything, e.g. 30min.
+    #[arg(long = "max-idle-time", value_parser = parse_duration::parse, env = "RQBIT_MAX_IDLE_TIME")]
+    max_idle_time: Option<Duration>,
+
+    /// What to do with torrents reaching --max-ratio, --max-seeding-time or --max-idle-time.
+    #[arg(
+        long = "seed-goal-action",
+        value_enum,
+        default_value_t = SeedGoalActionArg::Pause,
+        env = "RQBIT_SEED_GOAL_ACTION"
+    )]
+    seed_goal_action: SeedGoalActionArg,
+
+    /// How many torrents can download at the same time. The rest are queued.
+    #[arg(long = "max-active-downloads", env = "RQBIT_MAX_ACTIVE_DOWNLOADS")]
+    max_active_downloads: Option<usize>,
+
+    /// How many torrents can seed at the same time. The rest are queued.
+    #[arg(long = "max-active-seeds", env = "RQBIT_MAX_ACTIVE_SEEDS")]
+    max_active_seeds: Option<usize>,
+
+    /// How many torrents can be active (downloading or seeding) at the same time.
+    #[arg(long = "max-active-torrents", env = "RQBIT_MAX_ACTIVE_TORRENTS")]
+    max_active_torrents: Option<usize>,
+
+    /// Downloads slower than this (bytes per second) don't count against the queue limits.
+    #[arg(
+        long = "slow-download-threshold",
+        env = "RQBIT_SLOW_DOWNLOAD_THRESHOLD"
+    )]
+    slow_download_threshold_bps: Option<u64>,
+
+    /// Seeds slower than this (bytes per second) don't count against the queue limits.
+    #[arg(long = "slow-upload-threshold", env = "RQBIT_SLOW_UPLOAD_THRESHOLD")]
+    slow_upload_threshold_bps: Option<u64>,
+
+    /// For how long a torrent needs to be active before it's checked for being slow.
+    #[arg(long = "slow-torrent-grace-period", value_parser = parse_duration::parse, env = "RQBIT_SLOW_TORRENT_GRACE_PERIOD")]
+    slow_torrent_grace_period: Option<Duration>,
+
     /// How many threads to spawn for the executor.
     #[arg(short = 't', long, env = "RQBIT_RUNTIME_WORKER_THREADS")]
     worker_threads: Option<usize>,
@@ -240,6 +353,18 @@ struct Opts {
     #[arg(long, env = "RQBIT_SOCKS_PROXY_URL")]
     socks_url: Option<String>,
 
+    /// Message Stream Encryption (a.k.a. Protocol Encryption) for peer connections.
+    ///
+    /// "preferred" will try encrypting outgoing connections and accept both encrypted and plaintext
+    /// incoming ones. "required" will only allow encrypted connections.
+    #[arg(
+        long = "encryption",
+        value_enum,
+        default_value_t = Encryption::Disabled,
+        env = "RQBIT_ENCRYPTION"
+    )]
+    encryption: Encryption,
+
     /// How many torrents can be initializing (rehashing) at the same time
     #[arg(long, default_value = "5", env = "RQBIT_CONCURRENT_INIT_LIMIT")]
     concurrent_init_limit: usize,
@@ -294,6 +419,32 @@ struct Opts {
     /// Disable trackers (for debugging DHT, LSD and --initial-peers)
     #[arg(long = "disable-trackers", env = "RQBIT_TRACKERS_DISABLE")]
     disable_trackers: bool,
+
+    /// Announce to the trackers of public torrents one tier at a time (BEP 12), instead of
+    /// to all of them at once. Private torrents always use tiers.
+    #[arg(long = "tiered-announce", env = "RQBIT_TIERED_ANNOUNCE")]
+    tiered_announce: bool,
+
+    /// Run a built-in tracker, for networks without DHT or other trackers. It's served over
+    /// HTTP by the HTTP API at "/announce" and "/scrape".
+    #[arg(long = "tracker", env = "RQBIT_TRACKER")]
+    enable_tracker: bool,
+
+    /// Also serve the built-in tracker over UDP on this address.
+    #[arg(
+        long = "tracker-udp-listen-addr",
+        env = "RQBIT_TRACKER_UDP_LISTEN_ADDR",
+        requires = "enable_tracker"
+    )]
+    tracker_udp_listen_addr: Option<SocketAddr>,
+
+    /// Only track the torrents that are in the session.
+    #[arg(
+        long = "tracker-whitelist",
+        env = "RQBIT_TRACKER_WHITELIST",
+        requires = "enable_tracker"
+    )]
+    tracker_whitelist: bool,
 }
 
 #[derive(Parser)]
@@ -387,6 +538,11 @@ struct DownloadOpts {
     /// the global --disable-upnp-port-forward.
     #[arg(long = "upnp-port-forward", env = "RQBIT_UPNP_PORT_FORWARD")]
     upnp_port_forward: bool,
+
+    /// For mutable torrent magnet links (BEP 46): remove the previous version of the torrent
+    /// when a new one is published. Its files are kept.
+    #[arg(long = "retire-previous-versions")]
+    retire_previous_versions: bool,
 }
 
 #[derive(Clone)]
@@ -410,6 +566,60 @@ struct CompletionsOpts {
     shell: Shell,
 }
 
+#[derive(Parser)]
+struct DhtCrawlOpts {
+    /// Stop after discovering this many info hashes.
+    #[arg(long = "limit")]
+    limit: Option<usize>,
+}
+
+#[derive(Parser)]
+struct DhtNodeOpts {
+    /// The UDP port to listen on. Defaults to the persisted port, or a random one.
+    #[arg(long = "port", env = "RQBIT_DHT_NODE_PORT")]
+    port: Option<u16>,
+
+    /// Max number of nodes in each routing table (IPv4 and IPv6).
+    #[arg(
+        long = "routing-table-size",
+        default_value_t = 8192,
+        env = "RQBIT_DHT_NODE_ROUTING_TABLE_SIZE"
+    )]
+    routing_table_size: usize,
+
+    /// How many queries per second to answer from a single IP.
+    #[arg(
+        long = "queries-per-second-per-source",
+        default_value_t = librqbit::dht::DEFAULT_QUERIES_PER_SECOND_PER_SOURCE,
+        env = "RQBIT_DHT_NODE_QUERIES_PER_SECOND_PER_SOURCE"
+    )]
+    queries_per_second_per_source: u32,
+
+    /// How many queries per second to answer from a single /24 (IPv4) or /64 (IPv6) network.
+    #[arg(
+        long = "queries-per-second-per-prefix",
+        default_value_t = librqbit::dht::DEFAULT_QUERIES_PER_SECOND_PER_PREFIX,
+        env = "RQBIT_DHT_NODE_QUERIES_PER_SECOND_PER_PREFIX"
+    )]
+    queries_per_second_per_prefix: u32,
+
+    /// Where to store the routing table and peers. Defaults to the same file as other
+    /// rqbit commands use.
+    #[arg(
+        long = "persistence-filename",
+        env = "RQBIT_DHT_NODE_PERSISTENCE_FILENAME"
+    )]
+    persistence_filename: Option<PathBuf>,
+
+    /// Serve Prometheus metrics on http://<this address>/metrics
+    #[cfg(feature = "prometheus")]
+    #[arg(
+        long = "metrics-listen-addr",
+        env = "RQBIT_DHT_NODE_METRICS_LISTEN_ADDR"
+    )]
+    metrics_listen_addr: Option<SocketAddr>,
+}
+
 #[derive(Parser)]
 struct ShareOpts {
     /// The path to create and share a torrent from
@@ -422,6 +632,11 @@ struct ShareOpts {
     /// Tracker URLs to share to (comma separated). Will append these to trackers from RQBIT_TRACKERS_FILENAME.
     #[arg(value_delimiter = ',', num_args = 0..32)]
     trackers: Vec<url::Url>,
+
+    /// BitTorrent protocol version of the created torrent. Hybrid torrents can be
+    /// downloaded by both v1 and v2 clients.
+    #[arg(long = "torrent-version", value_enum, default_value_t = ShareTorrentVersion::V1)]
+    torrent_version: ShareTorrentVersion,
 }
 
 #[derive(Parser)]
@@ -434,6 +649,10 @@ enum SubCommand {
     Download(DownloadOpts),
     /// Shell completions. eval "$(rqbit completions bash)"
     Completions(CompletionsOpts),
+    /// Crawl the DHT for info hashes (BEP 51) and print them as they are discovered.
+    DhtCrawl(DhtCrawlOpts),
+    /// Run only a DHT node, without a torrent session. Useful as a bootstrap node.
+    DhtNode(DhtNodeOpts),
 }
 
 /// Return the API listener socket passed to rqbit by systemd, if any.
@@ -620,6 +839,7 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
         enable_upnp_port_forwarding: !opts.disable_upnp_port_forward,
         announce_port: opts.announce_port,
         ipv4_only: opts.ipv4_only,
+        encryption: opts.encryption.into(),
         ..Default::default()
     });
 
@@ -638,6 +858,7 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
                 .map(|s| s.split(",").map(|v| v.to_string()).collect()),
             port: None,
             persistence,
+            read_only: opts.dht_read_only,
         })
     };
 
@@ -655,6 +876,7 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
                 read_write_timeout: Some(opts.peer_read_write_timeout),
                 ..Default::default()
             }),
+            encryption: opts.encryption.into(),
         }),
         bind_device_name: opts.bind_device_name.take(),
         default_storage_factory: Some({
@@ -686,8 +908,31 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
         allowlist_url: opts.allowlist_url.take(),
         disable_local_service_discovery: opts.disable_local_peer_discovery,
         disable_trackers: opts.disable_trackers,
+        announce_to_all_trackers: !opts.tiered_announce,
         trackers,
+        tracker: opts.enable_tracker.then_some(BuiltinTrackerOptions {
+            udp_listen_addr: opts.tracker_udp_listen_addr,
+            whitelist: opts.tracker_whitelist,
+            announce_interval: None,
+        }),
         peer_limit: opts.peer_limit,
+        upload_slots: opts.upload_slots,
+        seed_goals: SeedGoals {
+            max_ratio: opts.max_ratio,
+            max_ratio_action: Some(opts.seed_goal_action.into()),
+            max_seeding_time: opts.max_seeding_time,
+            max_seeding_time_action: Some(opts.seed_goal_action.into()),
+            max_idle_time: opts.max_idle_time,
+            max_idle_time_action: Some(opts.seed_goal_action.into()),
+        },
+        queue: QueueLimits {
+            max_active_downloads: opts.max_active_downloads,
+            max_active_seeds: opts.max_active_seeds,
+            max_active_torrents: opts.max_active_torrents,
+            slow_download_threshold_bps: opts.slow_download_threshold_bps,
+            slow_upload_threshold_bps: opts.slow_upload_threshold_bps,
+            slow_grace_period: opts.slow_torrent_grace_period,
+        },
         runtime_worker_threads: Some(opts.max_blocking_threads as usize),
         ipv4_only: opts.ipv4_only,
         client_name_and_version: None,
@@ -815,6 +1060,7 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
                 sub_folder: download_opts.sub_folder.clone(),
                 initial_peers: download_opts.initial_peers.as_ref().map(|p| &p.0).cloned(),
                 disable_trackers: opts.disable_trackers,
+                retire_previous_versions: download_opts.retire_previous_versions,
                 ..Default::default()
             };
             let session = Session::new_with_opts(
@@ -973,6 +1219,7 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
                     CreateTorrentOptions {
                         name: share_opts.name.as_deref(),
                         trackers,
+                        version: share_opts.torrent_version.into(),
                         ..Default::default()
                     },
                 )
@@ -988,6 +1235,110 @@ async fn async_main(mut opts: Opts, cancel: CancellationToken) -> anyhow::Result
 
             http_api_fut.await
         }
+        SubCommand::DhtCrawl(crawl_opts) => {
+            if sopts.dht.is_none() {
+                anyhow::bail!("DHT is disabled, can't crawl it");
+            }
+            // Only the DHT is needed. Keep its persistence for a warm routing table.
+            sopts.persistence = None;
+            sopts.listen = None;
+
+            let session = Session::new_with_opts(PathBuf::new(), sopts)
+                .await
+                .context("error initializing rqbit session")?;
+            let dht = session.get_dht().context("DHT not initialized")?;
+
+            let mut info_hashes = dht.crawl_info_hashes();
+            let mut count = 0;
+            while crawl_opts.limit.is_none_or(|limit| count < limit) {
+                let info_hash = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    info_hash = futures::StreamExt::next(&mut info_hashes) => match info_hash {
+                        Some(info_hash) => info_hash,
+                        None => bail!("DHT crawl stopped"),
+                    },
+                };
+                println!("{}", info_hash.as_string());
+                count += 1;
+            }
+            info!(count, "finished crawling");
+            Ok(())
+        }
+        SubCommand::DhtNode(node_opts) => {
+            let Some(dht_opts) = sopts.dht.take() else {
+                bail!("DHT is disabled, can't run a DHT node");
+            };
+            let bind_device = match sopts.bind_device_name.as_ref() {
+                Some(name) => Some(
+                    librqbit_dualstack_sockets::BindDevice::new_from_name(name)
+                        .with_context(|| format!("error creating bind device {name}"))?,
+                ),
+                None => None,
+            };
+            let config = librqbit::dht::DhtConfig {
+                bootstrap_addrs: dht_opts.bootstrap_addrs,
+                cancellation_token: Some(cancel.child_token()),
+                bind_device: bind_device.as_ref(),
+                read_only: dht_opts.read_only,
+                routing_table_size: Some(node_opts.routing_table_size),
+                queries_per_second_per_source: Some(node_opts.queries_per_second_per_source),
+                queries_per_second_per_prefix: Some(node_opts.queries_per_second_per_prefix),
+                ..Default::default()
+            };
+            let dht = match dht_opts.persistence {
+                Some(_) => librqbit::dht::PersistentDht::create(
+                    DhtPersistenceConfig {
+                        config_filename: node_opts.persistence_filename.clone(),
+                        ..Default::default()
+                    },
+                    node_opts.port,
+                    sopts.ipv4_only,
+                    config,
+                )
+                .await
+                .context("error initializing persistent DHT")?,
+                None => librqbit::dht::DhtBuilder::with_config(librqbit::dht::DhtConfig {
+                    listen_addr: Some(librqbit::dht::dht_listen_addr(
+                        node_opts.port,
+                        None,
+                        sopts.ipv4_only,
+                    )),
+                    ..config
+                })
+                .await
+                .context("error initializing DHT")?,
+            };
+            info!(listen_addr=?dht.listen_addr(), "started DHT node");
+
+            #[cfg(feature = "prometheus")]
+            if let Some(addr) = node_opts.metrics_listen_addr {
+                let listener = TcpListener::bind_tcp(addr, Default::default())
+                    .with_context(|| format!("error binding metrics server to {addr}"))?;
+                info!("serving metrics at http://{}/metrics", listener.bind_addr());
+                let handle = http_api_opts.prometheus_handle.take();
+                let dht = dht.clone();
+                let app = axum::Router::new().route(
+                    "/metrics",
+                    axum::routing::get(move || async move {
+                        let mut metrics = handle.as_ref().map(|h| h.render()).unwrap_or_default();
+                        dht.stats().as_prometheus(&mut metrics);
+                        metrics
+                    }),
+                );
+                librqbit_spawn(
+                    debug_span!("dht_node_metrics"),
+                    "dht_node_metrics",
+                    async move {
+                        axum::serve(listener, app)
+                            .await
+                            .context("error running metrics server")
+                    },
+                );
+            }
+
+            cancel.cancelled().await;
+            Ok(())
+        }
         SubCommand::Completions(_) => unreachable!(),
     }
 }
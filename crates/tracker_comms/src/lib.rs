@@ -1,6 +1,8 @@
 mod tracker_comms;
 mod tracker_comms_http;
 mod tracker_comms_udp;
+mod tracker_server;
 
 pub use tracker_comms::*;
 pub use tracker_comms_udp::UdpTrackerClient;
+pub use tracker_server::{TrackerServer, TrackerServerOptions, TrackerWhitelist};
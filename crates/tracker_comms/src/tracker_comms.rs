@@ -1,9 +1,12 @@
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::net::SocketAddrV4;
 use std::net::SocketAddrV6;
 use std::sync::Arc;
+use std::sync::LazyLock;
 use std::time::Duration;
+use std::time::SystemTime;
 
 use anyhow::Context;
 use anyhow::bail;
@@ -11,9 +14,15 @@ use backon::ExponentialBuilder;
 use backon::Retryable;
 use futures::FutureExt;
 use futures::StreamExt;
+use futures::future::BoxFuture;
 use futures::future::Either;
 use futures::stream::BoxStream;
 use futures::stream::FuturesUnordered;
+use parking_lot::RwLock;
+use rand::seq::SliceRandom;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use serde_with::serde_as;
 use tracing::Instrument;
 use tracing::debug;
 use tracing::debug_span;
@@ -22,6 +31,7 @@ use tracing::trace_span;
 use url::Url;
 
 use crate::tracker_comms_http;
+use crate::tracker_comms_http::TrackerRequestEvent;
 use crate::tracker_comms_udp;
 use crate::tracker_comms_udp::UdpTrackerClient;
 use librqbit_core::hash_id::Id20;
@@ -36,9 +46,192 @@ pub struct TrackerComms {
     announce_port: u16,
     reqwest_client: reqwest::Client,
     key: u32,
+    statuses: TrackerStatuses,
+    events: AnnounceEvents,
 }
 
-#[derive(Default)]
+// Mixed with the info hash to get the announce key, so that a torrent keeps the same key when
+// announces are restarted, and for the "stopped" announce.
+static ANNOUNCE_KEY_SECRET: LazyLock<u32> = LazyLock::new(rand::random);
+
+fn announce_key(info_hash: &Id20) -> u32 {
+    let [a, b, c, d, ..] = info_hash.0;
+    *ANNOUNCE_KEY_SECRET ^ u32::from_be_bytes([a, b, c, d])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SentEvents {
+    // "started" was sent before the torrent finished, so "completed" is still due.
+    Started,
+    // Nothing else to send until "stopped".
+    Done,
+}
+
+/// The announce events sent to each tracker of a torrent, per swarm: "started" first, then
+/// "completed" once if the torrent finishes after that. The torrent keeps these, so that
+/// restarting the announces (e.g. when the trackers change) doesn't send "started" again.
+#[derive(Default, Clone)]
+pub struct AnnounceEvents(Arc<parking_lot::Mutex<HashMap<(Id20, Url), SentEvents>>>);
+
+impl AnnounceEvents {
+    fn next(&self, info_hash: Id20, url: &Url, completed: bool) -> Option<TrackerRequestEvent> {
+        match self.0.lock().get(&(info_hash, url.clone())) {
+            None => Some(TrackerRequestEvent::Started),
+            Some(SentEvents::Started) if completed => Some(TrackerRequestEvent::Completed),
+            Some(_) => None,
+        }
+    }
+
+    fn sent(
+        &self,
+        info_hash: Id20,
+        url: &Url,
+        event: Option<TrackerRequestEvent>,
+        completed: bool,
+    ) {
+        let sent = match event {
+            Some(TrackerRequestEvent::Started) if !completed => SentEvents::Started,
+            Some(TrackerRequestEvent::Started | TrackerRequestEvent::Completed) => SentEvents::Done,
+            _ => return,
+        };
+        self.0.lock().insert((info_hash, url.clone()), sent);
+    }
+
+    fn completed_due(&self, info_hash: Id20, url: &Url) -> bool {
+        self.0.lock().get(&(info_hash, url.clone())) == Some(&SentEvents::Started)
+    }
+
+    /// Forget what was sent to the tracker, as after "stopped" it starts over with "started".
+    pub fn forget(&self, url: &Url) {
+        self.0.lock().retain(|(_, u), _| u != url);
+    }
+}
+
+// Backoff when no tracker in any tier responds.
+const TIERS_RETRY_MIN_INTERVAL: Duration = Duration::from_secs(30);
+const TIERS_RETRY_MAX_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// How many info hashes to put into one HTTP scrape request. Some trackers limit this, and the
+// URL can't get too long anyway.
+const HTTP_SCRAPE_BATCH_SIZE: usize = 64;
+
+/// Swarm counts reported by a tracker scrape.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrapeStats {
+    pub seeders: u32,
+    pub leechers: u32,
+    /// How many times the torrent was downloaded to completion.
+    pub completed: u32,
+}
+
+/// What is known about one tracker of a torrent.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackerStatus {
+    #[serde_as(as = "serde_with::DisplayFromStr")]
+    pub url: Url,
+    /// The swarm announced to. Hybrid torrents have a status for each of their two swarms.
+    pub info_hash: Id20,
+    pub tier: usize,
+    /// When the tracker last responded to an announce.
+    #[serde_as(as = "Option<serde_with::TimestampSeconds<i64>>")]
+    pub last_announce: Option<SystemTime>,
+    #[serde_as(as = "Option<serde_with::TimestampSeconds<i64>>")]
+    pub next_announce: Option<SystemTime>,
+    /// How many peers the last announce returned.
+    pub peers_returned: usize,
+    /// The error of the last announce, if it failed.
+    pub last_error: Option<String>,
+    pub last_warning: Option<String>,
+    pub scrape: Option<ScrapeStats>,
+}
+
+impl TrackerStatus {
+    pub fn new(url: Url, info_hash: Id20) -> Self {
+        Self {
+            url,
+            info_hash,
+            tier: 0,
+            last_announce: None,
+            next_announce: None,
+            peers_returned: 0,
+            last_error: None,
+            last_warning: None,
+            scrape: None,
+        }
+    }
+}
+
+/// Statuses of a torrent's trackers by swarm, shared between the announce tasks and the torrent.
+#[derive(Debug, Default, Clone)]
+pub struct TrackerStatuses(Arc<RwLock<HashMap<(Url, Id20), TrackerStatus>>>);
+
+impl TrackerStatuses {
+    pub fn get(&self, url: &Url, info_hash: Id20) -> Option<TrackerStatus> {
+        self.0.read().get(&(url.clone(), info_hash)).cloned()
+    }
+
+    pub fn update(&self, url: &Url, info_hash: Id20, f: impl FnOnce(&mut TrackerStatus)) {
+        f(self
+            .0
+            .write()
+            .entry((url.clone(), info_hash))
+            .or_insert_with(|| TrackerStatus::new(url.clone(), info_hash)))
+    }
+
+    /// Forget the trackers that aren't in "urls", in all swarms.
+    pub fn retain(&self, urls: &HashSet<Url>) {
+        self.0.write().retain(|(url, _), _| urls.contains(url));
+    }
+
+    /// Track exactly the trackers in "tiers", remembering the tier of each in the swarm of
+    /// "info_hash".
+    pub fn set_tiers(&self, info_hash: Id20, tiers: &[Vec<Url>]) {
+        self.retain(&tiers.iter().flatten().cloned().collect());
+        for (tier_idx, tier) in tiers.iter().enumerate() {
+            for url in tier {
+                self.update(url, info_hash, |s| s.tier = tier_idx);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<TrackerStatus> {
+        let mut statuses = self.0.read().values().cloned().collect::<Vec<_>>();
+        statuses.sort_by(|l, r| {
+            (l.tier, l.url.as_str(), l.info_hash).cmp(&(r.tier, r.url.as_str(), r.info_hash))
+        });
+        statuses
+    }
+
+    fn announce_succeeded(
+        &self,
+        info_hash: Id20,
+        url: &Url,
+        peers: usize,
+        warning: Option<String>,
+    ) {
+        self.update(url, info_hash, |s| {
+            s.last_announce = Some(SystemTime::now());
+            s.peers_returned = peers;
+            s.last_error = None;
+            s.last_warning = warning;
+        })
+    }
+
+    fn announce_failed(&self, info_hash: Id20, url: &Url, error: &anyhow::Error) {
+        self.update(url, info_hash, |s| {
+            s.last_error = Some(format!("{error:#}"))
+        })
+    }
+
+    fn set_next_announce(&self, info_hash: Id20, url: &Url, after: Duration) {
+        self.update(url, info_hash, |s| {
+            s.next_announce = Some(SystemTime::now() + after)
+        })
+    }
+}
+
+#[derive(Default, Clone, Copy)]
 pub enum TrackerCommsStatsState {
     #[default]
     None,
@@ -47,7 +240,7 @@ pub enum TrackerCommsStatsState {
     Live,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct TrackerCommsStats {
     pub uploaded_bytes: u64,
     pub downloaded_bytes: u64,
@@ -72,6 +265,12 @@ impl TrackerCommsStats {
 
 pub trait TorrentStatsProvider: Send + Sync {
     fn get(&self) -> TrackerCommsStats;
+
+    /// Resolves once the torrent finishes downloading, so that "completed" is announced right
+    /// away instead of at the next announce interval.
+    fn wait_until_completed(&self) -> BoxFuture<'_, ()> {
+        futures::future::pending().boxed()
+    }
 }
 
 impl TorrentStatsProvider for () {
@@ -87,6 +286,30 @@ enum SupportedTracker {
     Http(Url),
 }
 
+impl SupportedTracker {
+    fn from_url(url: Url) -> Option<Self> {
+        match url.scheme() {
+            "http" | "https" => Some(SupportedTracker::Http(url)),
+            "udp" => Some(SupportedTracker::Udp(url)),
+            _ => {
+                debug!("unsupported tracker URL: {}", url);
+                None
+            }
+        }
+    }
+
+    fn url(&self) -> &Url {
+        match self {
+            SupportedTracker::Udp(u) | SupportedTracker::Http(u) => u,
+        }
+    }
+}
+
+// Move the tracker that responded to the front of its tier, keeping the order of the others.
+fn promote_in_tier<T>(tier: &mut [T], idx: usize) {
+    tier[..=idx].rotate_right(1);
+}
+
 impl std::fmt::Debug for SupportedTracker {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -138,35 +361,42 @@ async fn udp_tracker_to_socket_addrs(
 }
 
 impl TrackerComms {
+    /// Announce to the trackers. "tiers" are tracker tiers as in BEP 12: one tracker is announced
+    /// to at a time, falling through to the next tier only if no tracker in the tier responds.
+    /// With "announce_to_all", every tracker is announced to independently instead.
     // TODO: fix too many args
     #[allow(clippy::too_many_arguments)]
     pub fn start(
         info_hash: Id20,
         peer_id: Id20,
-        trackers: HashSet<Url>,
+        tiers: Vec<Vec<Url>>,
+        announce_to_all: bool,
         stats: Box<dyn TorrentStatsProvider>,
         force_interval: Option<Duration>,
         announce_port: u16,
         reqwest_client: reqwest::Client,
         udp_client: UdpTrackerClient,
+        statuses: TrackerStatuses,
+        events: AnnounceEvents,
     ) -> Option<BoxStream<'static, SocketAddr>> {
-        let trackers = trackers
+        let mut tiers = tiers
             .into_iter()
-            .filter_map(|t| match t.scheme() {
-                "http" | "https" => Some(SupportedTracker::Http(t)),
-                "udp" => Some(SupportedTracker::Udp(t)),
-                _ => {
-                    debug!("unsupported tracker URL: {}", t);
-                    None
-                }
+            .map(|tier| {
+                tier.into_iter()
+                    .filter_map(SupportedTracker::from_url)
+                    .collect::<Vec<_>>()
             })
+            .filter(|tier| !tier.is_empty())
             .collect::<Vec<_>>();
-        if trackers.is_empty() {
+        if tiers.is_empty() {
             debug!(?info_hash, "trackers list is empty");
             return None;
         }
+        for tier in tiers.iter_mut() {
+            tier.shuffle(&mut rand::rng());
+        }
 
-        tracing::trace!(?trackers);
+        tracing::trace!(?tiers);
 
         let (tx, mut rx) = tokio::sync::mpsc::channel::<SocketAddr>(16);
 
@@ -180,11 +410,24 @@ impl TrackerComms {
                 tx,
                 announce_port,
                 reqwest_client,
-                key: rand::random(),
+                key: announce_key(&info_hash),
+                statuses,
+                events,
             });
-            let mut futures = FuturesUnordered::new();
-            for tracker in trackers {
-                futures.push(comms.add_tracker(tracker, &udp_client))
+            let mut futures: FuturesUnordered<BoxFuture<'_, anyhow::Result<()>>> =
+                FuturesUnordered::new();
+            if announce_to_all {
+                for tracker in tiers.into_iter().flatten() {
+                    futures.push(comms.add_tracker(tracker, &udp_client).boxed())
+                }
+            } else {
+                let span = debug_span!(parent: None, "tiered_trackers", info_hash = ?info_hash);
+                futures.push(
+                    comms
+                        .task_tiered_monitor(tiers, udp_client.clone())
+                        .instrument(span)
+                        .boxed(),
+                );
             }
             while !(futures.is_empty()) {
                 tokio::select! {
@@ -235,40 +478,322 @@ impl TrackerComms {
         }
     }
 
+    // BEP 12: go through the tiers in order, and announce to the first tracker that responds.
+    async fn task_tiered_monitor(
+        &self,
+        mut tiers: Vec<Vec<SupportedTracker>>,
+        client: UdpTrackerClient,
+    ) -> anyhow::Result<()> {
+        let mut wake_on_completed = true;
+        let mut retry_interval = TIERS_RETRY_MIN_INTERVAL;
+        loop {
+            let mut responded = None;
+            'tiers: for tier in tiers.iter_mut() {
+                for idx in 0..tier.len() {
+                    let tracker = &tier[idx];
+                    let url = tracker.url().clone();
+                    let (stats, event) = self.next_announce(&url);
+                    match self.announce_once(tracker, &client, &stats, event).await {
+                        Ok(interval) => {
+                            trace!(%url, "tracker responded");
+                            self.events
+                                .sent(self.info_hash, &url, event, stats.is_completed());
+                            promote_in_tier(tier, idx);
+                            responded = Some((url, interval));
+                            break 'tiers;
+                        }
+                        Err(e) => {
+                            debug!(%url, "error calling tracker: {e:#}");
+                            self.statuses.announce_failed(self.info_hash, &url, &e);
+                        }
+                    }
+                }
+            }
+
+            match responded {
+                Some((url, interval)) => {
+                    retry_interval = TIERS_RETRY_MIN_INTERVAL;
+                    let interval = self.force_tracker_interval.unwrap_or(interval);
+                    self.statuses
+                        .set_next_announce(self.info_hash, &url, interval);
+                    debug!("sleeping for {:?} after calling trackers", interval);
+                    self.sleep_until_announce(&url, interval, &mut wake_on_completed)
+                        .await;
+                }
+                None => {
+                    let sleep = retry_interval;
+                    retry_interval = (retry_interval * 2).min(TIERS_RETRY_MAX_INTERVAL);
+                    debug!("sleeping for {:?} after calling trackers", sleep);
+                    tokio::time::sleep(sleep).await;
+                }
+            };
+        }
+    }
+
+    // The stats and the event to send with the next announce to the tracker.
+    fn next_announce(&self, url: &Url) -> (TrackerCommsStats, Option<TrackerRequestEvent>) {
+        let stats = self.stats.get();
+        let event = self.events.next(self.info_hash, url, stats.is_completed());
+        (stats, event)
+    }
+
+    // Sleep until the next announce to the tracker. If "completed" is due, wake up as soon as the
+    // torrent finishes to announce it. This happens once, so that a torrent that looks finished
+    // without the stats saying so doesn't make us announce in a loop.
+    async fn sleep_until_announce(
+        &self,
+        url: &Url,
+        interval: Duration,
+        wake_on_completed: &mut bool,
+    ) {
+        if !(*wake_on_completed && self.events.completed_due(self.info_hash, url)) {
+            tokio::time::sleep(interval).await;
+            return;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = self.stats.wait_until_completed() => {
+                debug!("torrent completed, announcing");
+                *wake_on_completed = false;
+            }
+        }
+    }
+
+    /// Announce "stopped" to the trackers, so that they stop handing out our address to other
+    /// peers. This runs when a torrent or the whole session is shutting down, so it gives up
+    /// after "timeout".
+    #[allow(clippy::too_many_arguments)]
+    pub async fn announce_stopped(
+        info_hash: Id20,
+        peer_id: Id20,
+        trackers: Vec<Url>,
+        stats: TrackerCommsStats,
+        announce_port: u16,
+        reqwest_client: reqwest::Client,
+        udp_client: UdpTrackerClient,
+        timeout: Duration,
+    ) {
+        // Peers are not forwarded for "stopped", so nobody needs to listen.
+        let (tx, _) = tokio::sync::mpsc::channel(1);
+        let comms = Self {
+            info_hash,
+            peer_id,
+            stats: Box::new(()),
+            force_tracker_interval: None,
+            tx,
+            announce_port,
+            reqwest_client,
+            key: announce_key(&info_hash),
+            statuses: Default::default(),
+            events: Default::default(),
+        };
+        let announces = trackers
+            .into_iter()
+            .filter_map(SupportedTracker::from_url)
+            .map(|tracker| {
+                let (comms, stats, udp_client) = (&comms, &stats, &udp_client);
+                async move {
+                    let res = comms
+                        .announce_once(
+                            &tracker,
+                            udp_client,
+                            stats,
+                            Some(TrackerRequestEvent::Stopped),
+                        )
+                        .await;
+                    match res {
+                        Ok(_) => trace!(?tracker, "announced stopped"),
+                        Err(e) => debug!(?tracker, "error announcing stopped: {e:#}"),
+                    }
+                }
+            });
+        if tokio::time::timeout(timeout, futures::future::join_all(announces))
+            .await
+            .is_err()
+        {
+            debug!(?info_hash, "timed out announcing stopped");
+        }
+    }
+
+    async fn announce_once(
+        &self,
+        tracker: &SupportedTracker,
+        client: &UdpTrackerClient,
+        stats: &TrackerCommsStats,
+        event: Option<TrackerRequestEvent>,
+    ) -> anyhow::Result<Duration> {
+        match tracker {
+            SupportedTracker::Http(url) => self.tracker_one_request_http(url, stats, event).await,
+            SupportedTracker::Udp(url) => {
+                let (host, port) = (
+                    url.host().context("missing host")?,
+                    url.port().context("missing port")?,
+                );
+                let addrs = udp_tracker_to_socket_addrs(host.clone(), port)
+                    .instrument(trace_span!("resolve", ?host))
+                    .await?;
+                self.announce_udp(url, addrs, client, stats, event).await
+            }
+        }
+    }
+
     async fn task_single_tracker_monitor_http(&self, tracker_url: Url) -> anyhow::Result<()> {
         trace!(url=%tracker_url, "starting monitor");
-        let mut event = Some(tracker_comms_http::TrackerRequestEvent::Started);
+        let mut wake_on_completed = true;
 
         loop {
-            let interval = (|| self.tracker_one_request_http(&tracker_url, event))
-                .retry(
-                    ExponentialBuilder::new()
-                        .without_max_times()
-                        .with_jitter()
-                        .with_factor(2.)
-                        .with_min_delay(Duration::from_secs(10))
-                        .with_max_delay(Duration::from_secs(600)),
-                )
-                .notify(|err, retry_in| debug!(?retry_in, "error calling tracker: {err:#}"))
-                .await
-                .context("this shouldn't fail")?;
+            let interval = (async || {
+                let (stats, event) = self.next_announce(&tracker_url);
+                let interval = self
+                    .tracker_one_request_http(&tracker_url, &stats, event)
+                    .await?;
+                self.events
+                    .sent(self.info_hash, &tracker_url, event, stats.is_completed());
+                anyhow::Ok(interval)
+            })
+            .retry(
+                ExponentialBuilder::new()
+                    .without_max_times()
+                    .with_jitter()
+                    .with_factor(2.)
+                    .with_min_delay(Duration::from_secs(10))
+                    .with_max_delay(Duration::from_secs(600)),
+            )
+            .notify(|err, retry_in| {
+                debug!(?retry_in, "error calling tracker: {err:#}");
+                self.statuses
+                    .announce_failed(self.info_hash, &tracker_url, err);
+                self.statuses
+                    .set_next_announce(self.info_hash, &tracker_url, retry_in);
+            })
+            .await
+            .context("this shouldn't fail")?;
 
-            event = None;
             let interval = self.force_tracker_interval.unwrap_or(interval);
+            self.statuses
+                .set_next_announce(self.info_hash, &tracker_url, interval);
             debug!("sleeping for {:?} after calling tracker", interval);
-            tokio::time::sleep(interval).await;
+            self.sleep_until_announce(&tracker_url, interval, &mut wake_on_completed)
+                .await;
         }
     }
 
+    /// Scrape the tracker for swarm counts of the given torrents, batching as many info hashes
+    /// into one request as the protocol allows. Torrents the tracker doesn't know about are
+    /// missing from the result.
+    pub async fn scrape(
+        tracker_url: &Url,
+        info_hashes: &[Id20],
+        reqwest_client: &reqwest::Client,
+        udp_client: &UdpTrackerClient,
+    ) -> anyhow::Result<HashMap<Id20, ScrapeStats>> {
+        match tracker_url.scheme() {
+            "http" | "https" => Self::scrape_http(tracker_url, info_hashes, reqwest_client).await,
+            "udp" => Self::scrape_udp(tracker_url, info_hashes, udp_client).await,
+            _ => bail!("unsupported tracker URL: {tracker_url}"),
+        }
+    }
+
+    async fn scrape_http(
+        tracker_url: &Url,
+        info_hashes: &[Id20],
+        reqwest_client: &reqwest::Client,
+    ) -> anyhow::Result<HashMap<Id20, ScrapeStats>> {
+        let scrape_url = tracker_comms_http::scrape_url(tracker_url)
+            .with_context(|| format!("tracker {tracker_url} doesn't support scrape"))?;
+        let mut result = HashMap::new();
+        for chunk in info_hashes.chunks(HTTP_SCRAPE_BATCH_SIZE) {
+            let mut url = scrape_url.clone();
+            let mut queries = tracker_comms_http::scrape_querystring(chunk);
+            if let Some(url_query) = url.query() {
+                queries.push_str(&format!("&{}", url_query));
+            }
+            url.set_query(Some(&queries));
+
+            let response: reqwest::Response = reqwest_client.get(url).send().await?;
+            if !response.status().is_success() {
+                anyhow::bail!("tracker responded with {:?}", response.status());
+            }
+            let bytes = response.bytes().await?;
+            if let Ok((error, _)) =
+                bencode::from_bytes_with_rest::<tracker_comms_http::TrackerError>(&bytes)
+            {
+                anyhow::bail!(
+                    "tracker returned failure. Failure reason: {}",
+                    error.failure_reason
+                )
+            };
+            let response =
+                bencode::from_bytes_with_rest::<tracker_comms_http::ScrapeResponse>(&bytes)
+                    .map_err(|e| {
+                        tracing::trace!("error deserializing ScrapeResponse: {e:#}");
+                        e.into_kind()
+                    })?
+                    .0;
+            let saturate = |v: u64| u32::try_from(v).unwrap_or(u32::MAX);
+            for (info_hash, file) in response.files {
+                let Ok(info_hash) = Id20::from_bytes(info_hash.as_ref()) else {
+                    continue;
+                };
+                result.insert(
+                    info_hash,
+                    ScrapeStats {
+                        seeders: saturate(file.complete),
+                        leechers: saturate(file.incomplete),
+                        completed: saturate(file.downloaded),
+                    },
+                );
+            }
+        }
+        Ok(result)
+    }
+
+    async fn scrape_udp(
+        tracker_url: &Url,
+        info_hashes: &[Id20],
+        client: &UdpTrackerClient,
+    ) -> anyhow::Result<HashMap<Id20, ScrapeStats>> {
+        let (host, port) = (
+            tracker_url.host().context("missing host")?,
+            tracker_url.port().context("missing port")?,
+        );
+        let addrs = match udp_tracker_to_socket_addrs(host, port).await? {
+            UdpTrackerResolveResult::One(addr) => vec![addr],
+            UdpTrackerResolveResult::Two(v4, v6) => vec![v4.into(), v6.into()],
+        };
+        let mut result = HashMap::new();
+        for chunk in info_hashes.chunks(tracker_comms_udp::MAX_SCRAPE_INFO_HASHES) {
+            // Use the first address that responds.
+            let mut last_err = None;
+            for addr in addrs.iter().copied() {
+                match client.scrape(addr, chunk).await {
+                    Ok(stats) => {
+                        result.extend(chunk.iter().copied().zip(stats));
+                        last_err = None;
+                        break;
+                    }
+                    Err(e) => {
+                        debug!(?addr, "error scraping: {e:#}");
+                        last_err = Some(e);
+                    }
+                }
+            }
+            if let Some(e) = last_err {
+                return Err(e);
+            }
+        }
+        Ok(result)
+    }
+
     async fn tracker_one_request_http(
         &self,
         tracker_url: &Url,
-        event: Option<tracker_comms_http::TrackerRequestEvent>,
+        stats: &TrackerCommsStats,
+        event: Option<TrackerRequestEvent>,
     ) -> anyhow::Result<Duration> {
-        let stats = self.stats.get();
         let request = tracker_comms_http::TrackerRequest {
-            info_hash: &self.info_hash,
-            peer_id: &self.peer_id,
+            info_hash: self.info_hash,
+            peer_id: self.peer_id,
             port: self.announce_port,
             uploaded: stats.uploaded_bytes,
             downloaded: stats.downloaded_bytes,
@@ -310,8 +835,19 @@ impl TrackerComms {
             })?
             .0;
 
-        for peer in response.iter_peers() {
-            self.tx.send(peer).await?;
+        self.statuses.announce_succeeded(
+            self.info_hash,
+            tracker_url,
+            response.iter_peers().count(),
+            response
+                .warning_message
+                .as_ref()
+                .map(|w| String::from_utf8_lossy(w.as_ref()).into_owned()),
+        );
+        if event != Some(TrackerRequestEvent::Stopped) {
+            for peer in response.iter_peers() {
+                self.tx.send(peer).await?;
+            }
         }
         Ok(Duration::from_secs(
             response.min_interval.unwrap_or(response.interval),
@@ -333,10 +869,12 @@ impl TrackerComms {
 
         let mut sleep_interval: Option<Duration> = None;
         let mut prev_addrs: Option<UdpTrackerResolveResult> = None;
+        let mut wake_on_completed = true;
         loop {
             if let Some(i) = sleep_interval {
                 trace!(interval=?sleep_interval, "sleeping");
-                tokio::time::sleep(i).await;
+                self.sleep_until_announce(&url, i, &mut wake_on_completed)
+                    .await;
             }
 
             // This should retry forever until the addrs are resolved.
@@ -358,62 +896,70 @@ impl TrackerComms {
 
             prev_addrs = Some(addrs);
 
-            match addrs {
-                UdpTrackerResolveResult::One(addr) => {
-                    match self
-                        .tracker_one_request_udp(addr, &client)
-                        .instrument(trace_span!("udp request", ?addr))
-                        .await
-                    {
-                        Ok(sleep) => sleep_interval = Some(sleep),
-                        Err(_) => {
-                            sleep_interval = Some(sleep_interval.unwrap_or(Duration::from_secs(60)))
-                        }
-                    }
+            let (stats, event) = self.next_announce(&url);
+            let sleep = match self.announce_udp(&url, addrs, &client, &stats, event).await {
+                Ok(sleep) => {
+                    self.events
+                        .sent(self.info_hash, &url, event, stats.is_completed());
+                    sleep
                 }
-                UdpTrackerResolveResult::Two(v4, v6) => {
-                    let (r4, r6) = tokio::join!(
-                        self.tracker_one_request_udp(v4.into(), &client)
-                            .instrument(trace_span!("udp request", addr=?v4)),
-                        self.tracker_one_request_udp(v6.into(), &client)
-                            .instrument(trace_span!("udp request", addr=?v6))
-                    );
-                    sleep_interval = Some(
-                        r4.or(r6)
-                            .ok()
-                            .or(sleep_interval)
-                            .unwrap_or(Duration::from_secs(60)),
-                    )
+                Err(e) => {
+                    self.statuses.announce_failed(self.info_hash, &url, &e);
+                    sleep_interval.unwrap_or(Duration::from_secs(60))
                 }
+            };
+            self.statuses.set_next_announce(self.info_hash, &url, sleep);
+            sleep_interval = Some(sleep);
+        }
+    }
+
+    async fn announce_udp(
+        &self,
+        url: &Url,
+        addrs: UdpTrackerResolveResult,
+        client: &UdpTrackerClient,
+        stats: &TrackerCommsStats,
+        event: Option<TrackerRequestEvent>,
+    ) -> anyhow::Result<Duration> {
+        match addrs {
+            UdpTrackerResolveResult::One(addr) => {
+                self.tracker_one_request_udp(url, addr, client, stats, event)
+                    .instrument(trace_span!("udp request", ?addr))
+                    .await
+            }
+            UdpTrackerResolveResult::Two(v4, v6) => {
+                let (r4, r6) = tokio::join!(
+                    self.tracker_one_request_udp(url, v4.into(), client, stats, event)
+                        .instrument(trace_span!("udp request", addr=?v4)),
+                    self.tracker_one_request_udp(url, v6.into(), client, stats, event)
+                        .instrument(trace_span!("udp request", addr=?v6))
+                );
+                r4.or(r6)
             }
         }
     }
 
     async fn tracker_one_request_udp(
         &self,
+        url: &Url,
         addr: SocketAddr,
         client: &UdpTrackerClient,
+        stats: &TrackerCommsStats,
+        event: Option<TrackerRequestEvent>,
     ) -> anyhow::Result<Duration> {
         use tracker_comms_udp::*;
 
-        let stats = self.stats.get();
         let request = AnnounceFields {
             info_hash: self.info_hash,
             peer_id: self.peer_id,
             downloaded: stats.downloaded_bytes,
             left: stats.get_left_to_download_bytes(),
             uploaded: stats.uploaded_bytes,
-            event: match stats.torrent_state {
-                TrackerCommsStatsState::None => EVENT_NONE,
-                TrackerCommsStatsState::Initializing => EVENT_STARTED,
-                TrackerCommsStatsState::Paused => EVENT_STOPPED,
-                TrackerCommsStatsState::Live => {
-                    if stats.is_completed() {
-                        EVENT_COMPLETED
-                    } else {
-                        EVENT_STARTED
-                    }
-                }
+            event: match event {
+                None => EVENT_NONE,
+                Some(TrackerRequestEvent::Started) => EVENT_STARTED,
+                Some(TrackerRequestEvent::Completed) => EVENT_COMPLETED,
+                Some(TrackerRequestEvent::Stopped) => EVENT_STOPPED,
             },
             key: self.key,
             port: self.announce_port,
@@ -422,8 +968,12 @@ impl TrackerComms {
         match client.announce(addr, request).await {
             Ok(response) => {
                 trace!(len = response.addrs.len(), "received announce response");
-                for addr in response.addrs {
-                    self.tx.send(addr).await.context("rx closed")?;
+                self.statuses
+                    .announce_succeeded(self.info_hash, url, response.addrs.len(), None);
+                if event != Some(TrackerRequestEvent::Stopped) {
+                    for addr in response.addrs {
+                        self.tx.send(addr).await.context("rx closed")?;
+                    }
                 }
                 let sleep = response.interval.max(5);
                 let sleep = Duration::from_secs(sleep as u64);
@@ -436,3 +986,95 @@ impl TrackerComms {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        time::{Duration, SystemTime},
+    };
+
+    use librqbit_core::hash_id::Id20;
+
+    use super::{AnnounceEvents, TrackerStatuses, promote_in_tier};
+    use crate::tracker_comms_http::TrackerRequestEvent::{Completed, Started};
+
+    #[test]
+    fn test_promote_in_tier() {
+        let mut tier = ['a', 'b', 'c', 'd'];
+        promote_in_tier(&mut tier, 2);
+        assert_eq!(tier, ['c', 'a', 'b', 'd']);
+        promote_in_tier(&mut tier, 0);
+        assert_eq!(tier, ['c', 'a', 'b', 'd']);
+        promote_in_tier(&mut tier, 3);
+        assert_eq!(tier, ['d', 'c', 'a', 'b']);
+    }
+
+    #[test]
+    fn test_announce_events() {
+        let a: url::Url = "udp://a.example:1337/announce".parse().unwrap();
+        let b: url::Url = "http://b.example/announce".parse().unwrap();
+        let h = Id20::new([1; 20]);
+        let events = AnnounceEvents::default();
+
+        // Started while downloading, "completed" is due once, after the torrent finishes.
+        assert_eq!(events.next(h, &a, false), Some(Started));
+        events.sent(h, &a, Some(Started), false);
+        assert!(events.completed_due(h, &a));
+        assert_eq!(events.next(h, &a, false), None);
+        assert_eq!(events.next(h, &a, true), Some(Completed));
+        events.sent(h, &a, Some(Completed), true);
+        assert!(!events.completed_due(h, &a));
+        assert_eq!(events.next(h, &a, true), None);
+
+        // Started when already finished, "completed" is never sent.
+        assert_eq!(events.next(h, &b, true), Some(Started));
+        events.sent(h, &b, Some(Started), true);
+        assert!(!events.completed_due(h, &b));
+        assert_eq!(events.next(h, &b, true), None);
+
+        // The other swarm of a hybrid torrent is announced separately.
+        let v2 = Id20::new([2; 20]);
+        assert_eq!(events.next(v2, &a, true), Some(Started));
+
+        // Starts over after "stopped".
+        events.forget(&a);
+        assert_eq!(events.next(h, &a, true), Some(Started));
+        assert_eq!(events.next(h, &b, true), None);
+    }
+
+    #[test]
+    fn test_tracker_statuses() {
+        let a: url::Url = "udp://a.example:1337/announce".parse().unwrap();
+        let b: url::Url = "http://b.example/announce".parse().unwrap();
+        let h = Id20::new([1; 20]);
+        let statuses = TrackerStatuses::default();
+        statuses.announce_failed(h, &b, &anyhow::anyhow!("timeout"));
+        statuses.announce_succeeded(h, &a, 10, Some("slow down".to_owned()));
+        statuses.set_next_announce(h, &a, Duration::from_secs(60));
+
+        let snapshot = statuses.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].url, b);
+        assert_eq!(snapshot[0].last_error.as_deref(), Some("timeout"));
+        assert_eq!(snapshot[0].last_announce, None);
+        assert_eq!(snapshot[1].peers_returned, 10);
+        assert_eq!(snapshot[1].last_warning.as_deref(), Some("slow down"));
+        assert!(snapshot[1].next_announce.unwrap() > SystemTime::now());
+
+        // The other swarm of a hybrid torrent has its own status.
+        let v2 = Id20::new([2; 20]);
+        statuses.announce_failed(v2, &a, &anyhow::anyhow!("unregistered torrent"));
+        assert_eq!(statuses.get(&a, h).unwrap().last_error, None);
+        assert_eq!(statuses.get(&a, h).unwrap().peers_returned, 10);
+        assert_eq!(
+            statuses.get(&a, v2).unwrap().last_error.as_deref(),
+            Some("unregistered torrent")
+        );
+
+        statuses.retain(&HashSet::from([a.clone()]));
+        assert_eq!(statuses.get(&b, h), None);
+        assert!(statuses.get(&a, h).is_some());
+        assert!(statuses.get(&a, v2).is_some());
+    }
+}
@@ -1,11 +1,15 @@
+use anyhow::Context;
 use buffers::ByteBuf;
 use itertools::Either;
-use serde::Deserializer;
-use serde_derive::Deserialize;
+use serde::{Deserializer, Serializer};
+use serde_derive::{Deserialize, Serialize};
 use serde_with::serde_as;
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
     marker::PhantomData,
     net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    str::FromStr,
 };
 
 use librqbit_core::{
@@ -13,18 +17,16 @@ use librqbit_core::{
     hash_id::Id20,
 };
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrackerRequestEvent {
     Started,
-    #[allow(dead_code)]
     Stopped,
-    #[allow(dead_code)]
     Completed,
 }
 
 pub struct TrackerRequest<'a> {
-    pub info_hash: &'a Id20,
-    pub peer_id: &'a Id20,
+    pub info_hash: Id20,
+    pub peer_id: Id20,
     pub event: Option<TrackerRequestEvent>,
     pub port: u16,
     pub uploaded: u64,
@@ -39,7 +41,7 @@ pub struct TrackerRequest<'a> {
     pub trackerid: Option<&'a str>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct TrackerError<'a> {
     #[serde(rename = "failure reason", borrow)]
     pub failure_reason: ByteBuf<'a>,
@@ -76,6 +78,39 @@ where
             Peers::Compact(l) => Either::Right(l.iter().map(Into::into)),
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Peers::DictPeers(a) => a.is_empty(),
+            Peers::Compact(l) => l.is_empty(),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct DictPeer {
+    #[serde_as(as = "serde_with::DisplayFromStr")]
+    ip: IpAddr,
+    port: u16,
+}
+
+impl<AddrType> serde::ser::Serialize for Peers<'_, AddrType>
+where
+    AddrType: CompactSerialize + CompactSerializeFixedLen,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Peers::DictPeers(addrs) => serializer.collect_seq(addrs.iter().map(|a| DictPeer {
+                ip: a.ip(),
+                port: a.port(),
+            })),
+            Peers::Compact(l) => l.serialize(serializer),
+        }
+    }
 }
 
 impl<'a, 'de, AddrType> serde::de::Deserialize<'de> for Peers<'a, AddrType>
@@ -87,14 +122,6 @@ where
     where
         D: Deserializer<'de>,
     {
-        #[serde_as]
-        #[derive(Deserialize)]
-        struct DictPeer {
-            #[serde_as(as = "serde_with::DisplayFromStr")]
-            ip: IpAddr,
-            port: u16,
-        }
-
         struct Visitor<'a, 'de, AddrType> {
             phantom: std::marker::PhantomData<&'de &'a AddrType>,
         }
@@ -132,26 +159,30 @@ where
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct TrackerResponse<'a> {
-    #[allow(dead_code)]
-    #[serde(rename = "warning message", borrow)]
+    #[serde(
+        rename = "warning message",
+        borrow,
+        skip_serializing_if = "Option::is_none"
+    )]
     pub warning_message: Option<ByteBuf<'a>>,
     #[allow(dead_code)]
     #[serde(default)]
     pub complete: u64,
     pub interval: u64,
     #[allow(dead_code)]
-    #[serde(rename = "min interval")]
+    #[serde(rename = "min interval", skip_serializing_if = "Option::is_none")]
     pub min_interval: Option<u64>,
     #[allow(dead_code)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tracker_id: Option<ByteBuf<'a>>,
     #[allow(dead_code)]
     #[serde(default)]
     pub incomplete: u64,
     #[serde(borrow)]
     pub peers: Peers<'a, SocketAddrV4>,
-    #[serde(default, borrow)]
+    #[serde(default, borrow, skip_serializing_if = "Peers::is_empty")]
     pub peers6: Peers<'a, SocketAddrV6>,
 }
 
@@ -204,9 +235,164 @@ impl TrackerRequest<'_> {
     }
 }
 
+// Split a query string into keys and percent-decoded values. The values are binary, so "+" is
+// not decoded into a space.
+fn query_pairs(query: &str) -> impl Iterator<Item = (&str, Cow<'_, [u8]>)> {
+    query.split('&').filter(|p| !p.is_empty()).map(|p| {
+        let (key, value) = p.split_once('=').unwrap_or((p, ""));
+        (key, urlencoding::decode_binary(value.as_bytes()))
+    })
+}
+
+fn parse_query_value<T: FromStr>(key: &str, value: &[u8]) -> anyhow::Result<T> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .with_context(|| format!("invalid {key}"))
+}
+
+impl TrackerRequest<'static> {
+    /// Parse an announce request, as sent by [`TrackerRequest::as_querystring`].
+    pub fn parse_querystring(query: &str) -> anyhow::Result<Self> {
+        let mut info_hash = None;
+        let mut peer_id = None;
+        let mut port = None;
+        let mut request = TrackerRequest {
+            info_hash: Id20::default(),
+            peer_id: Id20::default(),
+            event: None,
+            port: 0,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            compact: true,
+            no_peer_id: false,
+            ip: None,
+            numwant: None,
+            key: None,
+            trackerid: None,
+        };
+        for (key, value) in query_pairs(query) {
+            match key {
+                "info_hash" => {
+                    info_hash = Some(Id20::from_bytes(&value).context("invalid info_hash")?)
+                }
+                "peer_id" => peer_id = Some(Id20::from_bytes(&value).context("invalid peer_id")?),
+                "port" => port = Some(parse_query_value(key, &value)?),
+                "uploaded" => request.uploaded = parse_query_value(key, &value)?,
+                "downloaded" => request.downloaded = parse_query_value(key, &value)?,
+                "left" => request.left = parse_query_value(key, &value)?,
+                "compact" => request.compact = *value != *b"0",
+                "no_peer_id" => request.no_peer_id = *value == *b"1",
+                "event" => {
+                    request.event = match &*value {
+                        b"started" => Some(TrackerRequestEvent::Started),
+                        b"stopped" => Some(TrackerRequestEvent::Stopped),
+                        b"completed" => Some(TrackerRequestEvent::Completed),
+                        b"" | b"empty" => None,
+                        _ => anyhow::bail!("invalid event"),
+                    }
+                }
+                "numwant" => request.numwant = Some(parse_query_value(key, &value)?),
+                // Clients format the key differently, and it's only informational.
+                "key" => request.key = parse_query_value(key, &value).ok(),
+                "ip" => request.ip = parse_query_value(key, &value).ok(),
+                _ => {}
+            }
+        }
+        request.info_hash = info_hash.context("missing info_hash")?;
+        request.peer_id = peer_id.context("missing peer_id")?;
+        request.port = port.context("missing port")?;
+        Ok(request)
+    }
+}
+
+/// Derive the scrape URL from the announce URL. This only works if the last path component
+/// starts with "announce", e.g. "/announce.php?passkey=x" becomes "/scrape.php?passkey=x".
+pub fn scrape_url(announce_url: &url::Url) -> Option<url::Url> {
+    let (dir, last) = announce_url.path().rsplit_once('/')?;
+    let rest = last.strip_prefix("announce")?;
+    let mut url = announce_url.clone();
+    url.set_path(&format!("{dir}/scrape{rest}"));
+    Some(url)
+}
+
+pub fn scrape_querystring(info_hashes: &[Id20]) -> String {
+    info_hashes
+        .iter()
+        .map(|h| format!("info_hash={}", urlencoding::encode_binary(&h.0)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Parse the info hashes of a scrape request, as sent by [`scrape_querystring`].
+pub fn parse_scrape_querystring(query: &str) -> anyhow::Result<Vec<Id20>> {
+    query_pairs(query)
+        .filter(|(key, _)| *key == "info_hash")
+        .map(|(_, value)| Id20::from_bytes(&value).context("invalid info_hash"))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScrapeResponse<'a> {
+    #[serde(borrow)]
+    pub files: BTreeMap<ByteBuf<'a>, ScrapeResponseFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ScrapeResponseFile {
+    #[serde(default)]
+    pub complete: u64,
+    #[serde(default)]
+    pub downloaded: u64,
+    #[serde(default)]
+    pub incomplete: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_scrape_url() {
+        let scrape = |u: &str| scrape_url(&u.parse().unwrap()).map(|u| u.to_string());
+        assert_eq!(
+            scrape("http://example.com/announce").as_deref(),
+            Some("http://example.com/scrape")
+        );
+        assert_eq!(
+            scrape("http://example.com/x/announce.php?passkey=abc").as_deref(),
+            Some("http://example.com/x/scrape.php?passkey=abc")
+        );
+        assert_eq!(scrape("http://example.com/a"), None);
+        assert_eq!(scrape("http://example.com/myannounce"), None);
+        assert_eq!(scrape("http://example.com/announce/x"), None);
+    }
+
+    #[test]
+    fn test_parse_scrape_response() {
+        let mut buf = b"d5:filesd20:".to_vec();
+        buf.extend_from_slice(&[1; 20]);
+        buf.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10eeee");
+        let response = bencode::from_bytes::<ScrapeResponse>(&buf).unwrap();
+        assert_eq!(response.files.len(), 1);
+        assert_eq!(
+            response.files.get(&[1u8; 20][..]),
+            Some(&ScrapeResponseFile {
+                complete: 5,
+                downloaded: 50,
+                incomplete: 10
+            })
+        );
+        assert_eq!(
+            scrape_querystring(&[Id20::new([1; 20]), Id20::new([0xab; 20])]),
+            format!(
+                "info_hash={}&info_hash={}",
+                "%01".repeat(20),
+                "%AB".repeat(20)
+            )
+        );
+    }
     #[test]
     fn test_serialize() {
         let info_hash = Id20::new([
@@ -214,8 +400,8 @@ mod tests {
         ]);
         let peer_id = info_hash;
         let request = TrackerRequest {
-            info_hash: &info_hash,
-            peer_id: &peer_id,
+            info_hash,
+            peer_id,
             port: 6881,
             uploaded: 0,
             downloaded: 0,
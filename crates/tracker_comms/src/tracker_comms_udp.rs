@@ -8,6 +8,8 @@ use std::{
 
 use anyhow::{Context, bail};
 use librqbit_core::{hash_id::Id20, spawn_utils::spawn_with_cancel};
+
+use crate::ScrapeStats;
 use librqbit_dualstack_sockets::{BindDevice, UdpSocket};
 use parking_lot::RwLock;
 use rand::RngExt;
@@ -16,7 +18,7 @@ use tracing::{debug, debug_span, trace, warn};
 
 const ACTION_CONNECT: u32 = 0;
 const ACTION_ANNOUNCE: u32 = 1;
-// const ACTION_SCRAPE: u32 = 2;
+const ACTION_SCRAPE: u32 = 2;
 const ACTION_ERROR: u32 = 3;
 
 pub const EVENT_NONE: u32 = 0;
@@ -24,6 +26,9 @@ pub const EVENT_COMPLETED: u32 = 1;
 pub const EVENT_STARTED: u32 = 2;
 pub const EVENT_STOPPED: u32 = 3;
 
+/// How many info hashes fit into one scrape request.
+pub const MAX_SCRAPE_INFO_HASHES: usize = 74;
+
 pub type ConnectionId = u64;
 const CONNECTION_ID_MAGIC: ConnectionId = 0x41727101980;
 
@@ -49,6 +54,23 @@ pub struct AnnounceFields {
 pub enum Request {
     Connect,
     Announce(ConnectionId, AnnounceFields),
+    Scrape(ConnectionId, Vec<Id20>),
+}
+
+struct W<'a> {
+    buf: &'a mut [u8],
+    offset: usize,
+}
+
+impl W<'_> {
+    fn extend_from_slice(&mut self, s: &[u8]) -> anyhow::Result<()> {
+        if self.buf.len() < self.offset + s.len() {
+            bail!("not enough space in buffer")
+        }
+        self.buf[self.offset..self.offset + s.len()].copy_from_slice(s);
+        self.offset += s.len();
+        Ok(())
+    }
 }
 
 impl Request {
@@ -57,21 +79,6 @@ impl Request {
         transaction_id: TransactionId,
         buf: &mut [u8],
     ) -> anyhow::Result<usize> {
-        struct W<'a> {
-            buf: &'a mut [u8],
-            offset: usize,
-        }
-        impl W<'_> {
-            fn extend_from_slice(&mut self, s: &[u8]) -> anyhow::Result<()> {
-                if self.buf.len() < self.offset + s.len() {
-                    bail!("not enough space in buffer")
-                }
-                self.buf[self.offset..self.offset + s.len()].copy_from_slice(s);
-                self.offset += s.len();
-                Ok(())
-            }
-        }
-
         let mut w = W { buf, offset: 0 };
 
         match self {
@@ -95,17 +102,80 @@ impl Request {
                 w.extend_from_slice(&(-1i32).to_be_bytes())?; // num want -1
                 w.extend_from_slice(&fields.port.to_be_bytes())?;
             }
+            Request::Scrape(connection_id, info_hashes) => {
+                w.extend_from_slice(&connection_id.to_be_bytes())?;
+                w.extend_from_slice(&ACTION_SCRAPE.to_be_bytes())?;
+                w.extend_from_slice(&transaction_id.to_be_bytes())?;
+                for info_hash in info_hashes {
+                    w.extend_from_slice(&info_hash.0)?;
+                }
+            }
         }
         Ok(w.offset)
     }
 }
 
+impl Request {
+    /// Parse a request, as a tracker would.
+    pub fn parse(buf: &[u8]) -> anyhow::Result<(TransactionId, Self)> {
+        let (connection_id, buf) = u64::parse_num(buf).context("can't parse connection id")?;
+        let (action, buf) = u32::parse_num(buf).context("can't parse action")?;
+        let (tid, buf) = u32::parse_num(buf).context("can't parse transaction id")?;
+        let request = match action {
+            ACTION_CONNECT => {
+                if connection_id != CONNECTION_ID_MAGIC {
+                    bail!("invalid protocol id");
+                }
+                Request::Connect
+            }
+            ACTION_ANNOUNCE => {
+                let (info_hash, buf) = split_slice(buf, 20).context("can't parse info hash")?;
+                let (peer_id, buf) = split_slice(buf, 20).context("can't parse peer id")?;
+                let (downloaded, buf) = u64::parse_num(buf).context("can't parse downloaded")?;
+                let (left, buf) = u64::parse_num(buf).context("can't parse left")?;
+                let (uploaded, buf) = u64::parse_num(buf).context("can't parse uploaded")?;
+                let (event, buf) = u32::parse_num(buf).context("can't parse event")?;
+                // The IP address and "num want" are ignored.
+                let (_ip, buf) = u32::parse_num(buf).context("can't parse ip")?;
+                let (key, buf) = u32::parse_num(buf).context("can't parse key")?;
+                let (_num_want, buf) = i32::parse_num(buf).context("can't parse num want")?;
+                let (port, _extensions) = u16::parse_num(buf).context("can't parse port")?;
+                Request::Announce(
+                    connection_id,
+                    AnnounceFields {
+                        info_hash: Id20::new(s_to_arr(info_hash)),
+                        peer_id: Id20::new(s_to_arr(peer_id)),
+                        downloaded,
+                        left,
+                        uploaded,
+                        event,
+                        key,
+                        port,
+                    },
+                )
+            }
+            ACTION_SCRAPE => {
+                if buf.is_empty() || buf.len() % 20 != 0 {
+                    bail!("expected a list of info hashes");
+                }
+                Request::Scrape(
+                    connection_id,
+                    buf.chunks_exact(20)
+                        .take(MAX_SCRAPE_INFO_HASHES)
+                        .map(|h| Id20::new(s_to_arr(h)))
+                        .collect(),
+                )
+            }
+            _ => bail!("unsupported action {action}"),
+        };
+        Ok((tid, request))
+    }
+}
+
 #[derive(Debug)]
 pub struct AnnounceResponse {
     pub interval: u32,
-    #[allow(dead_code)]
     pub leechers: u32,
-    #[allow(dead_code)]
     pub seeders: u32,
     pub addrs: Vec<SocketAddr>,
 }
@@ -114,7 +184,8 @@ pub struct AnnounceResponse {
 pub enum Response {
     Connect(ConnectionId),
     Announce(AnnounceResponse),
-    #[allow(dead_code)]
+    // In the same order as the info hashes in the request.
+    Scrape(Vec<ScrapeStats>),
     Error(String),
     Unknown,
 }
@@ -158,6 +229,53 @@ parse_impl!(i64, 8);
 parse_impl!(i16, 2);
 
 impl Response {
+    /// Serialize the response, as a tracker would. Announced addresses are written in their own
+    /// format, so they all should be of the same family as the address the request came from.
+    pub fn serialize(
+        &self,
+        transaction_id: TransactionId,
+        buf: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        let mut w = W { buf, offset: 0 };
+        match self {
+            Response::Connect(connection_id) => {
+                w.extend_from_slice(&ACTION_CONNECT.to_be_bytes())?;
+                w.extend_from_slice(&transaction_id.to_be_bytes())?;
+                w.extend_from_slice(&connection_id.to_be_bytes())?;
+            }
+            Response::Announce(announce) => {
+                w.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes())?;
+                w.extend_from_slice(&transaction_id.to_be_bytes())?;
+                w.extend_from_slice(&announce.interval.to_be_bytes())?;
+                w.extend_from_slice(&announce.leechers.to_be_bytes())?;
+                w.extend_from_slice(&announce.seeders.to_be_bytes())?;
+                for addr in announce.addrs.iter() {
+                    match addr.ip() {
+                        IpAddr::V4(ip) => w.extend_from_slice(&ip.octets())?,
+                        IpAddr::V6(ip) => w.extend_from_slice(&ip.octets())?,
+                    }
+                    w.extend_from_slice(&addr.port().to_be_bytes())?;
+                }
+            }
+            Response::Scrape(stats) => {
+                w.extend_from_slice(&ACTION_SCRAPE.to_be_bytes())?;
+                w.extend_from_slice(&transaction_id.to_be_bytes())?;
+                for s in stats {
+                    w.extend_from_slice(&s.seeders.to_be_bytes())?;
+                    w.extend_from_slice(&s.completed.to_be_bytes())?;
+                    w.extend_from_slice(&s.leechers.to_be_bytes())?;
+                }
+            }
+            Response::Error(msg) => {
+                w.extend_from_slice(&ACTION_ERROR.to_be_bytes())?;
+                w.extend_from_slice(&transaction_id.to_be_bytes())?;
+                w.extend_from_slice(msg.as_bytes())?;
+            }
+            Response::Unknown => bail!("can't serialize an unknown response"),
+        }
+        Ok(w.offset)
+    }
+
     pub fn parse(buf: &[u8], is_ipv6: bool) -> anyhow::Result<(TransactionId, Self)> {
         let (action, buf) = u32::parse_num(buf).context("can't parse action")?;
         let (tid, buf) = u32::parse_num(buf).context("can't parse transaction id")?;
@@ -208,6 +326,21 @@ impl Response {
                     addrs,
                 })
             }
+            ACTION_SCRAPE => {
+                let mut stats = Vec::new();
+                while !buf.is_empty() {
+                    let (seeders, b) = u32::parse_num(buf).context("can't parse seeders")?;
+                    let (completed, b) = u32::parse_num(b).context("can't parse completed")?;
+                    let (leechers, b) = u32::parse_num(b).context("can't parse leechers")?;
+                    buf = b;
+                    stats.push(ScrapeStats {
+                        seeders,
+                        leechers,
+                        completed,
+                    });
+                }
+                Response::Scrape(stats)
+            }
             ACTION_ERROR => {
                 let msg = CStr::from_bytes_with_nul(buf)
                     .ok()
@@ -357,7 +490,7 @@ impl UdpTrackerClient {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let tid_g = self.reserve_transaction_id(tx)?;
 
-        let mut write_buf = [0u8; 1024];
+        let mut write_buf = [0u8; 2048];
         let len = request.serialize(tid_g.tid, &mut write_buf)?;
         self.state
             .sock
@@ -415,6 +548,33 @@ impl UdpTrackerClient {
             other => bail!("unexpected response {other:?}, expected announce"),
         }
     }
+
+    /// Scrape up to [MAX_SCRAPE_INFO_HASHES] info hashes in one request. The results are in the
+    /// same order as the info hashes.
+    pub async fn scrape(
+        &self,
+        tracker: SocketAddr,
+        info_hashes: &[Id20],
+    ) -> anyhow::Result<Vec<ScrapeStats>> {
+        if info_hashes.len() > MAX_SCRAPE_INFO_HASHES {
+            bail!(
+                "can't scrape more than {MAX_SCRAPE_INFO_HASHES} info hashes at once, got {}",
+                info_hashes.len()
+            );
+        }
+        let connection_id = self.get_connection_id(tracker).await?;
+        let request = Request::Scrape(connection_id, info_hashes.to_vec());
+        let response = self.request(tracker, request).await?;
+        match response {
+            Response::Scrape(r) if r.len() == info_hashes.len() => Ok(r),
+            Response::Scrape(r) => bail!(
+                "expected stats for {} info hashes, got {}",
+                info_hashes.len(),
+                r.len()
+            ),
+            other => bail!("unexpected response {other:?}, expected scrape"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -423,8 +583,9 @@ mod tests {
 
     use librqbit_core::{hash_id::Id20, peer_id::generate_peer_id};
 
-    use crate::tracker_comms_udp::{
-        AnnounceFields, EVENT_NONE, Request, Response, new_transaction_id,
+    use crate::{
+        ScrapeStats,
+        tracker_comms_udp::{AnnounceFields, EVENT_NONE, Request, Response, new_transaction_id},
     };
 
     #[test]
@@ -434,6 +595,50 @@ mod tests {
         dbg!(tid, response);
     }
 
+    #[test]
+    fn test_scrape_request_and_response() {
+        let info_hashes = [Id20::new([1; 20]), Id20::new([2; 20])];
+        let mut buf = [0u8; 1024];
+        let len = Request::Scrape(42, info_hashes.to_vec())
+            .serialize(7, &mut buf)
+            .unwrap();
+        assert_eq!(len, 16 + 40);
+        assert_eq!(&buf[..8], &42u64.to_be_bytes());
+        assert_eq!(&buf[8..12], &2u32.to_be_bytes());
+        assert_eq!(&buf[12..16], &7u32.to_be_bytes());
+        assert_eq!(&buf[16..36], &[1; 20]);
+        assert_eq!(&buf[36..56], &[2; 20]);
+
+        let mut response = Vec::new();
+        for n in [2u32, 7, 0, 10, 20, 30] {
+            response.extend_from_slice(&n.to_be_bytes());
+        }
+        let mut b = Vec::new();
+        b.extend_from_slice(&2u32.to_be_bytes());
+        b.extend_from_slice(&7u32.to_be_bytes());
+        b.extend_from_slice(&response);
+        let (tid, response) = Response::parse(&b, false).unwrap();
+        assert_eq!(tid, 7);
+        match response {
+            Response::Scrape(stats) => assert_eq!(
+                stats,
+                vec![
+                    ScrapeStats {
+                        seeders: 2,
+                        completed: 7,
+                        leechers: 0
+                    },
+                    ScrapeStats {
+                        seeders: 10,
+                        completed: 20,
+                        leechers: 30
+                    }
+                ]
+            ),
+            other => panic!("unexpected response {other:?}"),
+        }
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_announce() {
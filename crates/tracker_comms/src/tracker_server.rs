@@ -0,0 +1,601 @@
+// A built-in tracker, for networks with no DHT or external trackers.
+//
+// Serves HTTP (BEP 3) and UDP (BEP 15) announces and scrapes, returning compact IPv4 and IPv6
+// peer lists (BEP 23, BEP 7). Swarms are kept in memory only.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use buffers::ByteBuf;
+use librqbit_core::{compact_ip::CompactListInBufferOwned, hash_id::Id20};
+use librqbit_dualstack_sockets::UdpSocket;
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use tracing::{debug, trace, warn};
+
+use crate::{
+    ScrapeStats,
+    tracker_comms_http::{
+        Peers, ScrapeResponse, ScrapeResponseFile, TrackerError, TrackerRequest,
+        TrackerRequestEvent, TrackerResponse, parse_scrape_querystring,
+    },
+    tracker_comms_udp::{
+        AnnounceResponse, ConnectionId, EVENT_COMPLETED, EVENT_STARTED, EVENT_STOPPED, Request,
+        Response, TransactionId,
+    },
+};
+
+const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_MAX_PEERS: usize = 50;
+
+// Peers that didn't announce for this many intervals are forgotten.
+const PEER_TIMEOUT_INTERVALS: u32 = 2;
+
+// UDP connection IDs are valid for the bucket they were given out in and the next one, so for
+// at least a minute as BEP 15 requires.
+const CONNECTION_ID_BUCKET: Duration = Duration::from_secs(60);
+
+// Keeps UDP announce responses well within one datagram.
+const MAX_UDP_PEERS: usize = 200;
+
+/// Decides which torrents the tracker serves.
+pub type TrackerWhitelist = Box<dyn Fn(&Id20) -> bool + Send + Sync>;
+
+#[derive(Default)]
+pub struct TrackerServerOptions {
+    /// How often peers should announce. Defaults to 10 minutes.
+    pub announce_interval: Option<Duration>,
+    /// The most peers to return from one announce. Defaults to 50.
+    pub max_peers: Option<usize>,
+    /// If set, only the torrents it accepts are tracked.
+    pub whitelist: Option<TrackerWhitelist>,
+}
+
+struct Peer {
+    seeder: bool,
+    last_announce: Instant,
+}
+
+#[derive(Default)]
+struct Swarm {
+    peers: HashMap<SocketAddr, Peer>,
+    // How many times peers announced they finished downloading.
+    completed: u32,
+}
+
+impl Swarm {
+    fn stats(&self) -> ScrapeStats {
+        let seeders = self.peers.values().filter(|p| p.seeder).count();
+        let saturate = |v: usize| u32::try_from(v).unwrap_or(u32::MAX);
+        ScrapeStats {
+            seeders: saturate(seeders),
+            leechers: saturate(self.peers.len() - seeders),
+            completed: self.completed,
+        }
+    }
+}
+
+struct Locked {
+    swarms: HashMap<Id20, Swarm>,
+    last_cleanup: Instant,
+}
+
+// The announce parameters HTTP and UDP have in common.
+struct Announce {
+    info_hash: Id20,
+    addr: SocketAddr,
+    left: u64,
+    event: Option<TrackerRequestEvent>,
+    num_want: usize,
+    // Only return peers of the same address family as "addr".
+    same_family_only: bool,
+}
+
+struct AnnounceResult {
+    stats: ScrapeStats,
+    peers: Vec<SocketAddr>,
+}
+
+pub struct TrackerServer {
+    announce_interval: Duration,
+    max_peers: usize,
+    whitelist: Option<TrackerWhitelist>,
+    locked: Mutex<Locked>,
+    connection_id_secret: RandomState,
+    started: Instant,
+}
+
+impl TrackerServer {
+    pub fn new(opts: TrackerServerOptions) -> Self {
+        let now = Instant::now();
+        Self {
+            announce_interval: opts.announce_interval.unwrap_or(DEFAULT_ANNOUNCE_INTERVAL),
+            max_peers: opts.max_peers.unwrap_or(DEFAULT_MAX_PEERS),
+            whitelist: opts.whitelist,
+            locked: Mutex::new(Locked {
+                swarms: HashMap::new(),
+                last_cleanup: now,
+            }),
+            connection_id_secret: RandomState::new(),
+            started: now,
+        }
+    }
+
+    fn check_whitelist(&self, info_hash: &Id20) -> anyhow::Result<()> {
+        if self.whitelist.as_ref().is_some_and(|w| !w(info_hash)) {
+            bail!("torrent is not tracked here");
+        }
+        Ok(())
+    }
+
+    fn announce(&self, announce: Announce) -> anyhow::Result<AnnounceResult> {
+        self.check_whitelist(&announce.info_hash)?;
+        let now = Instant::now();
+        let mut g = self.locked.lock();
+        self.forget_stale_peers(&mut g, now);
+
+        let swarm = g.swarms.entry(announce.info_hash).or_default();
+        let seeder = announce.left == 0;
+        if announce.event == Some(TrackerRequestEvent::Stopped) {
+            swarm.peers.remove(&announce.addr);
+        } else {
+            let was_seeder = swarm.peers.get(&announce.addr).is_some_and(|p| p.seeder);
+            if announce.event == Some(TrackerRequestEvent::Completed) && !was_seeder {
+                swarm.completed = swarm.completed.saturating_add(1);
+            }
+            swarm.peers.insert(
+                announce.addr,
+                Peer {
+                    seeder,
+                    last_announce: now,
+                },
+            );
+        }
+
+        // Seeders have no use for other seeders.
+        let mut peers = swarm
+            .peers
+            .iter()
+            .filter(|(addr, peer)| **addr != announce.addr && !(seeder && peer.seeder))
+            .filter(|(addr, _)| {
+                !announce.same_family_only || addr.is_ipv6() == announce.addr.is_ipv6()
+            })
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+        peers.shuffle(&mut rand::rng());
+        peers.truncate(announce.num_want.min(self.max_peers));
+
+        Ok(AnnounceResult {
+            stats: swarm.stats(),
+            peers,
+        })
+    }
+
+    // Forget peers that stopped announcing, at most once per announce interval.
+    fn forget_stale_peers(&self, locked: &mut Locked, now: Instant) {
+        if now - locked.last_cleanup < self.announce_interval {
+            return;
+        }
+        locked.last_cleanup = now;
+        let timeout = self.announce_interval * PEER_TIMEOUT_INTERVALS;
+        for swarm in locked.swarms.values_mut() {
+            swarm
+                .peers
+                .retain(|_, peer| now - peer.last_announce < timeout);
+        }
+        locked.swarms.retain(|_, swarm| !swarm.peers.is_empty());
+    }
+
+    // Stats of the given torrents, in the same order. Unknown torrents have zero counts.
+    fn scrape(&self, info_hashes: &[Id20]) -> Vec<ScrapeStats> {
+        let g = self.locked.lock();
+        info_hashes
+            .iter()
+            .map(|h| g.swarms.get(h).map(Swarm::stats).unwrap_or_default())
+            .collect()
+    }
+
+    fn announce_interval_secs(&self) -> u32 {
+        u32::try_from(self.announce_interval.as_secs()).unwrap_or(u32::MAX)
+    }
+
+    /// Handle an HTTP announce, given its query string. Returns the bencoded response body.
+    /// Errors are returned to the peer as "failure reason".
+    pub fn http_announce(&self, query: &str, remote_ip: IpAddr) -> Vec<u8> {
+        self.http_announce_impl(query, remote_ip)
+            .unwrap_or_else(|e| http_failure(&e))
+    }
+
+    fn http_announce_impl(&self, query: &str, remote_ip: IpAddr) -> anyhow::Result<Vec<u8>> {
+        let request = TrackerRequest::parse_querystring(query)?;
+        let result = self.announce(Announce {
+            info_hash: request.info_hash,
+            addr: SocketAddr::new(remote_ip.to_canonical(), request.port),
+            left: request.left,
+            event: request.event,
+            num_want: request.numwant.unwrap_or(self.max_peers),
+            same_family_only: false,
+        })?;
+
+        let peers_v4 = CompactListInBufferOwned::new_from_iter(result.peers.iter().filter_map(
+            |addr| match addr {
+                SocketAddr::V4(addr) => Some(*addr),
+                SocketAddr::V6(_) => None,
+            },
+        ));
+        let peers_v6 = CompactListInBufferOwned::<SocketAddrV6>::new_from_iter(
+            result.peers.iter().filter_map(|addr| match addr {
+                SocketAddr::V4(_) => None,
+                SocketAddr::V6(addr) => Some(*addr),
+            }),
+        );
+        let (peers, peers6) = if request.compact {
+            (
+                Peers::<SocketAddrV4>::Compact(peers_v4.as_borrowed()),
+                Peers::Compact(peers_v6.as_borrowed()),
+            )
+        } else {
+            (Peers::DictPeers(result.peers), Peers::default())
+        };
+
+        let response = TrackerResponse {
+            warning_message: None,
+            complete: result.stats.seeders.into(),
+            incomplete: result.stats.leechers.into(),
+            interval: self.announce_interval.as_secs(),
+            min_interval: None,
+            tracker_id: None,
+            peers,
+            peers6,
+        };
+        let mut buf = Vec::new();
+        bencode::bencode_serialize_to_writer(&response, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Handle an HTTP scrape, given its query string. Returns the bencoded response body.
+    /// Without info hashes in the query, all the torrents are returned.
+    pub fn http_scrape(&self, query: &str) -> Vec<u8> {
+        self.http_scrape_impl(query)
+            .unwrap_or_else(|e| http_failure(&e))
+    }
+
+    fn http_scrape_impl(&self, query: &str) -> anyhow::Result<Vec<u8>> {
+        let mut info_hashes = parse_scrape_querystring(query)?;
+        if info_hashes.is_empty() {
+            info_hashes = self.locked.lock().swarms.keys().copied().collect();
+        }
+        let stats = self.scrape(&info_hashes);
+        let response = ScrapeResponse {
+            files: info_hashes
+                .iter()
+                .zip(stats)
+                .map(|(info_hash, stats)| {
+                    (
+                        ByteBuf(&info_hash.0),
+                        ScrapeResponseFile {
+                            complete: stats.seeders.into(),
+                            downloaded: stats.completed.into(),
+                            incomplete: stats.leechers.into(),
+                        },
+                    )
+                })
+                .collect::<BTreeMap<_, _>>(),
+        };
+        let mut buf = Vec::new();
+        bencode::bencode_serialize_to_writer(&response, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn connection_id(&self, addr: SocketAddr, bucket: u64) -> ConnectionId {
+        self.connection_id_secret.hash_one((addr, bucket))
+    }
+
+    fn connection_id_bucket(&self) -> u64 {
+        self.started.elapsed().as_secs() / CONNECTION_ID_BUCKET.as_secs()
+    }
+
+    fn check_connection_id(&self, connection_id: ConnectionId, addr: SocketAddr) -> bool {
+        let bucket = self.connection_id_bucket();
+        connection_id == self.connection_id(addr, bucket)
+            || (bucket > 0 && connection_id == self.connection_id(addr, bucket - 1))
+    }
+
+    // None if the request can't be parsed, as there is no transaction ID to reply with.
+    fn handle_udp_request(
+        &self,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> Option<(TransactionId, Response)> {
+        let (tid, request) = match Request::parse(buf) {
+            Ok(r) => r,
+            Err(e) => {
+                trace!(?addr, "error parsing UDP tracker request: {e:#}");
+                return None;
+            }
+        };
+        let response = match request {
+            Request::Connect => {
+                Response::Connect(self.connection_id(addr, self.connection_id_bucket()))
+            }
+            Request::Announce(connection_id, _) | Request::Scrape(connection_id, _)
+                if !self.check_connection_id(connection_id, addr) =>
+            {
+                Response::Error("invalid connection id".to_owned())
+            }
+            Request::Announce(_, fields) => {
+                let result = self.announce(Announce {
+                    info_hash: fields.info_hash,
+                    addr: SocketAddr::new(addr.ip(), fields.port),
+                    left: fields.left,
+                    event: match fields.event {
+                        EVENT_STARTED => Some(TrackerRequestEvent::Started),
+                        EVENT_COMPLETED => Some(TrackerRequestEvent::Completed),
+                        EVENT_STOPPED => Some(TrackerRequestEvent::Stopped),
+                        _ => None,
+                    },
+                    num_want: MAX_UDP_PEERS,
+                    same_family_only: true,
+                });
+                match result {
+                    Ok(result) => Response::Announce(AnnounceResponse {
+                        interval: self.announce_interval_secs(),
+                        leechers: result.stats.leechers,
+                        seeders: result.stats.seeders,
+                        addrs: result.peers,
+                    }),
+                    Err(e) => Response::Error(format!("{e:#}")),
+                }
+            }
+            Request::Scrape(_, info_hashes) => Response::Scrape(self.scrape(&info_hashes)),
+        };
+        Some((tid, response))
+    }
+
+    /// Serve UDP announces and scrapes on the socket, forever.
+    pub async fn run_udp(&self, sock: UdpSocket) -> anyhow::Result<()> {
+        let mut read_buf = [0u8; 2048];
+        let mut write_buf = [0u8; 4096];
+        loop {
+            let (len, addr) = match sock.recv_from(&mut read_buf).await {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("error in UdpSocket::recv_from: {e:#}");
+                    continue;
+                }
+            };
+            let Some((tid, response)) = self.handle_udp_request(&read_buf[..len], addr) else {
+                continue;
+            };
+            let len = match response.serialize(tid, &mut write_buf) {
+                Ok(len) => len,
+                Err(e) => {
+                    debug!(?addr, "error serializing UDP tracker response: {e:#}");
+                    continue;
+                }
+            };
+            if let Err(e) = sock.send_to(&write_buf[..len], addr).await {
+                debug!(?addr, "error sending UDP tracker response: {e:#}");
+            }
+        }
+    }
+}
+
+fn http_failure(error: &anyhow::Error) -> Vec<u8> {
+    let reason = format!("{error:#}");
+    let mut buf = Vec::new();
+    let _ = bencode::bencode_serialize_to_writer(
+        TrackerError {
+            failure_reason: ByteBuf(reason.as_bytes()),
+        },
+        &mut buf,
+    );
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use librqbit_core::hash_id::Id20;
+
+    use super::{TrackerServer, TrackerServerOptions};
+    use crate::{
+        ScrapeStats,
+        tracker_comms_http::{
+            ScrapeResponse, TrackerError, TrackerRequest, TrackerRequestEvent, TrackerResponse,
+            scrape_querystring,
+        },
+        tracker_comms_udp::{
+            AnnounceFields, EVENT_COMPLETED, EVENT_STARTED, Request, Response, TransactionId,
+        },
+    };
+
+    fn info_hash() -> Id20 {
+        Id20::new([1; 20])
+    }
+
+    fn announce_query(
+        peer: u8,
+        port: u16,
+        left: u64,
+        event: Option<TrackerRequestEvent>,
+    ) -> String {
+        TrackerRequest {
+            info_hash: info_hash(),
+            peer_id: Id20::new([peer; 20]),
+            event,
+            port,
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            compact: true,
+            no_peer_id: false,
+            ip: None,
+            numwant: None,
+            key: None,
+            trackerid: None,
+        }
+        .as_querystring()
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_http_announce_and_scrape() {
+        let server = TrackerServer::new(Default::default());
+
+        let body = server.http_announce(
+            &announce_query(1, 6881, 0, Some(TrackerRequestEvent::Started)),
+            ip(1),
+        );
+        let response = bencode::from_bytes::<TrackerResponse>(&body).unwrap();
+        assert_eq!(response.iter_peers().count(), 0);
+        assert_eq!(response.complete, 1);
+
+        let body = server.http_announce(
+            &announce_query(2, 6882, 100, Some(TrackerRequestEvent::Started)),
+            "::ffff:10.0.0.2".parse().unwrap(),
+        );
+        let response = bencode::from_bytes::<TrackerResponse>(&body).unwrap();
+        assert_eq!(
+            response.iter_peers().collect::<Vec<_>>(),
+            vec![SocketAddr::new(ip(1), 6881)]
+        );
+        assert_eq!((response.complete, response.incomplete), (1, 1));
+
+        let body = server.http_announce(
+            &announce_query(2, 6882, 0, Some(TrackerRequestEvent::Completed)),
+            ip(2),
+        );
+        let response = bencode::from_bytes::<TrackerResponse>(&body).unwrap();
+        // Seeders don't get other seeders.
+        assert_eq!(response.iter_peers().count(), 0);
+
+        let body = server.http_scrape(&scrape_querystring(&[info_hash()]));
+        let response = bencode::from_bytes::<ScrapeResponse>(&body).unwrap();
+        let file = response.files.get(&info_hash().0[..]).unwrap();
+        assert_eq!((file.complete, file.incomplete, file.downloaded), (2, 0, 1));
+
+        server.http_announce(
+            &announce_query(1, 6881, 0, Some(TrackerRequestEvent::Stopped)),
+            ip(1),
+        );
+        assert_eq!(
+            server.scrape(&[info_hash()]),
+            vec![ScrapeStats {
+                seeders: 1,
+                leechers: 0,
+                completed: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_http_whitelist() {
+        let server = TrackerServer::new(TrackerServerOptions {
+            whitelist: Some(Box::new(|h| *h == info_hash())),
+            ..Default::default()
+        });
+        let body = server.http_announce(&announce_query(1, 6881, 0, None), ip(1));
+        assert!(bencode::from_bytes::<TrackerResponse>(&body).is_ok());
+
+        let mut query = announce_query(1, 6881, 0, None);
+        query = query.replace(&"%01".repeat(20), &"%02".repeat(20));
+        let body = server.http_announce(&query, ip(1));
+        let error = bencode::from_bytes::<TrackerError>(&body).unwrap();
+        assert_eq!(
+            error.failure_reason.as_ref(),
+            b"torrent is not tracked here"
+        );
+    }
+
+    fn udp_request(
+        server: &TrackerServer,
+        addr: SocketAddr,
+        request: Request,
+    ) -> (TransactionId, Response) {
+        let mut buf = [0u8; 1024];
+        let len = request.serialize(42, &mut buf).unwrap();
+        let (tid, response) = server.handle_udp_request(&buf[..len], addr).unwrap();
+        let len = response.serialize(tid, &mut buf).unwrap();
+        Response::parse(&buf[..len], addr.is_ipv6()).unwrap()
+    }
+
+    #[test]
+    fn test_udp_announce_and_scrape() {
+        let server = TrackerServer::new(Default::default());
+        let announce = |port: u16, left: u64, event: u32| AnnounceFields {
+            info_hash: info_hash(),
+            peer_id: Id20::default(),
+            downloaded: 0,
+            left,
+            uploaded: 0,
+            event,
+            key: 0,
+            port,
+        };
+
+        let addr1 = SocketAddr::new(ip(1), 10000);
+        let (tid, response) = udp_request(&server, addr1, Request::Announce(1, announce(1, 0, 0)));
+        assert_eq!(tid, 42);
+        assert!(matches!(response, Response::Error(e) if e == "invalid connection id"));
+
+        let Response::Connect(connection_id) = udp_request(&server, addr1, Request::Connect).1
+        else {
+            panic!("expected connect response")
+        };
+        let response = udp_request(
+            &server,
+            addr1,
+            Request::Announce(connection_id, announce(1, 0, EVENT_STARTED)),
+        )
+        .1;
+        assert!(matches!(response, Response::Announce(r) if r.seeders == 1 && r.addrs.is_empty()));
+
+        let addr2 = SocketAddr::new(ip(2), 10000);
+        let Response::Connect(connection_id) = udp_request(&server, addr2, Request::Connect).1
+        else {
+            panic!("expected connect response")
+        };
+        let Response::Announce(response) = udp_request(
+            &server,
+            addr2,
+            Request::Announce(connection_id, announce(2, 0, EVENT_COMPLETED)),
+        )
+        .1
+        else {
+            panic!("expected announce response")
+        };
+        assert_eq!(response.seeders, 2);
+        assert!(response.addrs.is_empty());
+
+        let Response::Scrape(stats) = udp_request(
+            &server,
+            addr2,
+            Request::Scrape(connection_id, vec![info_hash(), Id20::new([2; 20])]),
+        )
+        .1
+        else {
+            panic!("expected scrape response")
+        };
+        assert_eq!(
+            stats,
+            vec![
+                ScrapeStats {
+                    seeders: 2,
+                    leechers: 0,
+                    completed: 1
+                },
+                ScrapeStats::default()
+            ]
+        );
+    }
+}
@@ -17,6 +17,7 @@ pub struct RqbitDesktopConfigDht {
     pub disable: bool,
     pub disable_persistence: bool,
     pub persistence_filename: PathBuf,
+    pub read_only: bool,
 }
 
 impl Default for RqbitDesktopConfigDht {
@@ -25,6 +26,7 @@ impl Default for RqbitDesktopConfigDht {
             disable: false,
             disable_persistence: false,
             persistence_filename: PersistentDht::default_persistence_filename().unwrap(),
+            read_only: false,
         }
     }
 }
@@ -72,6 +74,7 @@ impl RqbitDesktopConfigConnections {
                 read_write_timeout: Some(self.peer_read_write_timeout),
                 ..Default::default()
             }),
+            ..Default::default()
         };
         (listener_opts, connect_opts)
     }
@@ -116,6 +116,7 @@ async fn api_from_config(
         };
         Some(DhtSessionConfig {
             persistence,
+            read_only: config.dht.read_only,
             ..Default::default()
         })
     };
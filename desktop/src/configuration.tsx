@@ -6,6 +6,7 @@ interface RqbitDesktopConfigDht {
   disable: boolean;
   disable_persistence: boolean;
   persistence_filename: PathLike;
+  read_only: boolean;
 }
 
 interface RqbitDesktopConfigConnections {
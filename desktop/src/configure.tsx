@@ -196,6 +196,14 @@ Might be useful e.g. if rqbit upload consumes all your upload bandwidth and inte
             onChange={handleInputChange}
             help="The filename to store DHT state into"
           />
+          <FormCheck
+            label="Read-only DHT"
+            name="dht.read_only"
+            checked={config.dht.read_only}
+            onChange={handleToggleChange}
+            disabled={config.dht.disable}
+            help="Use the DHT without answering queries from other nodes. Saves bandwidth on metered connections."
+          />
         </Fieldset>
       ),
     },